@@ -0,0 +1,47 @@
+//! What the large-file benchmarks share. Each takes `[GIB] [RUNS]`: the size of the file to copy in GiB, 4 by default,
+//! and how many times to copy it each way, 3 by default, keeping the best time. The file is created in the system's
+//! temporary directory and removed afterwards.
+
+use std::{fs::File, io::Write, path::Path, path::PathBuf};
+
+pub const GIB: u64 = 1024 * 1024 * 1024;
+
+/// `GIB` and `RUNS` from the command line.
+pub fn args() -> (u64, usize) {
+    let mut args = std::env::args().skip(1);
+    let gib = args
+        .next()
+        .map_or(4, |gib| gib.parse().expect("GIB must be a number"));
+    let runs = args
+        .next()
+        .map_or(3, |runs| runs.parse().expect("RUNS must be a number"));
+    (gib, runs)
+}
+
+/// A directory for the benchmark called `name` to work in, with an empty `src` directory in it for the file.
+pub fn root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("ninecopy-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(root.join("src")).expect("unable to create source directory");
+    root
+}
+
+/// Write `len` bytes to `path` that the filesystem can't compress or leave as holes.
+pub fn create(path: &Path, len: u64) {
+    let mut file = File::create(path).expect("unable to create source file");
+    let mut block = vec![0u8; 1024 * 1024];
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut written = 0;
+    while written < len {
+        for chunk in block.chunks_exact_mut(8) {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            chunk.copy_from_slice(&state.to_le_bytes());
+        }
+        let size = block.len().min((len - written) as usize);
+        file.write_all(&block[..size])
+            .expect("unable to write source file");
+        written += size as u64;
+    }
+}
@@ -0,0 +1,96 @@
+//! Times copying one large file and hashing it, both at once the way `--checksum-file` does, and one after the other,
+//! to show what hashing as it copies saves over reading the source again.
+//!
+//! ```text
+//! cargo run --release --example copy_hash -- [GIB] [RUNS]
+//! ```
+//!
+//! Both ways hash with XXH3, `--hash`'s default. Hashing as it copies means the data has to pass through ninecopy, so it
+//! can't use the kernel's copy (like `copy_file_range`) the way the copy on its own can. A file that fits in memory is
+//! read back from the cache, so the second read only costs the hashing, and the two come out close. Make it bigger
+//! than memory to see what reading it from disk again costs as well.
+
+mod common;
+
+use std::{
+    fs::File,
+    io::Read,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ninecopy::CopyOptions;
+use xxhash_rust::xxh3::Xxh3;
+
+fn main() {
+    let (gib, runs) = common::args();
+
+    let root = common::root("copy-hash");
+    let src_dir = root.join("src");
+    let src = src_dir.join("large");
+    let len = gib * common::GIB;
+    let start = Instant::now();
+    common::create(&src, len);
+    println!(
+        "Created a {} GiB file in {:.3} seconds",
+        gib,
+        start.elapsed().as_secs_f64()
+    );
+
+    let manifest = root.join("checksums.txt");
+    let fused = CopyOptions::builder()
+        .threads(Some(1))
+        .checksum_file(Some(manifest.clone()))
+        .build()
+        .expect("invalid options");
+    let plain = CopyOptions::builder()
+        .threads(Some(1))
+        .build()
+        .expect("invalid options");
+    let (mut fused_best, mut separate_best) = (Duration::MAX, Duration::MAX);
+    for run in 0..runs {
+        let dst = root.join(format!("fused-{}", run));
+        let start = Instant::now();
+        ninecopy::copy_tree(&src_dir, &dst, &fused).expect("copy failed");
+        fused_best = fused_best.min(start.elapsed());
+        let _ = std::fs::remove_dir_all(&dst);
+        let _ = std::fs::remove_file(&manifest);
+
+        let dst = root.join(format!("separate-{}", run));
+        let start = Instant::now();
+        ninecopy::copy_tree(&src_dir, &dst, &plain).expect("copy failed");
+        hash(&src);
+        separate_best = separate_best.min(start.elapsed());
+        let _ = std::fs::remove_dir_all(&dst);
+    }
+    let _ = std::fs::remove_dir_all(&root);
+
+    for (name, best) in [
+        ("copy and hash at once", fused_best),
+        ("copy, then hash", separate_best),
+    ] {
+        println!(
+            "{:<24} best {:.3}s, {:.0} MiB/s",
+            name,
+            best.as_secs_f64(),
+            len as f64 / (1024.0 * 1024.0) / best.as_secs_f64()
+        );
+    }
+    println!(
+        "Hashing as it copies saved {:.0}% of the time",
+        100.0 * (1.0 - fused_best.as_secs_f64() / separate_best.as_secs_f64())
+    );
+}
+
+/// Read `path` through again and hash it, the way a separate pass would.
+fn hash(path: &Path) -> u128 {
+    let mut file = File::open(path).expect("unable to open source file");
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut hasher = Xxh3::new();
+    loop {
+        match file.read(&mut buffer).expect("unable to read source file") {
+            0 => return hasher.digest128(),
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+}
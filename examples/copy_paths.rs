@@ -0,0 +1,126 @@
+//! Times copying one large file the ways it can be copied, to check that ninecopy keeps the kernel's fast path rather
+//! than reading and writing through a buffer itself.
+//!
+//! ```text
+//! cargo run --release --example copy_paths -- [GIB] [RUNS]
+//! ```
+//!
+//! Each run copies it:
+//!
+//! - with ninecopy, which uses `copy_file_range` on Linux, `fcopyfile` on macOS, and `CopyFileExW` on Windows,
+//! - with [`std::fs::copy`], for comparison,
+//! - through a buffer with `read` and `write`, the way ninecopy copies where there's nothing faster, using the same
+//!   buffer size.
+//!
+//! The source is read first so it's equally cached for each of them. Copies within the cache mostly measure the copy
+//! itself, and bigger files than memory measure the disk as well.
+
+mod common;
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ninecopy::CopyOptions;
+
+/// The kernel copy ninecopy uses on this platform.
+const KERNEL_PATH: &str = if cfg!(target_os = "linux") {
+    "copy_file_range"
+} else if cfg!(target_os = "macos") {
+    "fcopyfile"
+} else if cfg!(windows) {
+    "CopyFileExW"
+} else {
+    "std::fs::copy"
+};
+
+fn main() {
+    let (gib, runs) = common::args();
+
+    let root = common::root("copy-paths");
+    let src_dir = root.join("src");
+    let src = src_dir.join("large");
+    let len = gib * common::GIB;
+    let start = Instant::now();
+    common::create(&src, len);
+    println!(
+        "Created a {} GiB file in {:.3} seconds",
+        gib,
+        start.elapsed().as_secs_f64()
+    );
+    read_through(&src);
+
+    let opts = CopyOptions::builder()
+        .threads(Some(1))
+        .build()
+        .expect("invalid options");
+    let buffer_size = opts.buffer_size;
+    let mut times: [Vec<Duration>; 3] = Default::default();
+    for run in 0..runs {
+        let dst = root.join(format!("dst-{}", run));
+        times[0].push(timed(|| {
+            ninecopy::copy_tree(&src_dir, &dst, &opts).expect("copy failed");
+        }));
+        let _ = std::fs::remove_dir_all(&dst);
+
+        let dst = root.join(format!("std-{}", run));
+        times[1].push(timed(|| {
+            std::fs::copy(&src, &dst).expect("copy failed");
+        }));
+        let _ = std::fs::remove_file(&dst);
+
+        let dst = root.join(format!("buffered-{}", run));
+        times[2].push(timed(|| buffered_copy(&src, &dst, buffer_size)));
+        let _ = std::fs::remove_file(&dst);
+    }
+    let _ = std::fs::remove_dir_all(&root);
+
+    for (name, times) in [
+        (format!("ninecopy ({})", KERNEL_PATH), &times[0]),
+        ("std::fs::copy".to_string(), &times[1]),
+        (
+            format!("read/write, {} KiB buffer", buffer_size / 1024),
+            &times[2],
+        ),
+    ] {
+        let best = times.iter().min().expect("no runs");
+        println!(
+            "{:<32} best {:.3}s, {:.0} MiB/s",
+            name,
+            best.as_secs_f64(),
+            len as f64 / (1024.0 * 1024.0) / best.as_secs_f64()
+        );
+    }
+}
+
+fn read_through(path: &Path) {
+    let mut file = File::open(path).expect("unable to open source file");
+    let mut buffer = vec![0; 1024 * 1024];
+    while file.read(&mut buffer).expect("unable to read source file") > 0 {}
+}
+
+fn buffered_copy(src: &Path, dst: &Path, buffer_size: usize) {
+    let mut reader = File::open(src).expect("unable to open source file");
+    let mut writer = File::create(dst).expect("unable to create destination file");
+    let mut buffer = vec![0; buffer_size];
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .expect("unable to read source file");
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buffer[..read])
+            .expect("unable to write destination file");
+    }
+}
+
+fn timed(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
@@ -0,0 +1,112 @@
+//! Times copying one large file with and without `--direct-io`, and checks that each copy matches the source byte for
+//! byte, which it only does if the direct copy handles alignment right.
+//!
+//! ```text
+//! cargo run --release --example direct_io -- [GIB] [RUNS]
+//! ```
+//!
+//! The file is a few bytes longer than GIB, so the end of it isn't aligned. The system's temporary directory has to be
+//! on a filesystem that supports direct I/O for the comparison to mean anything (`tmpfs` doesn't, and the copy quietly
+//! falls back to a normal one).
+//!
+//! Without direct I/O, copies of a file that's already cached are about as fast as memory. With it, every copy goes
+//! to the disk, so the difference shows what the page cache is doing for you and what it costs everything else.
+
+mod common;
+
+use std::{
+    fs::File,
+    io::Read,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ninecopy::CopyOptions;
+
+/// Added to the size of the file so its end isn't a multiple of any block size.
+const UNALIGNED_TAIL: u64 = 12345;
+
+fn main() {
+    let (gib, runs) = common::args();
+
+    let root = common::root("direct-io");
+    let src_dir = root.join("src");
+    let src = src_dir.join("large");
+    let len = gib * common::GIB + UNALIGNED_TAIL;
+    let start = Instant::now();
+    common::create(&src, len);
+    println!(
+        "Created a {} byte file in {:.3} seconds",
+        len,
+        start.elapsed().as_secs_f64()
+    );
+
+    let mut results = Vec::new();
+    for direct_io in [false, true] {
+        let opts = CopyOptions::builder()
+            .threads(Some(1))
+            .direct_io(direct_io)
+            .direct_io_threshold(0)
+            .build()
+            .expect("invalid options");
+        let mut best = Duration::MAX;
+        for run in 0..runs {
+            let dst = root.join(format!("dst-{}-{}", direct_io, run));
+            let start = Instant::now();
+            ninecopy::copy_tree(&src_dir, &dst, &opts).expect("copy failed");
+            best = best.min(start.elapsed());
+            assert!(
+                same_contents(&src, &dst.join("large")),
+                "the copy with direct_io {} doesn't match the source",
+                direct_io
+            );
+            let _ = std::fs::remove_dir_all(&dst);
+        }
+        results.push((direct_io, best));
+    }
+    let _ = std::fs::remove_dir_all(&root);
+
+    for (direct_io, best) in results {
+        println!(
+            "{:<16} best {:.3}s, {:.0} MiB/s",
+            match direct_io {
+                true => "--direct-io",
+                false => "buffered",
+            },
+            best.as_secs_f64(),
+            len as f64 / (1024.0 * 1024.0) / best.as_secs_f64()
+        );
+    }
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+    let (mut a, mut b) = (
+        File::open(a).expect("unable to open source file"),
+        File::open(b).expect("unable to open copy"),
+    );
+    let (mut a_buffer, mut b_buffer) = (vec![0; 1024 * 1024], vec![0; 1024 * 1024]);
+    loop {
+        let read = fill(&mut a, &mut a_buffer);
+        if read != fill(&mut b, &mut b_buffer) || a_buffer[..read] != b_buffer[..read] {
+            return false;
+        }
+        if read == 0 {
+            return true;
+        }
+    }
+}
+
+/// Read into as much of `buffer` as there's left of `file` to fill, returning how much that was.
+fn fill(file: &mut File, buffer: &mut [u8]) -> usize {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file
+            .read(&mut buffer[filled..])
+            .expect("unable to read file")
+        {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    filled
+}
@@ -0,0 +1,62 @@
+//! Times copying a tree of empty files, which measures ninecopy's overhead per file rather than how fast the disk is.
+//!
+//! ```text
+//! cargo run --release --example tiny_files -- [FILES] [THREADS]
+//! ```
+//!
+//! FILES defaults to 100,000, and THREADS to one per core. The tree is created in the system's temporary directory
+//! and removed afterwards.
+
+use std::{fs::File, time::Instant};
+
+use ninecopy::CopyOptions;
+
+/// Files are spread across directories of this many, so no one directory gets big enough to slow things down.
+const FILES_PER_DIR: usize = 1000;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let files: usize = args.next().map_or(100_000, |files| {
+        files.parse().expect("FILES must be a number")
+    });
+    let threads: Option<usize> = args
+        .next()
+        .map(|threads| threads.parse().expect("THREADS must be a number"));
+
+    let root = std::env::temp_dir().join(format!("ninecopy-tiny-files-{}", std::process::id()));
+    let src = root.join("src");
+    let dst = root.join("dst");
+    let start = Instant::now();
+    for i in 0..files {
+        let dir = src.join(format!("{:05}", i / FILES_PER_DIR));
+        if i % FILES_PER_DIR == 0 {
+            std::fs::create_dir_all(&dir).expect("unable to create source directory");
+        }
+        File::create(dir.join(i.to_string())).expect("unable to create source file");
+    }
+    println!(
+        "Created {} files in {:.3} seconds",
+        files,
+        start.elapsed().as_secs_f64()
+    );
+
+    let opts = CopyOptions::builder()
+        .threads(threads)
+        .build()
+        .expect("invalid options");
+    let start = Instant::now();
+    let result = ninecopy::copy_tree(&src, &dst, &opts);
+    let elapsed = start.elapsed();
+    let _ = std::fs::remove_dir_all(&root);
+    let report = result.expect("copy failed");
+
+    println!(
+        "Copied {} files with {} threads in {:.3} seconds: search {:.3}s, copy {:.3}s, {:.0} files/s",
+        report.accumulator.file_count_copied,
+        report.threads,
+        elapsed.as_secs_f64(),
+        report.search_time.as_secs_f64(),
+        report.copy_time.as_secs_f64(),
+        report.accumulator.file_count_copied as f64 / elapsed.as_secs_f64()
+    );
+}
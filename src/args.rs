@@ -1,6 +1,30 @@
-use std::path::PathBuf;
+use std::{
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
 
+use byte_unit::Byte;
 use clap::Parser;
+use ninecopy::{
+    options::{
+        AclMode, CaseTransform, FsyncMode, HashAlgorithm, LinkMode, Normalization, Policy,
+        Preserve, ReflinkMode, Remap, SampleBy, SparseMode,
+    },
+    CopyOptions, OptionsError, StatusOutput,
+};
+
+/// The largest `--buffer-size` allowed.
+const MAX_BUFFER_SIZE: u64 = 256 * 1024 * 1024;
+
+/// What `--threads` was given.
+#[derive(Clone, Debug)]
+pub enum Threads {
+    /// Use this many threads.
+    Count(usize),
+    /// Start at the default and adjust the number of copy threads by how fast they copy.
+    AutoTune,
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "ninecopy")]
@@ -8,7 +32,11 @@ use clap::Parser;
 #[command(version = "1.0")]
 #[command(about = "Fast, multithreaded directory copy utility", long_about = None)]
 pub struct Args {
-    /// The folder you want to copy.
+    /// The folder you want to copy, or a single file.
+    ///
+    /// If it doesn't exist and has `*`, `?`, or `[` in it, it's a glob pattern like `D:\logs\2024-*`, for shells
+    /// that don't expand them, and everything it matches is copied to DESTINATION/<name>. Matches with the same name
+    /// are an error unless `--per-source-dirs` is given.
     ///
     /// e.x. "C:\MyFolder"
     #[arg(value_name = "SOURCE")]
@@ -16,10 +44,28 @@ pub struct Args {
 
     /// The location you want to copy SOURCE to.
     ///
+    /// A single file is copied into DESTINATION if it's a directory or ends with a separator, and to DESTINATION as a
+    /// file name otherwise.
+    ///
     /// e.x. "D:\MyFolder"
     #[arg(value_name = "DESTINATION")]
     pub dst: PathBuf,
 
+    /// More locations to copy SOURCE to at the same time, like `--also-to`.
+    #[arg(value_name = "MORE_DESTINATIONS")]
+    pub more_dst: Vec<PathBuf>,
+
+    /// Copy SOURCE here as well as to DESTINATION, at the same time. Can be given more than once, and more
+    /// destinations can be given after DESTINATION instead.
+    ///
+    /// SOURCE is only searched once, and each destination has copy threads of its own working through the same files
+    /// in the same order, so a file read for one is usually still cached when the others get to it. A destination
+    /// that fails, e.g. because it's full, doesn't stop the copy to the others, and the summary is printed for each
+    /// one. Can't be used with `--move`, `--diff`, `--check-manifest`, `--checksum-file`, `--state-file`, or
+    /// `--backup-dir`.
+    #[arg(long, value_name = "PATH")]
+    pub also_to: Vec<PathBuf>,
+
     /// Overwrite existing files.
     ///
     /// If this is false, the process will exit if existing files at the destination are encountered.
@@ -27,23 +73,253 @@ pub struct Args {
     #[arg(short, long)]
     pub overwrite: bool,
 
+    /// Copy files that already exist at the destination alongside them instead, under the first free name of the form
+    /// `name (1).ext`, `name (2).ext`, and so on. Each file copied this way is printed, and listed under its new name
+    /// in the `--checksum-file`.
+    ///
+    /// On a destination that ignores case, source files whose names only differ by case, like `Readme.md` and
+    /// `README.md`, are copied the same way instead of stopping the copy before it starts.
+    #[arg(long)]
+    pub rename_collisions: bool,
+
+    /// What happens to existing files whose paths, relative to SOURCE, match GLOB, instead of what `--skip`,
+    /// `--overwrite`, `--update`, or `--checksum` say: `overwrite`, `skip`, `update`, or `error`. Can be given more
+    /// than once, and the first GLOB that matches wins, e.g. `--policy '*.log=overwrite' --policy 'db/**=skip'`.
+    ///
+    /// `update` compares contents too with `--checksum`. The summary counts what each rule skipped and overwrote.
+    #[arg(long, value_name = "GLOB=POLICY", value_parser = parse_policy)]
+    pub policy: Vec<(glob::Pattern, Policy)>,
+
+    /// Replace destination files even if they're read-only.
+    ///
+    /// When overwriting a read-only file fails, it's made writable and the copy is tried once more. The copy ends up
+    /// read-only again if the source is and `--preserve-perms` or `--preserve-attributes` is on.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Before overwriting a file at the destination, rename it by adding SUFFIX (`~` if it isn't given).
+    ///
+    /// Only the most recent previous version is kept: a backup left by an earlier run is replaced by the next one.
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "~"
+    )]
+    pub backup: Option<String>,
+
+    /// Before overwriting a file at the destination, move it into this directory, at the same path relative to it as
+    /// it had in the destination. The `--backup` suffix is only added if that's given too.
+    ///
+    /// Like `--backup`, only the most recent previous version is kept.
+    #[arg(long, value_name = "PATH")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// When overwriting a file, compare it with the source block by block and only write the blocks that differ,
+    /// instead of rewriting all of it. Files at the destination smaller than 1MiB are copied in full.
+    ///
+    /// This reads the destination as well as the source, so it's only faster when most of each file is unchanged,
+    /// e.g. refreshing VM images or database dumps.
+    #[arg(long)]
+    pub delta: bool,
+
     /// Skip files that already exist at the destination.
     ///
     /// Mutually exlusive with `overwrite`.
     #[arg(short, long)]
     pub skip: bool,
 
-    /// Periodically log progress.
+    /// Skip files that already exist at the destination with the same size and modified time, and overwrite the
+    /// rest.
+    ///
+    /// This is the recommended way to re-run a copy, e.g. after it was interrupted or to keep a backup in sync.
+    /// Mutually exclusive with `skip`.
+    #[arg(long)]
+    pub update: bool,
+
+    /// Compare the contents of files that already exist at the destination with the same size, skipping them if the
+    /// checksums match and overwriting them otherwise. Mutually exclusive with `skip`.
+    ///
+    /// With `--update`, files whose modified times match are skipped without being read, and only the rest are
+    /// compared, so identical files whose times have changed aren't copied again.
+    #[arg(long)]
+    pub checksum: bool,
+
+    /// How far apart modified times can be for `--update` to count them as the same, for filesystems that store them
+    /// coarsely. FAT needs at least 2s.
+    ///
+    /// e.x. "0s", "2s"
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration)]
+    pub modify_window: Duration,
+
+    /// After copying each file, read it back and check that its checksum matches the source's.
+    ///
+    /// The source is hashed as it's copied where possible, and read again otherwise (e.g. for clones and sparse
+    /// files), so this is slower.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Don't copy anything, just check that every file in the source exists at the destination with the same
+    /// contents.
+    #[arg(long)]
+    pub verify_only: bool,
+
+    /// Verify a random sample of this percentage of the files copied, like `--verify` does every file. A sampled file
+    /// that doesn't match is always an error, even with `--continue-on-error`, since it means others likely don't
+    /// either.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub verify_sample: Option<f64>,
+
+    /// Whether `--verify-sample` is a percentage of the files or of the bytes copied. With bytes, bigger files are
+    /// more likely to be picked.
+    #[arg(long, value_enum, value_name = "UNIT", default_value = "files")]
+    pub verify_sample_by: SampleBy,
+
+    /// Pick the files `--verify-sample` verifies with this seed, so the same files are picked from the same source
+    /// again. Without it, a new seed is picked and printed.
+    #[arg(long, value_name = "SEED")]
+    pub verify_sample_seed: Option<u64>,
+
+    /// Once the copy is done, search the destination again and check that every file copied is there with the size,
+    /// and unless `--no-preserve-times` is given, the modified time it was copied with. Each file that isn't is
+    /// listed, and the copy fails if there are any. Nothing is read, so this is much cheaper than `--verify`.
+    #[arg(long)]
+    pub audit: bool,
+
+    /// The hash `--checksum`, `--verify`, and `--checksum-file` use. xxh3 is the fastest, and blake3 and sha256 are
+    /// cryptographic, for when the digests need to stand up on their own.
+    #[arg(long, value_enum, value_name = "HASH", default_value = "xxh3")]
+    pub hash: HashAlgorithm,
+
+    /// Write the digest of every file copied to this file, in the format `sha256sum` and friends use, with paths
+    /// relative to the destination. Files are listed in sorted order, so the same tree always gives the same file.
+    /// Files copied somewhere other than their path in the source, like with `--remap`, have a comment before them,
+    /// `# <source path> -> <destination path>`. Files removed from the source before they could be copied are listed at
+    /// the end, `# vanished before copy: <destination path>`.
+    #[arg(long, value_name = "PATH")]
+    pub checksum_file: Option<PathBuf>,
+
+    /// Don't copy anything, just check the destination against a file written by `--checksum-file`, using the same
+    /// `--hash`. The source isn't read.
+    #[arg(long, value_name = "PATH")]
+    pub check_manifest: Option<PathBuf>,
+
+    /// Don't copy anything, just list how the destination differs from the source, one line per path, each starting
+    /// with what differs and a tab: `only-src`, `only-dst`, `type`, `size`, `mtime` (beyond `--modify-window`), or
+    /// with `--checksum`, `content` instead of `mtime`. With `--preserve-symlinks`, `target` for symlinks that point
+    /// somewhere else.
+    ///
+    /// Exits with 0 if they're the same, 1 if they differ, and 2 if something went wrong.
+    #[arg(long)]
+    pub diff: bool,
+
+    /// Replace files copied to the destination with hard links to identical files copied earlier in the same run,
+    /// so each distinct content is only stored once. Files are matched by size and `--hash` digest.
+    ///
+    /// Linked files share their permissions and modified time, which are whichever copy was made first.
+    #[arg(long)]
+    pub dedupe_dest: bool,
+
+    /// Remove each source file once it's been copied (and verified, with `--verify`), then remove the source
+    /// directories that were emptied. Files are renamed instead of copied when the destination is on the same
+    /// filesystem.
+    #[arg(long = "move")]
+    pub move_files: bool,
+
+    /// After copying, delete everything in the destination that isn't in the source, so the destination ends up an
+    /// exact mirror of it. Nothing is deleted if anything failed to copy.
+    #[arg(long)]
+    pub delete: bool,
+
+    /// Let `--delete` run even when the source is empty, which deletes everything in the destination. Without this,
+    /// an empty source is treated as a mistake (e.g. an unmounted drive) and nothing is copied or deleted.
+    #[arg(long)]
+    pub delete_even_if_empty: bool,
+
+    /// With `--delete`, list everything in the destination that would be deleted, and how much, without deleting any
+    /// of it. The copy itself still happens.
+    #[arg(long)]
+    pub delete_dry_run: bool,
+
+    /// With `--delete` and `--checksum`, rename files that would be deleted to where identical new files in the
+    /// source go, instead of copying those and deleting these. Only destination files the same size as some new
+    /// source file are hashed to find them.
+    #[arg(long)]
+    pub detect_renames: bool,
+
+    /// After copying, keep watching the source and copy whatever changes in it, until Ctrl-C, which prints a summary
+    /// of everything copied. Requires `--update` (or `--skip`, `--overwrite`, or `--checksum`), so files already in
+    /// the destination are handled. Deletions aren't mirrored, except by `--delete` on a `--watch-rescan`.
+    #[arg(long)]
+    pub watch: bool,
+
+    /// With `--watch`, wait until nothing has changed for this long before copying what did.
+    ///
+    /// e.x. "500ms", "2s", "1m"
+    #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = parse_duration)]
+    pub watch_debounce: Duration,
+
+    /// With `--watch`, search the whole source again this often and copy it as the first copy did, in case a change
+    /// was missed. A rescan also happens whenever the system reports dropping notifications.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub watch_rescan: Option<Duration>,
+
+    /// Search and copy everything again this long after each copy started, until Ctrl-C, printing a summary of each
+    /// copy and one of them all at the end. Requires `--update` (or `--skip`, `--overwrite`, or `--checksum`). Files
+    /// recorded in `--state-file` are skipped by every copy, so only new ones are copied. A copy that fails doesn't
+    /// stop the next one, unless `--repeat-max-failures` says to.
+    ///
+    /// e.x. "30s", "10m", "1h"
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub repeat: Option<Duration>,
+
+    /// With `--repeat`, stop once this many copies in a row have failed.
+    #[arg(long, value_name = "N")]
+    pub repeat_max_failures: Option<u32>,
+
+    /// Periodically log progress, including how far along each file of 64MiB or more is.
     #[arg(short, long)]
     pub progress: bool,
 
+    /// Every few seconds, list what each copy thread is working on, relative to SOURCE, and how long it's been on it.
+    #[arg(long)]
+    pub show_active: bool,
+
+    /// Where to print progress, messages, errors, and the summary. Output, like the differences found by `--diff`,
+    /// always goes to stdout.
+    ///
+    /// Everything used to go to stdout, and `--status=stdout` brings that back.
+    #[arg(long, value_enum, value_name = "STREAM", default_value = "stderr")]
+    pub status: StatusOutput,
+
+    /// Print where each copied file ended up to stdout, each followed by a NUL, for `xargs -0` and the like.
+    ///
+    /// Files that were skipped or already up to date aren't printed. Can't be used with `--status=stdout`.
+    #[arg(long)]
+    pub print0: bool,
+
     /// The number of threads to use for search and copy.
     ///
-    /// Defaults to one per core.
+    /// Defaults to one per core, or one per four cores with `--background`.
     ///
     /// Transfers with mostly large files may benefit from thread counts higher than one per core, depending on the core count and disk throughput.
-    #[arg(short, long)]
-    pub threads: Option<usize>,
+    ///
+    /// `auto-tune` starts at the default, then every 15 seconds tries more or fewer copy threads, logging each change,
+    /// and settles on however many copy fastest.
+    #[arg(short, long, value_name = "THREADS|auto-tune", value_parser = parse_threads)]
+    pub threads: Option<Threads>,
+
+    /// The most copy threads `--threads auto-tune` will try. Defaults to four times the starting count.
+    #[arg(long, value_name = "N")]
+    pub max_threads: Option<usize>,
+
+    /// Run at low CPU and I/O priority, so the machine stays usable while the copy runs.
+    ///
+    /// Uses the idle I/O class on Linux, I/O throttling on macOS, and background mode on Windows. What couldn't be
+    /// lowered is reported and the copy carries on.
+    #[arg(long)]
+    pub background: bool,
 
     /// Copy files that already exist at the destination if the last modified time of the source
     /// file is more current.
@@ -62,4 +338,859 @@ pub struct Args {
     /// Skip files that encounter an error and continue copying instead of exiting.
     #[arg(short, long)]
     pub continue_on_error: bool,
+
+    /// Copy a file once more if it changed size while it was being copied, like a log that's still being written.
+    ///
+    /// Files that change size are always listed as they're copied, counted in the summary, and marked in the
+    /// `--checksum-file`. Files split with `--split-large-files` aren't copied again.
+    #[arg(long)]
+    pub recopy_changed: bool,
+
+    /// Skip files that are open in another process with a lock that stops them being read (a sharing violation, on
+    /// Windows) instead of failing, once `--retries` are used up. Every file skipped this way is listed at the end, so
+    /// they can be copied later.
+    #[arg(long)]
+    pub skip_locked: bool,
+
+    /// Exit with code 3 if anything found wasn't copied or already up to date: files that were skipped, failed,
+    /// couldn't be read, or disappeared, special files, and unreadable directories.
+    ///
+    /// With `--continue-on-error` the copy still finishes, so exit code 0 always means a complete copy.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Write every error encountered during the copy to this file, along with every file removed from the source
+    /// before it could be copied.
+    #[arg(long, value_name = "PATH")]
+    pub error_log: Option<PathBuf>,
+
+    /// Record copied files in this file, and skip files already recorded there by a previous run.
+    ///
+    /// Use the same state file when rerunning an interrupted copy to pick up where it left off.
+    #[arg(long, value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+
+    /// Write everything the search finds to this file: each entry's kind, size, modified time, and path relative to
+    /// SOURCE, one per line. Read it back with `--files-from` to skip searching next time.
+    #[arg(long, value_name = "PATH")]
+    pub save_file_list: Option<PathBuf>,
+
+    /// Copy what's listed in this file, written by `--save-file-list`, instead of searching SOURCE. Anything that's
+    /// gone since the list was saved is skipped, and nothing new is found. Filters were applied when the list was
+    /// saved.
+    #[arg(long, value_name = "PATH")]
+    pub files_from: Option<PathBuf>,
+
+    /// Stop starting new files once this many have been copied, letting the ones already being copied finish, and
+    /// exit with code 4. Files that are already up to date don't count. With `--state-file`, the next run picks up
+    /// where this one stopped.
+    #[arg(long, value_name = "N")]
+    pub max_files: Option<u64>,
+
+    /// Stop starting new files once this much has been copied, like `--max-files`.
+    ///
+    /// e.x. "500GiB", "2TB"
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_bytes: Option<u64>,
+
+    /// Keep the list of files found by the search in a temporary file in this directory, once it's longer than
+    /// `--spool-after`, instead of holding it all in memory.
+    ///
+    /// Useful for trees with tens of millions of files. The file is removed when the copy finishes.
+    #[arg(long, value_name = "PATH")]
+    pub spool_dir: Option<PathBuf>,
+
+    /// How many found items to hold in memory before the rest go to `--spool-dir`.
+    #[arg(long, value_name = "COUNT", default_value_t = 1_000_000)]
+    pub spool_after: usize,
+
+    /// Keep memory use to about this much, shrinking `--buffer-size` and spooling the list of files found to
+    /// `--spool-dir` (or the system temporary directory) as needed.
+    ///
+    /// Counts the copy buffers and the list of files found, which is where nearly all the memory goes.
+    ///
+    /// e.x. "512MB"
+    #[arg(long, value_name = "SIZE", value_parser = parse_max_memory)]
+    pub max_memory: Option<u64>,
+
+    /// Copy each file to a temporary name in the destination directory and rename it into place once it's complete.
+    ///
+    /// The destination never contains half written files, even if the copy is interrupted. Temporary files left by
+    /// an interrupted run are removed at startup.
+    #[arg(long)]
+    pub atomic: bool,
+
+    /// Leave partially written destination files in place when a copy fails instead of removing them.
+    #[arg(long)]
+    pub keep_partial: bool,
+
+    /// Retry copies and directory creation up to this many times when they fail with an error that's likely to be
+    /// transient, like a timeout or interrupted network connection.
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// How long to wait before the first retry. The wait doubles after each attempt.
+    ///
+    /// e.x. "500ms", "2s", "1m"
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration)]
+    pub retry_wait: Duration,
+
+    /// Give up on any single file that takes longer than this to copy, removing the partially written destination.
+    ///
+    /// The file is reported as an error, so use `continue-on-error` to move on to the next one.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub file_timeout: Option<Duration>,
+
+    /// Extend the file timeout by the time a file would take to copy at this rate, so large files that are slow but
+    /// still making progress aren't abandoned.
+    ///
+    /// e.x. "1MB/s"
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub min_rate: Option<u64>,
+
+    /// When the destination fills up, wait for space to be freed instead of failing.
+    ///
+    /// Free space is checked every 30 seconds, and the copy resumes once there's room for the file that didn't fit.
+    #[arg(long)]
+    pub wait_on_full: bool,
+
+    /// Start copying even if the destination doesn't appear to have enough free space.
+    #[arg(long)]
+    pub no_space_check: bool,
+
+    /// Before copying, list every source file and directory whose path at the destination would be longer than
+    /// Windows' MAX_PATH limit of 260 characters (248 for directories), and stop without copying anything if there
+    /// are any.
+    ///
+    /// ninecopy itself copies such paths on Windows fine, but other programs often can't open them.
+    #[arg(long)]
+    pub check_path_length: bool,
+
+    /// Rename files and directories whose names Windows can't use as they're copied, replacing the characters it
+    /// doesn't allow (`<>:"/\|?*` and control characters) and trailing dots and spaces with REPLACEMENT (`_` if it
+    /// isn't given). Reserved device names like `con` or `aux.txt` get REPLACEMENT before the extension. Each rename
+    /// is listed before copying starts, and nothing is copied if two names end up the same.
+    ///
+    /// Without this, copying stops before it starts on Windows if any names can't be used.
+    #[arg(
+        long,
+        value_name = "REPLACEMENT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "_"
+    )]
+    pub sanitize_names: Option<String>,
+
+    /// Normalize the Unicode in paths at the destination to FORM, so names written differently on different systems,
+    /// like `é` as one character or as `e` and an accent, are copied to the same name. Existing files are matched
+    /// against the normalized names too. Each path that changes is listed before copying starts, and nothing is copied
+    /// if two paths end up the same. Applied after `--sanitize-names` and `--transform-case`.
+    #[arg(long, value_enum, value_name = "FORM", default_value = "none")]
+    pub normalize: Normalization,
+
+    /// Change every name at the destination to lowercase or uppercase. Names that only differ by case collide once
+    /// they're changed, which stops the copy before it starts unless `--rename-collisions` is given. Applied after
+    /// `--remap`, `--remap-regex`, and `--sanitize-names`, and before `--normalize`.
+    #[arg(long, value_enum, value_name = "CASE", default_value = "none")]
+    pub transform_case: CaseTransform,
+
+    /// Copy what's under FROM in SOURCE to TO in DESTINATION instead, e.g. `--remap raw/=originals/`. An empty TO
+    /// strips FROM, so `--remap export/=` copies what's in `export` straight into DESTINATION. Both have to be relative
+    /// paths that don't go up with `..`.
+    ///
+    /// Can be given more than once, and each rule applies to the path the rules before it left, before
+    /// `--remap-regex`, `--sanitize-names`, `--transform-case`, and `--normalize`. Directories that end up the same are
+    /// merged, and nothing is copied if two files would. Files copied somewhere else are listed with their source paths
+    /// in the `--checksum-file`.
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_remap)]
+    pub remap: Vec<Remap>,
+
+    /// Rewrite paths relative to SOURCE, with `/` between names, with a sed-style substitution, e.g.
+    /// `--remap-regex 's/^(\d{4})-(\d{2})/\1\/\2/'` to copy `2024-05-notes` to `2024/05-notes`. `\1` to `\9` and `&`
+    /// insert what was matched, and a trailing `g` replaces every match instead of the first.
+    ///
+    /// Can be given more than once, and applies after `--remap` in the same way. Nothing is copied if a path would end
+    /// up outside DESTINATION.
+    #[arg(long, value_name = "s/REGEX/REPLACEMENT/", value_parser = parse_remap_regex)]
+    pub remap_regex: Vec<Remap>,
+
+    /// Fail if DESTINATION doesn't exist instead of creating it.
+    #[arg(long)]
+    pub no_create_dest: bool,
+
+    /// Follow rsync's convention for SOURCE: with a trailing separator, like `src/`, its contents are copied into
+    /// DESTINATION, and without one, like `src`, it's copied to `DESTINATION/src`.
+    ///
+    /// Without this, the contents of SOURCE are always copied into DESTINATION. On Windows, `\` and `/` both count as
+    /// separators. `.`, `..`, and roots always mean their contents. `--no-create-dest` still only needs DESTINATION to
+    /// exist.
+    #[arg(long)]
+    pub rsync_paths: bool,
+
+    /// When SOURCE is a pattern, copy each source it matches to a directory of its own in DESTINATION, named after the
+    /// end of its path that tells it apart from the others, e.g. `ninecopy '/*/logs' /backup` copies `/a/logs` to
+    /// `/backup/a_logs` and `/b/logs` to `/backup/b_logs`.
+    ///
+    /// Without this, sources with the same name are an error rather than being merged into one. `--checksum-file`
+    /// lists every source, each after a comment naming it, and the summary ends with what was copied from each.
+    #[arg(long)]
+    pub per_source_dirs: bool,
+
+    /// Copy the source at PATH to NAME in DESTINATION with `--per-source-dirs`, instead of the name it would get.
+    /// Can be given more than once.
+    #[arg(long, value_name = "NAME:PATH", value_parser = parse_source_name)]
+    pub source_name: Vec<(OsString, PathBuf)>,
+
+    /// Fail if SOURCE is a single file instead of copying it.
+    #[arg(long)]
+    pub strict_dir_only: bool,
+
+    /// Don't create directories at the destination that nothing is copied into. Directories are created as the
+    /// files and symlinks in them are copied, so empty source directories, and ones where everything was skipped or
+    /// failed, are left out.
+    #[arg(long)]
+    pub prune_empty_dirs: bool,
+
+    /// Only recreate the source's directories at the destination, leaving out the files and symlinks in them, e.g.
+    /// to set up a skeleton to copy into later. The summary counts the directories created.
+    #[arg(long, conflicts_with = "files_only")]
+    pub dirs_only: bool,
+
+    /// Only copy files, leaving out symlinks, and only create the directories they're copied into, as with
+    /// `--prune-empty-dirs`.
+    #[arg(long)]
+    pub files_only: bool,
+
+    /// Only copy files owned by this user, given by name or ID. Unix only.
+    #[arg(long, value_name = "USER", conflicts_with = "uid")]
+    pub owner: Option<String>,
+
+    /// Only copy files owned by the user with this ID. Unix only.
+    #[arg(long, value_name = "ID")]
+    pub uid: Option<u32>,
+
+    /// Only copy files in this group, given by name or ID. Unix only.
+    #[arg(long, value_name = "GROUP")]
+    pub group: Option<String>,
+
+    /// With `--owner`, `--uid`, or `--group`, skip directories that don't match too, along with everything in them.
+    /// Otherwise directories are always searched, and only the files in them are left out.
+    #[arg(long)]
+    pub prune_foreign_dirs: bool,
+
+    /// Don't give copied files the source's modification and access times.
+    ///
+    /// Times are preserved by default, so `copy-if-newer` won't recopy files on a later run. Directories get theirs once
+    /// everything has been copied into them.
+    #[arg(long)]
+    pub no_preserve_times: bool,
+
+    /// Archive mode: preserve everything ninecopy can on this platform.
+    ///
+    #[cfg_attr(
+        unix,
+        doc = "Same as `--preserve-perms --preserve-owner --preserve-symlinks --xattrs`."
+    )]
+    #[cfg_attr(
+        windows,
+        doc = "Same as `--preserve-perms --preserve-symlinks --preserve-attributes`."
+    )]
+    #[cfg_attr(
+        not(any(unix, windows)),
+        doc = "Same as `--preserve-perms --preserve-symlinks`."
+    )]
+    ///
+    /// Times are preserved regardless, and ownership only when running as root. Anything in the bundle can be turned
+    /// back off with its `--no-preserve-*` flag, e.g. `-a --no-preserve-owner`.
+    #[arg(short, long)]
+    pub archive: bool,
+
+    /// Give copied files and directories the source's permissions, including setuid, setgid, and sticky bits.
+    ///
+    /// Directory permissions are applied after their contents are copied, so read-only directories copy correctly.
+    #[arg(long)]
+    pub preserve_perms: bool,
+
+    /// Give copied files and directories the source's owner and group.
+    ///
+    /// Only takes effect when running as root. Otherwise a warning is printed and ownership is not preserved.
+    #[arg(long)]
+    pub preserve_owner: bool,
+
+    /// Copy extended attributes, such as `user.*` attributes, capabilities, and SELinux labels on Linux or Finder
+    /// tags on macOS.
+    ///
+    /// Attributes that can't be copied are reported as warnings and counted in the summary.
+    #[arg(long)]
+    pub xattrs: bool,
+
+    /// Copy files with `copyfile(3)` to preserve all of their macOS metadata, including ACLs, extended attributes,
+    /// and flags. macOS only.
+    #[arg(long)]
+    pub preserve_all: bool,
+
+    /// Give copied files and directories the source's Windows attributes: hidden, read-only, system, archive, etc.
+    ///
+    /// Has no effect on other platforms.
+    #[arg(long)]
+    pub preserve_attributes: bool,
+
+    /// Copy the Windows ACLs of files and directories. Windows only.
+    ///
+    /// `--acls` copies the permissions (DACL). `--acls=all` also copies the owner, group, and auditing (SACL)
+    /// entries, which requires running as an administrator.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHICH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "dacl"
+    )]
+    pub acls: Option<AclMode>,
+
+    /// Fail if the source contains FIFOs, sockets, or device nodes, instead of skipping them.
+    #[arg(long)]
+    pub fail_on_special: bool,
+
+    /// Skip cloud placeholder files, like OneDrive or iCloud Drive files that are only online, instead of downloading
+    /// them to copy them. They're left out of the totals, and counted in the summary. Windows and macOS only.
+    ///
+    /// Without this or `--hydrate`, placeholders are copied with a warning.
+    #[arg(long)]
+    pub skip_placeholders: bool,
+
+    /// Copy cloud placeholder files, downloading each one as it's copied, without warning about it.
+    #[arg(long)]
+    pub hydrate: bool,
+
+    /// Sync every copied file to disk before counting it as copied, so the destination can be unplugged as soon as
+    /// ninecopy exits.
+    ///
+    /// `--fsync` syncs each file as soon as it's written, and in `--atomic` mode the directory it's renamed in as well.
+    /// `--fsync=batch` syncs everything once at the end instead, which is faster.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "each"
+    )]
+    pub fsync: Option<FsyncMode>,
+
+    /// Copy files without going through the system cache. Windows only.
+    ///
+    /// Files over 1 GiB are always copied this way on Windows.
+    #[arg(long)]
+    pub unbuffered: bool,
+
+    /// Size of the buffer files are read and written through when they're copied in chunks, e.g. with
+    /// `--file-timeout`, `--sparse`, or `--direct-io`. Larger buffers help over high latency network shares.
+    ///
+    /// Each thread has its own buffer. At most 256MiB.
+    #[arg(long, value_name = "SIZE", default_value = "1MiB", value_parser = parse_buffer_size)]
+    pub buffer_size: usize,
+
+    /// Limit how fast files are copied, across all threads together. Files skipped or cloned don't count.
+    ///
+    /// Not applied to `--preserve-all` on macOS.
+    ///
+    /// e.x. "50MB/s"
+    #[arg(long, value_name = "RATE", value_parser = parse_bwlimit)]
+    pub bwlimit: Option<u64>,
+
+    /// Copy large files without going through the system cache, so a big copy doesn't push everything else out of
+    /// memory.
+    ///
+    /// Uses `O_DIRECT` on Linux, `F_NOCACHE` on macOS, and unbuffered copies on Windows. Files are copied normally if
+    /// the filesystem doesn't support this.
+    #[arg(long)]
+    pub direct_io: bool,
+
+    /// Files at least this big are copied with `--direct-io`.
+    ///
+    /// e.x. "1GiB"
+    #[arg(long, value_name = "SIZE", default_value = "256MiB", value_parser = parse_size)]
+    pub direct_io_threshold: u64,
+
+    /// Drop files from the system cache as they're copied, so a big copy doesn't push everything else out of memory.
+    /// Linux and FreeBSD only.
+    ///
+    /// Unlike `--direct-io`, reads and writes still go through the cache, they just don't stay there.
+    #[arg(long)]
+    pub drop_cache: bool,
+
+    /// Copy files bigger than this in chunks on several threads at once, so a few very large files don't leave most
+    /// threads idle.
+    ///
+    /// Split files are written in place, so `--reflink`, `--sparse`, and `--direct-io` don't apply to them.
+    /// `--split-large-files` on its own splits files over 1GiB.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1GiB",
+        value_parser = parse_split_threshold
+    )]
+    pub split_large_files: Option<u64>,
+
+    /// Files at least this big count towards `--max-large-concurrent`.
+    ///
+    /// e.x. "1GiB"
+    #[arg(long, value_name = "SIZE", default_value = "256MiB", value_parser = parse_size)]
+    pub large_file_size: u64,
+
+    /// At most this many copy threads copy files of at least `--large-file-size` at once, leaving the rest to work
+    /// through smaller files instead of waiting behind a few very large ones.
+    ///
+    /// Defaults to half the copy threads, and at least one.
+    #[arg(long, value_name = "N")]
+    pub max_large_concurrent: Option<usize>,
+
+    /// Give each copy thread the files of one directory at a time, instead of files from all over the tree at once.
+    ///
+    /// Helps destinations on hard drives, which otherwise keep seeking between directories, and network shares,
+    /// where threads writing to the same directory hold each other up.
+    #[arg(long)]
+    pub locality: bool,
+
+    /// Copy files whose paths, relative to SOURCE, match GLOB before everything else, e.g. `--prefer 'db/**'`. Can be
+    /// given more than once, and a file matching any of them is preferred.
+    ///
+    /// `--progress` shows how many of them are done, and a message says when they all are, so the copy can be
+    /// stopped from then on without losing any of them.
+    #[arg(long, value_name = "GLOB")]
+    pub prefer: Vec<glob::Pattern>,
+
+    /// Clone files with copy-on-write instead of copying their data, on filesystems that support it: Btrfs, XFS, and
+    /// APFS.
+    ///
+    /// `auto` clones when it can and copies otherwise, `always` fails if a file can't be cloned, and `never` always
+    /// copies. `--reflink` on its own means `auto`.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        default_value = "never",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto"
+    )]
+    pub reflink: ReflinkMode,
+
+    /// Copy sparse files, like VM disk images, without filling in their holes.
+    ///
+    /// `auto` only does this for files that are mostly holes, `always` looks for holes in every file, and `never`
+    /// writes out every byte. `--sparse` on its own means `auto`.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        default_value = "never",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto"
+    )]
+    pub sparse: SparseMode,
+
+    /// Hard link destination files to the source files instead of copying them.
+    ///
+    /// Files are copied instead, with a warning, if the source and destination are on different devices.
+    /// `--link=strict` makes that an error. Linked files share their times and permissions with the source.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "fallback"
+    )]
+    pub link: Option<LinkMode>,
+
+    /// Keep hard links within the source as hard links in the copy, instead of copying the same data once per link.
+    #[arg(long)]
+    pub hard_links: bool,
+
+    /// Copy symbolic links as links pointing at the same target, instead of copying what they point to.
+    #[arg(long)]
+    pub preserve_symlinks: bool,
+
+    /// Go through junctions and directory symbolic links, copying what's in them, instead of recreating them as links.
+    /// Ones that lead back to a directory being copied, like `AppData\Local\Application Data`, are still recreated as
+    /// links. Windows only.
+    #[arg(long)]
+    pub follow_junctions: bool,
+
+    /// Don't preserve permissions, even with `--archive`.
+    #[arg(long)]
+    pub no_preserve_perms: bool,
+
+    /// Don't preserve ownership, even with `--archive`.
+    #[arg(long)]
+    pub no_preserve_owner: bool,
+
+    /// Don't copy symbolic links as links, even with `--archive`.
+    #[arg(long)]
+    pub no_preserve_symlinks: bool,
+
+    /// Don't copy extended attributes, even with `--archive`.
+    #[arg(long)]
+    pub no_preserve_xattrs: bool,
+
+    /// Don't preserve Windows attributes, even with `--archive`.
+    #[arg(long)]
+    pub no_preserve_attributes: bool,
+}
+
+/// Everything but SOURCE and DESTINATION, which are passed to `copy_tree` on their own. Goes through
+/// [`CopyOptions::builder`], so the flags are checked the same way options set from code are.
+impl TryFrom<Args> for CopyOptions {
+    type Error = OptionsError;
+
+    fn try_from(args: Args) -> Result<Self, OptionsError> {
+        // Names are looked up once, rather than for every file.
+        let owner = match args.owner.as_deref() {
+            Some(name) => Some(ninecopy::user_id(name)?),
+            None => args.uid,
+        };
+        let group = args.group.as_deref().map(ninecopy::group_id).transpose()?;
+        let preserve = [
+            (Preserve::TIMES, !args.no_preserve_times),
+            (Preserve::PERMS, args.preserve_perms),
+            (Preserve::OWNER, args.preserve_owner),
+            (Preserve::XATTRS, args.xattrs),
+            (Preserve::SYMLINKS, args.preserve_symlinks),
+            (Preserve::ATTRIBUTES, args.preserve_attributes),
+        ];
+        let no_preserve = [
+            (Preserve::PERMS, args.no_preserve_perms),
+            (Preserve::OWNER, args.no_preserve_owner),
+            (Preserve::XATTRS, args.no_preserve_xattrs),
+            (Preserve::SYMLINKS, args.no_preserve_symlinks),
+            (Preserve::ATTRIBUTES, args.no_preserve_attributes),
+        ];
+        CopyOptions::builder()
+            .overwrite(policy(&args)?)
+            .also_to(args.more_dst.into_iter().chain(args.also_to).collect())
+            .policies(args.policy)
+            .preserve(flags(&preserve))
+            .no_preserve(flags(&no_preserve))
+            .force(args.force)
+            .backup(args.backup)
+            .backup_dir(args.backup_dir)
+            .delta(args.delta)
+            .checksum(args.checksum)
+            .modify_window(args.modify_window)
+            .verify(args.verify)
+            .verify_only(args.verify_only)
+            .verify_sample(args.verify_sample)
+            .verify_sample_by(args.verify_sample_by)
+            .verify_sample_seed(args.verify_sample_seed)
+            .audit(args.audit)
+            .hash(args.hash)
+            .checksum_file(args.checksum_file)
+            .check_manifest(args.check_manifest)
+            .diff(args.diff)
+            .dedupe_dest(args.dedupe_dest)
+            .move_files(args.move_files)
+            .delete(args.delete)
+            .delete_even_if_empty(args.delete_even_if_empty)
+            .delete_dry_run(args.delete_dry_run)
+            .detect_renames(args.detect_renames)
+            .watch(args.watch)
+            .watch_debounce(args.watch_debounce)
+            .watch_rescan(args.watch_rescan)
+            .repeat(args.repeat)
+            .repeat_max_failures(args.repeat_max_failures)
+            .progress(args.progress)
+            .show_active(args.show_active)
+            .print0(args.print0)
+            .threads(match args.threads {
+                Some(Threads::Count(threads)) => Some(threads),
+                Some(Threads::AutoTune) | None => None,
+            })
+            .auto_tune(matches!(args.threads, Some(Threads::AutoTune)))
+            .max_threads(args.max_threads)
+            .background(args.background)
+            .copy_if_newer(args.copy_if_newer)
+            .copy_if_larger(args.copy_if_larger)
+            .continue_on_error(args.continue_on_error)
+            .recopy_changed(args.recopy_changed)
+            .skip_locked(args.skip_locked)
+            .strict(args.strict)
+            .error_log(args.error_log)
+            .state_file(args.state_file)
+            .save_file_list(args.save_file_list)
+            .files_from(args.files_from)
+            .max_files(args.max_files)
+            .max_bytes(args.max_bytes)
+            .spool_dir(args.spool_dir)
+            .spool_after(args.spool_after)
+            .max_memory(args.max_memory)
+            .atomic(args.atomic)
+            .keep_partial(args.keep_partial)
+            .retries(args.retries)
+            .retry_wait(args.retry_wait)
+            .file_timeout(args.file_timeout)
+            .min_rate(args.min_rate)
+            .wait_on_full(args.wait_on_full)
+            .no_space_check(args.no_space_check)
+            .check_path_length(args.check_path_length)
+            .sanitize_names(args.sanitize_names)
+            .normalize(args.normalize)
+            .transform_case(args.transform_case)
+            .remaps(args.remap.into_iter().chain(args.remap_regex).collect())
+            .no_create_dest(args.no_create_dest)
+            .rsync_paths(args.rsync_paths)
+            .strict_dir_only(args.strict_dir_only)
+            .prune_empty_dirs(args.prune_empty_dirs)
+            .dirs_only(args.dirs_only)
+            .files_only(args.files_only)
+            .owner(owner)
+            .group(group)
+            .prune_foreign_dirs(args.prune_foreign_dirs)
+            .archive(args.archive)
+            .preserve_all(args.preserve_all)
+            .acls(args.acls)
+            .fail_on_special(args.fail_on_special)
+            .skip_placeholders(args.skip_placeholders)
+            .hydrate(args.hydrate)
+            .fsync(args.fsync)
+            .unbuffered(args.unbuffered)
+            .buffer_size(args.buffer_size)
+            .bwlimit(args.bwlimit)
+            .direct_io(args.direct_io)
+            .direct_io_threshold(args.direct_io_threshold)
+            .drop_cache(args.drop_cache)
+            .split_large_files(args.split_large_files)
+            .large_file_size(args.large_file_size)
+            .max_large_concurrent(args.max_large_concurrent)
+            .locality(args.locality)
+            .prefer(args.prefer)
+            .reflink(args.reflink)
+            .sparse(args.sparse)
+            .link(args.link)
+            .hard_links(args.hard_links)
+            .follow_junctions(args.follow_junctions)
+            .build()
+    }
+}
+
+/// The policy for existing files picked by `--skip`, `--overwrite`, `--update`, and `--rename-collisions`. Only
+/// `--update` and `--overwrite` can be given together, which is the same as `--update` alone.
+fn policy(args: &Args) -> Result<Policy, OptionsError> {
+    let policies = [
+        (Policy::Rename, "rename_collisions", args.rename_collisions),
+        (Policy::Skip, "skip", args.skip),
+        (Policy::Update, "update", args.update),
+        (Policy::Overwrite, "overwrite", args.overwrite),
+    ];
+    let mut given = policies.iter().filter(|(_, _, given)| *given);
+    match (given.next(), given.next()) {
+        (Some((Policy::Update, _, _)), _) => Ok(Policy::Update),
+        (Some((_, option, _)), Some((_, other, _))) => Err(OptionsError::Conflict(option, other)),
+        (Some((policy, _, _)), None) => Ok(*policy),
+        (None, _) => Ok(Policy::Error),
+    }
+}
+
+/// All the flags that are set.
+fn flags(flags: &[(Preserve, bool)]) -> Preserve {
+    flags
+        .iter()
+        .filter(|(_, set)| *set)
+        .fold(Preserve::NONE, |all, (flag, _)| all | *flag)
+}
+
+/// Parse a rate like `50MB/s` or `1 GiB/s` into bytes per second.
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    parse_size(value.strip_suffix("/s").unwrap_or(value))
+}
+
+/// Parse a `--buffer-size`, which has to be more than 0 and at most [`MAX_BUFFER_SIZE`].
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    match parse_size(value)? {
+        0 => Err("buffer size must be more than 0".to_string()),
+        size if size > MAX_BUFFER_SIZE => Err("buffer size can be at most 256MiB".to_string()),
+        size => Ok(size as usize),
+    }
+}
+
+/// Parse a `--bwlimit`, which has to be more than 0.
+fn parse_bwlimit(value: &str) -> Result<u64, String> {
+    match parse_rate(value)? {
+        0 => Err("bandwidth limit must be more than 0".to_string()),
+        rate => Ok(rate),
+    }
+}
+
+/// Parse a `--max-memory`, which has to be more than 0.
+fn parse_max_memory(value: &str) -> Result<u64, String> {
+    match parse_size(value)? {
+        0 => Err("memory limit must be more than 0".to_string()),
+        size => Ok(size),
+    }
+}
+
+/// Parse a `--split-large-files` threshold, which has to be more than 0.
+fn parse_split_threshold(value: &str) -> Result<u64, String> {
+    match parse_size(value)? {
+        0 => Err("split threshold must be more than 0".to_string()),
+        size => Ok(size),
+    }
+}
+
+/// Parse a percentage like `5` or `0.5%`, which has to be more than 0 and at most 100.
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let percent: f64 = value
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .parse()
+        .map_err(|_| format!("Invalid percentage: {}", value))?;
+    match percent > 0.0 && percent <= 100.0 {
+        true => Ok(percent),
+        false => Err("percentage must be more than 0 and at most 100".to_string()),
+    }
+}
+
+/// Parse a `--policy` rule like `*.log=overwrite`. The last `=` separates the pattern from the policy, so patterns
+/// can have one in them.
+fn parse_policy(value: &str) -> Result<(glob::Pattern, Policy), String> {
+    let Some((pattern, policy)) = value.rsplit_once('=') else {
+        return Err(format!("Expected GLOB=POLICY: {}", value));
+    };
+    let policy = match policy.trim() {
+        "overwrite" => Policy::Overwrite,
+        "skip" => Policy::Skip,
+        "update" => Policy::Update,
+        "error" => Policy::Error,
+        other => {
+            return Err(format!(
+                "Invalid policy {:?}, expected overwrite, skip, update, or error",
+                other
+            ))
+        }
+    };
+    let pattern = glob::Pattern::new(pattern)
+        .map_err(|err| format!("Invalid pattern {:?}: {}", pattern, err))?;
+    Ok((pattern, policy))
+}
+
+/// Parse a `--remap` rule like `raw/=originals/`. The first `=` separates the two paths.
+fn parse_remap(value: &str) -> Result<Remap, String> {
+    let Some((from, to)) = value.split_once('=') else {
+        return Err(format!("Expected FROM=TO: {}", value));
+    };
+    Ok(Remap::Prefix(PathBuf::from(from), PathBuf::from(to)))
+}
+
+/// Parse a `--source-name` like `a_logs:/a/logs`, whose name has to be a single name that isn't `.` or `..`.
+fn parse_source_name(value: &str) -> Result<(OsString, PathBuf), String> {
+    let Some((name, path)) = value.split_once(':') else {
+        return Err(format!("Expected NAME:PATH: {}", value));
+    };
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => Ok((name.to_os_string(), PathBuf::from(path))),
+        _ => Err(format!("{:?} isn't a name a directory can have", name)),
+    }
+}
+
+/// Parse a `--remap-regex` substitution like `s/^(\d{4})-/\1\//`. Any character can stand in for `/` after the `s`,
+/// and a backslash before it makes it part of the pattern or replacement instead.
+fn parse_remap_regex(value: &str) -> Result<Remap, String> {
+    let expected = || format!("Expected s/REGEX/REPLACEMENT/: {}", value);
+    let mut chars = value.chars();
+    let (Some('s'), Some(delimiter)) = (chars.next(), chars.next()) else {
+        return Err(expected());
+    };
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in chars {
+        match (std::mem::take(&mut escaped), c) {
+            (false, '\\') => escaped = true,
+            (false, c) if c == delimiter => parts.push(String::new()),
+            (true, c) if c == delimiter => parts.last_mut().unwrap().push(c),
+            (true, c) => parts.last_mut().unwrap().extend(['\\', c]),
+            (false, c) => parts.last_mut().unwrap().push(c),
+        }
+    }
+    let [pattern, replacement, flags] = parts.as_slice() else {
+        return Err(expected());
+    };
+    let all = match flags.as_str() {
+        "" => false,
+        "g" => true,
+        _ => return Err(format!("Invalid flags {:?}, expected g or nothing", flags)),
+    };
+    let pattern = regex::Regex::new(pattern)
+        .map_err(|err| format!("Invalid regular expression {:?}: {}", pattern, err))?;
+    Ok(Remap::Regex {
+        pattern,
+        replacement: sed_replacement(replacement),
+        all,
+    })
+}
+
+/// Turn the replacement in a sed substitution into the form [`regex::Regex::replace`] takes: `\1` to `\9` become
+/// `${1}` to `${9}`, `&` becomes `${0}`, `$` is escaped, and a backslash before anything else is dropped.
+fn sed_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => converted.push_str(&format!("${{{}}}", digit)),
+                Some('$') => converted.push_str("$$"),
+                Some(other) => converted.push(other),
+                None => converted.push('\\'),
+            },
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// Parse a `--threads`, either a count or `auto-tune`.
+fn parse_threads(value: &str) -> Result<Threads, String> {
+    match value.trim() {
+        "auto-tune" => Ok(Threads::AutoTune),
+        count => count
+            .parse()
+            .map(Threads::Count)
+            .map_err(|_| format!("Invalid thread count: {}", count)),
+    }
+}
+
+/// Parse a size like `64KiB` or `2 GB` into bytes.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    Byte::from_str(value.trim())
+        .map(|bytes| bytes.get_bytes() as u64)
+        .map_err(|err| err.to_string())
+}
+
+/// Parse a duration like `500ms`, `30s`, `5m`, or `2h`. A bare number is taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("Invalid duration unit: {}", unit)),
+    };
+    Ok(Duration::from_secs_f64(seconds))
 }
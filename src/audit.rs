@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::diff::Kind;
+
+/// A file the copy put in place, for `--audit` to look for at the destination once it's done.
+pub struct Expected {
+    /// The file's path relative to the destination.
+    pub relative: PathBuf,
+    /// The size it was copied at.
+    pub len: u64,
+    /// The modified time it was given, when times are preserved and nothing else has since taken its place, like a
+    /// link to an identical file with `--dedupe-dest`.
+    pub modified: Option<SystemTime>,
+}
+
+/// One way a copied file isn't at the destination as it was copied.
+#[derive(Clone, Copy)]
+pub enum Discrepancy {
+    Missing,
+    /// Something other than a file is there.
+    Kind,
+    Size {
+        expected: u64,
+        found: u64,
+    },
+    Modified,
+}
+
+impl Discrepancy {
+    /// The prefix this discrepancy is printed with, matching the labels `--diff` uses where they overlap.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Kind => "type",
+            Self::Size { .. } => "size",
+            Self::Modified => "mtime",
+        }
+    }
+}
+
+/// Check each of `expected` against what a search of the destination `found`, keyed by path relative to the
+/// destination, returning every file that isn't there as it was copied, sorted by path. Modified times count as the
+/// same within `modify_window`.
+pub fn check(
+    expected: Vec<Expected>,
+    found: &HashMap<PathBuf, (Kind, Metadata)>,
+    modify_window: Duration,
+) -> Vec<(PathBuf, Discrepancy)> {
+    let mut discrepancies: Vec<_> = expected
+        .into_iter()
+        .filter_map(|expected| {
+            let discrepancy = match found.get(&expected.relative) {
+                None => Discrepancy::Missing,
+                Some((kind, _)) if *kind != Kind::File => Discrepancy::Kind,
+                Some((_, metadata)) if metadata.len() != expected.len => Discrepancy::Size {
+                    expected: expected.len,
+                    found: metadata.len(),
+                },
+                Some((_, metadata)) => {
+                    let difference = match (expected.modified, metadata.modified()) {
+                        (Some(expected), Ok(found)) => expected
+                            .duration_since(found)
+                            .or_else(|_| found.duration_since(expected))
+                            .unwrap_or(Duration::MAX),
+                        // Without times to go on, there's nothing to say they differ.
+                        _ => Duration::ZERO,
+                    };
+                    if difference <= modify_window {
+                        return None;
+                    }
+                    Discrepancy::Modified
+                }
+            };
+            Some((expected.relative, discrepancy))
+        })
+        .collect();
+    discrepancies.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    discrepancies
+}
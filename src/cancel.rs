@@ -0,0 +1,27 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Stops a copy from another thread, e.g. a Ctrl-C handler. Clones share the same flag, so cancelling any of them
+/// cancels the copy.
+///
+/// Once cancelled, no new files are started, files being copied in chunks are abandoned at the next chunk (and
+/// removed, unless `keep_partial` is set), and the copy returns early with what it got done, flagged as cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the copy. Returns true if it had already been cancelled.
+    pub fn cancel(&self) -> bool {
+        self.0.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
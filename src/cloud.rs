@@ -0,0 +1,28 @@
+use std::fs::Metadata;
+
+/// Returns true if the file described by `metadata` is a cloud placeholder whose contents aren't on disk, like a
+/// OneDrive or Google Drive file that's only online, so reading it downloads it first.
+#[cfg(windows)]
+pub fn is_placeholder(metadata: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    // FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_RECALL_ON_OPEN, and FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS.
+    const PLACEHOLDER: u32 = 0x1000 | 0x40000 | 0x400000;
+    metadata.file_attributes() & PLACEHOLDER != 0
+}
+
+/// Returns true if the file described by `metadata` is a dataless file, like an iCloud Drive file that's only in the
+/// cloud, so reading it downloads it first.
+#[cfg(target_os = "macos")]
+pub fn is_placeholder(metadata: &Metadata) -> bool {
+    use std::os::macos::fs::MetadataExt;
+
+    // SF_DATALESS.
+    metadata.st_flags() & 0x40000000 != 0
+}
+
+/// Cloud placeholders can't be detected on this platform.
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn is_placeholder(_metadata: &Metadata) -> bool {
+    false
+}
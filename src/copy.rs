@@ -0,0 +1,1835 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::{File, Metadata},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cancel::CancellationToken,
+    hash::{self, Digest, Hasher},
+    options::{CopyOptions, FsyncMode, ReflinkMode, SparseMode},
+    space::available_space,
+    sparse,
+    throttle::Throttle,
+};
+
+/// Files being written in atomic mode are named `<name>.<pid>.ninecopy-partial` until they're complete.
+pub const PARTIAL_SUFFIX: &str = "ninecopy-partial";
+
+/// Destination files smaller than this are copied in full rather than compared block by block with `--delta`, since
+/// there's little to save.
+const DELTA_MIN_SIZE: u64 = 1024 * 1024;
+
+/// Files at least this big have their own progress reported as they're copied, and are copied in chunks rather than
+/// with [`std::fs::copy`] so there's progress to report.
+pub const PROGRESS_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often free space is checked while waiting for a full destination to make room.
+const FULL_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What buffers and transfers are aligned to for `--direct-io` on Linux. This is the largest block size in common
+/// use, and anything aligned to it is also aligned to smaller ones.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// The error wrapped in the [`ErrorKind::TimedOut`] error returned when a file takes longer than `--file-timeout`.
+#[derive(Debug)]
+struct FileTimeout(Duration);
+
+impl std::fmt::Display for FileTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "Copy took longer than {:.1} seconds",
+            self.0.as_secs_f64()
+        ))
+    }
+}
+
+impl std::error::Error for FileTimeout {}
+
+/// The error wrapped in the [`ErrorKind::Interrupted`] error returned when a copy is abandoned partway through
+/// because the whole copy was cancelled.
+#[derive(Debug)]
+struct CopyCancelled;
+
+impl std::fmt::Display for CopyCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Copy cancelled")
+    }
+}
+
+impl std::error::Error for CopyCancelled {}
+
+/// Files larger than this are copied without buffering on Windows, so they don't push everything else out of the
+/// system cache.
+#[cfg(windows)]
+const UNBUFFERED_THRESHOLD: u64 = 1024 * 1024 * 1024;
+
+/// Bytes written so far to files that are still being copied, for the progress display. Only copies that report
+/// their progress as they go count towards this.
+static BYTES_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes written so far to files that haven't finished copying.
+pub fn bytes_in_flight() -> u64 {
+    BYTES_IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Take `bytes` back out of [`BYTES_IN_FLIGHT`], once the file they were written to has finished or failed.
+pub fn release_in_flight(bytes: u64) {
+    BYTES_IN_FLIGHT.fetch_sub(bytes, Ordering::Relaxed);
+}
+
+thread_local! {
+    /// Where this thread reports how much of the file or chunk it's copying has been written, set with
+    /// [`report_file_bytes`].
+    static FILE_BYTES: RefCell<Option<Arc<AtomicU64>>> = const { RefCell::new(None) };
+    /// Bytes [`add_in_flight`] has counted for the file this thread is copying, until [`InFlightBytes`] takes them
+    /// back out.
+    static COUNTED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Add how much of each file or chunk this thread copies has been written to `file_bytes`, for per-file progress.
+/// Whoever hands the thread its work resets it in between.
+pub fn report_file_bytes(file_bytes: Arc<AtomicU64>) {
+    FILE_BYTES.with(|slot| *slot.borrow_mut() = Some(file_bytes));
+}
+
+/// Count `bytes` just written to the file or chunk this thread is copying towards its own progress.
+fn file_progress(bytes: u64) {
+    FILE_BYTES.with(|slot| {
+        if let Some(file_bytes) = &*slot.borrow() {
+            file_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Count `bytes` just written by one of the chunked copy loops in [`BYTES_IN_FLIGHT`], until [`InFlightBytes`] takes
+/// them back out.
+fn add_in_flight(bytes: u64) {
+    BYTES_IN_FLIGHT.fetch_add(bytes, Ordering::Relaxed);
+    COUNTED.set(COUNTED.get() + bytes);
+    file_progress(bytes);
+}
+
+/// Takes what [`add_in_flight`] counted for a file back out of [`BYTES_IN_FLIGHT`] when it's dropped. By then the copy
+/// is over, one way or another, and its bytes are counted by the caller instead.
+struct InFlightBytes;
+
+impl Drop for InFlightBytes {
+    fn drop(&mut self) {
+        release_in_flight(COUNTED.replace(0));
+    }
+}
+
+/// Returns true if `err` means a copy was abandoned because the whole copy was cancelled.
+pub fn is_cancelled(err: &std::io::Error) -> bool {
+    err.get_ref()
+        .is_some_and(|inner| inner.is::<CopyCancelled>())
+}
+
+/// Files currently being written, so they can be removed if the process is forced to exit.
+static IN_FLIGHT: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Keeps a path in [`IN_FLIGHT`] for as long as it's alive.
+struct InFlight(Option<PathBuf>);
+
+impl InFlight {
+    fn new(path: &Path, track: bool) -> Self {
+        if !track {
+            return Self(None);
+        }
+        IN_FLIGHT.lock().unwrap().push(path.to_path_buf());
+        Self(Some(path.to_path_buf()))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let mut in_flight = IN_FLIGHT.lock().unwrap();
+            if let Some(idx) = in_flight.iter().position(|p| p == path) {
+                in_flight.swap_remove(idx);
+            }
+        }
+    }
+}
+
+/// The buffer that files copied in chunks go through. Each copy thread has one that it reuses for every file, and it
+/// isn't allocated until a file needs it, since most files are copied without one.
+pub struct CopyBuffer {
+    size: usize,
+    allocation: Vec<u8>,
+}
+
+impl CopyBuffer {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            allocation: Vec::new(),
+        }
+    }
+
+    /// How many bytes a buffer of `size` bytes takes once it's allocated.
+    pub fn footprint(size: usize) -> usize {
+        // Leave room to align the buffer for --direct-io.
+        size.max(DIRECT_IO_ALIGNMENT) + DIRECT_IO_ALIGNMENT
+    }
+
+    fn allocation(&mut self) -> &mut [u8] {
+        if self.allocation.is_empty() {
+            self.allocation = vec![0; Self::footprint(self.size)];
+        }
+        &mut self.allocation
+    }
+
+    /// The buffer, `--buffer-size` bytes long.
+    pub fn get(&mut self) -> &mut [u8] {
+        let size = self.size;
+        &mut self.allocation()[..size]
+    }
+
+    /// The buffer, starting at an address aligned for `O_DIRECT` and rounded down to a multiple of the alignment.
+    #[cfg(target_os = "linux")]
+    fn aligned(&mut self) -> &mut [u8] {
+        let len = (self.size / DIRECT_IO_ALIGNMENT).max(1) * DIRECT_IO_ALIGNMENT;
+        let allocation = self.allocation();
+        let start = allocation.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        &mut allocation[start..start + len]
+    }
+}
+
+/// The result of copying a single file.
+#[derive(Default)]
+pub struct Copied {
+    pub bytes: u64,
+    /// The file was cloned with `--reflink`, so no data was actually copied.
+    pub cloned: bool,
+    /// If the file was copied sparsely, how many bytes of it were holes that didn't need writing.
+    pub holes: Option<u64>,
+    /// How long it took to sync the file to disk with `--fsync`.
+    pub sync_time: Duration,
+    /// The source's digest, if `--verify` or `--checksum-file` needed one and it was hashed as it was copied.
+    pub digest: Option<Digest>,
+    /// If the file was compared with the existing destination block by block with `--delta`, how many bytes of it
+    /// actually had to be written.
+    pub delta_written: Option<u64>,
+}
+
+/// Copy a single file of `len` bytes from `src` to `dst`.
+///
+/// With `--atomic`, the data is written to a partial file next to `dst` which is then renamed over it, so `dst` is
+/// never seen half written. With `--fsync`, the file (and in atomic mode, the directory it was renamed in) is synced
+/// to disk before this returns.
+pub fn copy_file(
+    src: &Path,
+    dst: &Path,
+    len: u64,
+    opts: &CopyOptions,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    buffer: &mut CopyBuffer,
+) -> std::io::Result<Copied> {
+    let sync = opts.fsync == Some(FsyncMode::Each);
+
+    if !opts.atomic {
+        let _in_flight = InFlight::new(dst, !opts.keep_partial);
+        let mut copied = copy_data(src, dst, len, opts, cancelled, throttle, buffer)?;
+        if sync {
+            let start = Instant::now();
+            sync_file(dst)?;
+            copied.sync_time = start.elapsed();
+        }
+        return Ok(copied);
+    }
+
+    let partial = partial_path(dst);
+    let _in_flight = InFlight::new(&partial, true);
+    let result =
+        copy_data(src, &partial, len, opts, cancelled, throttle, buffer).and_then(|mut copied| {
+            let start = Instant::now();
+            if sync {
+                sync_file(&partial)?;
+            }
+            std::fs::rename(&partial, dst)?;
+            if sync {
+                sync_dir(dst.parent().unwrap_or(dst))?;
+                copied.sync_time = start.elapsed();
+            }
+            Ok(copied)
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result
+}
+
+/// Returns true if `dst` already matches the source, so it doesn't need copying again, along with the number of bytes
+/// read to decide.
+///
+/// Files of different sizes never match. With `update`, files whose modified times are within `--modify-window` of
+/// each other match without being read. With `--checksum`, the rest are hashed and match if their contents do.
+pub fn is_up_to_date(
+    src: &Path,
+    src_metadata: &Metadata,
+    dst: &Path,
+    update: bool,
+    opts: &CopyOptions,
+    buffer: &mut CopyBuffer,
+) -> (bool, u64) {
+    let Ok(dst_metadata) = std::fs::metadata(dst) else {
+        return (false, 0);
+    };
+    if dst_metadata.len() != src_metadata.len() {
+        return (false, 0);
+    }
+
+    if update {
+        if let (Ok(src_modified), Ok(dst_modified)) =
+            (src_metadata.modified(), dst_metadata.modified())
+        {
+            let difference = src_modified
+                .duration_since(dst_modified)
+                .or_else(|_| dst_modified.duration_since(src_modified))
+                .unwrap_or(Duration::MAX);
+            if difference <= opts.modify_window {
+                return (true, 0);
+            }
+        }
+    }
+
+    if !opts.checksum {
+        return (false, 0);
+    }
+    // If either file can't be read, copying it again will report why.
+    let Ok((src_digest, src_read)) = hash::hash_file(src, opts.hash, buffer) else {
+        return (false, 0);
+    };
+    match hash::hash_file(dst, opts.hash, buffer) {
+        Ok((dst_digest, dst_read)) => (src_digest == dst_digest, src_read + dst_read),
+        Err(_) => (false, src_read),
+    }
+}
+
+/// Copy `len` bytes starting at `offset` in `src` to the same place in `dst`, which must already exist. This is how a
+/// file split with `--split-large-files` is copied, one chunk per thread, all writing into the same destination.
+///
+/// Bytes are added to [`BYTES_IN_FLIGHT`] as they're written, and left there if this succeeds.
+pub fn copy_range(
+    src: &Path,
+    dst: &Path,
+    offset: u64,
+    len: u64,
+    buffer: &mut CopyBuffer,
+    drop_cache: bool,
+    throttle: &Throttle,
+) -> std::io::Result<()> {
+    let reader = open_sequential(src)?;
+    let writer = std::fs::OpenOptions::new().write(true).open(dst)?;
+    let buffer = buffer.get();
+
+    let mut copied = 0;
+    let result = loop {
+        if copied == len {
+            break Ok(());
+        }
+        let want = buffer.len().min((len - copied) as usize);
+        let read = match read_at(&reader, &mut buffer[..want], offset + copied) {
+            Ok(0) => {
+                break Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "the file got shorter while it was being copied",
+                ))
+            }
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => break Err(err),
+        };
+        throttle.acquire(read as u64);
+        if let Err(err) = write_all_at(&writer, &buffer[..read], offset + copied) {
+            break Err(err);
+        }
+        if drop_cache {
+            drop_cached(&reader, offset + copied, read as u64);
+            drop_cached(&writer, offset + copied, read as u64);
+        }
+        copied += read as u64;
+        BYTES_IN_FLIGHT.fetch_add(read as u64, Ordering::Relaxed);
+        file_progress(read as u64);
+    };
+    if result.is_err() {
+        release_in_flight(copied);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buffer, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buffer: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buffer: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buffer.is_empty() {
+        match file.seek_write(buffer, offset) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => {
+                buffer = &buffer[written..];
+                offset += written as u64;
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_at(mut file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buffer)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write_all_at(mut file: &File, buffer: &[u8], offset: u64) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buffer)
+}
+
+/// Finish a file that was copied in chunks with [`copy_range`] into `target`: give it the source's permissions,
+/// sync it with `--fsync`, and with `--atomic`, rename it from its partial file to `dst`. Returns how long syncing
+/// took.
+pub fn finish_split(
+    target: &Path,
+    dst: &Path,
+    permissions: std::fs::Permissions,
+    opts: &CopyOptions,
+) -> std::io::Result<Duration> {
+    std::fs::set_permissions(target, permissions)?;
+
+    let sync = opts.fsync == Some(FsyncMode::Each);
+    let start = Instant::now();
+    if sync {
+        sync_file(target)?;
+    }
+    if target != dst {
+        std::fs::rename(target, dst)?;
+        if sync {
+            sync_dir(dst.parent().unwrap_or(dst))?;
+        }
+    }
+    Ok(match sync {
+        true => start.elapsed(),
+        false => Duration::ZERO,
+    })
+}
+
+/// Flush the data and metadata of the file at `path` to disk.
+pub fn sync_file(path: &Path) -> std::io::Result<()> {
+    // Windows can only flush files that are open for writing.
+    #[cfg(windows)]
+    return std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .sync_all();
+
+    #[cfg(not(windows))]
+    File::open(path)?.sync_all()
+}
+
+/// Flush the directory at `path` to disk, so files just created in or renamed into it survive a crash. Windows
+/// doesn't need this.
+pub fn sync_dir(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return File::open(path)?.sync_all();
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Recreate the symbolic link `src` at `dst`, pointing at the same target.
+pub fn copy_symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(src)?;
+
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, dst);
+
+    // Windows has separate kinds of links for files and directories, and junctions, which are recreated as junctions
+    // since they don't need the privilege symbolic links do.
+    #[cfg(windows)]
+    if crate::links::is_junction(src) {
+        crate::links::create_junction(&target, dst)
+    } else if std::fs::metadata(src).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(target, dst)
+    }
+}
+
+/// Clear the read-only attribute on `path`, or on Unix give its owner write permission, so it can be overwritten.
+pub fn make_writable(path: &Path) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Run `op`, retrying it with exponential backoff per `--retries` and `--retry-wait` if it fails with an error that's
+/// likely to be transient. Returns the final result and the number of attempts made.
+pub fn with_retries<T>(
+    opts: &CopyOptions,
+    mut op: impl FnMut() -> std::io::Result<T>,
+) -> (std::io::Result<T>, u32) {
+    let mut attempts = 1;
+    let mut wait = opts.retry_wait;
+    loop {
+        match op() {
+            Err(err) if attempts <= opts.retries && is_retryable(&err) => {
+                std::thread::sleep(wait);
+                wait *= 2;
+                attempts += 1;
+            }
+            result => return (result, attempts),
+        }
+    }
+}
+
+/// Returns true if `err` means another process has the file open in a way that stops it being opened or read here:
+/// ERROR_SHARING_VIOLATION or ERROR_LOCK_VIOLATION on Windows, which are reported as PermissionDenied. Other
+/// platforms don't have mandatory locks, so nothing counts there.
+pub fn is_locked(err: &std::io::Error) -> bool {
+    cfg!(windows) && matches!(err.raw_os_error(), Some(32 | 33))
+}
+
+/// Errors that network filesystems in particular produce sporadically and that usually succeed on a second try.
+fn is_retryable(err: &std::io::Error) -> bool {
+    // A file that hit its timeout would most likely just time out again.
+    if err
+        .get_ref()
+        .is_some_and(|inner| inner.is::<FileTimeout>() || inner.is::<CopyCancelled>())
+    {
+        return false;
+    }
+
+    if is_locked(err) {
+        return true;
+    }
+
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock
+    )
+}
+
+/// Returns true if `err` means the destination filesystem is out of space.
+pub fn is_storage_full(err: &std::io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL.
+    #[cfg(windows)]
+    if matches!(err.raw_os_error(), Some(39) | Some(112)) {
+        return true;
+    }
+
+    err.kind() == ErrorKind::StorageFull
+}
+
+/// Wait until the filesystem containing `dir` has room for `needed` more bytes, checking every 30 seconds. Returns
+/// false if the copy was cancelled or the free space can't be determined.
+pub fn wait_for_space(dir: &Path, needed: u64, cancelled: &CancellationToken) -> bool {
+    message!(
+        "Destination is full. Waiting for {} bytes of free space in {}",
+        needed,
+        dir.display()
+    );
+
+    let mut last_check = Instant::now();
+    loop {
+        if cancelled.is_cancelled() {
+            return false;
+        }
+
+        std::thread::sleep(Duration::from_secs(1));
+        if last_check.elapsed() < FULL_POLL_INTERVAL {
+            continue;
+        }
+        last_check = Instant::now();
+
+        match available_space(dir) {
+            Ok(available) if available > needed => {
+                message!("Space available in {}, resuming", dir.display());
+                return true;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                message!("Unable to check free space in {}: {}", dir.display(), err);
+                return false;
+            }
+        }
+    }
+}
+
+/// Best-effort removal of whatever a failed copy left at `dst`, returning true if a file was removed.
+///
+/// `before` is the destination's metadata from before the copy started. The file is only removed if the copy
+/// created or wrote to it, so an existing file that couldn't be opened for writing is left alone.
+pub fn remove_partial(dst: &Path, before: Option<&Metadata>, opts: &CopyOptions) -> bool {
+    if opts.keep_partial || opts.atomic {
+        return false;
+    }
+
+    let written = match (before, std::fs::metadata(dst)) {
+        (_, Err(_)) => false,
+        (None, Ok(_)) => true,
+        (Some(before), Ok(after)) => {
+            before.len() != after.len() || before.modified().ok() != after.modified().ok()
+        }
+    };
+
+    written && std::fs::remove_file(dst).is_ok()
+}
+
+/// Remove every file that's currently being written. Used when the process is about to exit without waiting for
+/// copies to finish.
+pub fn remove_in_flight() {
+    if let Ok(in_flight) = IN_FLIGHT.lock() {
+        for path in in_flight.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Copy the contents and permissions of `src` to `dst`.
+///
+/// This clones the file if `--reflink` allows it, and is otherwise [`std::fs::copy`] (`CopyFileExW` on Windows) unless a
+/// per-file timeout is in effect, in which case the file is copied in chunks so the deadline can be checked as it
+/// goes. Files are also copied in chunks for `--drop-cache`, for `--bwlimit` everywhere but Windows, and when
+/// `--verify` or `--checksum-file` need the source hashed, so it can be hashed as it's read instead of read again.
+/// Outside Windows and macOS, files of at least [`PROGRESS_FILE_SIZE`] are too, so their progress can be followed.
+fn copy_data(
+    src: &Path,
+    dst: &Path,
+    len: u64,
+    opts: &CopyOptions,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    buffer: &mut CopyBuffer,
+) -> std::io::Result<Copied> {
+    let _in_flight_bytes = InFlightBytes;
+    let mut hasher = (opts.verifies(src, len) || opts.checksum_file.is_some() || opts.dedupe_dest)
+        .then(|| Hasher::new(opts.hash));
+    let deadline = file_timeout(len, opts).map(|timeout| Deadline {
+        at: Instant::now() + timeout,
+        timeout,
+    });
+
+    if opts.delta && std::fs::metadata(dst).is_ok_and(|metadata| metadata.len() >= DELTA_MIN_SIZE) {
+        let written = copy_delta(
+            src,
+            dst,
+            buffer,
+            deadline,
+            cancelled,
+            throttle,
+            hasher.as_mut(),
+        )?;
+        return Ok(Copied {
+            bytes: len,
+            digest: hasher.map(Hasher::finish),
+            delta_written: Some(written),
+            ..Default::default()
+        });
+    }
+
+    match opts.reflink {
+        ReflinkMode::Never => {}
+        ReflinkMode::Auto => {
+            if clone_file(src, dst).is_ok() {
+                return Ok(Copied {
+                    bytes: len,
+                    cloned: true,
+                    ..Default::default()
+                });
+            }
+        }
+        ReflinkMode::Always => {
+            return clone_file(src, dst).map(|_| Copied {
+                bytes: len,
+                cloned: true,
+                ..Default::default()
+            });
+        }
+    }
+
+    if opts.sparse != SparseMode::Never {
+        let reader = open_sequential(src)?;
+        let metadata = reader.metadata()?;
+        if opts.sparse == SparseMode::Always || sparse::is_sparse(&metadata) {
+            return copy_sparse(
+                reader,
+                &metadata,
+                dst,
+                buffer,
+                opts.drop_cache,
+                deadline,
+                cancelled,
+                throttle,
+            );
+        }
+    }
+
+    let copied = |bytes| Copied {
+        bytes,
+        ..Default::default()
+    };
+
+    #[cfg(target_os = "macos")]
+    if opts.preserve_all {
+        return copyfile_all(src, dst).map(|_| copied(len));
+    }
+
+    let direct_io = opts.direct_io && len >= opts.direct_io_threshold;
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if direct_io {
+        if let Some(bytes) = copy_direct(
+            src,
+            dst,
+            buffer,
+            deadline,
+            cancelled,
+            throttle,
+            hasher.as_mut(),
+        )? {
+            return Ok(Copied {
+                digest: hasher.map(Hasher::finish),
+                ..copied(bytes)
+            });
+        }
+    }
+
+    // CopyFileExW reports its progress as it goes, so it can be throttled, but std::fs::copy can't be. Large files are
+    // copied in chunks instead, so their progress can be followed, except on macOS, where fcopyfile doesn't report any
+    // either.
+    let follow = !cfg!(target_os = "macos") && len >= PROGRESS_FILE_SIZE;
+    if deadline.is_none()
+        && !opts.drop_cache
+        && hasher.is_none()
+        && (cfg!(windows) || (throttle.rate().is_none() && !follow))
+    {
+        #[cfg(windows)]
+        return copy_file_ex(
+            src,
+            dst,
+            opts.unbuffered || direct_io || len > UNBUFFERED_THRESHOLD,
+            cancelled,
+            throttle,
+        )
+        .map(copied);
+
+        #[cfg(not(windows))]
+        {
+            let _ = (cancelled, direct_io);
+            return std::fs::copy(src, dst).map(copied);
+        }
+    }
+
+    let reader = open_sequential(src)?;
+    let permissions = reader.metadata()?.permissions();
+    let writer = File::create(dst)?;
+    let bytes_copied = copy_chunked(
+        &reader,
+        &writer,
+        buffer,
+        opts.drop_cache,
+        deadline,
+        cancelled,
+        throttle,
+        hasher.as_mut(),
+    )?;
+    writer.set_permissions(permissions)?;
+    Ok(Copied {
+        digest: hasher.map(Hasher::finish),
+        ..copied(bytes_copied)
+    })
+}
+
+/// Bring the existing file at `dst` up to date with `src` for `--delta`, comparing them a block at a time and writing
+/// only the blocks that differ, then trimming or extending `dst` to the source's length. What's read from the source
+/// is fed to `hasher`. Returns the number of bytes written.
+fn copy_delta(
+    src: &Path,
+    dst: &Path,
+    buffer: &mut CopyBuffer,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    mut hasher: Option<&mut Hasher>,
+) -> std::io::Result<u64> {
+    let reader = open_sequential(src)?;
+    let writer = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(dst)?;
+    let half = buffer.size / 2;
+    let (src_block, dst_block) = buffer.get().split_at_mut(half);
+
+    let mut offset = 0;
+    let mut written = 0;
+    loop {
+        Deadline::check(deadline)?;
+        check_cancelled(cancelled)?;
+
+        let read = read_full_at(&reader, src_block, offset)?;
+        if read == 0 {
+            break;
+        }
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&src_block[..read]);
+        }
+        let existing = read_full_at(&writer, &mut dst_block[..read], offset)?;
+        if existing != read || src_block[..read] != dst_block[..read] {
+            throttle.acquire(read as u64);
+            write_all_at(&writer, &src_block[..read], offset)?;
+            written += read as u64;
+        }
+        offset += read as u64;
+        add_in_flight(read as u64);
+    }
+
+    writer.set_len(offset)?;
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    Ok(written)
+}
+
+/// Read from `offset` in `file` until `buffer` is full or the file ends, returning how much was read.
+fn read_full_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match read_at(file, &mut buffer[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Copy only the ranges of `reader` that hold data, leaving holes in `dst` wherever the source has them.
+#[allow(clippy::too_many_arguments)]
+fn copy_sparse(
+    mut reader: File,
+    metadata: &Metadata,
+    dst: &Path,
+    buffer: &mut CopyBuffer,
+    drop_cache: bool,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+) -> std::io::Result<Copied> {
+    let len = metadata.len();
+    let extents = sparse::data_extents(&reader, len)?;
+    let mut writer = File::create(dst)?;
+    sparse::mark_sparse(&writer)?;
+
+    let mut written = 0;
+    for (offset, length) in extents {
+        reader.seek(SeekFrom::Start(offset))?;
+        writer.seek(SeekFrom::Start(offset))?;
+        written += read_write_chunks(
+            &reader,
+            &writer,
+            length,
+            buffer.get(),
+            drop_cache,
+            deadline,
+            cancelled,
+            throttle,
+            None,
+        )?;
+    }
+
+    // Extend the file over any hole at the end.
+    writer.set_len(len)?;
+    writer.set_permissions(metadata.permissions())?;
+    Ok(Copied {
+        bytes: len,
+        holes: Some(len.saturating_sub(written)),
+        ..Default::default()
+    })
+}
+
+/// Copy `src` to `dst` with `O_DIRECT`, so neither file goes through the page cache, feeding what's read to `hasher`.
+/// Returns `None` without copying anything if either file can't be opened that way, which is usually because the
+/// filesystem doesn't support it.
+#[cfg(target_os = "linux")]
+fn copy_direct(
+    src: &Path,
+    dst: &Path,
+    buffer: &mut CopyBuffer,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    mut hasher: Option<&mut Hasher>,
+) -> std::io::Result<Option<u64>> {
+    use std::{fs::OpenOptions, os::unix::fs::OpenOptionsExt};
+
+    let Ok(mut reader) = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(src)
+    else {
+        return Ok(None);
+    };
+    let Ok(mut writer) = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(dst)
+    else {
+        return Ok(None);
+    };
+
+    // O_DIRECT needs the buffer's address and the size of every transfer to be a multiple of the device's block size.
+    let buffer = buffer.aligned();
+
+    let mut copied = 0;
+    loop {
+        Deadline::check(deadline)?;
+        check_cancelled(cancelled)?;
+
+        let read = match reader.read(buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        // The last block is padded out to full size, and the file is trimmed back afterwards.
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        let padded = read.next_multiple_of(DIRECT_IO_ALIGNMENT);
+        buffer[read..padded].fill(0);
+        throttle.acquire(read as u64);
+        writer.write_all(&buffer[..padded])?;
+        copied += read as u64;
+        add_in_flight(read as u64);
+
+        // A partial block only comes at the end of the file.
+        if read != padded {
+            break;
+        }
+    }
+
+    writer.set_len(copied)?;
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    Ok(Some(copied))
+}
+
+/// Copy `src` to `dst` with `F_NOCACHE` set on both, so neither file goes through the unified buffer cache, feeding
+/// what's read to `hasher`.
+#[cfg(target_os = "macos")]
+fn copy_direct(
+    src: &Path,
+    dst: &Path,
+    buffer: &mut CopyBuffer,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    hasher: Option<&mut Hasher>,
+) -> std::io::Result<Option<u64>> {
+    use std::os::fd::AsRawFd;
+
+    let reader = File::open(src)?;
+    let writer = File::create(dst)?;
+    for fd in [reader.as_raw_fd(), writer.as_raw_fd()] {
+        if unsafe { libc::fcntl(fd, libc::F_NOCACHE, 1) } != 0 {
+            return Ok(None);
+        }
+    }
+
+    let copied = read_write_chunks(
+        &reader,
+        &writer,
+        u64::MAX,
+        buffer.get(),
+        false,
+        deadline,
+        cancelled,
+        throttle,
+        hasher,
+    )?;
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    Ok(Some(copied))
+}
+
+/// When a file being copied in chunks has to be finished by.
+#[derive(Clone, Copy)]
+struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    fn check(deadline: Option<Deadline>) -> std::io::Result<()> {
+        match deadline {
+            Some(deadline) if Instant::now() > deadline.at => Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                FileTimeout(deadline.timeout),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Fail with [`CopyCancelled`] if the copy has been cancelled, so files copied in chunks are abandoned at the next
+/// chunk.
+fn check_cancelled(cancelled: &CancellationToken) -> std::io::Result<()> {
+    match cancelled.is_cancelled() {
+        true => Err(std::io::Error::new(ErrorKind::Interrupted, CopyCancelled)),
+        false => Ok(()),
+    }
+}
+
+/// Copy the rest of `reader` to `writer` in chunks, returning the number of bytes copied, or failing with
+/// [`FileTimeout`] if `deadline` passes first. With `drop_cache`, each chunk is dropped from the cache once it's
+/// copied.
+///
+/// On Linux this uses `copy_file_range`, so the data never has to pass through userspace. On macOS it's `fcopyfile`
+/// when there's no deadline to check between chunks. Otherwise, when those aren't supported for these files, or when
+/// there's a `hasher` that needs to see the data, it's a buffered read/write loop.
+#[allow(clippy::too_many_arguments)]
+fn copy_chunked(
+    reader: &File,
+    writer: &File,
+    buffer: &mut CopyBuffer,
+    drop_cache: bool,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    hasher: Option<&mut Hasher>,
+) -> std::io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    let copied = match hasher.is_none() {
+        true => match copy_file_range_chunks(
+            reader,
+            writer,
+            buffer.size,
+            drop_cache,
+            deadline,
+            cancelled,
+            throttle,
+        )? {
+            (copied, true) => return Ok(copied),
+            (copied, false) => copied,
+        },
+        false => 0,
+    };
+
+    #[cfg(target_os = "macos")]
+    if deadline.is_none() && throttle.rate().is_none() && hasher.is_none() {
+        return fcopyfile_data(reader, writer);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let copied = 0;
+
+    read_write_chunks(
+        reader,
+        writer,
+        u64::MAX,
+        buffer.get(),
+        drop_cache,
+        deadline,
+        cancelled,
+        throttle,
+        hasher,
+    )
+    .map(|rest| copied + rest)
+}
+
+/// Copy with `copy_file_range`, `chunk_size` bytes at a time, until the end of `reader`. Returns the number of bytes
+/// copied, and false if `copy_file_range` isn't supported for these files, in which case the rest needs to be copied
+/// some other way.
+#[cfg(target_os = "linux")]
+fn copy_file_range_chunks(
+    reader: &File,
+    writer: &File,
+    chunk_size: usize,
+    drop_cache: bool,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+) -> std::io::Result<(u64, bool)> {
+    use std::os::fd::AsRawFd;
+
+    let mut copied = 0;
+    loop {
+        Deadline::check(deadline)?;
+        check_cancelled(cancelled)?;
+
+        let result = unsafe {
+            libc::copy_file_range(
+                reader.as_raw_fd(),
+                std::ptr::null_mut(),
+                writer.as_raw_fd(),
+                std::ptr::null_mut(),
+                chunk_size,
+                0,
+            )
+        };
+        match result {
+            // Some virtual filesystems report nothing to copy through copy_file_range even when there is, so an empty
+            // first chunk gets a second opinion from read.
+            0 => return Ok((copied, copied > 0)),
+            // Short copies are normal, and the loop picks up where they left off.
+            copied_now if copied_now > 0 => {
+                // There's no telling how much copy_file_range will copy until it has, so the bandwidth it used is
+                // accounted for afterwards.
+                throttle.acquire(copied_now as u64);
+                if drop_cache {
+                    drop_cached(reader, copied, copied_now as u64);
+                    drop_cached(writer, copied, copied_now as u64);
+                }
+                copied += copied_now as u64;
+                add_in_flight(copied_now as u64);
+            }
+            _ => {
+                let err = std::io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // Kernels without copy_file_range, filesystems that don't support it, and copies between
+                    // filesystems on kernels before 5.3.
+                    Some(
+                        libc::ENOSYS
+                        | libc::EXDEV
+                        | libc::EINVAL
+                        | libc::EOPNOTSUPP
+                        | libc::EPERM
+                        | libc::EBADF,
+                    ) => return Ok((copied, false)),
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+}
+
+/// Copy all of `reader` to `writer` with `fcopyfile(3)`.
+#[cfg(target_os = "macos")]
+fn fcopyfile_data(reader: &File, writer: &File) -> std::io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    if unsafe {
+        libc::fcopyfile(
+            reader.as_raw_fd(),
+            writer.as_raw_fd(),
+            std::ptr::null_mut(),
+            libc::COPYFILE_DATA,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(reader.metadata()?.len())
+}
+
+/// Copy up to `limit` bytes from the current position in `reader` to the same position in `writer` through `buffer`,
+/// feeding each chunk to `hasher` as it goes.
+#[allow(clippy::too_many_arguments)]
+fn read_write_chunks(
+    reader: &File,
+    mut writer: &File,
+    limit: u64,
+    buffer: &mut [u8],
+    drop_cache: bool,
+    deadline: Option<Deadline>,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+    mut hasher: Option<&mut Hasher>,
+) -> std::io::Result<u64> {
+    let start = match drop_cache {
+        true => writer.stream_position()?,
+        false => 0,
+    };
+    let mut reader = reader.take(limit);
+    let mut copied = 0;
+    loop {
+        Deadline::check(deadline)?;
+        check_cancelled(cancelled)?;
+
+        let read = match reader.read(buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        throttle.acquire(read as u64);
+        writer.write_all(&buffer[..read])?;
+        if drop_cache {
+            drop_cached(reader.get_ref(), start + copied, read as u64);
+            drop_cached(writer, start + copied, read as u64);
+        }
+        copied += read as u64;
+        add_in_flight(read as u64);
+    }
+    Ok(copied)
+}
+
+/// Open `src` to be read from start to finish, telling the OS so it can read further ahead.
+pub fn open_sequential(src: &Path) -> std::io::Result<File> {
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_FLAG_SEQUENTIAL_SCAN;
+
+        std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(FILE_FLAG_SEQUENTIAL_SCAN)
+            .open(src)?
+    };
+
+    #[cfg(not(windows))]
+    let file = File::open(src)?;
+
+    // This is only advice, so it doesn't matter if it's ignored.
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    unsafe {
+        use std::os::fd::AsRawFd;
+
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+    }
+
+    Ok(file)
+}
+
+/// Tell the OS that `len` bytes of `file` from `offset` won't be needed again, for `--drop-cache`. Written data only
+/// leaves the cache once it's been flushed to disk.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn drop_cached(file: &File, offset: u64, len: u64) {
+    use std::os::fd::AsRawFd;
+
+    unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            libc::POSIX_FADV_DONTNEED,
+        );
+    }
+}
+
+/// `--drop-cache` isn't supported on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn drop_cached(_file: &File, _offset: u64, _len: u64) {}
+
+/// Copy `src` to `dst` with `CopyFileExW`, which is what [`std::fs::copy`] uses on Windows, but reporting progress
+/// to [`BYTES_IN_FLIGHT`] and giving up if the copy is cancelled.
+#[cfg(windows)]
+fn copy_file_ex(
+    src: &Path,
+    dst: &Path,
+    unbuffered: bool,
+    cancelled: &CancellationToken,
+    throttle: &Throttle,
+) -> std::io::Result<u64> {
+    use std::{cell::Cell, ffi::c_void, os::windows::ffi::OsStrExt};
+    use windows_sys::Win32::{
+        Foundation::HANDLE,
+        Storage::FileSystem::{CopyFileExW, COPY_FILE_NO_BUFFERING},
+    };
+
+    const PROGRESS_CONTINUE: u32 = 0;
+    const PROGRESS_CANCEL: u32 = 1;
+
+    struct Progress<'a> {
+        reported: Cell<u64>,
+        cancelled: &'a CancellationToken,
+        throttle: &'a Throttle,
+    }
+
+    unsafe extern "system" fn progress_routine(
+        _total_file_size: i64,
+        total_bytes_transferred: i64,
+        _stream_size: i64,
+        _stream_bytes_transferred: i64,
+        _stream_number: u32,
+        _callback_reason: u32,
+        _source_file: HANDLE,
+        _destination_file: HANDLE,
+        data: *const c_void,
+    ) -> u32 {
+        let progress = &*(data as *const Progress);
+        let transferred = total_bytes_transferred as u64;
+        BYTES_IN_FLIGHT.fetch_add(transferred - progress.reported.get(), Ordering::Relaxed);
+        file_progress(transferred - progress.reported.get());
+        // Waiting here holds up the copy, which is how --bwlimit applies to CopyFileExW.
+        progress
+            .throttle
+            .acquire(transferred - progress.reported.get());
+        progress.reported.set(transferred);
+        if progress.cancelled.is_cancelled() {
+            return PROGRESS_CANCEL;
+        }
+        PROGRESS_CONTINUE
+    }
+
+    let to_wide = |path: &Path| -> Vec<u16> {
+        path.as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let (src, dst) = (to_wide(src), to_wide(dst));
+    let progress = Progress {
+        reported: Cell::new(0),
+        cancelled,
+        throttle,
+    };
+    let flags = if unbuffered {
+        COPY_FILE_NO_BUFFERING
+    } else {
+        0
+    };
+    let success = unsafe {
+        CopyFileExW(
+            src.as_ptr(),
+            dst.as_ptr(),
+            Some(progress_routine),
+            &progress as *const Progress as *const c_void,
+            std::ptr::null_mut(),
+            flags,
+        )
+    };
+
+    // Once the copy is over, its bytes are counted by the caller instead.
+    let copied = progress.reported.get();
+    BYTES_IN_FLIGHT.fetch_sub(copied, Ordering::Relaxed);
+
+    if success == 0 {
+        if cancelled.is_cancelled() {
+            return Err(std::io::Error::new(ErrorKind::Interrupted, CopyCancelled));
+        }
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(copied)
+}
+
+/// Make `dst` a copy-on-write clone of `src`, which shares its data until one of them is modified.
+#[cfg(target_os = "linux")]
+fn clone_file(src: &Path, dst: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // FICLONE from linux/fs.h. Supported by Btrfs, XFS, and a few others.
+    const FICLONE: u64 = 0x40049409;
+
+    let reader = File::open(src)?;
+    let writer = File::create(dst)?;
+    if unsafe { libc::ioctl(writer.as_raw_fd(), FICLONE as _, reader.as_raw_fd()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    writer.set_permissions(reader.metadata()?.permissions())
+}
+
+/// Make `dst` a copy-on-write clone of `src`, which shares its data until one of them is modified.
+#[cfg(target_os = "macos")]
+fn clone_file(src: &Path, dst: &Path) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let to_c = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidInput, err))
+    };
+    let (src_c, dst_c) = (to_c(src)?, to_c(dst)?);
+
+    // clonefile refuses to replace an existing file.
+    match std::fs::remove_file(dst) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    if unsafe { libc::clonefile(src_c.as_ptr(), dst_c.as_ptr(), 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Cloning is only supported on Linux and macOS.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_src: &Path, _dst: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "cloning files is not supported on this platform",
+    ))
+}
+
+/// Copy `src` to `dst` with `copyfile(3)`, which brings along every piece of metadata macOS knows about: ACLs,
+/// extended attributes, Finder info, flags, and times.
+#[cfg(target_os = "macos")]
+fn copyfile_all(src: &Path, dst: &Path) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let to_c = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidInput, err))
+    };
+    let (src, dst) = (to_c(src)?, to_c(dst)?);
+    if unsafe {
+        libc::copyfile(
+            src.as_ptr(),
+            dst.as_ptr(),
+            std::ptr::null_mut(),
+            libc::COPYFILE_ALL,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// How long a file of `len` bytes is allowed to take, per `--file-timeout` and `--min-rate`.
+fn file_timeout(len: u64, opts: &CopyOptions) -> Option<Duration> {
+    if opts.file_timeout.is_none() && opts.min_rate.is_none() {
+        return None;
+    }
+
+    let mut timeout = opts.file_timeout.unwrap_or_default();
+    if let Some(min_rate) = opts.min_rate {
+        timeout += Duration::from_secs_f64(len as f64 / min_rate.max(1) as f64);
+    }
+    Some(timeout)
+}
+
+/// Move the existing file at `dst` out of the way for `--backup` or `--backup-dir`, replacing any older backup of it.
+/// `relative` is its path relative to the destination. Does nothing if neither option is set.
+///
+/// Returns true if there was a file to back up.
+pub fn backup(dst: &Path, relative: &Path, opts: &CopyOptions) -> std::io::Result<bool> {
+    if opts.backup.is_none() && opts.backup_dir.is_none() {
+        return Ok(false);
+    }
+    let Ok(metadata) = std::fs::symlink_metadata(dst) else {
+        return Ok(false);
+    };
+
+    let mut backup = match &opts.backup_dir {
+        Some(dir) => dir.join(relative),
+        None => dst.to_path_buf(),
+    };
+    if let Some(suffix) = &opts.backup {
+        let mut name = backup.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        backup.set_file_name(name);
+    }
+    if let Some(parent) = backup.parent() {
+        std::fs::DirBuilder::new().recursive(true).create(parent)?;
+    }
+
+    match std::fs::rename(dst, &backup) {
+        Ok(()) => Ok(true),
+        // The backup directory can be on another filesystem.
+        Err(err) if err.kind() == ErrorKind::CrossesDevices && !metadata.is_symlink() => {
+            std::fs::copy(dst, &backup)?;
+            std::fs::remove_file(dst)?;
+            Ok(true)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Create something at the first free name of the form `name (1).ext`, `name (2).ext`, and so on, next to `dst` for
+/// `--rename-collisions`. `create` is called with each name in turn and has to fail with `AlreadyExists` if the name
+/// is taken, so threads racing for the same name never both get it. Returns the name that was free and what `create`
+/// returned for it.
+pub fn with_free_name<T>(
+    dst: &Path,
+    mut create: impl FnMut(&Path) -> std::io::Result<T>,
+) -> std::io::Result<(PathBuf, T)> {
+    let stem = dst.file_stem().unwrap_or_default();
+    let mut number = 1u64;
+    loop {
+        let mut name = stem.to_os_string();
+        name.push(format!(" ({})", number));
+        if let Some(extension) = dst.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        let candidate = dst.with_file_name(name);
+        match create(&candidate) {
+            Ok(created) => return Ok((candidate, created)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => number += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// The name `dst` is written to in atomic mode before it's renamed into place.
+pub fn partial_path(dst: &Path) -> PathBuf {
+    let mut name = dst.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.{}", std::process::id(), PARTIAL_SUFFIX));
+    dst.with_file_name(name)
+}
+
+/// Remove partial files left under `dir` by atomic copies that never finished, returning how many were removed. Ones
+/// written by a process that's still running, like this one or another copy to the same destination, are left alone.
+/// Anything that can't be read or removed is warned about and skipped.
+pub fn remove_partials(dir: &Path) -> u64 {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            message!(
+                "Warning: unable to check {} for partial files: {}",
+                dir.display(),
+                err
+            );
+            return 0;
+        }
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                message!(
+                    "Warning: unable to check {} for partial files: {}",
+                    dir.display(),
+                    err
+                );
+                continue;
+            }
+        };
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            removed += remove_partials(&entry.path());
+            continue;
+        }
+        let name = entry.file_name();
+        let Some(pid) = partial_pid(&name.to_string_lossy()) else {
+            continue;
+        };
+        if pid.is_some_and(is_running) {
+            continue;
+        }
+        match std::fs::remove_file(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(err) => message!(
+                "Warning: unable to remove partial file {}: {}",
+                entry.path().display(),
+                err
+            ),
+        }
+    }
+    removed
+}
+
+/// For a partial file named `name`, the id of the process that was writing it, if the name has one. `None` if it isn't
+/// a partial file at all.
+fn partial_pid(name: &str) -> Option<Option<u32>> {
+    let stem = name.strip_suffix(PARTIAL_SUFFIX)?.strip_suffix('.')?;
+    Some(stem.rsplit_once('.').and_then(|(_, pid)| pid.parse().ok()))
+}
+
+/// Whether the process with id `pid` is still running.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    // Anything bigger would be taken as a process group.
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process is there. Not being allowed to signal it still means it is.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether the process with id `pid` is still running.
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, STILL_ACTIVE},
+        System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    };
+
+    if pid == std::process::id() {
+        return true;
+    }
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process == 0 {
+            return false;
+        }
+        let mut code = 0;
+        let running = GetExitCodeProcess(process, &mut code) != 0 && code == STILL_ACTIVE as u32;
+        CloseHandle(process);
+        running
+    }
+}
+
+/// Whether the process with id `pid` is still running. Only this one is known to be.
+#[cfg(not(any(unix, windows)))]
+fn is_running(pid: u32) -> bool {
+    pid == std::process::id()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A file of `len` bytes in `dir` that don't repeat from one buffer to the next, and what's in it.
+    fn source(dir: &Path, len: usize) -> (PathBuf, Vec<u8>) {
+        let contents: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let path = dir.join("source");
+        std::fs::write(&path, &contents).unwrap();
+        (path, contents)
+    }
+
+    // These only run the `--drop-cache` advice, since what the kernel does with it can't be relied on.
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drop_cache_copies_everything() {
+        let dir = TempDir::new().unwrap();
+        let (src, contents) = source(dir.path(), 1024 * 1024 + 123);
+        let dst = dir.path().join("copy");
+        let opts = CopyOptions {
+            drop_cache: true,
+            ..CopyOptions::default()
+        };
+        let mut buffer = CopyBuffer::new(64 * 1024);
+
+        let copied = copy_file(
+            &src,
+            &dst,
+            contents.len() as u64,
+            &opts,
+            &CancellationToken::new(),
+            &Throttle::default(),
+            &mut buffer,
+        )
+        .unwrap();
+
+        assert_eq!(copied.bytes, contents.len() as u64);
+        assert_eq!(std::fs::read(&dst).unwrap(), contents);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drop_cache_read_write_from_an_offset() {
+        let dir = TempDir::new().unwrap();
+        let (src, contents) = source(dir.path(), 300 * 1024);
+        let reader = open_sequential(&src).unwrap();
+        let mut writer = File::create(dir.path().join("copy")).unwrap();
+        writer.write_all(b"header").unwrap();
+        let mut buffer = CopyBuffer::new(64 * 1024);
+
+        let copied = read_write_chunks(
+            &reader,
+            &writer,
+            u64::MAX,
+            buffer.get(),
+            true,
+            None,
+            &CancellationToken::new(),
+            &Throttle::default(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(copied, contents.len() as u64);
+        let written = std::fs::read(dir.path().join("copy")).unwrap();
+        assert_eq!(&written[..6], b"header");
+        assert_eq!(&written[6..], contents);
+    }
+
+    #[test]
+    fn sequential_source_reads_from_the_start() {
+        let dir = TempDir::new().unwrap();
+        let (src, contents) = source(dir.path(), 10_000);
+        let mut read = Vec::new();
+        open_sequential(&src)
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, contents);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn copy_file_ex_copies_everything() {
+        let dir = TempDir::new().unwrap();
+        let (src, contents) = source(dir.path(), 1024 * 1024 + 123);
+        let dst = dir.path().join("copy");
+
+        let copied = copy_file_ex(
+            &src,
+            &dst,
+            false,
+            &CancellationToken::new(),
+            &Throttle::default(),
+        )
+        .unwrap();
+
+        assert_eq!(copied, contents.len() as u64);
+        assert_eq!(std::fs::read(&dst).unwrap(), contents);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn copy_file_ex_unbuffered_copies_everything() {
+        let dir = TempDir::new().unwrap();
+        let (src, contents) = source(dir.path(), 4 * 1024 * 1024 + 123);
+        let dst = dir.path().join("copy");
+
+        let copied = copy_file_ex(
+            &src,
+            &dst,
+            true,
+            &CancellationToken::new(),
+            &Throttle::default(),
+        )
+        .unwrap();
+
+        assert_eq!(copied, contents.len() as u64);
+        assert_eq!(std::fs::read(&dst).unwrap(), contents);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn copy_file_ex_copies_empty_files() {
+        let dir = TempDir::new().unwrap();
+        let (src, _) = source(dir.path(), 0);
+        let dst = dir.path().join("copy");
+
+        let copied = copy_file_ex(
+            &src,
+            &dst,
+            false,
+            &CancellationToken::new(),
+            &Throttle::default(),
+        )
+        .unwrap();
+
+        assert_eq!(copied, 0);
+        assert_eq!(std::fs::metadata(&dst).unwrap().len(), 0);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn copy_file_ex_stops_when_cancelled() {
+        let dir = TempDir::new().unwrap();
+        let (src, _) = source(dir.path(), 1024 * 1024);
+        let dst = dir.path().join("copy");
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+
+        let err = copy_file_ex(&src, &dst, false, &cancelled, &Throttle::default()).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert!(err.get_ref().is_some_and(|err| err.is::<CopyCancelled>()));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn copy_file_ex_reports_a_missing_source() {
+        let dir = TempDir::new().unwrap();
+
+        let err = copy_file_ex(
+            &dir.path().join("missing"),
+            &dir.path().join("copy"),
+            false,
+            &CancellationToken::new(),
+            &Throttle::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn copy_file_with_defaults_copies_everything() {
+        // Without anything that needs the copy done in chunks, this goes through `copy_file_ex`.
+        let dir = TempDir::new().unwrap();
+        let (src, contents) = source(dir.path(), 100 * 1024);
+        let dst = dir.path().join("copy");
+
+        let copied = copy_file(
+            &src,
+            &dst,
+            contents.len() as u64,
+            &CopyOptions::default(),
+            &CancellationToken::new(),
+            &Throttle::default(),
+            &mut CopyBuffer::new(64 * 1024),
+        )
+        .unwrap();
+
+        assert_eq!(copied.bytes, contents.len() as u64);
+        assert_eq!(std::fs::read(&dst).unwrap(), contents);
+    }
+
+    #[test]
+    fn partial_pid_reads_the_pid_from_the_name() {
+        assert_eq!(
+            partial_pid("file.txt.1234.ninecopy-partial"),
+            Some(Some(1234))
+        );
+        assert_eq!(partial_pid("file.ninecopy-partial"), Some(None));
+        assert_eq!(partial_pid("file.txt"), None);
+    }
+
+    #[test]
+    fn remove_partials_keeps_ones_still_being_written() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let ours = partial_path(&dir.path().join("sub/ours"));
+        let dead = dir
+            .path()
+            .join(format!("dead.999999999.{}", PARTIAL_SUFFIX));
+        for path in [&ours, &dead] {
+            std::fs::write(path, "partial").unwrap();
+        }
+        std::fs::write(dir.path().join("complete"), "complete").unwrap();
+
+        assert_eq!(remove_partials(dir.path()), 1);
+        assert!(ours.exists());
+        assert!(!dead.exists());
+        assert!(dir.path().join("complete").exists());
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    errors::CopyError,
+    filter::{self, FileFilter},
+    reporter,
+    stats::Accumulator,
+};
+
+/// Remove everything in `dst` that has no counterpart in `found`, the paths relative to the source that the search
+/// turned up. Directories that are in `found` are walked into, and ones that aren't are removed with everything in
+/// them. Anything `filters` leave out is kept, even inside a directory that's removed, since it wasn't searched for in
+/// the source.
+///
+/// With `dry_run`, each path that would be removed is listed instead, and counted as if it had been.
+///
+/// Returns the stats for what was deleted and the errors for anything that couldn't be.
+pub fn delete_extraneous(
+    dst: &Path,
+    found: &HashSet<PathBuf>,
+    filters: &[Arc<dyn FileFilter>],
+    dry_run: bool,
+) -> (Accumulator, Vec<CopyError>) {
+    let mut accumulator = Accumulator::default();
+    let mut errors = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative) = dirs.pop() {
+        let dir = dst.join(&relative);
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                errors.push(CopyError::DeleteFailed {
+                    path: dir,
+                    source: err,
+                });
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    errors.push(CopyError::DeleteFailed {
+                        path: dir.clone(),
+                        source: err,
+                    });
+                    continue;
+                }
+            };
+            let entry_relative = relative.join(entry.file_name());
+            // Symlinks are never followed, whatever they point to.
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            if found.contains(&entry_relative) {
+                if is_dir {
+                    dirs.push(entry_relative);
+                }
+                continue;
+            }
+            remove(
+                dst,
+                &entry_relative,
+                is_dir,
+                filters,
+                dry_run,
+                &mut accumulator,
+                &mut errors,
+            );
+        }
+    }
+    (accumulator, errors)
+}
+
+/// Remove the file or directory at `relative` in `dst`, and everything in it if it's a directory, except for anything
+/// `filters` leave out. A directory that still holds something they left out is kept along with it.
+///
+/// Returns whether anything was kept.
+fn remove(
+    dst: &Path,
+    relative: &Path,
+    is_dir: bool,
+    filters: &[Arc<dyn FileFilter>],
+    dry_run: bool,
+    accumulator: &mut Accumulator,
+    errors: &mut Vec<CopyError>,
+) -> bool {
+    let path = dst.join(relative);
+    if !filters.is_empty() {
+        let excluded = std::fs::symlink_metadata(&path)
+            .is_ok_and(|metadata| !filter::included(filters, relative, &metadata));
+        if excluded {
+            return true;
+        }
+    }
+    if !is_dir {
+        let len = std::fs::symlink_metadata(&path).map_or(0, |metadata| metadata.len());
+        if dry_run {
+            reporter::output(format!("delete\t{}", relative.display()));
+            *accumulator += Accumulator::deleted(1, len);
+            return false;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => *accumulator += Accumulator::deleted(1, len),
+            Err(err) => {
+                *accumulator += Accumulator::delete_failed(1);
+                errors.push(CopyError::DeleteFailed { path, source: err });
+            }
+        }
+        return false;
+    }
+
+    let errors_before = errors.len();
+    let mut kept = false;
+    match std::fs::read_dir(&path) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+                kept |= remove(
+                    dst,
+                    &relative.join(entry.file_name()),
+                    is_dir,
+                    filters,
+                    dry_run,
+                    accumulator,
+                    errors,
+                );
+            }
+        }
+        Err(err) => {
+            errors.push(CopyError::DeleteFailed { path, source: err });
+            return false;
+        }
+    }
+    if kept {
+        // It isn't empty, and isn't meant to be.
+        return true;
+    }
+    if dry_run {
+        reporter::output(format!("delete\t{}", relative.display()));
+        *accumulator += Accumulator::dirs_deleted(1);
+        return false;
+    }
+    match std::fs::remove_dir(&path) {
+        Ok(()) => *accumulator += Accumulator::dirs_deleted(1),
+        // Whatever couldn't be removed from inside it has already been reported.
+        Err(_) if errors.len() > errors_before => {}
+        Err(err) => errors.push(CopyError::DeleteFailed { path, source: err }),
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Leaves out anything called `keep`.
+    fn protect_keep() -> Vec<Arc<dyn FileFilter>> {
+        vec![Arc::new(|path: &Path, _: &std::fs::Metadata| {
+            path.file_name() != Some("keep".as_ref())
+        })]
+    }
+
+    #[test]
+    fn keeps_protected_entries_inside_extraneous_dirs() {
+        let dst = TempDir::new().unwrap();
+        std::fs::create_dir_all(dst.path().join("gone/kept/deeper")).unwrap();
+        std::fs::write(dst.path().join("gone/a"), "a").unwrap();
+        std::fs::write(dst.path().join("gone/kept/deeper/keep"), "keep").unwrap();
+        std::fs::write(dst.path().join("gone/kept/b"), "b").unwrap();
+        std::fs::create_dir(dst.path().join("empty")).unwrap();
+
+        let (deleted, errors) =
+            delete_extraneous(dst.path(), &HashSet::new(), &protect_keep(), false);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(deleted.file_count_deleted, 2);
+        assert_eq!(deleted.dir_count_deleted, 1);
+        assert!(dst.path().join("gone/kept/deeper/keep").exists());
+        assert!(!dst.path().join("gone/a").exists());
+        assert!(!dst.path().join("gone/kept/b").exists());
+        assert!(!dst.path().join("empty").exists());
+    }
+
+    #[test]
+    fn dry_run_deletes_nothing() {
+        let dst = TempDir::new().unwrap();
+        std::fs::create_dir_all(dst.path().join("gone/kept")).unwrap();
+        std::fs::write(dst.path().join("gone/a"), "a").unwrap();
+        std::fs::write(dst.path().join("gone/kept/keep"), "keep").unwrap();
+        std::fs::write(dst.path().join("b"), "bb").unwrap();
+
+        let (deleted, errors) =
+            delete_extraneous(dst.path(), &HashSet::new(), &protect_keep(), true);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(deleted.file_count_deleted, 2);
+        assert_eq!(deleted.byte_count_deleted, 3);
+        assert_eq!(deleted.dir_count_deleted, 0);
+        assert!(dst.path().join("gone/a").exists());
+        assert!(dst.path().join("b").exists());
+    }
+}
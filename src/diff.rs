@@ -0,0 +1,123 @@
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{copy::CopyBuffer, errors::CopyError, hash, options::CopyOptions};
+
+/// What kind of thing is at a path, for `--diff` to tell when the source and destination disagree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink,
+    Special,
+}
+
+/// One way a path differs between the source and destination.
+#[derive(Clone, Copy)]
+pub enum Difference {
+    OnlyInSource,
+    OnlyInDestination,
+    /// A file at one end and a directory, symlink, or special file at the other.
+    Kind,
+    Size,
+    Modified,
+    /// The files' contents differ, with `--checksum`.
+    Contents,
+    /// Symlinks that point to different places, with `--preserve-symlinks`.
+    Target,
+}
+
+impl Difference {
+    /// The prefix this difference is printed with, which scripts can match on.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::OnlyInSource => "only-src",
+            Self::OnlyInDestination => "only-dst",
+            Self::Kind => "type",
+            Self::Size => "size",
+            Self::Modified => "mtime",
+            Self::Contents => "content",
+            Self::Target => "target",
+        }
+    }
+}
+
+/// A path found at both the source and destination, to be compared.
+pub struct Pair {
+    pub relative: PathBuf,
+    pub kind: Kind,
+    pub src: Metadata,
+    pub dst: Metadata,
+}
+
+/// Compare a path found at both ends, given its source and destination base directories. Files of different sizes
+/// always differ, and the rest differ by modified time (within `--modify-window`), or with `--checksum`, by contents.
+/// Directories and special files only differ by kind, which has already been checked.
+///
+/// Returns the difference, if there is one, and the number of bytes read to find it.
+pub fn compare(
+    pair: &Pair,
+    src_base: &Path,
+    dst_base: &Path,
+    opts: &CopyOptions,
+    buffer: &mut CopyBuffer,
+) -> Result<(Option<Difference>, u64), CopyError> {
+    let src = src_base.join(&pair.relative);
+    let dst = dst_base.join(&pair.relative);
+    match pair.kind {
+        Kind::File => {}
+        Kind::Symlink => {
+            let targets = (std::fs::read_link(&src), std::fs::read_link(&dst));
+            return match targets {
+                (Ok(src_target), Ok(dst_target)) if src_target == dst_target => Ok((None, 0)),
+                (Ok(_), Ok(_)) => Ok((Some(Difference::Target), 0)),
+                (Err(err), _) => Err(CopyError::MetadataFailed {
+                    path: src,
+                    source: err,
+                }),
+                (_, Err(err)) => Err(CopyError::MetadataFailed {
+                    path: dst,
+                    source: err,
+                }),
+            };
+        }
+        Kind::Directory | Kind::Special => return Ok((None, 0)),
+    }
+
+    if pair.src.len() != pair.dst.len() {
+        return Ok((Some(Difference::Size), 0));
+    }
+
+    if !opts.checksum {
+        let modified = match (pair.src.modified(), pair.dst.modified()) {
+            (Ok(src_modified), Ok(dst_modified)) => src_modified
+                .duration_since(dst_modified)
+                .or_else(|_| dst_modified.duration_since(src_modified))
+                .unwrap_or(Duration::MAX),
+            // Without times to go on, there's nothing to say they differ.
+            _ => Duration::ZERO,
+        };
+        return Ok((
+            (modified > opts.modify_window).then_some(Difference::Modified),
+            0,
+        ));
+    }
+
+    let (src_digest, src_read) =
+        hash::hash_file(&src, opts.hash, buffer).map_err(|err| CopyError::HashFailed {
+            path: src,
+            source: err,
+        })?;
+    let (dst_digest, dst_read) =
+        hash::hash_file(&dst, opts.hash, buffer).map_err(|err| CopyError::HashFailed {
+            path: dst,
+            source: err,
+        })?;
+    Ok((
+        (src_digest != dst_digest).then_some(Difference::Contents),
+        src_read + dst_read,
+    ))
+}
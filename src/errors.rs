@@ -1,14 +1,262 @@
-use std::path::PathBuf;
+use std::{
+    any::Any,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use byte_unit::Byte;
+
+use crate::{paths, stats::Accumulator};
 
 pub enum CopyError {
     NotFaster,
     SourceNotFound(PathBuf),
-    CannotOverwrite(PathBuf),
-    DirectoryCreationFailed(String),
-    AccessDenied((PathBuf, PathBuf)),
+    NoMatches(PathBuf),
+    NestedPaths(PathBuf, PathBuf),
+    DestinationMissing(PathBuf),
+    DestinationNotDirectory(PathBuf),
+    /// The same directory was given as more than one destination.
+    DuplicateDestination(PathBuf),
+    /// Two of the sources a pattern matched would be copied to the same `name` under the destination.
+    SourceNameCollision {
+        name: OsString,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    /// With `--also-to`, the copy to this destination, as it was given, stopped with `source`. The copies to the
+    /// others carried on.
+    DestinationFailed {
+        dst: PathBuf,
+        source: Box<CopyError>,
+    },
+    SpecialFile(PathBuf),
+    CannotOverwrite {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    DirectoryCreationFailed {
+        path: PathBuf,
+        source: std::io::Error,
+        attempts: u32,
+    },
+    AccessDenied {
+        src: PathBuf,
+        dst: PathBuf,
+        source: std::io::Error,
+        attempts: u32,
+        partial_removed: bool,
+        read_only: bool,
+    },
+    /// Another process has `src` locked, so it couldn't be read.
+    Locked {
+        src: PathBuf,
+        source: std::io::Error,
+        attempts: u32,
+    },
+    MetadataFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    CopyFailed {
+        src: PathBuf,
+        dst: PathBuf,
+        source: std::io::Error,
+        attempts: u32,
+        partial_removed: bool,
+    },
+    SyncFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    BackupFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    VerifyFailed {
+        src: PathBuf,
+        dst: PathBuf,
+        src_digest: String,
+        dst_digest: String,
+    },
+    HashFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    DestinationFull {
+        dst: PathBuf,
+        remaining: u64,
+    },
+    InsufficientSpace {
+        needed: u64,
+        available: u64,
+    },
+    StateFileFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A `--save-file-list` couldn't be written, or a `--files-from` list couldn't be read.
+    FileListFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    SpoolFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A source file or directory that permission was denied to read.
+    Unreadable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    DeleteFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    CaseCollision {
+        src: PathBuf,
+        other: PathBuf,
+    },
+    OutsideSource {
+        path: PathBuf,
+        base: PathBuf,
+    },
+    SanitizeCollision {
+        src: PathBuf,
+        other: PathBuf,
+        relative: PathBuf,
+    },
+    /// A `--remap-regex` rule would move the source file or directory `src` to `relative`, which isn't inside the
+    /// destination.
+    RemappedOutside {
+        src: PathBuf,
+        relative: PathBuf,
+    },
+    ChecksumFileFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// `--watch` couldn't be told about changes to the source at `path`.
+    WatchFailed {
+        path: PathBuf,
+        source: notify::Error,
+    },
+    /// A worker thread panicked with this message instead of finishing its work.
+    WorkerPanicked {
+        thread_id: usize,
+        message: String,
+    },
+    /// `--check-path-length` found this many paths that would be too long at the destination.
+    PathsTooLong(usize),
+    /// This many files and directories have names that can't be used on Windows.
+    InvalidNames(usize),
+    Multiple(Vec<CopyError>),
+    /// `--diff` found this many differences. Not a failure as such, but it sets the exit code.
+    Differences(u64),
+    /// `--audit` found this many files copied that aren't at the destination as they were copied.
+    AuditFailed(u64),
+    /// With `--strict`, the copy finished but didn't copy everything it found. These are its stats.
+    Incomplete(Box<Accumulator>),
+    InvalidOptions(OptionsError),
     Other(String),
 }
 
+/// Why [`CopyOptions::validate`](crate::CopyOptions::validate) rejected a set of options.
+#[derive(Clone, PartialEq, Eq)]
+pub enum OptionsError {
+    /// The first option can't be set at the same time as the second.
+    Conflict(&'static str, &'static str),
+    /// The first option only works with the second set.
+    Requires(&'static str, &'static str),
+    /// The option only works on the platforms listed.
+    Unsupported(&'static str, &'static str),
+    /// The option has to be more than 0.
+    Zero(&'static str),
+    /// The option is a percentage, and has to be more than 0 and at most 100.
+    Percentage(&'static str),
+    /// The `sanitize_names` replacement is a name Windows can't use itself.
+    InvalidReplacement(String),
+    /// A `remaps` prefix rule, shown as it was given, with a path that isn't relative or goes up with `..`.
+    InvalidRemap(String),
+    /// There's no user with the name given for `owner`.
+    UnknownUser(String),
+    /// There's no group with the name given for `group`.
+    UnknownGroup(String),
+}
+
+impl CopyError {
+    /// The error for a worker thread that panicked, given the payload its join handle returned.
+    pub fn worker_panicked(thread_id: usize, payload: Box<dyn Any + Send>) -> Self {
+        // `panic!` with a format string carries a `String`, and with just a literal, a `&str`.
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => "unknown panic payload".to_string(),
+            },
+        };
+        Self::WorkerPanicked { thread_id, message }
+    }
+
+    /// Whether this is [`CopyError::Incomplete`], or is made up of copies to several destinations that all were.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            Self::Incomplete(_) => true,
+            Self::DestinationFailed { source, .. } => source.is_incomplete(),
+            Self::Multiple(errors) => errors.iter().all(Self::is_incomplete),
+            _ => false,
+        }
+    }
+
+    /// The file or directory this error is about, if there is one. For errors involving both a source and a
+    /// destination, this is the source.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::SourceNotFound(path) => Some(path),
+            Self::NoMatches(path) => Some(path),
+            Self::NestedPaths(src, _) => Some(src),
+            Self::DestinationMissing(path) => Some(path),
+            Self::DestinationNotDirectory(path) => Some(path),
+            Self::DuplicateDestination(path) => Some(path),
+            Self::SourceNameCollision { first, .. } => Some(first),
+            Self::DestinationFailed { source, .. } => source.path(),
+            Self::SpecialFile(path) => Some(path),
+            Self::CannotOverwrite { src, .. } => Some(src),
+            Self::DirectoryCreationFailed { path, .. } => Some(path),
+            Self::AccessDenied { src, .. } => Some(src),
+            Self::Locked { src, .. } => Some(src),
+            Self::MetadataFailed { path, .. } => Some(path),
+            Self::CopyFailed { src, .. } => Some(src),
+            Self::SyncFailed { path, .. } => Some(path),
+            Self::BackupFailed { path, .. } => Some(path),
+            Self::VerifyFailed { src, .. } => Some(src),
+            Self::HashFailed { path, .. } => Some(path),
+            Self::DestinationFull { dst, .. } => Some(dst),
+            Self::StateFileFailed { path, .. } => Some(path),
+            Self::FileListFailed { path, .. } => Some(path),
+            Self::SpoolFailed { path, .. } => Some(path),
+            Self::Unreadable { path, .. } => Some(path),
+            Self::DeleteFailed { path, .. } => Some(path),
+            Self::CaseCollision { src, .. } => Some(src),
+            Self::OutsideSource { path, .. } => Some(path),
+            Self::SanitizeCollision { src, .. } => Some(src),
+            Self::RemappedOutside { src, .. } => Some(src),
+            Self::ChecksumFileFailed { path, .. } => Some(path),
+            Self::WatchFailed { path, .. } => Some(path),
+            Self::NotFaster
+            | Self::InsufficientSpace { .. }
+            | Self::WorkerPanicked { .. }
+            | Self::PathsTooLong(_)
+            | Self::InvalidNames(_)
+            | Self::Multiple(_)
+            | Self::Differences(_)
+            | Self::AuditFailed(_)
+            | Self::Incomplete(_)
+            | Self::InvalidOptions(_)
+            | Self::Other(_) => None,
+        }
+    }
+}
+
 impl std::fmt::Debug for CopyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -18,20 +266,597 @@ impl std::fmt::Debug for CopyError {
             Self::SourceNotFound(path) => {
                 f.write_fmt(format_args!("Source path not found: {}", path.display()))
             }
-            Self::CannotOverwrite(path) => f.write_fmt(format_args!(
-                "Destination file already exists: {}",
+            Self::NoMatches(pattern) => {
+                f.write_fmt(format_args!("Nothing matches {}", pattern.display()))
+            }
+            Self::NestedPaths(src, dst) => f.write_fmt(format_args!(
+                "Source {} and destination {} are the same directory or one is inside the other",
+                src.display(),
+                dst.display()
+            )),
+            Self::DestinationMissing(path) => f.write_fmt(format_args!(
+                "Destination directory does not exist: {}",
                 path.display()
             )),
-            Self::DirectoryCreationFailed(error) => f.write_fmt(format_args!(
-                "Could not create destination directory: {}",
-                error
+            Self::DestinationNotDirectory(path) => f.write_fmt(format_args!(
+                "Destination exists but is not a directory: {}",
+                path.display()
+            )),
+            Self::DuplicateDestination(path) => f.write_fmt(format_args!(
+                "{} is given as a destination more than once",
+                path.display()
+            )),
+            Self::SourceNameCollision {
+                name,
+                first,
+                second,
+            } => f.write_fmt(format_args!(
+                "{} and {} would both be copied to {} in the destination. Use --per-source-dirs to copy each to a \
+                 directory of its own, or --source-name to name them",
+                first.display(),
+                second.display(),
+                name.to_string_lossy()
+            )),
+            Self::DestinationFailed { dst, source } => {
+                f.write_fmt(format_args!("Copy to {} failed: {:?}", dst.display(), source))
+            }
+            Self::SpecialFile(path) => f.write_fmt(format_args!(
+                "Source contains a FIFO, socket, or device, which can't be copied: {}",
+                path.display()
+            )),
+            Self::CannotOverwrite { src, dst } => f.write_fmt(format_args!(
+                "Destination file already exists: {} (copying from {})",
+                dst.display(),
+                src.display()
+            )),
+            Self::DirectoryCreationFailed {
+                path,
+                source,
+                attempts,
+            } => f.write_fmt(format_args!(
+                "Could not create destination directory {}: {}{}{}",
+                path.display(),
+                source,
+                name_note(path, source),
+                attempts_note(*attempts)
             )),
-            Self::AccessDenied((src_path, dst_path)) => f.write_fmt(format_args!(
-                "Access denied copying {} to {}",
-                src_path.display(),
-                dst_path.display()
+            Self::AccessDenied {
+                src,
+                dst,
+                source,
+                attempts,
+                partial_removed,
+                read_only,
+            } => f.write_fmt(format_args!(
+                "Access denied copying {} to {}: {}{}{}{}",
+                src.display(),
+                dst.display(),
+                source,
+                read_only_note(*read_only),
+                attempts_note(*attempts),
+                partial_note(*partial_removed)
+            )),
+            Self::Locked {
+                src,
+                source,
+                attempts,
+            } => f.write_fmt(format_args!(
+                "{} is locked by another process: {}{}",
+                src.display(),
+                source,
+                attempts_note(*attempts)
+            )),
+            Self::MetadataFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to read metadata for {}: {}",
+                path.display(),
+                source
+            )),
+            Self::CopyFailed {
+                src,
+                dst,
+                source,
+                attempts,
+                partial_removed,
+            } => f.write_fmt(format_args!(
+                "Error copying {} to {}: {}{}{}{}",
+                src.display(),
+                dst.display(),
+                source,
+                name_note(dst, source),
+                attempts_note(*attempts),
+                partial_note(*partial_removed)
+            )),
+            Self::SyncFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to sync {} to disk: {}",
+                path.display(),
+                source
+            )),
+            Self::BackupFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to back up {} before overwriting it: {}",
+                path.display(),
+                source
+            )),
+            Self::VerifyFailed {
+                src,
+                dst,
+                src_digest,
+                dst_digest,
+            } => f.write_fmt(format_args!(
+                "Verification failed: {} ({}) doesn't match {} ({})",
+                dst.display(),
+                dst_digest,
+                src.display(),
+                src_digest
+            )),
+            Self::HashFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to read {} to hash it: {}",
+                path.display(),
+                source
+            )),
+            Self::DestinationFull { dst, remaining } => f.write_fmt(format_args!(
+                "Destination is full copying {}, with {} bytes remaining to copy",
+                dst.display(),
+                remaining
+            )),
+            Self::InsufficientSpace { needed, available } => f.write_fmt(format_args!(
+                "Not enough space at the destination: {} needed, {} available. Use --no-space-check to copy anyway.",
+                Byte::from_bytes(*needed as u128).get_appropriate_unit(false),
+                Byte::from_bytes(*available as u128).get_appropriate_unit(false)
+            )),
+            Self::StateFileFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to open state file {}: {}",
+                path.display(),
+                source
+            )),
+            Self::FileListFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to use file list {}: {}",
+                path.display(),
+                source
+            )),
+            Self::SpoolFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to use spool file {}: {}",
+                path.display(),
+                source
+            )),
+            Self::Unreadable { path, source } => f.write_fmt(format_args!(
+                "Unable to read {}: {}",
+                path.display(),
+                source
+            )),
+            Self::DeleteFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to delete {}: {}",
+                path.display(),
+                source
+            )),
+            Self::CaseCollision { src, other } => f.write_fmt(format_args!(
+                "{} and {} would be copied to the same path, since the destination ignores case or its case is changed",
+                other.display(),
+                src.display()
+            )),
+            Self::OutsideSource { path, base } => f.write_fmt(format_args!(
+                "{} was found while searching {} but isn't inside it, so it can't be copied",
+                path.display(),
+                base.display()
+            )),
+            Self::SanitizeCollision {
+                src,
+                other,
+                relative,
+            } => f.write_fmt(format_args!(
+                "{} and {} would both be copied to {} once they're renamed for the destination",
+                other.display(),
+                src.display(),
+                relative.display()
+            )),
+            Self::RemappedOutside { src, relative } => f.write_fmt(format_args!(
+                "{} would be remapped to {:?}, which isn't inside the destination",
+                src.display(),
+                relative
+            )),
+            Self::ChecksumFileFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to use checksum file {}: {}",
+                path.display(),
+                source
+            )),
+            Self::WatchFailed { path, source } => f.write_fmt(format_args!(
+                "Unable to watch {} for changes: {}",
+                path.display(),
+                source
+            )),
+            Self::WorkerPanicked { thread_id, message } => f.write_fmt(format_args!(
+                "Thread {} panicked: {}",
+                thread_id, message
+            )),
+            Self::PathsTooLong(count) => f.write_fmt(format_args!(
+                "{} paths would be longer than MAX_PATH at the destination",
+                count
+            )),
+            Self::InvalidNames(count) => f.write_fmt(format_args!(
+                "{} names can't be used on Windows, use --sanitize-names to rename them",
+                count
+            )),
+            Self::Multiple(errors) => {
+                f.write_fmt(format_args!("{} errors occurred:", errors.len()))?;
+                for error in errors {
+                    f.write_fmt(format_args!("\n  {:?}", error))?;
+                }
+                Ok(())
+            }
+            Self::Differences(count) => f.write_fmt(format_args!(
+                "{} differences found between the source and destination",
+                count
             )),
+            Self::AuditFailed(count) => f.write_fmt(format_args!(
+                "The audit found {} files that aren't at the destination as they were copied",
+                count
+            )),
+            Self::Incomplete(accumulator) => {
+                f.write_str("The copy is incomplete")?;
+                let not_copied = accumulator.files_not_copied();
+                if not_copied > 0 {
+                    let reasons: Vec<String> = [
+                        (accumulator.file_count_failed, "failed"),
+                        (accumulator.file_count_unreadable, "couldn't be read"),
+                        (
+                            not_copied
+                                - accumulator.file_count_failed
+                                - accumulator.file_count_unreadable,
+                            "skipped",
+                        ),
+                    ]
+                    .into_iter()
+                    .filter(|(count, _)| *count > 0)
+                    .map(|(count, reason)| format!("{} {}", count, reason))
+                    .collect();
+                    f.write_fmt(format_args!(
+                        ": {} of {} files weren't copied ({})",
+                        not_copied,
+                        accumulator.file_count_found,
+                        reasons.join(", ")
+                    ))?;
+                }
+                if accumulator.file_count_special_skipped > 0 {
+                    f.write_fmt(format_args!(
+                        ", {} special files were skipped",
+                        accumulator.file_count_special_skipped
+                    ))?;
+                }
+                if accumulator.dir_count_unreadable > 0 {
+                    f.write_fmt(format_args!(
+                        ", {} directories couldn't be read",
+                        accumulator.dir_count_unreadable
+                    ))?;
+                }
+                Ok(())
+            }
+            Self::InvalidOptions(err) => err.fmt(f),
             Self::Other(msg) => f.write_fmt(format_args!("Error: {}", msg)),
         }
     }
 }
+
+impl From<OptionsError> for CopyError {
+    fn from(err: OptionsError) -> Self {
+        Self::InvalidOptions(err)
+    }
+}
+
+impl std::fmt::Debug for OptionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Conflict(option, other) => {
+                f.write_fmt(format_args!("{} can't be used with {}.", option, other))
+            }
+            Self::Requires(option, other) => f.write_fmt(format_args!(
+                "{} must be specified to use {}.",
+                other, option
+            )),
+            Self::Unsupported(option, platforms) => f.write_fmt(format_args!(
+                "{} is only supported on {}.",
+                option, platforms
+            )),
+            Self::Zero(option) => f.write_fmt(format_args!("{} must be more than 0.", option)),
+            Self::Percentage(option) => f.write_fmt(format_args!(
+                "{} must be more than 0 and at most 100.",
+                option
+            )),
+            Self::InvalidReplacement(replacement) => f.write_fmt(format_args!(
+                "The sanitize_names replacement {:?} isn't a name Windows can use itself.",
+                replacement
+            )),
+            Self::InvalidRemap(rule) => f.write_fmt(format_args!(
+                "The remap rule {} has to use relative paths that don't go up with `..`.",
+                rule
+            )),
+            Self::UnknownUser(name) => f.write_fmt(format_args!("There's no user named {}.", name)),
+            Self::UnknownGroup(name) => {
+                f.write_fmt(format_args!("There's no group named {}.", name))
+            }
+        }
+    }
+}
+
+/// Why the name at the end of `path` couldn't be created, if the filesystem rejected it as invalid and it's one
+/// Windows can't use, e.g. on NTFS or exFAT.
+fn name_note(path: &Path, source: &std::io::Error) -> String {
+    if !matches!(
+        source.kind(),
+        std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidFilename
+    ) {
+        return String::new();
+    }
+    match path.file_name().and_then(paths::windows_name_problem) {
+        Some(problem) => format!(" (the name {})", problem),
+        None => String::new(),
+    }
+}
+
+fn partial_note(partial_removed: bool) -> &'static str {
+    if partial_removed {
+        " (partial file removed)"
+    } else {
+        ""
+    }
+}
+
+pub fn read_only_note(read_only: bool) -> &'static str {
+    if read_only {
+        " (the destination is read-only, use --force to overwrite it)"
+    } else {
+        ""
+    }
+}
+
+fn attempts_note(attempts: u32) -> String {
+    if attempts > 1 {
+        format!(" (after {} attempts)", attempts)
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    fn file() -> PathBuf {
+        Path::new("some dir").join("file.txt")
+    }
+
+    fn other() -> PathBuf {
+        Path::new("other dir").join("other.txt")
+    }
+
+    fn io_error() -> io::Error {
+        io::Error::other("failed")
+    }
+
+    /// An error of every kind that's about paths, with the paths its message has to mention. The first is the one
+    /// [`CopyError::path`] returns.
+    fn with_paths() -> Vec<(CopyError, Vec<PathBuf>)> {
+        vec![
+            (CopyError::SourceNotFound(file()), vec![file()]),
+            (CopyError::NoMatches(file()), vec![file()]),
+            (
+                CopyError::NestedPaths(file(), other()),
+                vec![file(), other()],
+            ),
+            (CopyError::DestinationMissing(file()), vec![file()]),
+            (CopyError::DestinationNotDirectory(file()), vec![file()]),
+            (CopyError::DuplicateDestination(file()), vec![file()]),
+            (
+                CopyError::SourceNameCollision {
+                    name: "name".into(),
+                    first: file(),
+                    second: other(),
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::DestinationFailed {
+                    dst: other(),
+                    source: Box::new(CopyError::SourceNotFound(file())),
+                },
+                vec![file(), other()],
+            ),
+            (CopyError::SpecialFile(file()), vec![file()]),
+            (
+                CopyError::CannotOverwrite {
+                    src: file(),
+                    dst: other(),
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::DirectoryCreationFailed {
+                    path: file(),
+                    source: io_error(),
+                    attempts: 1,
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::AccessDenied {
+                    src: file(),
+                    dst: other(),
+                    source: io_error(),
+                    attempts: 1,
+                    partial_removed: false,
+                    read_only: false,
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::Locked {
+                    src: file(),
+                    source: io_error(),
+                    attempts: 1,
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::MetadataFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::CopyFailed {
+                    src: file(),
+                    dst: other(),
+                    source: io_error(),
+                    attempts: 1,
+                    partial_removed: false,
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::SyncFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::BackupFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::VerifyFailed {
+                    src: file(),
+                    dst: other(),
+                    src_digest: "1234".to_string(),
+                    dst_digest: "5678".to_string(),
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::HashFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::DestinationFull {
+                    dst: file(),
+                    remaining: 1024,
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::StateFileFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::FileListFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::SpoolFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::Unreadable {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::DeleteFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::CaseCollision {
+                    src: file(),
+                    other: other(),
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::OutsideSource {
+                    path: file(),
+                    base: other(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::SanitizeCollision {
+                    src: file(),
+                    other: other(),
+                    relative: PathBuf::from("file.txt"),
+                },
+                vec![file(), other()],
+            ),
+            (
+                CopyError::RemappedOutside {
+                    src: file(),
+                    relative: Path::new("..").join("file.txt"),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::ChecksumFileFailed {
+                    path: file(),
+                    source: io_error(),
+                },
+                vec![file()],
+            ),
+            (
+                CopyError::WatchFailed {
+                    path: file(),
+                    source: notify::Error::generic("failed"),
+                },
+                vec![file()],
+            ),
+        ]
+    }
+
+    #[test]
+    fn messages_mention_their_paths() {
+        for (err, paths) in with_paths() {
+            let message = format!("{:?}", err);
+            for path in paths {
+                assert!(
+                    message.contains(&path.display().to_string()),
+                    "{:?} isn't in {:?}",
+                    path,
+                    message
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn path_is_the_source() {
+        for (err, paths) in with_paths() {
+            assert_eq!(err.path(), Some(paths[0].as_path()), "{:?}", err);
+        }
+        for err in [
+            CopyError::NotFaster,
+            CopyError::Multiple(vec![CopyError::SourceNotFound(file())]),
+            CopyError::Other("failed".to_string()),
+        ] {
+            assert_eq!(err.path(), None, "{:?}", err);
+        }
+    }
+}
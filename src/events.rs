@@ -0,0 +1,203 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    time::Duration,
+};
+
+use crate::{
+    cancel::CancellationToken,
+    errors::CopyError,
+    options::CopyOptions,
+    report::{CopyReport, ReportedError},
+    reporter::{Outcome, ProgressReporter, SkipReason},
+    stats::Accumulator,
+};
+
+/// How many events can be waiting in the channel from [`copy_tree_with_events`] before the copy waits for them to be
+/// received.
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened during a copy started with [`copy_tree_with_events`]. Each one matches a
+/// [`ProgressReporter`] method, apart from `Done`.
+#[derive(Debug)]
+pub enum CopyEvent {
+    SearchProgress(Accumulator),
+    CopyProgress(Accumulator),
+    FileProgress {
+        path: PathBuf,
+        written: u64,
+        len: u64,
+    },
+    Active {
+        thread_id: usize,
+        path: PathBuf,
+        elapsed: Duration,
+    },
+    FileCopied {
+        path: PathBuf,
+        bytes: u64,
+    },
+    FileSkipped {
+        path: PathBuf,
+        reason: SkipReason,
+    },
+    DirCreated(PathBuf),
+    /// Where a file that was copied ended up, with `print0`.
+    CopiedTo(PathBuf),
+    /// An item that was neither copied nor skipped, like one chunk of a file split with `split_large_files`.
+    ItemDone(PathBuf),
+    Error(ReportedError),
+    Message(String),
+    Output(String),
+    Summary(CopyReport),
+    /// The copy is over, and this is what [`copy_tree`](crate::copy_tree) would have returned. Always the last event.
+    Done(Result<CopyReport, CopyError>),
+}
+
+/// Like [`copy_tree`](crate::copy_tree), but copying on a thread of its own and sending what happens to the
+/// receiver returned.
+///
+/// The channel holds [`EVENT_CAPACITY`] events. Once it's full, the copy waits for them to be received, so a slow
+/// receiver slows the copy down rather than piling events up in memory. Dropping the receiver cancels the copy with
+/// `opts.cancellation`.
+pub fn copy_tree_with_events(src: &Path, dst: &Path, opts: &CopyOptions) -> Receiver<CopyEvent> {
+    let (sender, receiver) = sync_channel(EVENT_CAPACITY);
+    let src = src.to_path_buf();
+    let dst = dst.to_path_buf();
+    let opts = opts.clone();
+    std::thread::spawn(move || {
+        let mut reporter = EventReporter::new(sender, opts.cancellation.clone());
+        let result = crate::copy_tree_with_reporter(&src, &dst, &opts, &mut reporter);
+        reporter.send(CopyEvent::Done(result));
+    });
+    receiver
+}
+
+/// Pass every event from `receiver` on to the matching method of `reporter`, returning the result from the `Done`
+/// event at the end.
+pub fn forward_events(
+    receiver: Receiver<CopyEvent>,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    for event in receiver {
+        if let Some(result) = forward_event(event, reporter) {
+            return result;
+        }
+    }
+    Err(CopyError::Other(
+        "The copy thread exited without finishing".to_string(),
+    ))
+}
+
+/// Pass `event` on to the matching method of `reporter`, or for `Done`, return the result it carries.
+pub(crate) fn forward_event(
+    event: CopyEvent,
+    reporter: &mut dyn ProgressReporter,
+) -> Option<Result<CopyReport, CopyError>> {
+    match event {
+        CopyEvent::SearchProgress(accumulator) => reporter.on_search_progress(&accumulator),
+        CopyEvent::CopyProgress(accumulator) => reporter.on_copy_progress(&accumulator),
+        CopyEvent::FileProgress { path, written, len } => {
+            reporter.on_file_progress(&path, written, len)
+        }
+        CopyEvent::Active {
+            thread_id,
+            path,
+            elapsed,
+        } => reporter.on_active(thread_id, &path, elapsed),
+        CopyEvent::FileCopied { path, bytes } => {
+            reporter.on_file_done(&path, Outcome::Copied(bytes))
+        }
+        CopyEvent::FileSkipped { path, reason } => {
+            reporter.on_file_done(&path, Outcome::Skipped(reason))
+        }
+        CopyEvent::DirCreated(path) => reporter.on_file_done(&path, Outcome::DirCreated),
+        CopyEvent::CopiedTo(dst) => reporter.on_copied_to(&dst),
+        CopyEvent::ItemDone(path) => reporter.on_file_done(&path, Outcome::Done),
+        CopyEvent::Error(err) => reporter.on_error(&err),
+        CopyEvent::Message(message) => reporter.on_message(&message),
+        CopyEvent::Output(line) => reporter.on_output(&line),
+        CopyEvent::Summary(report) => reporter.on_summary(&report),
+        CopyEvent::Done(result) => return Some(result),
+    }
+    None
+}
+
+/// Sends everything it's told down a channel, like the one from [`copy_tree_with_events`].
+pub(crate) struct EventReporter {
+    sender: SyncSender<CopyEvent>,
+    cancellation: CancellationToken,
+}
+
+impl EventReporter {
+    /// A reporter sending to `sender`, which cancels the copy with `cancellation` if nobody's receiving any more.
+    pub(crate) fn new(sender: SyncSender<CopyEvent>, cancellation: CancellationToken) -> Self {
+        Self {
+            sender,
+            cancellation,
+        }
+    }
+
+    fn send(&self, event: CopyEvent) {
+        // Nobody's listening any more, so there's no point carrying on.
+        if self.sender.send(event).is_err() {
+            self.cancellation.cancel();
+        }
+    }
+}
+
+impl ProgressReporter for EventReporter {
+    fn on_search_progress(&mut self, accumulator: &Accumulator) {
+        self.send(CopyEvent::SearchProgress(accumulator.clone()));
+    }
+
+    fn on_copy_progress(&mut self, accumulator: &Accumulator) {
+        self.send(CopyEvent::CopyProgress(accumulator.clone()));
+    }
+
+    fn on_file_progress(&mut self, path: &Path, written: u64, len: u64) {
+        self.send(CopyEvent::FileProgress {
+            path: path.to_path_buf(),
+            written,
+            len,
+        });
+    }
+
+    fn on_active(&mut self, thread_id: usize, path: &Path, elapsed: Duration) {
+        self.send(CopyEvent::Active {
+            thread_id,
+            path: path.to_path_buf(),
+            elapsed,
+        });
+    }
+
+    fn on_file_done(&mut self, path: &Path, outcome: Outcome) {
+        let path = path.to_path_buf();
+        self.send(match outcome {
+            Outcome::Copied(bytes) => CopyEvent::FileCopied { path, bytes },
+            Outcome::Skipped(reason) => CopyEvent::FileSkipped { path, reason },
+            Outcome::DirCreated => CopyEvent::DirCreated(path),
+            Outcome::Done => CopyEvent::ItemDone(path),
+        });
+    }
+
+    fn on_copied_to(&mut self, dst: &Path) {
+        self.send(CopyEvent::CopiedTo(dst.to_path_buf()));
+    }
+
+    fn on_error(&mut self, err: &ReportedError) {
+        self.send(CopyEvent::Error(err.clone()));
+    }
+
+    fn on_message(&mut self, message: &str) {
+        self.send(CopyEvent::Message(message.to_string()));
+    }
+
+    fn on_output(&mut self, line: &str) {
+        self.send(CopyEvent::Output(line.to_string()));
+    }
+
+    fn on_summary(&mut self, report: &CopyReport) {
+        self.send(CopyEvent::Summary(report.clone()));
+    }
+}
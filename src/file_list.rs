@@ -0,0 +1,213 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    errors::CopyError,
+    options::CopyOptions,
+    queue::{EntryKind, Queue},
+    state::{path_from_bytes, path_to_bytes},
+    stats::Accumulator,
+    SearchResult,
+};
+
+/// The first line of every file list, which [`load`] checks for.
+const HEADER: &str = "# ninecopy file list";
+
+/// Writes what the search found to a `--save-file-list`, so a later run can read it back with `--files-from` instead
+/// of searching again.
+///
+/// Each entry is a line of its kind (`f`, `d`, `l`, or `s`), its size, its modified time as seconds and nanoseconds
+/// since the Unix epoch, and its path relative to the source, separated by tabs. Backslashes and newlines in paths are
+/// escaped with a backslash. The modified time is `-` if it isn't known.
+///
+/// A list that's never finished, because the search failed or was cancelled, is removed rather than left with only
+/// part of the source in it.
+pub(crate) struct Writer {
+    path: PathBuf,
+    /// Taken once the list is finished.
+    writer: Option<BufWriter<File>>,
+}
+
+impl Writer {
+    pub(crate) fn create(path: &Path) -> Result<Self, CopyError> {
+        let failed = |source| CopyError::FileListFailed {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut writer = BufWriter::new(File::create(path).map_err(failed)?);
+        writeln!(writer, "{}", HEADER).map_err(failed)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: Some(writer),
+        })
+    }
+
+    fn failed(&self, source: std::io::Error) -> CopyError {
+        CopyError::FileListFailed {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    /// Add `item`, which the search found in `src`.
+    pub(crate) fn record(&mut self, item: &SearchResult, src: &Path) -> Result<(), CopyError> {
+        let (kind, info) = match item {
+            SearchResult::File(info) => ('f', info),
+            SearchResult::Directory(info) => ('d', info),
+            SearchResult::Symlink(info) => ('l', info),
+            SearchResult::Special(info) => ('s', info),
+            SearchResult::Unreadable(..) | SearchResult::Done(_) => return Ok(()),
+        };
+        let Ok(relative) = info.path.strip_prefix(src) else {
+            return Ok(());
+        };
+        let modified = match info
+            .metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        {
+            Some(since) => format!("{}.{:09}", since.as_secs(), since.subsec_nanos()),
+            None => "-".to_string(),
+        };
+        let mut line = format!("{}\t{}\t{}\t", kind, info.metadata.len(), modified).into_bytes();
+        line.extend(escape(&path_to_bytes(relative)));
+        line.push(b'\n');
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        writer.write_all(&line).map_err(|err| self.failed(err))
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), CopyError> {
+        match self.writer.take() {
+            Some(mut writer) => writer.flush().map_err(|err| self.failed(err)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Read the file list at `path`, written by an earlier run's [`Writer`], into a queue of everything in it under
+/// `opts.src`, counting what was found in `accumulator` from the sizes it lists.
+///
+/// Nothing is looked at until it's taken from the queue, so anything that's gone since the list was saved is skipped
+/// then, the same as a file that's removed during a copy. The filters were applied when the list was saved, and
+/// aren't applied again.
+pub(crate) fn load(
+    path: &Path,
+    opts: &CopyOptions,
+    accumulator: &mut Accumulator,
+) -> Result<Queue, CopyError> {
+    let failed = |source| CopyError::FileListFailed {
+        path: path.to_path_buf(),
+        source,
+    };
+    let invalid = |line: usize| {
+        failed(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("line {} isn't a file list entry", line),
+        ))
+    };
+    let mut reader = BufReader::new(File::open(path).map_err(failed)?);
+    let mut queue = Queue::new(opts.spool_dir.as_deref(), 0, Some(0));
+    let mut line = Vec::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).map_err(failed)? == 0 {
+            break;
+        }
+        number += 1;
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        if number == 1 {
+            if line != HEADER.as_bytes() {
+                return Err(invalid(number));
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.splitn(4, |byte| *byte == b'\t');
+        let (Some(kind), Some(len), Some(_modified), Some(escaped)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid(number));
+        };
+        let kind = match kind {
+            b"f" => EntryKind::File,
+            b"d" => EntryKind::Directory,
+            b"l" => EntryKind::Symlink,
+            b"s" => EntryKind::Special,
+            _ => return Err(invalid(number)),
+        };
+        let len: u64 = std::str::from_utf8(len)
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| invalid(number))?;
+        let relative = unescape(escaped)
+            .map(|bytes| path_from_bytes(&bytes))
+            .ok_or_else(|| invalid(number))?;
+        if relative.is_absolute()
+            || relative
+                .components()
+                .any(|component| component == std::path::Component::ParentDir)
+        {
+            return Err(invalid(number));
+        }
+
+        match kind {
+            EntryKind::File => *accumulator += Accumulator::found(1, len),
+            EntryKind::Symlink => *accumulator += Accumulator::found(1, 0),
+            EntryKind::Directory | EntryKind::Special => {}
+        }
+        queue.push_record(kind, len, &opts.src.join(relative))?;
+    }
+    queue.finish()?;
+    Ok(queue)
+}
+
+/// `path` with backslashes and newlines escaped, so it fits on one line.
+fn escape(path: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(path.len());
+    for &byte in path {
+        match byte {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            byte => escaped.push(byte),
+        }
+    }
+    escaped
+}
+
+/// The path [`escape`] made `escaped` from, or `None` if it has an escape `escape` wouldn't have written.
+fn unescape(escaped: &[u8]) -> Option<Vec<u8>> {
+    let mut path = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        path.push(match byte {
+            b'\\' => match bytes.next()? {
+                b'\\' => b'\\',
+                b'n' => b'\n',
+                _ => return None,
+            },
+            byte => byte,
+        });
+    }
+    Some(path)
+}
@@ -0,0 +1,61 @@
+use std::{fs::Metadata, path::Path};
+
+/// Decides which files and directories in the source are copied, added to
+/// [`CopyOptions::filters`](crate::CopyOptions::filters).
+///
+/// Filters are called from the search threads, several at once, so they have to be `Send` and `Sync`. Any function
+/// or closure with the same signature as [`include`](FileFilter::include) is a filter too.
+///
+/// ```
+/// use std::{fs::Metadata, path::Path};
+///
+/// /// Only copies the files that hash into this shard.
+/// struct Shard(u64, u64);
+///
+/// impl ninecopy::FileFilter for Shard {
+///     fn include(&self, rel_path: &Path, metadata: &Metadata) -> bool {
+///         use std::hash::{DefaultHasher, Hash, Hasher};
+///
+///         let mut hasher = DefaultHasher::new();
+///         rel_path.hash(&mut hasher);
+///         metadata.is_dir() || hasher.finish() % self.1 == self.0
+///     }
+/// }
+///
+/// let opts = ninecopy::CopyOptions::builder()
+///     .filter(Shard(0, 2))
+///     .filter(|_: &Path, metadata: &Metadata| metadata.len() < 1024 * 1024)
+///     .build()
+///     .unwrap();
+/// ```
+pub trait FileFilter: Send + Sync {
+    /// Whether to copy what's at `rel_path`, relative to the source. Directories that aren't included are skipped
+    /// along with everything in them.
+    fn include(&self, rel_path: &Path, metadata: &Metadata) -> bool;
+}
+
+impl<F> FileFilter for F
+where
+    F: Fn(&Path, &Metadata) -> bool + Send + Sync,
+{
+    fn include(&self, rel_path: &Path, metadata: &Metadata) -> bool {
+        self(rel_path, metadata)
+    }
+}
+
+impl std::fmt::Debug for dyn FileFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FileFilter")
+    }
+}
+
+/// Whether every one of `filters` includes what's at `rel_path`.
+pub(crate) fn included(
+    filters: &[std::sync::Arc<dyn FileFilter>],
+    rel_path: &Path,
+    metadata: &Metadata,
+) -> bool {
+    filters
+        .iter()
+        .all(|filter| filter.include(rel_path, metadata))
+}
@@ -0,0 +1,86 @@
+use std::{
+    fmt,
+    io::{ErrorKind, Read},
+    path::Path,
+};
+
+use sha2::Digest as _;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{
+    copy::{self, CopyBuffer},
+    options::HashAlgorithm,
+};
+
+/// A file's digest, printed as lowercase hex the way `sha256sum` and friends print them.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Digest(Vec<u8>);
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A running hash in whichever algorithm `--hash` picked.
+pub enum Hasher {
+    Xxh3(Box<Xxh3>),
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::new(Xxh3::new())),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Xxh3(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Digest {
+        Digest(match self {
+            // Big-endian is XXH128's canonical form, so digests match `xxhsum -H2`.
+            Self::Xxh3(hasher) => hasher.digest128().to_be_bytes().to_vec(),
+            Self::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+        })
+    }
+}
+
+/// Hash the contents of the file at `path`, reading it through `buffer` the same way files are copied in chunks.
+/// Returns the digest and the number of bytes read.
+pub fn hash_file(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    buffer: &mut CopyBuffer,
+) -> std::io::Result<(Digest, u64)> {
+    let mut file = copy::open_sequential(path)?;
+    let buffer = buffer.get();
+    let mut hasher = Hasher::new(algorithm);
+    let mut read_total = 0;
+    loop {
+        let read = match file.read(buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buffer[..read]);
+        read_total += read as u64;
+    }
+    Ok((hasher.finish(), read_total))
+}
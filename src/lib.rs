@@ -0,0 +1,4707 @@
+//! The engine behind `ninecopy`: [`copy_tree`] copies a directory tree the way the command line tool does, with a
+//! [`CopyOptions`] field for each of its flags.
+
+/// Like `println!`, but passed on to the [`ProgressReporter`] instead of printed.
+macro_rules! message {
+    ($($arg:tt)*) => {
+        $crate::reporter::message(format!($($arg)*))
+    };
+}
+
+mod audit;
+mod cancel;
+mod cloud;
+mod copy;
+mod delete;
+mod diff;
+mod errors;
+mod events;
+mod file_list;
+mod filter;
+mod hash;
+mod links;
+mod manifest;
+mod memory;
+pub mod options;
+mod owner;
+mod paths;
+mod preserve;
+mod priority;
+mod queue;
+mod renames;
+mod repeat;
+mod report;
+pub mod reporter;
+pub mod signals;
+mod sources;
+mod space;
+mod sparse;
+mod split;
+mod state;
+mod stats;
+mod throttle;
+mod tune;
+mod watch;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    ffi::{OsStr, OsString},
+    fs::{Metadata, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use audit::Discrepancy;
+use byte_unit::Byte;
+use copy::CopyBuffer;
+use crossbeam_channel::{select, tick, unbounded, Receiver, SendError, Sender};
+use diff::{Difference, Kind};
+use events::EventReporter;
+use hash::Digest;
+use links::{FollowedLinks, HardLinks};
+use memory::Budget;
+use options::{CaseTransform, FsyncMode, LinkMode, Normalization, Policy};
+use queue::{EntryKind, Popped, Queue};
+use split::{Chunk, SplitFile};
+use state::StateFile;
+use throttle::Throttle;
+use tune::{Adjustment, Tuner};
+
+pub use cancel::CancellationToken;
+pub use errors::{CopyError, OptionsError};
+pub use events::{copy_tree_with_events, forward_events, CopyEvent, EVENT_CAPACITY};
+pub use filter::FileFilter;
+pub use options::CopyOptions;
+pub use owner::{group_id, user_id};
+pub use report::{CopyReport, ReportedError, Skipped};
+pub use reporter::{
+    ConsoleReporter, NullReporter, Outcome, ProgressReporter, SkipReason, StatusOutput,
+};
+pub use sources::{check_distinct_names, expand_glob, per_source_names};
+pub use stats::{Accumulator, PolicyCount};
+
+/// Copy everything in `src` into `dst`, creating `dst` if it doesn't exist, and return what was done.
+///
+/// If `src` is a single file, it's copied into `dst` if that's a directory, and to `dst` as a file name otherwise.
+///
+/// With `diff`, `verify_only`, or `check_manifest` set, nothing is copied and the destination is checked instead.
+pub fn copy_tree(src: &Path, dst: &Path, opts: &CopyOptions) -> Result<CopyReport, CopyError> {
+    copy_tree_with_reporter(src, dst, opts, &mut NullReporter)
+}
+
+/// Like [`copy_tree`], telling `reporter` what's happening as it goes.
+pub fn copy_tree_with_reporter(
+    src: &Path,
+    dst: &Path,
+    opts: &CopyOptions,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    let mut recorder = reporter::Recorder::new(reporter);
+    let result = run(src, dst, opts, &mut recorder);
+    reporter::deliver(&mut recorder);
+    result.map(|report| recorder.fill(report))
+}
+
+fn run(
+    src: &Path,
+    dst: &Path,
+    opts: &CopyOptions,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    let started = Instant::now();
+    let mut cli = opts.clone();
+    cli.src = src.to_path_buf();
+    cli.dst = dst.to_path_buf();
+    if !cli.src.exists() {
+        return Err(CopyError::SourceNotFound(cli.src));
+    }
+
+    if !cli.src.is_dir() && cli.strict_dir_only {
+        return Err(CopyError::NotFaster);
+    }
+
+    cli.validate()?;
+
+    let single_file = !cli.src.is_dir() && cli.src.file_name().is_some();
+    if single_file {
+        for (option, set) in [
+            ("delete", cli.delete),
+            ("diff", cli.diff),
+            ("watch", cli.watch),
+            ("dirs_only", cli.dirs_only),
+            ("save_file_list", cli.save_file_list.is_some()),
+            ("files_from", cli.files_from.is_some()),
+        ] {
+            if set {
+                return Err(OptionsError::Conflict(option, "a single file source").into());
+            }
+        }
+        // Every other thread would sit idle while one copies the file.
+        cli.split_large_files
+            .get_or_insert(SINGLE_FILE_SPLIT_THRESHOLD);
+    }
+
+    // Canonicalizing drops the trailing separator, so this has to look at `src` as it was given.
+    let nested_name =
+        match !single_file && cli.rsync_paths && !paths::has_trailing_separator(&cli.src) {
+            true => cli.src.file_name().map(|name| name.to_os_string()),
+            false => None,
+        };
+
+    if let Some(error_log) = cli.error_log.as_deref() {
+        if let Err(err) = std::fs::File::create(error_log) {
+            message!(
+                "Warning: unable to create error log {}: {}",
+                error_log.display(),
+                err
+            );
+        }
+    }
+
+    let mut destinations: Vec<Destination> = Vec::with_capacity(1 + cli.also_to.len());
+    for dst in std::iter::once(&cli.dst).chain(&cli.also_to) {
+        let destination = destination(&cli, dst, nested_name.as_deref())?;
+        if destinations.iter().any(|other| {
+            other.dst == destination.dst && other.single_file == destination.single_file
+        }) {
+            return Err(CopyError::DuplicateDestination(destination.display));
+        }
+        destinations.push(destination);
+    }
+
+    // See `destination` for why these are canonical.
+    cli.src_display = cli.src.clone();
+    for path in [Some(&mut cli.src), cli.backup_dir.as_mut()]
+        .into_iter()
+        .flatten()
+    {
+        *path = paths::canonicalize_lenient(path).map_err(|err| CopyError::MetadataFailed {
+            path: path.clone(),
+            source: err,
+        })?;
+    }
+    // Anything only done once, like the search, goes by the first destination.
+    let mut cli = destinations[0].options(&cli);
+
+    if let Some(mode) = cli.acls {
+        if let Err(err) = preserve::enable_acl_privileges(mode) {
+            return Err(CopyError::Other(format!(
+                "--acls=all needs to run as an administrator: {}",
+                err
+            )));
+        }
+    }
+
+    cli.apply_archive();
+
+    if let (Some(percent), None) = (cli.verify_sample, cli.verify_sample_seed) {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64)
+            ^ std::process::id() as u64;
+        cli.verify_sample_seed = Some(seed);
+        // The seed is all it takes to pick the same files again.
+        message!(
+            "Verifying a {}% sample of files, with --verify-sample-seed={}",
+            percent,
+            seed
+        );
+    }
+
+    if cli.preserve_owner && !preserve::can_set_owner() {
+        // Archive mode only preserves ownership when it can, like rsync.
+        if !cli.archive {
+            message!(
+                "Warning: ownership can only be preserved when running as root, so it won't be."
+            );
+        }
+        cli.preserve_owner = false;
+    }
+
+    let threads = cli.threads.unwrap_or_else(|| match cli.background {
+        // Leave most of the machine to everything else.
+        true => (default_thread_count() / 4).max(2),
+        false => default_thread_count(),
+    });
+
+    // Each destination has copy threads of its own.
+    let copy_threads = cli.copy_threads(threads) * destinations.len();
+    let budget = cli
+        .max_memory
+        .map(|max| Budget::new(max, copy_threads, cli.buffer_size));
+    if let Some(budget) = budget {
+        if budget.buffer_size < cli.buffer_size {
+            message!(
+                "Using {} copy buffers to stay within --max-memory",
+                Byte::from_bytes(budget.buffer_size as u128).get_appropriate_unit(true)
+            );
+            cli.buffer_size = budget.buffer_size;
+        }
+        if budget.queue_bytes == 0 {
+            message!(
+                "Warning: --max-memory is too small for the copy buffers of {} threads, so it will be exceeded",
+                copy_threads
+            );
+        }
+        // The queue is spooled once it's used up its share.
+        cli.spool_dir.get_or_insert_with(std::env::temp_dir);
+    }
+    if cli.files_from.is_some() {
+        // What's listed is spooled as it's read, and only looked at as it's copied.
+        cli.spool_dir.get_or_insert_with(std::env::temp_dir);
+    }
+
+    let opts = Arc::new(cli);
+    let destinations: Vec<(Arc<CopyOptions>, PathBuf)> = destinations
+        .into_iter()
+        .map(|destination| (Arc::new(destination.options(&opts)), destination.absolute))
+        .collect();
+
+    for (opts, _) in &destinations {
+        if opts.atomic
+            && !opts.verify_only
+            && opts.check_manifest.is_none()
+            && !opts.diff
+            && opts.dst.is_dir()
+        {
+            match copy::remove_partials(&opts.dst) {
+                0 => {}
+                removed => message!(
+                    "Removed {} partial files left behind by a previous run",
+                    removed
+                ),
+            }
+        }
+    }
+
+    let cancelled = opts.cancellation.clone();
+
+    if opts.background {
+        priority::lower();
+    }
+
+    if let Some(manifest) = opts.check_manifest.as_deref() {
+        let start = Instant::now();
+        let accumulator = check_manifest(manifest, &opts, threads, &cancelled)?;
+        return Ok(CopyReport {
+            accumulator,
+            threads,
+            copy_time: start.elapsed(),
+            total_time: started.elapsed(),
+            cancelled: cancelled.is_cancelled(),
+            ..Default::default()
+        });
+    }
+    if opts.diff {
+        let start = Instant::now();
+        let accumulator = diff_trees(opts.clone(), threads, &cancelled, reporter)?;
+        return Ok(CopyReport {
+            accumulator,
+            threads,
+            copy_time: start.elapsed(),
+            total_time: started.elapsed(),
+            cancelled: cancelled.is_cancelled(),
+            ..Default::default()
+        });
+    }
+
+    let dst_display: Vec<String> = destinations
+        .iter()
+        .map(|(opts, _)| destination_display(opts).display().to_string())
+        .collect();
+    message!(
+        "Copying {} to {} with {} threads{}",
+        opts.src_display.display(),
+        dst_display.join(", "),
+        threads,
+        match destinations.len() {
+            1 => "",
+            _ => " each",
+        }
+    );
+    if opts.auto_tune {
+        message!(
+            "Auto-tuning the number of copy threads, up to {}",
+            opts.copy_threads(threads)
+        );
+    }
+
+    // Started before the search, so nothing that changes during the copy is missed.
+    let watcher = match opts.watch {
+        true => Some(watch::Watcher::new(&opts.src)?),
+        false => None,
+    };
+
+    let queue_bytes = budget.map(|budget| budget.queue_bytes);
+    let report = search_and_copy(
+        &opts,
+        &destinations,
+        threads,
+        queue_bytes,
+        started,
+        reporter,
+    );
+    if let Some(watcher) = watcher {
+        let report = report?;
+        let [(opts, absolute_dst)] = &destinations[..] else {
+            unreachable!("--watch is only allowed with one destination");
+        };
+        return match report.cancelled {
+            true => Ok(report),
+            false => watch::watch(
+                watcher,
+                report,
+                opts.clone(),
+                absolute_dst,
+                threads,
+                started,
+                reporter,
+            ),
+        };
+    }
+    match opts.repeat {
+        Some(_) => repeat::repeat(
+            report,
+            &opts,
+            &destinations,
+            threads,
+            queue_bytes,
+            started,
+            reporter,
+        ),
+        None => report,
+    }
+}
+
+/// Search the source and copy everything found to each of `destinations`.
+fn search_and_copy(
+    opts: &Arc<CopyOptions>,
+    destinations: &[(Arc<CopyOptions>, PathBuf)],
+    threads: usize,
+    queue_bytes: Option<u64>,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    let cancelled = &opts.cancellation;
+    let mut accumulator = Accumulator::default();
+    // With `--repeat`, it may have gone since the last copy, e.g. a drive that was unmounted.
+    if !opts.src.exists() {
+        return Err(CopyError::SourceNotFound(opts.src.clone()));
+    }
+
+    // If this list is very large, it could use quite a lot of memory, unless it's spooled to disk with `--spool-dir`.
+    // TODO: Run search and copy in parallel.
+    let search_start = Instant::now();
+    let queue = match (opts.single_file.is_some(), opts.files_from.as_deref()) {
+        (true, _) => single_file_queue(opts, &mut accumulator)?,
+        (false, Some(list)) => {
+            let queue = file_list::load(list, opts, &mut accumulator)?;
+            message!(
+                "Read {} files ({}) from {}",
+                accumulator.file_count_found,
+                Byte::from_bytes(accumulator.byte_count_found as u128).get_appropriate_unit(false),
+                list.display()
+            );
+            queue
+        }
+        (false, None) => search_dir(
+            &opts.src,
+            &opts.src,
+            &mut accumulator,
+            threads,
+            opts.clone(),
+            queue_bytes,
+            cancelled,
+            reporter,
+        )?,
+    };
+    let search_time = search_start.elapsed();
+    if cancelled.is_cancelled() {
+        return Ok(CopyReport {
+            accumulator,
+            threads,
+            search_time,
+            total_time: started.elapsed(),
+            cancelled: true,
+            ..Default::default()
+        });
+    }
+
+    if opts.fail_on_special {
+        for entry in queue.entries() {
+            let (kind, path) = entry?;
+            if kind == EntryKind::Special {
+                return Err(CopyError::SpecialFile(path.into_owned()));
+            }
+        }
+    }
+
+    if cfg!(windows)
+        || opts.sanitize_names.is_some()
+        || opts.normalize != Normalization::None
+        || !opts.remaps.is_empty()
+    {
+        check_names(&queue, opts)?;
+    }
+
+    let [(opts, absolute_dst)] = destinations else {
+        return copy_to_each(
+            queue,
+            accumulator,
+            destinations,
+            threads,
+            search_time,
+            started,
+            reporter,
+        );
+    };
+    copy_to(
+        queue,
+        accumulator,
+        absolute_dst,
+        threads,
+        opts.clone(),
+        search_time,
+        started,
+        reporter,
+    )
+}
+
+/// Where one of the destinations of a copy is, worked out by [`destination`].
+struct Destination {
+    /// Canonical.
+    dst: PathBuf,
+    /// `dst` as it was given, for messages.
+    display: PathBuf,
+    /// What `--check-path-length` measures, as it was given rather than canonical.
+    absolute: PathBuf,
+    /// For a single-file source, the name it's copied to inside `dst`.
+    single_file: Option<OsString>,
+}
+
+impl Destination {
+    /// `opts`, for copying to this destination.
+    fn options(&self, opts: &CopyOptions) -> CopyOptions {
+        CopyOptions {
+            dst: self.dst.clone(),
+            dst_display: self.display.clone(),
+            single_file: self.single_file.clone(),
+            ..opts.clone()
+        }
+    }
+}
+
+/// Work out where copying `cli.src` to `dst` puts it, with `nested_name` added for `--rsync-paths`, and create it if
+/// it doesn't exist yet.
+fn destination(
+    cli: &CopyOptions,
+    dst: &Path,
+    nested_name: Option<&OsStr>,
+) -> Result<Destination, CopyError> {
+    let mut dst = dst.to_path_buf();
+    let mut single_file = None;
+    if let (false, Some(src_name)) = (cli.src.is_dir(), cli.src.file_name()) {
+        // Into `dst` if it's a directory or is written like one, and to `dst` as a file name otherwise.
+        let into_dir = dst.is_dir() || paths::has_trailing_separator(&dst);
+        let name = match (into_dir, dst.file_name(), dst.parent()) {
+            (false, Some(dst_name), Some(parent)) => {
+                let dst_name = dst_name.to_os_string();
+                dst = match parent.as_os_str().is_empty() {
+                    true => PathBuf::from("."),
+                    false => parent.to_path_buf(),
+                };
+                dst_name
+            }
+            _ => src_name.to_os_string(),
+        };
+        single_file = Some(name);
+    }
+
+    if let Some(name) = nested_name {
+        if cli.no_create_dest && !dst.exists() {
+            return Err(CopyError::DestinationMissing(dst));
+        }
+        dst.push(name);
+    }
+
+    let canonical_src =
+        paths::canonicalize_lenient(&cli.src).map_err(|err| CopyError::MetadataFailed {
+            path: cli.src.clone(),
+            source: err,
+        })?;
+    let canonical_dst =
+        paths::canonicalize_lenient(&dst).map_err(|err| CopyError::MetadataFailed {
+            path: dst.clone(),
+            source: err,
+        })?;
+    let nested = match &single_file {
+        Some(name) => canonical_src == canonical_dst.join(name),
+        None => {
+            paths::is_within(&canonical_dst, &canonical_src)
+                || paths::is_within(&canonical_src, &canonical_dst)
+        }
+    };
+    if nested {
+        return Err(CopyError::NestedPaths(cli.src.clone(), dst));
+    }
+
+    if !dst.exists() {
+        if (cli.no_create_dest && nested_name.is_none())
+            || cli.verify_only
+            || cli.check_manifest.is_some()
+            || cli.diff
+        {
+            return Err(CopyError::DestinationMissing(dst));
+        }
+        if let Err(err) = std::fs::DirBuilder::new().recursive(true).create(&dst) {
+            return Err(CopyError::DirectoryCreationFailed {
+                path: dst,
+                source: err,
+                attempts: 1,
+            });
+        }
+    } else if !dst.is_dir() {
+        return Err(CopyError::DestinationNotDirectory(dst));
+    }
+
+    let absolute = std::path::absolute(&dst).unwrap_or_else(|_| dst.clone());
+    // Every path copied is built from this and the source and stripped of them again, so they're resolved once, up
+    // front, and however they were typed (relative, with `.` or `..`, a trailing separator, or through a symlink) they
+    // come out the same. On Windows, canonical paths are in extended-length `\\?\` form (`\\?\UNC\` for shares), so
+    // none of the paths built from them are limited to MAX_PATH. `\\?\` paths skip normalizing `..` and `/`, which is
+    // why they have to be canonical.
+    let canonical = paths::canonicalize_lenient(&dst).map_err(|err| CopyError::MetadataFailed {
+        path: dst.clone(),
+        source: err,
+    })?;
+    Ok(Destination {
+        dst: canonical,
+        display: dst,
+        absolute,
+        single_file,
+    })
+}
+
+/// Where `opts` copies to, as it was given, for messages.
+fn destination_display(opts: &CopyOptions) -> PathBuf {
+    match &opts.single_file {
+        Some(name) => opts.dst_display.join(name),
+        None => opts.dst_display.clone(),
+    }
+}
+
+/// Copy everything in `queue` to `opts.dst`: the part of [`copy_tree`] that's done for each destination once the
+/// source has been searched. `accumulator` has what the search found.
+#[allow(clippy::too_many_arguments)]
+fn copy_to(
+    queue: Queue,
+    mut accumulator: Accumulator,
+    absolute_dst: &Path,
+    threads: usize,
+    opts: Arc<CopyOptions>,
+    search_time: Duration,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    let cancelled = opts.cancellation.clone();
+
+    if opts.delete && accumulator.file_count_found == 0 && !opts.delete_even_if_empty {
+        return Err(CopyError::Other(
+            "The source is empty, so --delete would delete everything in the destination. Use \
+             --delete-even-if-empty to do it anyway."
+                .to_string(),
+        ));
+    }
+
+    if !opts.no_space_check && !opts.verify_only {
+        check_free_space(&queue, &accumulator, &opts)?;
+    }
+
+    if opts.check_path_length {
+        check_path_lengths(&queue, absolute_dst, &opts)?;
+    }
+
+    // Changing the case of names makes ones that only differ by case collide on any destination.
+    let case_collisions = match opts.verify_only
+        || (opts.transform_case == CaseTransform::None && !paths::is_case_insensitive(&opts.dst))
+    {
+        true => HashSet::new(),
+        false => check_case_collisions(&queue, &opts)?,
+    };
+
+    // Everything that was found, relative to the source, so `--delete` knows what to keep.
+    let mut found: Option<HashSet<PathBuf>> = None;
+    if opts.delete {
+        let found = found.insert(HashSet::new());
+        for entry in queue.entries() {
+            let (_, path) = entry?;
+            if let Ok(relative) = path.strip_prefix(&opts.src) {
+                found.insert(destination_relative(relative, &opts).into_owned());
+            }
+        }
+    }
+
+    if let (true, Some(found)) = (opts.detect_renames, &found) {
+        let renamed = renames::rename_moved(
+            &queue,
+            found,
+            |path| {
+                let relative = path.strip_prefix(&opts.src).ok()?;
+                Some(opts.dst.join(destination_relative(relative, &opts)))
+            },
+            &opts,
+            &cancelled,
+        )?;
+        accumulator += renamed;
+    }
+
+    // Source directories, deepest first, for `--move` to remove once they've been emptied.
+    let mut source_dirs: Vec<PathBuf> = Vec::new();
+    if opts.move_files {
+        for entry in queue.entries() {
+            if let (EntryKind::Directory, path) = entry? {
+                source_dirs.push(path.into_owned());
+            }
+        }
+        source_dirs.sort_unstable_by_key(|path| std::cmp::Reverse(path.components().count()));
+    }
+
+    // A single file is found relative to the directory it's in.
+    let copy_base = match opts.single_file.is_some() {
+        true => opts.src.parent().unwrap_or(&opts.src).to_path_buf(),
+        false => opts.src.clone(),
+    };
+    // Directory metadata is applied once nothing else is going to change them: after the copy, and after anything
+    // extraneous has been deleted from them. Writing into a directory changes its modification time, and one made
+    // read-only early couldn't have anything copied into it.
+    let directories = queued_directories(&queue, &copy_base, &opts.dst, &opts)?;
+    let copied = copy_queue(
+        queue,
+        copy_base,
+        opts.dst.clone(),
+        &mut accumulator,
+        threads,
+        opts.clone(),
+        &cancelled,
+        Arc::new(Throttle::new(opts.bwlimit)),
+        case_collisions,
+        search_time,
+        started,
+        reporter,
+    );
+    let (copy_time, limit_reached, audited) = match copied {
+        Ok(copied) => copied,
+        Err(err) => {
+            preserve::apply_to_directories(directories, &opts);
+            return Err(err);
+        }
+    };
+    if cancelled.is_cancelled() || limit_reached {
+        preserve::apply_to_directories(directories, &opts);
+        // Nothing is removed from the source or deleted from the destination after a partial copy.
+        return Ok(CopyReport {
+            accumulator,
+            threads,
+            search_time,
+            copy_time,
+            total_time: started.elapsed(),
+            cancelled: cancelled.is_cancelled(),
+            limit_reached,
+            ..Default::default()
+        });
+    }
+
+    if opts.move_files {
+        // Anything left behind, like special files, keeps its directory around.
+        let removed = source_dirs
+            .iter()
+            .filter(|dir| std::fs::remove_dir(dir).is_ok())
+            .count();
+        if removed > 0 {
+            message!("{} empty source directories removed.", removed);
+        }
+    }
+
+    let mut delete_errors = Vec::new();
+    if let Some(found) = found {
+        let (deleted, errors) =
+            delete::delete_extraneous(&opts.dst, &found, &opts.filters, opts.delete_dry_run);
+        message!(
+            "{} files ({}) and {} directories {}.",
+            deleted.file_count_deleted,
+            Byte::from_bytes(deleted.byte_count_deleted as u128).get_appropriate_unit(false),
+            deleted.dir_count_deleted,
+            match opts.delete_dry_run {
+                true => "would be deleted",
+                false => "deleted",
+            }
+        );
+        if deleted.file_count_delete_failed > 0 {
+            message!(
+                "{} files couldn't be deleted.",
+                deleted.file_count_delete_failed
+            );
+        }
+        // Nothing was actually deleted, so the totals above are all there is to report.
+        if !opts.delete_dry_run {
+            accumulator += deleted;
+        }
+        delete_errors = errors;
+    }
+
+    preserve::apply_to_directories(directories, &opts);
+    if !delete_errors.is_empty() {
+        return Err(collect_errors(delete_errors, opts.error_log.as_deref()));
+    }
+
+    if opts.audit {
+        audit_destination(audited, &opts, threads, &cancelled, reporter)?;
+    }
+
+    if opts.strict
+        && (accumulator.files_not_copied() > 0
+            || accumulator.file_count_special_skipped > 0
+            || accumulator.dir_count_unreadable > 0)
+    {
+        return Err(CopyError::Incomplete(Box::new(accumulator)));
+    }
+
+    Ok(CopyReport {
+        accumulator,
+        threads,
+        search_time,
+        copy_time,
+        total_time: started.elapsed(),
+        cancelled: false,
+        ..Default::default()
+    })
+}
+
+/// Copy everything in `queue` to each of `destinations` at the same time, on threads of their own with `threads` copy
+/// threads each, the way [`copy_to`] copies to one. What they report is passed on to `reporter` as it happens. A
+/// destination that fails doesn't stop the others, and its error is returned once they've all finished.
+fn copy_to_each(
+    queue: Queue,
+    accumulator: Accumulator,
+    destinations: &[(Arc<CopyOptions>, PathBuf)],
+    threads: usize,
+    search_time: Duration,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    // Every destination works through the same files in the same order, so a file read for one is usually still
+    // cached when the others get to it.
+    let mut queues = Vec::with_capacity(destinations.len());
+    for _ in 1..destinations.len() {
+        queues.push(queue.duplicate()?);
+    }
+    queues.push(queue);
+
+    let (sender, receiver) = std::sync::mpsc::sync_channel(EVENT_CAPACITY);
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = destinations
+            .iter()
+            .zip(queues)
+            .map(|((opts, absolute_dst), queue)| {
+                let mut events = EventReporter::new(sender.clone(), opts.cancellation.clone());
+                let accumulator = accumulator.clone();
+                scope.spawn(move || {
+                    let mut recorder = reporter::Recorder::new(&mut events);
+                    let result = copy_to(
+                        queue,
+                        accumulator,
+                        absolute_dst,
+                        threads,
+                        opts.clone(),
+                        search_time,
+                        started,
+                        &mut recorder,
+                    );
+                    reporter::deliver(&mut recorder);
+                    result.map(|report| recorder.fill(report))
+                })
+            })
+            .collect();
+        // The channel closes once every destination is done with it.
+        drop(sender);
+        for event in receiver {
+            events::forward_event(event, reporter);
+        }
+        handles.into_iter().map(|handle| handle.join()).collect()
+    });
+
+    let mut reports = Vec::with_capacity(destinations.len());
+    let mut errors = Vec::new();
+    for (idx, ((opts, _), result)) in destinations.iter().zip(results).enumerate() {
+        let dst = destination_display(opts);
+        match result.unwrap_or_else(|payload| Err(CopyError::worker_panicked(idx, payload))) {
+            Ok(report) => reports.push(CopyReport {
+                destination: Some(dst),
+                ..report
+            }),
+            Err(err) => errors.push(CopyError::DestinationFailed {
+                dst,
+                source: Box::new(err),
+            }),
+        }
+    }
+    match errors.len() {
+        0 => {
+            let limit_reached = reports.iter().any(|report| report.limit_reached);
+            let mut reports = reports.into_iter();
+            let first = reports.next().unwrap_or_default();
+            Ok(CopyReport {
+                also_to: reports.collect(),
+                limit_reached,
+                ..first
+            })
+        }
+        1 => Err(errors.pop().unwrap()),
+        _ => Err(CopyError::Multiple(errors)),
+    }
+}
+
+/// Check every file listed in a `--checksum-file` written by an earlier run against the destination, spreading the
+/// files across `threads` threads.
+fn check_manifest(
+    manifest: &Path,
+    opts: &CopyOptions,
+    threads: usize,
+    cancelled: &CancellationToken,
+) -> Result<Accumulator, CopyError> {
+    let entries = manifest::read(manifest).map_err(|err| CopyError::ChecksumFileFailed {
+        path: manifest.to_path_buf(),
+        source: err,
+    })?;
+    message!(
+        "Checking {} files against {} with {} threads",
+        entries.len(),
+        manifest.display(),
+        threads
+    );
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let (accumulator, errors) = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut buffer = CopyBuffer::new(opts.buffer_size);
+                    let mut accumulator = Accumulator::default();
+                    let mut errors = Vec::new();
+                    while let Some((relative, expected)) =
+                        entries.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        if cancelled.is_cancelled() {
+                            break;
+                        }
+                        let path = opts.dst.join(relative);
+                        match hash::hash_file(&path, opts.hash, &mut buffer) {
+                            Ok((digest, read)) if digest.to_string() == *expected => {
+                                accumulator += Accumulator::verified(1, read);
+                            }
+                            Ok((digest, _)) => {
+                                accumulator += Accumulator::verify_failed(1);
+                                errors.push(CopyError::VerifyFailed {
+                                    src: manifest.to_path_buf(),
+                                    dst: path,
+                                    src_digest: expected.clone(),
+                                    dst_digest: digest.to_string(),
+                                });
+                            }
+                            Err(err) => {
+                                accumulator += Accumulator::verify_failed(1);
+                                errors.push(CopyError::HashFailed { path, source: err });
+                            }
+                        }
+                    }
+                    (accumulator, errors)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .enumerate()
+            .map(|(thread_id, handle)| {
+                handle.join().unwrap_or_else(|payload| {
+                    let err = CopyError::worker_panicked(thread_id, payload);
+                    (Accumulator::default(), vec![err])
+                })
+            })
+            .fold(
+                (Accumulator::default(), Vec::new()),
+                |(accumulator, mut errors), (thread_accumulator, thread_errors)| {
+                    errors.extend(thread_errors);
+                    (accumulator + thread_accumulator, errors)
+                },
+            )
+    });
+
+    message!(
+        "Checked {} files ({}) in {:.2} seconds, {} failed.",
+        accumulator.file_count_verified + accumulator.file_count_verify_failed,
+        Byte::from_bytes(accumulator.byte_count_verified as u128).get_appropriate_unit(false),
+        start.elapsed().as_secs_f64(),
+        accumulator.file_count_verify_failed
+    );
+    if !errors.is_empty() {
+        return Err(collect_errors(errors, opts.error_log.as_deref()));
+    }
+    Ok(accumulator)
+}
+
+/// Compare the source and destination for `--diff` without copying anything, printing one line per difference,
+/// sorted by path, and totals. Both trees are searched with `threads` threads, and the paths found at both ends are
+/// compared across as many. Returns what was found in the source.
+fn diff_trees(
+    opts: Arc<CopyOptions>,
+    threads: usize,
+    cancelled: &CancellationToken,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<Accumulator, CopyError> {
+    message!(
+        "Comparing {} with {} using {} threads",
+        opts.src_display.display(),
+        opts.dst_display.display(),
+        threads
+    );
+    let mut src_accumulator = Accumulator::default();
+    let src_found = search_dir(
+        &opts.src,
+        &opts.src,
+        &mut src_accumulator,
+        threads,
+        opts.clone(),
+        None,
+        cancelled,
+        reporter,
+    )?
+    .into_memory()?;
+    let mut dst_accumulator = Accumulator::default();
+    let dst_found = search_dir(
+        &opts.dst,
+        &opts.dst,
+        &mut dst_accumulator,
+        threads,
+        opts.clone(),
+        None,
+        cancelled,
+        reporter,
+    )?
+    .into_memory()?;
+    if cancelled.is_cancelled() {
+        return Ok(src_accumulator);
+    }
+
+    let mut dst_entries = relative_entries(dst_found, &opts.dst);
+    let mut differences = Vec::new();
+    let mut pairs = Vec::new();
+    for (relative, (kind, src_metadata)) in relative_entries(src_found, &opts.src) {
+        match dst_entries.remove(&relative) {
+            None => differences.push((relative, Difference::OnlyInSource)),
+            Some((dst_kind, _)) if dst_kind != kind => {
+                differences.push((relative, Difference::Kind))
+            }
+            Some((_, dst_metadata)) => pairs.push(diff::Pair {
+                relative,
+                kind,
+                src: src_metadata,
+                dst: dst_metadata,
+            }),
+        }
+    }
+    differences.extend(
+        dst_entries
+            .into_keys()
+            .map(|relative| (relative, Difference::OnlyInDestination)),
+    );
+
+    let next = AtomicUsize::new(0);
+    let (compared, read, errors) = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut buffer = CopyBuffer::new(opts.buffer_size);
+                    let mut differences = Vec::new();
+                    let mut read = 0;
+                    let mut errors = Vec::new();
+                    while let Some(pair) = pairs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if cancelled.is_cancelled() {
+                            break;
+                        }
+                        match diff::compare(pair, &opts.src, &opts.dst, &opts, &mut buffer) {
+                            Ok((difference, pair_read)) => {
+                                read += pair_read;
+                                if let Some(difference) = difference {
+                                    differences.push((pair.relative.clone(), difference));
+                                }
+                            }
+                            Err(err) => errors.push(err),
+                        }
+                    }
+                    (differences, read, errors)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .enumerate()
+            .map(|(thread_id, handle)| {
+                handle.join().unwrap_or_else(|payload| {
+                    let err = CopyError::worker_panicked(thread_id, payload);
+                    (Vec::new(), 0, vec![err])
+                })
+            })
+            .fold(
+                (Vec::new(), 0, Vec::new()),
+                |(mut differences, read, mut errors),
+                 (thread_differences, thread_read, thread_errors)| {
+                    differences.extend(thread_differences);
+                    errors.extend(thread_errors);
+                    (differences, read + thread_read, errors)
+                },
+            )
+    });
+    let identical = pairs.len() - compared.len() - errors.len();
+    differences.extend(compared);
+    let only_in_source = differences
+        .iter()
+        .filter(|(_, difference)| matches!(difference, Difference::OnlyInSource))
+        .count();
+    let only_in_destination = differences
+        .iter()
+        .filter(|(_, difference)| matches!(difference, Difference::OnlyInDestination))
+        .count();
+    let differing = differences.len() - only_in_source - only_in_destination;
+    differences.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (relative, difference) in &differences {
+        reporter::output(format!("{}\t{}", difference.label(), relative.display()));
+    }
+    message!(
+        "{} only in source, {} only in destination, {} differing, {} identical.",
+        only_in_source,
+        only_in_destination,
+        differing,
+        identical
+    );
+    if read > 0 {
+        message!(
+            "{} read to compare checksums.",
+            Byte::from_bytes(read as u128).get_appropriate_unit(false)
+        );
+    }
+
+    if !errors.is_empty() {
+        return Err(collect_errors(errors, opts.error_log.as_deref()));
+    }
+    // What was compared before cancelling isn't the whole story, so it doesn't count as finding differences.
+    if cancelled.is_cancelled() {
+        return Ok(src_accumulator);
+    }
+    if !differences.is_empty() {
+        return Err(CopyError::Differences(differences.len() as u64));
+    }
+    Ok(src_accumulator)
+}
+
+/// Search the destination again for `--audit` with `threads` threads, and check that every file in `expected` is
+/// there as it was copied, listing each one that isn't. The search takes in everything at the destination, whatever
+/// the filters say, since they were matched against paths in the source.
+fn audit_destination(
+    expected: Vec<audit::Expected>,
+    opts: &CopyOptions,
+    threads: usize,
+    cancelled: &CancellationToken,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<(), CopyError> {
+    message!(
+        "Auditing {} files copied to {} using {} threads",
+        expected.len(),
+        opts.dst_display.display(),
+        threads
+    );
+    let search_opts = CopyOptions {
+        filters: Vec::new(),
+        save_file_list: None,
+        locality: false,
+        owner: None,
+        group: None,
+        prune_foreign_dirs: false,
+        skip_placeholders: false,
+        files_only: false,
+        // A symlink where a file was copied is a discrepancy, not something to follow.
+        preserve_symlinks: true,
+        ..opts.clone()
+    };
+    let found = search_dir(
+        &opts.dst,
+        &opts.dst,
+        &mut Accumulator::default(),
+        threads,
+        Arc::new(search_opts),
+        None,
+        cancelled,
+        reporter,
+    )?
+    .into_memory()?;
+    if cancelled.is_cancelled() {
+        return Ok(());
+    }
+
+    let count = expected.len();
+    let discrepancies = audit::check(
+        expected,
+        &relative_entries(found, &opts.dst),
+        opts.modify_window,
+    );
+    for (relative, discrepancy) in &discrepancies {
+        match discrepancy {
+            Discrepancy::Size { expected, found } => message!(
+                "{}\t{} (copied at {} bytes, now {})",
+                discrepancy.label(),
+                relative.display(),
+                expected,
+                found
+            ),
+            _ => message!("{}\t{}", discrepancy.label(), relative.display()),
+        }
+    }
+    message!(
+        "Audited {} files copied, {} not at the destination as they were copied.",
+        count,
+        discrepancies.len()
+    );
+    if !discrepancies.is_empty() {
+        return Err(CopyError::AuditFailed(discrepancies.len() as u64));
+    }
+    Ok(())
+}
+
+/// Everything a search found, keyed by its path relative to `base`, for `--diff` to match up with the other end.
+fn relative_entries(
+    found: VecDeque<SearchResult>,
+    base: &Path,
+) -> HashMap<PathBuf, (Kind, Metadata)> {
+    found
+        .into_iter()
+        .filter_map(|item| {
+            let (kind, info) = match item {
+                SearchResult::File(info) => (Kind::File, info),
+                SearchResult::Directory(info) => (Kind::Directory, info),
+                SearchResult::Symlink(info) => (Kind::Symlink, info),
+                SearchResult::Special(info) => (Kind::Special, info),
+                SearchResult::Unreadable(..) | SearchResult::Done(_) => return None,
+            };
+            let relative = info.path.strip_prefix(base).ok()?.to_path_buf();
+            Some((relative, (kind, info.metadata)))
+        })
+        .collect()
+}
+
+/// Make sure the destination has room for everything that was found.
+///
+/// With `skip`, `update`, `checksum`, or `overwrite`, files that already exist at the destination are either left alone or
+/// replaced, so their sizes are subtracted from the space needed. Files that are backed up before they're replaced
+/// still take up space, so nothing is subtracted for them.
+fn check_free_space(
+    queue: &Queue,
+    accumulator: &Accumulator,
+    opts: &CopyOptions,
+) -> Result<(), CopyError> {
+    // The destination itself may not have been created yet.
+    let Some(existing) = opts.dst.ancestors().find(|path| path.exists()) else {
+        return Ok(());
+    };
+    let available = match space::available_space(existing) {
+        Ok(available) => available,
+        Err(err) => {
+            message!(
+                "Warning: unable to check free space at {}: {}",
+                existing.display(),
+                err
+            );
+            return Ok(());
+        }
+    };
+
+    let mut needed = accumulator.byte_count_found;
+    let replaced = (opts.update || opts.checksum || opts.overwrite)
+        && opts.backup.is_none()
+        && opts.backup_dir.is_none();
+    if (opts.skip || replaced) && opts.dst.exists() {
+        for entry in queue.entries() {
+            let (EntryKind::File, path) = entry? else {
+                continue;
+            };
+            let Ok(relative) = path.strip_prefix(&opts.src) else {
+                continue;
+            };
+            let dst = opts.dst.join(destination_relative(relative, opts));
+            if let Ok(metadata) = std::fs::metadata(dst) {
+                needed = needed.saturating_sub(metadata.len());
+            }
+        }
+    }
+
+    if needed > available {
+        return Err(CopyError::InsufficientSpace { needed, available });
+    }
+
+    Ok(())
+}
+
+/// List everything in `queue` whose path under `dst` would be longer than Windows allows without the extended-length
+/// form, for `--check-path-length`. `dst` should be absolute, since the limit applies to the whole path.
+fn check_path_lengths(queue: &Queue, dst: &Path, opts: &CopyOptions) -> Result<(), CopyError> {
+    let mut too_long = 0;
+    for entry in queue.entries() {
+        let (kind, path) = entry?;
+        let limit = match kind {
+            EntryKind::Directory => paths::MAX_DIR_PATH,
+            EntryKind::File | EntryKind::Symlink => paths::MAX_PATH,
+            EntryKind::Special => continue,
+        };
+        let Ok(relative) = path.strip_prefix(&opts.src) else {
+            continue;
+        };
+        let len = paths::windows_len(&dst.join(destination_relative(relative, opts)));
+        // The limit includes the terminating null.
+        if len >= limit {
+            message!("{} ({} characters at the destination)", path.display(), len);
+            too_long += 1;
+        }
+    }
+    match too_long {
+        0 => Ok(()),
+        _ => Err(CopyError::PathsTooLong(too_long)),
+    }
+}
+
+/// List everything in `queue` with a name Windows can't use. With `--sanitize-names` these are the renames that will be
+/// made, and otherwise they're an error, since creating them would fail partway through the copy. Names `--normalize`
+/// changes are listed too. Renames that would give two paths the same name are an error, and so are `--remap` rules
+/// that move anything out of the destination.
+fn check_names(queue: &Queue, opts: &CopyOptions) -> Result<(), CopyError> {
+    let mut invalid = 0;
+    for entry in queue.entries() {
+        let (_, path) = entry?;
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        // Each directory is an entry of its own, so looking at the last name is enough to list every change once.
+        if let Cow::Owned(normalized) =
+            paths::normalize(Cow::Borrowed(Path::new(name)), opts.normalize)
+        {
+            message!(
+                "{} isn't normalized, copying it as {}",
+                path.display(),
+                normalized.display()
+            );
+        }
+        let Some(problem) = paths::windows_name_problem(name) else {
+            continue;
+        };
+        match opts.sanitize_names.as_deref() {
+            Some(replacement) => message!(
+                "{} {}, copying it as {}",
+                path.display(),
+                problem,
+                paths::sanitize_name(name, replacement)
+                    .unwrap_or_default()
+                    .to_string_lossy()
+            ),
+            None => {
+                message!("{} {}", path.display(), problem);
+                invalid += 1;
+            }
+        }
+    }
+    if invalid > 0 {
+        return Err(CopyError::InvalidNames(invalid));
+    }
+
+    // Distinct source paths can only end up at the same destination path if renaming made them the same.
+    let mut destinations = HashMap::new();
+    let mut errors = Vec::new();
+    for entry in queue.entries() {
+        let (kind, path) = entry?;
+        let Ok(relative) = path.strip_prefix(&opts.src) else {
+            continue;
+        };
+        let dst_relative = destination_relative(relative, opts).into_owned();
+        // A remapped directory can become the destination itself, but nothing else can.
+        if !paths::is_plain_relative(&dst_relative)
+            || (dst_relative.as_os_str().is_empty() && kind != EntryKind::Directory)
+        {
+            errors.push(CopyError::RemappedOutside {
+                src: path.into_owned(),
+                relative: dst_relative,
+            });
+            continue;
+        }
+        // With `--transform-case`, `check_case_collisions` finds every collision this would, and `--rename-collisions`
+        // can deal with them there.
+        if opts.transform_case != CaseTransform::None {
+            continue;
+        }
+        let is_dir = kind == EntryKind::Directory;
+        // Directories that end up the same are merged, which loses nothing.
+        if let Some((other, other_is_dir)) =
+            destinations.insert(dst_relative.clone(), (path.to_path_buf(), is_dir))
+        {
+            if is_dir && other_is_dir {
+                continue;
+            }
+            errors.push(CopyError::SanitizeCollision {
+                src: path.into_owned(),
+                other,
+                relative: dst_relative,
+            });
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(collect_errors(errors, opts.error_log.as_deref())),
+    }
+}
+
+/// Find files in `queue` that would be copied to the same path as another one because the destination ignores case or
+/// `--transform-case` changes it, like `Readme.md` and `README.md`. Directories whose names differ only by case are
+/// merged, which loses nothing, so they only count when they collide with a file.
+///
+/// With `--rename-collisions`, files that collide with each other are allowed, and every one but the first (in sorted
+/// order) is returned, to be copied under a free name. Anything else is an error.
+fn check_case_collisions(queue: &Queue, opts: &CopyOptions) -> Result<HashSet<PathBuf>, CopyError> {
+    let mut found: Vec<(String, bool, Cow<Path>)> = Vec::new();
+    for entry in queue.entries() {
+        let (kind, path) = entry?;
+        let Ok(relative) = path.strip_prefix(&opts.src) else {
+            continue;
+        };
+        let folded = destination_relative(relative, opts)
+            .to_string_lossy()
+            .to_lowercase();
+        found.push((folded, kind == EntryKind::Directory, path));
+    }
+    found.sort_unstable_by(|(a, _, a_path), (b, _, b_path)| a.cmp(b).then(a_path.cmp(b_path)));
+
+    let mut renamed = HashSet::new();
+    let mut errors = Vec::new();
+    for pair in found.windows(2) {
+        let [(first, first_is_dir, first_path), (second, second_is_dir, second_path)] = pair else {
+            continue;
+        };
+        if first != second || (*first_is_dir && *second_is_dir) {
+            continue;
+        }
+        if opts.rename_collisions && !first_is_dir && !second_is_dir {
+            renamed.insert(second_path.to_path_buf());
+            continue;
+        }
+        errors.push(CopyError::CaseCollision {
+            src: second_path.to_path_buf(),
+            other: first_path.to_path_buf(),
+        });
+    }
+    match errors.is_empty() {
+        true => Ok(renamed),
+        false => Err(collect_errors(errors, opts.error_log.as_deref())),
+    }
+}
+
+/// Where something at `relative` in the source goes, relative to the destination: the same path, unless `--remap`
+/// rules move it, `--sanitize-names` has to rename parts of it, `--transform-case` changes its case, or `--normalize`
+/// changes its Unicode, in that order.
+fn destination_relative<'a>(relative: &'a Path, opts: &CopyOptions) -> Cow<'a, Path> {
+    if let Some(name) = &opts.single_file {
+        return Cow::Owned(PathBuf::from(name));
+    }
+    let remapped = paths::remap(relative, &opts.remaps);
+    let sanitized = match opts.sanitize_names.as_deref() {
+        Some(replacement) => paths::sanitize(remapped, replacement),
+        None => remapped,
+    };
+    let cased = paths::transform_case(sanitized, opts.transform_case);
+    paths::normalize(cased, opts.normalize)
+}
+
+/// Get the number of available cores as a default, or `2` if we cannot determine the number of cores available.
+///
+/// # Notes
+/// This isn't strictly number of available cores as implementation varies by platform. See [`std::thread::available_parallelism`]
+/// for more details.
+///
+/// Prints an error and warning if we're falling back due to being unable to determine core count.
+fn default_thread_count() -> usize {
+    match std::thread::available_parallelism() {
+        Ok(num) => usize::from(num),
+        Err(e) => {
+            message!("Error: {:?}", e);
+            message!("Warning: could not determine available core count. Defaulting to 2 threads.");
+            2
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ResultInfo {
+    path: PathBuf,
+    metadata: Metadata,
+}
+
+enum SearchResult {
+    File(ResultInfo),
+    Directory(ResultInfo),
+    /// Produced with `--preserve-symlinks`, for junctions and directory symlinks on Windows without
+    /// `--follow-junctions`, and for links to directories that would loop. Otherwise links are followed and show up as
+    /// what they point to.
+    Symlink(ResultInfo),
+    /// FIFOs, sockets, and device nodes, which are skipped rather than copied.
+    Special(ResultInfo),
+    /// A directory that couldn't be read, sent in place of what's in it or the rest of it, or an entry in one whose
+    /// metadata couldn't be read, sent in place of the entry. Never queued.
+    Unreadable(PathBuf, std::io::Error),
+    /// Everything in this directory has been sent.
+    Done(PathBuf),
+}
+
+impl SearchResult {
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File(info)
+            | Self::Directory(info)
+            | Self::Symlink(info)
+            | Self::Special(info) => Some(&info.path),
+            Self::Unreadable(path, _) => Some(path),
+            Self::Done(_) => None,
+        }
+    }
+
+    fn info(&self) -> Option<&ResultInfo> {
+        match self {
+            Self::File(info)
+            | Self::Directory(info)
+            | Self::Symlink(info)
+            | Self::Special(info) => Some(info),
+            Self::Unreadable(..) | Self::Done(_) => None,
+        }
+    }
+}
+
+/// What the copy threads are handed: something found by the search, or a piece of a file being copied by several
+/// threads with `--split-large-files`.
+enum WorkItem {
+    Found(SearchResult),
+    Chunk(Chunk),
+}
+
+impl WorkItem {
+    /// What a copy thread is working on once it's been handed this.
+    fn current(&self) -> Option<Current> {
+        match self {
+            Self::Found(SearchResult::File(file_result)) => Some(Current {
+                path: file_result.path.clone(),
+                len: file_result.metadata.len(),
+                split: None,
+                preferred: false,
+                started: Instant::now(),
+            }),
+            Self::Found(result) => Some(Current {
+                path: result.path()?.to_path_buf(),
+                len: 0,
+                split: None,
+                preferred: false,
+                started: Instant::now(),
+            }),
+            Self::Chunk(chunk) => Some(Current {
+                path: chunk.file.src.clone(),
+                len: chunk.len,
+                split: Some(chunk.file.clone()),
+                preferred: false,
+                started: Instant::now(),
+            }),
+        }
+    }
+}
+
+/// What a copy thread is working on, for status snapshots and per-file progress.
+struct Current {
+    path: PathBuf,
+    /// The length of the file, or of the chunk with `split`.
+    len: u64,
+    /// The file a chunk belongs to.
+    split: Option<Arc<SplitFile>>,
+    /// Whether it's one of the files picked with `--prefer`.
+    preferred: bool,
+    /// When the thread was handed it.
+    started: Instant,
+}
+
+/// Everything copied so far, counting what the copy threads have written of the files they're on, so a window of
+/// `--threads auto-tune` isn't thrown off by large files finishing just after it ends.
+fn written(
+    accumulator: &Accumulator,
+    in_flight: &[Option<Current>],
+    file_bytes: &[Arc<AtomicU64>],
+) -> u64 {
+    accumulator.byte_count_copied
+        + in_flight
+            .iter()
+            .zip(file_bytes)
+            .filter(|(current, _)| current.is_some())
+            .map(|(_, written)| written.load(Ordering::Relaxed))
+            .sum::<u64>()
+}
+
+/// How far along each large file the copy threads are working on is, as its path, the bytes written so far, and its
+/// length. `file_bytes` holds each thread's bytes written, and the chunks of a split file are counted together.
+fn large_files(
+    in_flight: &[Option<Current>],
+    file_bytes: &[Arc<AtomicU64>],
+) -> Vec<(PathBuf, u64, u64)> {
+    let mut files = Vec::new();
+    let mut splits: Vec<(&Arc<SplitFile>, u64)> = Vec::new();
+    for (current, written) in in_flight.iter().zip(file_bytes) {
+        let Some(current) = current else {
+            continue;
+        };
+        let written = written.load(Ordering::Relaxed);
+        match &current.split {
+            None => files.push((current.path.clone(), written.min(current.len), current.len)),
+            Some(split) => match splits.iter_mut().find(|(seen, _)| Arc::ptr_eq(seen, split)) {
+                Some((_, total)) => *total += written,
+                None => splits.push((split, written)),
+            },
+        }
+    }
+    files.extend(splits.into_iter().map(|(split, written)| {
+        let len = split.metadata.len();
+        // A chunk that's just finished can be counted in `landed` before its thread is seen to be done with it.
+        (split.src.clone(), (split.landed() + written).min(len), len)
+    }));
+    files.retain(|(_, _, len)| *len >= copy::PROGRESS_FILE_SIZE);
+    files
+}
+
+/// Search `from`, which is `src` or a directory inside it, with paths passed to the filters relative to `src`.
+#[allow(clippy::too_many_arguments)]
+fn search_dir(
+    src: &Path,
+    from: &Path,
+    accumulator: &mut Accumulator,
+    threads: usize,
+    opts: Arc<CopyOptions>,
+    queue_bytes: Option<u64>,
+    cancelled: &CancellationToken,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<Queue, CopyError> {
+    let start = Instant::now();
+
+    let (result_sender, result_receiver) = unbounded();
+
+    // Every search thread takes directories from the same channel, so whichever one is free reads the next.
+    let (path_sender, path_receiver) = unbounded();
+
+    let mut thread_handles = Vec::with_capacity(threads);
+
+    let followed = Arc::new(FollowedLinks::default());
+
+    for _ in 0..threads {
+        let path_receiver = path_receiver.clone();
+        let result_sender = result_sender.clone();
+        let base = src.to_path_buf();
+        let opts = opts.clone();
+        let followed = followed.clone();
+        let handle = std::thread::spawn(move || {
+            search(path_receiver, result_sender, &base, &opts, &followed);
+        });
+
+        thread_handles.push(handle);
+    }
+
+    // Only the search threads hold receivers from here on, so sending fails once they've all exited.
+    drop(path_receiver);
+
+    if path_sender.send(from.to_path_buf()).is_err() {
+        return Err(CopyError::Other(
+            "The search threads exited before the search started".to_string(),
+        ));
+    }
+
+    let mut pending = 1;
+
+    let mut last_time = Instant::now();
+    let ticker = tick(STATUS_POLL_INTERVAL);
+
+    let mut queue = Queue::new(opts.spool_dir.as_deref(), opts.spool_after, queue_bytes);
+    // What stopped the search early, other than a panic or being cancelled.
+    let mut failed = None;
+    // Only a search of the whole source is saved.
+    let mut file_list = match (&opts.save_file_list, from == src) {
+        (Some(path), true) => Some(file_list::Writer::create(path)?),
+        _ => None,
+    };
+    // What's been found in each directory still being read, with `--locality`.
+    let mut groups = opts.locality.then(HashMap::new);
+
+    while pending > 0 {
+        if cancelled.is_cancelled() {
+            break;
+        }
+
+        // The ticker wakes the loop up for progress and status requests even while nothing is being found.
+        let result = select! {
+            recv(result_receiver) -> result => result.ok(),
+            recv(ticker) -> _ => None,
+        };
+
+        match result {
+            // Left out of what was found altogether, the same as anything the filters leave out.
+            Some(SearchResult::File(_) | SearchResult::Special(_)) if opts.dirs_only => {}
+            Some(SearchResult::Symlink(_)) if opts.dirs_only || opts.files_only => {}
+            Some(SearchResult::File(file_result)) => {
+                if !owner::owned(&opts, &file_result.metadata) {
+                    *accumulator += Accumulator::foreign(1, 0);
+                    continue;
+                }
+                if cloud::is_placeholder(&file_result.metadata) {
+                    *accumulator += Accumulator::placeholders(1, file_result.metadata.len());
+                    // Left out of what was found, so progress is measured against what will actually be copied.
+                    if opts.skip_placeholders {
+                        reporter.on_file_done(
+                            &file_result.path,
+                            Outcome::Skipped(SkipReason::Placeholder),
+                        );
+                        continue;
+                    }
+                }
+                *accumulator += Accumulator::found(1, file_result.metadata.len());
+                if let Err(err) = push_grouped(
+                    &mut groups,
+                    &mut queue,
+                    &mut file_list,
+                    src,
+                    SearchResult::File(file_result),
+                ) {
+                    failed = Some(err);
+                    break;
+                }
+            }
+            Some(SearchResult::Directory(dir_result)) => {
+                if opts.prune_foreign_dirs && !owner::owned(&opts, &dir_result.metadata) {
+                    *accumulator += Accumulator::foreign(0, 1);
+                    continue;
+                }
+                pending += 1;
+                if path_sender.send(dir_result.path.clone()).is_err() {
+                    // Every search thread has panicked.
+                    break;
+                }
+                if let Err(err) = push_found(
+                    &mut queue,
+                    &mut file_list,
+                    src,
+                    SearchResult::Directory(dir_result),
+                ) {
+                    failed = Some(err);
+                    break;
+                }
+            }
+            Some(SearchResult::Symlink(link_result)) => {
+                *accumulator += Accumulator::found(1, 0);
+                if let Err(err) = push_grouped(
+                    &mut groups,
+                    &mut queue,
+                    &mut file_list,
+                    src,
+                    SearchResult::Symlink(link_result),
+                ) {
+                    failed = Some(err);
+                    break;
+                }
+            }
+            Some(SearchResult::Special(special_result)) => {
+                if let Err(err) = push_grouped(
+                    &mut groups,
+                    &mut queue,
+                    &mut file_list,
+                    src,
+                    SearchResult::Special(special_result),
+                ) {
+                    failed = Some(err);
+                    break;
+                }
+            }
+            Some(SearchResult::Unreadable(path, source)) => {
+                let dir = path.is_dir();
+                let err = CopyError::Unreadable { path, source };
+                if !opts.continue_on_error {
+                    failed = Some(err);
+                    break;
+                }
+                *accumulator += match dir {
+                    true => Accumulator::unreadable_dirs(1),
+                    false => Accumulator::unreadable_files(1),
+                };
+                log_errors(std::slice::from_ref(&err), opts.error_log.as_deref());
+                reporter::error(err);
+            }
+            Some(SearchResult::Done(dir)) => {
+                pending -= 1;
+                let group = groups.as_mut().and_then(|groups| groups.remove(&dir));
+                for item in group.into_iter().flatten() {
+                    if let Err(err) = push_found(&mut queue, &mut file_list, src, item) {
+                        failed = Some(err);
+                        break;
+                    }
+                }
+                if failed.is_some() {
+                    break;
+                }
+            }
+            None => {
+                // Search threads only exit once the path sender is dropped below, so one that has finished already
+                // panicked, and the directory it was reading will never be done. It's joined below for the panic
+                // message.
+                if thread_handles.iter().any(JoinHandle::is_finished) {
+                    break;
+                }
+            }
+        }
+
+        reporter::deliver(reporter);
+
+        if signals::take_status_request() {
+            reporter.on_search_progress(accumulator);
+        }
+
+        if opts.progress {
+            let now = Instant::now();
+            if now.duration_since(last_time).as_secs() >= 5 {
+                reporter.on_search_progress(accumulator);
+                last_time = now;
+            }
+        }
+    }
+    let search_finish = Instant::now();
+
+    message!(
+        "Found {} files. Total size: {} bytes",
+        accumulator.file_count_found,
+        Byte::from_bytes(accumulator.byte_count_found as u128).get_appropriate_unit(false)
+    );
+
+    message!(
+        "Search finished in {:.3} seconds",
+        search_finish.duration_since(start).as_secs_f32()
+    );
+
+    if accumulator.file_count_placeholders > 0 && !opts.skip_placeholders && !opts.hydrate {
+        message!(
+            "Warning: {} files ({}) are cloud placeholders that are only online, and copying them will download them. \
+             Use --skip-placeholders to leave them out, or --hydrate to copy them without this warning.",
+            accumulator.file_count_placeholders,
+            Byte::from_bytes(accumulator.byte_count_placeholders as u128).get_appropriate_unit(false)
+        );
+    }
+
+    drop(path_sender);
+
+    let mut panicked = None;
+    for (thread_id, thread) in thread_handles.into_iter().enumerate() {
+        if let Err(payload) = thread.join() {
+            panicked.get_or_insert(CopyError::worker_panicked(thread_id, payload));
+        }
+    }
+    if let Some(err) = panicked.or(failed) {
+        return Err(err);
+    }
+
+    if let (Some(file_list), false) = (file_list, cancelled.is_cancelled()) {
+        file_list.finish()?;
+    }
+    queue.finish()?;
+    Ok(queue)
+}
+
+/// The directories in `queue`, starting with `copy_base` itself, each with where it's copied to under `dest_base` and
+/// its metadata, for [`preserve::apply_to_directories`] once everything has been copied into them. Empty when nothing
+/// about directories is preserved.
+fn queued_directories(
+    queue: &Queue,
+    copy_base: &Path,
+    dest_base: &Path,
+    opts: &CopyOptions,
+) -> Result<Vec<(PathBuf, PathBuf, Metadata)>, CopyError> {
+    let mut directories = Vec::new();
+    if opts.verify_only
+        || !(!opts.no_preserve_times
+            || opts.preserve_perms
+            || opts.preserve_owner
+            || opts.preserve_attributes
+            || opts.acls.is_some())
+    {
+        return Ok(directories);
+    }
+    // The directory a single file is in isn't part of the copy.
+    if let (None, Ok(metadata)) = (&opts.single_file, std::fs::metadata(copy_base)) {
+        directories.push((copy_base.to_path_buf(), dest_base.to_path_buf(), metadata));
+    }
+    for entry in queue.directories() {
+        let (path, metadata) = entry?;
+        let Ok(relative) = path.strip_prefix(copy_base) else {
+            continue;
+        };
+        let dst = dest_base.join(destination_relative(relative, opts));
+        directories.push((path.into_owned(), dst, metadata.into_owned()));
+    }
+    Ok(directories)
+}
+
+/// Add `item` to `queue`, and to the `--save-file-list` being written if there is one.
+fn push_found(
+    queue: &mut Queue,
+    file_list: &mut Option<file_list::Writer>,
+    src: &Path,
+    item: SearchResult,
+) -> Result<(), CopyError> {
+    if let Some(file_list) = file_list {
+        file_list.record(&item, src)?;
+    }
+    queue.push_back(item)
+}
+
+/// Add `item` like [`push_found`], or with `groups`, hold it with the rest of its directory until everything in that
+/// has been found, so the whole directory is queued together.
+fn push_grouped(
+    groups: &mut Option<HashMap<PathBuf, Vec<SearchResult>>>,
+    queue: &mut Queue,
+    file_list: &mut Option<file_list::Writer>,
+    src: &Path,
+    item: SearchResult,
+) -> Result<(), CopyError> {
+    let parent = item.path().and_then(Path::parent).map(Path::to_path_buf);
+    match (groups, parent) {
+        (Some(groups), Some(parent)) => {
+            groups.entry(parent).or_default().push(item);
+            Ok(())
+        }
+        _ => push_found(queue, file_list, src, item),
+    }
+}
+
+/// The queue for a single-file source: just the file, found without searching.
+fn single_file_queue(
+    opts: &CopyOptions,
+    accumulator: &mut Accumulator,
+) -> Result<Queue, CopyError> {
+    let metadata = std::fs::metadata(&opts.src).map_err(|err| CopyError::MetadataFailed {
+        path: opts.src.clone(),
+        source: err,
+    })?;
+    let result_info = ResultInfo {
+        path: opts.src.clone(),
+        metadata,
+    };
+    let mut queue = Queue::new(None, 1, None);
+    match is_special(&result_info.metadata.file_type()) {
+        true => queue.push_back(SearchResult::Special(result_info))?,
+        false => {
+            *accumulator += Accumulator::found(1, result_info.metadata.len());
+            queue.push_back(SearchResult::File(result_info))?;
+        }
+    }
+    Ok(queue)
+}
+
+/// Read each directory sent to `rx`, sending what's in it to `found` unless `opts.filters` leave it out. Paths are
+/// passed to the filters relative to `base`. Links to directories that `followed` says would loop are sent as links.
+fn search(
+    rx: Receiver<PathBuf>,
+    found: Sender<SearchResult>,
+    base: &Path,
+    opts: &CopyOptions,
+    followed: &FollowedLinks,
+) {
+    for path in rx {
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                found
+                    .send(SearchResult::Unreadable(path.clone(), err))
+                    .unwrap();
+                found.send(SearchResult::Done(path)).unwrap();
+                continue;
+            }
+        };
+        for item in entries {
+            // Once reading the directory fails, the rest of it can't be relied on, so it's given up on.
+            let entry = match item {
+                Ok(entry) => entry,
+                Err(err) => {
+                    found
+                        .send(SearchResult::Unreadable(path.clone(), err))
+                        .unwrap();
+                    break;
+                }
+            };
+            let mut metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    found
+                        .send(SearchResult::Unreadable(entry.path(), err))
+                        .unwrap();
+                    continue;
+                }
+            };
+            // The entry's name joined onto the directory being read, never where a symlink points, so everything
+            // found starts with the path the search started from.
+            let path = entry.path();
+            if let Ok(relative) = path.strip_prefix(base) {
+                if !filter::included(&opts.filters, relative, &metadata) {
+                    continue;
+                }
+            }
+            if metadata.file_type().is_symlink() {
+                // Junctions and directory symlinks on Windows can lead anywhere, including back up the tree, so
+                // they're recreated as links unless `--follow-junctions` says to go through them.
+                let mut recreate = opts.preserve_symlinks
+                    || (links::is_dir_link(&metadata) && !opts.follow_junctions);
+                if !recreate {
+                    // The link is followed, so what matters is the metadata of whatever it points to.
+                    match std::fs::metadata(&path) {
+                        Ok(target_metadata)
+                            if target_metadata.is_dir() && !followed.follow(&path) =>
+                        {
+                            message!(
+                                "{} leads to a directory that's already being copied, so it's copied as a link",
+                                path.display()
+                            );
+                            recreate = true;
+                        }
+                        Ok(target_metadata) => metadata = target_metadata,
+                        Err(_) => {}
+                    }
+                }
+                if recreate {
+                    let result_info = ResultInfo { path, metadata };
+                    found.send(SearchResult::Symlink(result_info)).unwrap();
+                    continue;
+                }
+            }
+            if path.is_dir() {
+                let result_info = ResultInfo { path, metadata };
+                found.send(SearchResult::Directory(result_info)).unwrap();
+            } else if is_special(&metadata.file_type()) {
+                let result_info = ResultInfo { path, metadata };
+                found.send(SearchResult::Special(result_info)).unwrap();
+            } else {
+                let result_info = ResultInfo { path, metadata };
+                found.send(SearchResult::File(result_info)).unwrap();
+            }
+        }
+        found.send(SearchResult::Done(path)).unwrap();
+    }
+}
+
+/// Returns true for FIFOs, sockets, and device nodes. Copying these would block forever or fail.
+fn is_special(file_type: &std::fs::FileType) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        file_type.is_fifo()
+            || file_type.is_socket()
+            || file_type.is_block_device()
+            || file_type.is_char_device()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = file_type;
+        false
+    }
+}
+
+/// How often the search and copy loops check for progress and status requests while no messages are arriving.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The size from which a single-file source is split into chunks copied on several threads at once, unless
+/// `split_large_files` says otherwise.
+const SINGLE_FILE_SPLIT_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// A copy thread's id, the stats for the item it just finished, and that item if it was a file that was copied.
+struct ThreadReady(usize, Accumulator, Option<CopiedFile>);
+
+/// A file a copy thread copied or linked into place.
+struct CopiedFile {
+    /// The file's path relative to the source.
+    relative: PathBuf,
+    /// Where the file ended up, which is only somewhere other than the same relative path in the destination with
+    /// `--rename-collisions`, or when its destination path was changed with `--remap` and the like.
+    dst: PathBuf,
+    /// The file's digest, if `--checksum-file` needs it.
+    digest: Option<Digest>,
+    /// The size it was copied at, and the modified time it was given, if any, for `--audit`.
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// The modified time a copy of a file with `metadata` was given, for `--audit`, unless times aren't preserved or the
+/// copy was replaced with a link to an identical file, which `stats` for it says.
+fn preserved_time(
+    metadata: &Metadata,
+    stats: &Accumulator,
+    opts: &CopyOptions,
+) -> Option<SystemTime> {
+    if opts.no_preserve_times || stats.file_count_deduped > 0 {
+        return None;
+    }
+    metadata.modified().ok()
+}
+
+/// Copy threads report back over a channel of these. `Ok` means the thread is ready for another item, and `Err` is
+/// always the last message a thread sends before it exits.
+type ThreadMessage = Result<ThreadReady, CopyError>;
+
+#[allow(clippy::too_many_arguments)]
+fn copy_thread(
+    thread_id: usize,
+    copy_base: PathBuf,
+    dest_base: PathBuf,
+    request_sender: Sender<ThreadMessage>,
+    path_receiver: Receiver<WorkItem>,
+    split_sender: Sender<Chunk>,
+    opts: Arc<CopyOptions>,
+    cancelled: CancellationToken,
+    throttle: Arc<Throttle>,
+    hard_links: Arc<HardLinks>,
+    case_collisions: Arc<HashSet<PathBuf>>,
+) {
+    let mut buffer = CopyBuffer::new(opts.buffer_size);
+    // The destination directory of the last file copied, which exists.
+    let mut last_dir: Option<PathBuf> = None;
+    if request_sender
+        .send(Ok(ThreadReady(thread_id, Accumulator::default(), None)))
+        .is_ok()
+    {
+        for item in path_receiver {
+            let result = match item {
+                WorkItem::Found(SearchResult::File(file_result)) if opts.verify_only => {
+                    // Nothing is copied, the destination is only compared against the source.
+                    let relative =
+                        match relative_path(&file_result.path, &copy_base, thread_id, &opts) {
+                            Ok(relative) => relative,
+                            Err(message) => {
+                                let failed = message.is_err();
+                                if request_sender.send(message).is_err() || failed {
+                                    return;
+                                }
+                                continue;
+                            }
+                        };
+                    let message = verify_copy(
+                        &file_result.path,
+                        &dest_base.join(destination_relative(relative, &opts)),
+                        None,
+                        &opts,
+                        &mut buffer,
+                    )
+                    .map(|(verified, _)| ThreadReady(thread_id, verified, None));
+                    let failed = message.is_err();
+                    if request_sender.send(message).is_err() || failed {
+                        return;
+                    }
+                    continue;
+                }
+                WorkItem::Found(SearchResult::Directory(_) | SearchResult::Symlink(_))
+                    if opts.verify_only =>
+                {
+                    if request_sender
+                        .send(Ok(ThreadReady(thread_id, Accumulator::default(), None)))
+                        .is_err()
+                    {
+                        return;
+                    }
+                    continue;
+                }
+                WorkItem::Found(result) => result,
+                WorkItem::Chunk(chunk) => {
+                    let message = copy_chunk(chunk, &opts, &throttle, &mut buffer, &hard_links)
+                        .map(|(accumulator, copied)| ThreadReady(thread_id, accumulator, copied));
+                    let failed = message.is_err();
+                    if request_sender.send(message).is_err() || failed {
+                        return;
+                    }
+                    continue;
+                }
+            };
+            let (accumulator, copied) = match result {
+                SearchResult::File(file_result) => {
+                    let relative =
+                        match relative_path(&file_result.path, &copy_base, thread_id, &opts) {
+                            Ok(relative) => relative,
+                            Err(message) => {
+                                let failed = message.is_err();
+                                if request_sender.send(message).is_err() || failed {
+                                    return;
+                                }
+                                continue;
+                            }
+                        };
+                    let dst_relative = destination_relative(relative, &opts);
+                    let mut new_path = dest_base.join(&dst_relative);
+                    let mut skipped: bool = false;
+                    // Bytes read comparing checksums with a destination file that turned out to be different.
+                    let mut hashing = Accumulator::default();
+                    // The `--policy` rule for this file, if one matches, decides what happens if it exists instead of
+                    // the flags.
+                    let rule = opts.policy_for(relative);
+                    let (skip, overwrite, update, checksum, rename_collisions) =
+                        existing_policy(rule, &opts);
+                    let mut decided = Accumulator::default();
+                    if !file_result.path.exists() {
+                        message!(
+                            "File found during scan no longer exists: {:?}",
+                            file_result.path.as_os_str()
+                        );
+                        if request_sender
+                            .send(Ok(ThreadReady(
+                                thread_id,
+                                Accumulator::vanished(file_result.metadata.len()),
+                                None,
+                            )))
+                            .is_err()
+                        {
+                            return;
+                        }
+                        continue;
+                    }
+                    #[cfg(test)]
+                    if let Some(err) = opts
+                        .before_copy
+                        .as_ref()
+                        .and_then(|hook| (hook.0)(thread_id, &file_result.path, &new_path))
+                    {
+                        let _ = request_sender.send(Err(err));
+                        return;
+                    }
+                    if new_path.exists() {
+                        if update || checksum {
+                            let (up_to_date, hashed) = copy::is_up_to_date(
+                                &file_result.path,
+                                &file_result.metadata,
+                                &new_path,
+                                update,
+                                &opts,
+                                &mut buffer,
+                            );
+                            if up_to_date {
+                                let decided = rule
+                                    .map_or_else(Accumulator::default, |(rule, _)| {
+                                        Accumulator::policy_applied(rule, false)
+                                    });
+                                let moved = match move_up_to_date(
+                                    &file_result.path,
+                                    &new_path,
+                                    file_result.metadata.len(),
+                                    &opts,
+                                    &mut buffer,
+                                ) {
+                                    Ok(moved) => moved,
+                                    Err(err) => {
+                                        let _ = request_sender.send(Err(err));
+                                        return;
+                                    }
+                                };
+                                if request_sender
+                                    .send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::up_to_date(1, file_result.metadata.len())
+                                            + Accumulator::hashed(hashed)
+                                            + decided
+                                            + moved,
+                                        None,
+                                    )))
+                                    .is_err()
+                                {
+                                    return;
+                                }
+                                continue;
+                            }
+                            hashing = Accumulator::hashed(hashed);
+                        }
+                        if !skip && !overwrite && !update && !checksum && !rename_collisions {
+                            if opts.continue_on_error {
+                                message!(
+                                    "File already exists at destination: {:?}",
+                                    file_result.path.as_os_str()
+                                );
+                                let _ = request_sender.send(Ok(ThreadReady(
+                                    thread_id,
+                                    Accumulator::failed(1),
+                                    None,
+                                )));
+                                continue;
+                            }
+                            // If many files exist at the destination, all of the threads will hit this condition, but the first one to hit it will
+                            // succeed with this send. Ignore the result and just kill the thread either way.
+                            let _ = request_sender.send(Err(CopyError::CannotOverwrite {
+                                src: file_result.path,
+                                dst: new_path,
+                            }));
+                            return;
+                        }
+                        if skip {
+                            if rule.is_none() && (opts.copy_if_larger || opts.copy_if_newer) {
+                                let metadata = std::fs::metadata(&new_path)
+                                    .map_err(|err| (new_path.clone(), err))
+                                    .and_then(|new_meta| {
+                                        std::fs::metadata(&file_result.path)
+                                            .map(|old_meta| (new_meta, old_meta))
+                                            .map_err(|err| (file_result.path.clone(), err))
+                                    });
+                                match metadata {
+                                    Ok((new_meta, old_meta)) => {
+                                        match (new_meta.modified(), old_meta.modified()) {
+                                            (Ok(new_modified), Ok(old_modified)) => {
+                                                skipped = !((new_meta.len() < old_meta.len()
+                                                    && opts.copy_if_larger)
+                                                    || (new_modified < old_modified
+                                                        && opts.copy_if_newer))
+                                            }
+                                            (Err(err), _) | (_, Err(err)) => {
+                                                if opts.continue_on_error {
+                                                    message!(
+                                                        "copy-if-newer specified but unable to read modified time: {:?}",
+                                                        file_result.path.as_os_str()
+                                                    );
+                                                    let _ = request_sender.send(Ok(ThreadReady(
+                                                        thread_id,
+                                                        Accumulator::failed(1),
+                                                        None,
+                                                    )));
+                                                    continue;
+                                                }
+                                                let _ = request_sender.send(Err(
+                                                    CopyError::MetadataFailed {
+                                                        path: new_path,
+                                                        source: err,
+                                                    },
+                                                ));
+                                                return;
+                                            }
+                                        }
+                                    }
+                                    Err((path, err)) => {
+                                        if opts.continue_on_error {
+                                            message!(
+                                                "copy-if-newer or copy-if-larger specified but unable to read file size: {:?}",
+                                                file_result.path.as_os_str()
+                                            );
+                                            let _ = request_sender.send(Ok(ThreadReady(
+                                                thread_id,
+                                                Accumulator::failed(1),
+                                                None,
+                                            )));
+                                            continue;
+                                        }
+                                        let _ =
+                                            request_sender.send(Err(CopyError::MetadataFailed {
+                                                path,
+                                                source: err,
+                                            }));
+                                        return;
+                                    }
+                                }
+                            } else {
+                                skipped = true;
+                            }
+                        }
+                        if let Some((rule, _)) = rule {
+                            decided = Accumulator::policy_applied(rule, !skipped);
+                        }
+                    }
+                    // With `--rename-collisions`, an empty file is created under the first free name to claim it. Files
+                    // that would collide with another one on a case-insensitive destination always are, even if that
+                    // one hasn't been copied yet.
+                    let mut claimed = false;
+                    if !skipped
+                        && rename_collisions
+                        && (case_collisions.contains(&file_result.path)
+                            || std::fs::symlink_metadata(&new_path).is_ok())
+                    {
+                        let result = copy::with_free_name(&new_path, |candidate| {
+                            OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(candidate)
+                        });
+                        match result {
+                            Ok((free, _)) => {
+                                message!(
+                                    "{} already exists, copying {} to {} instead",
+                                    new_path.display(),
+                                    file_result.path.display(),
+                                    free.display()
+                                );
+                                new_path = free;
+                                claimed = true;
+                            }
+                            Err(err) => {
+                                if opts.continue_on_error {
+                                    message!(
+                                        "Unable to find a free name for {}: {}",
+                                        new_path.display(),
+                                        err
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ = request_sender.send(Err(CopyError::CopyFailed {
+                                    src: file_result.path,
+                                    dst: new_path,
+                                    source: err,
+                                    attempts: 1,
+                                    partial_removed: false,
+                                }));
+                                return;
+                            }
+                        }
+                    }
+                    if !skipped {
+                        let dir = new_path.parent().unwrap();
+                        // With `--locality` the files of a directory come one after another, so the directory is
+                        // only looked for once.
+                        let known = opts.locality && last_dir.as_deref() == Some(dir);
+                        if !known && !dir.exists() {
+                            let (result, attempts) = copy::with_retries(&opts, || {
+                                std::fs::DirBuilder::new().recursive(true).create(dir)
+                            });
+                            if let Err(err) = result {
+                                if opts.continue_on_error {
+                                    message!(
+                                        "Unable to create path for file: {:?}",
+                                        file_result.path.as_os_str()
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ =
+                                    request_sender.send(Err(CopyError::DirectoryCreationFailed {
+                                        path: dir.to_path_buf(),
+                                        source: err,
+                                        attempts,
+                                    }));
+                                return;
+                            }
+                        }
+                        if opts.locality && !known {
+                            last_dir = Some(dir.to_path_buf());
+                        }
+                        // What replacing an existing file took: moving it out of the way, and the `--policy` rule
+                        // that decided to.
+                        let replaced = match copy::backup(&new_path, &dst_relative, &opts) {
+                            Ok(backed_up) => Accumulator::backed_up(backed_up) + decided,
+                            Err(err) => {
+                                if opts.continue_on_error {
+                                    message!(
+                                        "Unable to back up {}, so it wasn't overwritten: {}",
+                                        new_path.display(),
+                                        err
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ = request_sender.send(Err(CopyError::BackupFailed {
+                                    path: new_path,
+                                    source: err,
+                                }));
+                                return;
+                            }
+                        };
+                        // Renaming fails across filesystems, and anything else that stops it will stop the copy too
+                        // and be reported from there. Followed symlinks are copied, since renaming one would move
+                        // the link rather than what it points to.
+                        if opts.move_files
+                            && !file_result.path.is_symlink()
+                            && std::fs::rename(&file_result.path, &new_path).is_ok()
+                        {
+                            let len = file_result.metadata.len();
+                            let digest = match manifest_digest(&new_path, &opts, &mut buffer) {
+                                Ok(digest) => digest,
+                                Err(err) => {
+                                    let _ = request_sender.send(Err(err));
+                                    return;
+                                }
+                            };
+                            let deduped =
+                                dedupe(&new_path, len, digest.as_ref(), &hard_links, &opts);
+                            if request_sender
+                                .send(Ok(ThreadReady(
+                                    thread_id,
+                                    Accumulator::copies(1, len)
+                                        + Accumulator::moves(1, len)
+                                        + replaced
+                                        + deduped.clone(),
+                                    Some(CopiedFile {
+                                        relative: relative.to_path_buf(),
+                                        dst: new_path.clone(),
+                                        digest,
+                                        len,
+                                        modified: preserved_time(
+                                            &file_result.metadata,
+                                            &deduped,
+                                            &opts,
+                                        ),
+                                    }),
+                                )))
+                                .is_err()
+                            {
+                                return;
+                            }
+                            continue;
+                        }
+                        if let Some(mode) = opts.link {
+                            match links::hard_link(&file_result.path, &new_path) {
+                                Ok(()) => {
+                                    if request_sender
+                                        .send(Ok(ThreadReady(
+                                            thread_id,
+                                            Accumulator::links(1, file_result.metadata.len())
+                                                + replaced,
+                                            Some(CopiedFile {
+                                                relative: relative.to_path_buf(),
+                                                dst: new_path.clone(),
+                                                digest: None,
+                                                len: file_result.metadata.len(),
+                                                modified: preserved_time(
+                                                    &file_result.metadata,
+                                                    &Accumulator::default(),
+                                                    &opts,
+                                                ),
+                                            }),
+                                        )))
+                                        .is_err()
+                                    {
+                                        return;
+                                    }
+                                    continue;
+                                }
+                                Err(err)
+                                    if err.kind() == ErrorKind::CrossesDevices
+                                        && mode == LinkMode::Fallback =>
+                                {
+                                    links::warn_cross_device();
+                                }
+                                Err(err) => {
+                                    if opts.continue_on_error {
+                                        message!(
+                                            "Unable to link file: {:?}: {}",
+                                            file_result.path.as_os_str(),
+                                            err
+                                        );
+                                        let _ = request_sender.send(Ok(ThreadReady(
+                                            thread_id,
+                                            Accumulator::failed(1),
+                                            None,
+                                        )));
+                                        continue;
+                                    }
+                                    let _ = request_sender.send(Err(CopyError::CopyFailed {
+                                        src: file_result.path,
+                                        dst: new_path,
+                                        source: err,
+                                        attempts: 1,
+                                        partial_removed: false,
+                                    }));
+                                    return;
+                                }
+                            }
+                        }
+                        let file_id = match opts.hard_links {
+                            true => links::file_id(&file_result.path, &file_result.metadata),
+                            false => None,
+                        };
+                        if let Some(first_copy) = file_id.and_then(|id| hard_links.first_copy(id)) {
+                            match links::hard_link(&first_copy, &new_path) {
+                                Ok(()) => {
+                                    if request_sender
+                                        .send(Ok(ThreadReady(
+                                            thread_id,
+                                            Accumulator::links(1, file_result.metadata.len())
+                                                + replaced
+                                                + remove_source(
+                                                    &file_result.path,
+                                                    file_result.metadata.len(),
+                                                    &opts,
+                                                ),
+                                            Some(CopiedFile {
+                                                relative: relative.to_path_buf(),
+                                                dst: new_path.clone(),
+                                                digest: None,
+                                                len: file_result.metadata.len(),
+                                                modified: preserved_time(
+                                                    &file_result.metadata,
+                                                    &Accumulator::default(),
+                                                    &opts,
+                                                ),
+                                            }),
+                                        )))
+                                        .is_err()
+                                    {
+                                        return;
+                                    }
+                                    continue;
+                                }
+                                Err(err) => message!(
+                                    "Warning: unable to link {} to {}, copying it instead: {}",
+                                    new_path.display(),
+                                    first_copy.display(),
+                                    err
+                                ),
+                            }
+                        }
+                        if opts
+                            .split_large_files
+                            .is_some_and(|threshold| file_result.metadata.len() > threshold)
+                        {
+                            match SplitFile::split(
+                                file_result.path.clone(),
+                                new_path.clone(),
+                                relative.to_path_buf(),
+                                file_result.metadata.clone(),
+                                file_id,
+                                &opts,
+                            ) {
+                                Ok(chunks) => {
+                                    // The chunks have to reach the dispatcher before this thread says it's ready.
+                                    for chunk in chunks {
+                                        let _ = split_sender.send(chunk);
+                                    }
+                                    if request_sender
+                                        .send(Ok(ThreadReady(thread_id, replaced, None)))
+                                        .is_err()
+                                    {
+                                        return;
+                                    }
+                                    continue;
+                                }
+                                Err(err) => {
+                                    if opts.continue_on_error {
+                                        message!(
+                                            "Error copying file: {:?}: {}",
+                                            file_result.path.as_os_str(),
+                                            err
+                                        );
+                                        let _ = request_sender.send(Ok(ThreadReady(
+                                            thread_id,
+                                            Accumulator::failed(1),
+                                            None,
+                                        )));
+                                        continue;
+                                    }
+                                    let _ = request_sender.send(Err(CopyError::CopyFailed {
+                                        src: file_result.path,
+                                        dst: new_path,
+                                        source: err,
+                                        attempts: 1,
+                                        partial_removed: false,
+                                    }));
+                                    return;
+                                }
+                            }
+                        }
+                        // The empty file claiming a free name is as good as not there, and is removed if the copy
+                        // fails.
+                        let before = match claimed {
+                            true => None,
+                            false => std::fs::metadata(&new_path).ok(),
+                        };
+                        let read_only = before
+                            .as_ref()
+                            .is_some_and(|metadata| metadata.permissions().readonly());
+                        let mut forced = false;
+                        let (result, attempts) = loop {
+                            let (result, attempts) = copy::with_retries(&opts, || {
+                                copy::copy_file(
+                                    &file_result.path,
+                                    &new_path,
+                                    file_result.metadata.len(),
+                                    &opts,
+                                    &cancelled,
+                                    &throttle,
+                                    &mut buffer,
+                                )
+                            });
+                            match &result {
+                                Err(err) if opts.wait_on_full && copy::is_storage_full(err) => {
+                                    copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                    if !copy::wait_for_space(
+                                        dir,
+                                        file_result.metadata.len(),
+                                        &cancelled,
+                                    ) {
+                                        break (result, attempts);
+                                    }
+                                }
+                                Err(err)
+                                    if opts.force
+                                        && read_only
+                                        && !forced
+                                        && err.kind() == ErrorKind::PermissionDenied =>
+                                {
+                                    forced = true;
+                                    if let Err(err) = copy::make_writable(&new_path) {
+                                        message!(
+                                            "Warning: unable to make {} writable: {}",
+                                            new_path.display(),
+                                            err
+                                        );
+                                        break (result, attempts);
+                                    }
+                                }
+                                _ => break (result, attempts),
+                            }
+                        };
+                        let mut copied = match result {
+                            Ok(copied) => copied,
+                            Err(err) if copy::is_cancelled(&err) => {
+                                copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                let _ = request_sender.send(Ok(ThreadReady(
+                                    thread_id,
+                                    Accumulator::skips(1, 0),
+                                    None,
+                                )));
+                                continue;
+                            }
+                            Err(err) if copy::is_storage_full(&err) => {
+                                let partial_removed =
+                                    copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                if opts.continue_on_error {
+                                    message!(
+                                        "Destination full copying file: {:?}",
+                                        file_result.path.as_os_str()
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1)
+                                            + Accumulator::partials_removed(partial_removed),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ = request_sender.send(Err(CopyError::DestinationFull {
+                                    dst: new_path,
+                                    remaining: 0,
+                                }));
+                                return;
+                            }
+                            Err(err) if copy::is_locked(&err) => {
+                                let partial_removed =
+                                    copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                if opts.skip_locked {
+                                    message!(
+                                        "Skipping locked file: {:?}",
+                                        file_result.path.as_os_str()
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::locked(1)
+                                            + Accumulator::partials_removed(partial_removed),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let err = CopyError::Locked {
+                                    src: file_result.path,
+                                    source: err,
+                                    attempts,
+                                };
+                                if opts.continue_on_error {
+                                    log_errors(
+                                        std::slice::from_ref(&err),
+                                        opts.error_log.as_deref(),
+                                    );
+                                    reporter::error(err);
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1)
+                                            + Accumulator::partials_removed(partial_removed),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ = request_sender.send(Err(err));
+                                return;
+                            }
+                            Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                                let partial_removed =
+                                    copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                let unreadable = std::fs::File::open(&file_result.path)
+                                    .is_err_and(|err| err.kind() == ErrorKind::PermissionDenied);
+                                if opts.continue_on_error && unreadable {
+                                    let err = CopyError::Unreadable {
+                                        path: file_result.path,
+                                        source: err,
+                                    };
+                                    log_errors(
+                                        std::slice::from_ref(&err),
+                                        opts.error_log.as_deref(),
+                                    );
+                                    reporter::error(err);
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::unreadable_files(1)
+                                            + Accumulator::partials_removed(partial_removed),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                if opts.continue_on_error {
+                                    message!(
+                                        "Permission Denied copying file: {:?}{}{}",
+                                        file_result.path.as_os_str(),
+                                        errors::read_only_note(read_only),
+                                        if partial_removed {
+                                            " (partial file removed)"
+                                        } else {
+                                            ""
+                                        }
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1)
+                                            + Accumulator::partials_removed(partial_removed),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ = request_sender.send(Err(CopyError::AccessDenied {
+                                    src: file_result.path,
+                                    dst: new_path,
+                                    source: err,
+                                    attempts,
+                                    partial_removed,
+                                    read_only,
+                                }));
+                                return;
+                            }
+                            Err(err) => {
+                                let partial_removed =
+                                    copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                if opts.continue_on_error {
+                                    message!(
+                                        "Error copying file: {:?}: {}{}",
+                                        file_result.path.as_os_str(),
+                                        err,
+                                        if partial_removed {
+                                            " (partial file removed)"
+                                        } else {
+                                            ""
+                                        }
+                                    );
+                                    let _ = request_sender.send(Ok(ThreadReady(
+                                        thread_id,
+                                        Accumulator::failed(1)
+                                            + Accumulator::partials_removed(partial_removed),
+                                        None,
+                                    )));
+                                    continue;
+                                }
+                                let _ = request_sender.send(Err(CopyError::CopyFailed {
+                                    src: file_result.path,
+                                    dst: new_path,
+                                    source: err,
+                                    attempts,
+                                    partial_removed,
+                                }));
+                                return;
+                            }
+                        };
+                        // A live source can be written to after the search found it, or while it's being copied.
+                        let found = file_result.metadata.len();
+                        let mut now = std::fs::metadata(&file_result.path).ok();
+                        let mut recopied = false;
+                        if opts.recopy_changed
+                            && now.as_ref().is_some_and(|now| now.len() != copied.bytes)
+                        {
+                            message!(
+                                "File changed size while being copied, copying it again: {:?}",
+                                file_result.path.as_os_str()
+                            );
+                            let len = now.as_ref().map_or(found, Metadata::len);
+                            let (result, _) = copy::with_retries(&opts, || {
+                                copy::copy_file(
+                                    &file_result.path,
+                                    &new_path,
+                                    len,
+                                    &opts,
+                                    &cancelled,
+                                    &throttle,
+                                    &mut buffer,
+                                )
+                            });
+                            match result {
+                                Ok(again) => copied = again,
+                                Err(err) => {
+                                    let partial_removed =
+                                        copy::remove_partial(&new_path, before.as_ref(), &opts);
+                                    if opts.continue_on_error {
+                                        message!(
+                                            "Error copying file again: {:?}: {}",
+                                            file_result.path.as_os_str(),
+                                            err
+                                        );
+                                        let _ = request_sender.send(Ok(ThreadReady(
+                                            thread_id,
+                                            Accumulator::failed(1)
+                                                + Accumulator::partials_removed(partial_removed),
+                                            None,
+                                        )));
+                                        continue;
+                                    }
+                                    let _ = request_sender.send(Err(CopyError::CopyFailed {
+                                        src: file_result.path,
+                                        dst: new_path,
+                                        source: err,
+                                        attempts: 2,
+                                        partial_removed,
+                                    }));
+                                    return;
+                                }
+                            }
+                            now = std::fs::metadata(&file_result.path).ok();
+                            recopied = true;
+                        }
+                        let now_len = now.as_ref().map_or(copied.bytes, Metadata::len);
+                        let changed = match copied.bytes != found || now_len != copied.bytes {
+                            true => {
+                                message!(
+                                    "File changed size during copy: {:?} (found at {} bytes, copied at {}, now {})",
+                                    file_result.path.as_os_str(),
+                                    found,
+                                    copied.bytes,
+                                    now_len
+                                );
+                                Accumulator::changed(found, copied.bytes)
+                            }
+                            false => Accumulator::default(),
+                        };
+                        // The copy is of the file as it is now, not as it was found.
+                        let src_metadata = match changed.file_count_changed {
+                            0 => &file_result.metadata,
+                            _ => now.as_ref().unwrap_or(&file_result.metadata),
+                        };
+                        let preserved =
+                            preserve_file(&file_result.path, &new_path, src_metadata, &opts);
+                        if let Some(id) = file_id {
+                            hard_links.copied(id, &new_path);
+                        }
+                        let (verified, digest) = match check_copy(
+                            &file_result.path,
+                            &new_path,
+                            file_result.metadata.len(),
+                            copied.digest.clone(),
+                            &opts,
+                            &mut buffer,
+                        ) {
+                            Ok(checked) => checked,
+                            Err(err) => {
+                                let _ = request_sender.send(Err(err));
+                                return;
+                            }
+                        };
+                        // A copy that failed verification is left where it is.
+                        let moved = match verified.file_count_verify_failed {
+                            0 => {
+                                dedupe(&new_path, copied.bytes, digest.as_ref(), &hard_links, &opts)
+                                    + remove_source(
+                                        &file_result.path,
+                                        file_result.metadata.len(),
+                                        &opts,
+                                    )
+                            }
+                            _ => Accumulator::default(),
+                        };
+                        (
+                            match copied.cloned {
+                                true => Accumulator::clones(1, copied.bytes),
+                                false => Accumulator::copies(1, copied.bytes),
+                            } + Accumulator::sparse(
+                                copied.holes.is_some() as u64,
+                                copied.holes.map_or(0, |_| copied.bytes),
+                                copied.holes.unwrap_or(0),
+                            ) + Accumulator::delta(
+                                copied.delta_written.map_or(0, |_| copied.bytes),
+                                copied.delta_written.unwrap_or(0),
+                            ) + Accumulator::synced(copied.sync_time)
+                                + Accumulator::retried(attempts > 1)
+                                + Accumulator::recopied(recopied)
+                                + changed
+                                + hashing
+                                + replaced
+                                + preserved
+                                + moved.clone()
+                                + verified,
+                            Some(CopiedFile {
+                                relative: relative.to_path_buf(),
+                                dst: new_path.clone(),
+                                digest,
+                                len: copied.bytes,
+                                modified: preserved_time(src_metadata, &moved, &opts),
+                            }),
+                        )
+                    } else {
+                        (
+                            Accumulator::skips(1, file_result.metadata.len()) + decided,
+                            None,
+                        )
+                    }
+                }
+                // Files create their own parent directories, so with `--prune-empty-dirs` or `--files-only` only the
+                // ones that get something copied into them are created.
+                SearchResult::Directory(_) if opts.prune_empty_dirs || opts.files_only => {
+                    (Accumulator::default(), None)
+                }
+                SearchResult::Directory(dir_result) => {
+                    let relative =
+                        match relative_path(&dir_result.path, &copy_base, thread_id, &opts) {
+                            Ok(relative) => relative,
+                            Err(message) => {
+                                let failed = message.is_err();
+                                if request_sender.send(message).is_err() || failed {
+                                    return;
+                                }
+                                continue;
+                            }
+                        };
+                    let new_path = dest_base.join(destination_relative(relative, &opts));
+                    let (result, attempts) = copy::with_retries(&opts, || {
+                        std::fs::DirBuilder::new().recursive(true).create(&new_path)
+                    });
+                    if let Err(err) = result {
+                        let _ = request_sender.send(Err(CopyError::DirectoryCreationFailed {
+                            path: new_path,
+                            source: err,
+                            attempts,
+                        }));
+                        return;
+                    }
+                    (Accumulator::dirs_created(1), None)
+                }
+                SearchResult::Symlink(link_result) => {
+                    let relative =
+                        match relative_path(&link_result.path, &copy_base, thread_id, &opts) {
+                            Ok(relative) => relative,
+                            Err(message) => {
+                                let failed = message.is_err();
+                                if request_sender.send(message).is_err() || failed {
+                                    return;
+                                }
+                                continue;
+                            }
+                        };
+                    let dst_relative = destination_relative(relative, &opts);
+                    let new_path = dest_base.join(&dst_relative);
+                    let exists = std::fs::symlink_metadata(&new_path).is_ok();
+                    let rule = opts.policy_for(relative);
+                    let (skip, overwrite, update, checksum, rename_collisions) =
+                        existing_policy(rule, &opts);
+                    let decided = match (exists, rule) {
+                        (true, Some((rule, _))) => Accumulator::policy_applied(rule, !skip),
+                        _ => Accumulator::default(),
+                    };
+                    if exists && !skip && !overwrite && !update && !checksum && !rename_collisions {
+                        if opts.continue_on_error {
+                            message!(
+                                "File already exists at destination: {:?}",
+                                link_result.path.as_os_str()
+                            );
+                            let _ = request_sender.send(Ok(ThreadReady(
+                                thread_id,
+                                Accumulator::failed(1),
+                                None,
+                            )));
+                            continue;
+                        }
+                        let _ = request_sender.send(Err(CopyError::CannotOverwrite {
+                            src: link_result.path,
+                            dst: new_path,
+                        }));
+                        return;
+                    }
+                    if exists && skip {
+                        (Accumulator::skips(1, 0) + decided, None)
+                    } else {
+                        let mut backed_up = false;
+                        let result = std::fs::DirBuilder::new()
+                            .recursive(true)
+                            .create(new_path.parent().unwrap())
+                            .and_then(|_| {
+                                if rename_collisions
+                                    && (exists || case_collisions.contains(&link_result.path))
+                                {
+                                    let (free, ()) =
+                                        copy::with_free_name(&new_path, |candidate| {
+                                            copy::copy_symlink(&link_result.path, candidate)
+                                        })?;
+                                    message!(
+                                        "{} already exists, copying {} to {} instead",
+                                        new_path.display(),
+                                        link_result.path.display(),
+                                        free.display()
+                                    );
+                                    return Ok(());
+                                }
+                                if exists {
+                                    backed_up = copy::backup(&new_path, &dst_relative, &opts)?;
+                                    if !backed_up {
+                                        std::fs::remove_file(&new_path)?;
+                                    }
+                                }
+                                copy::copy_symlink(&link_result.path, &new_path)
+                            });
+                        if let Err(err) = result {
+                            if opts.continue_on_error {
+                                message!(
+                                    "Unable to copy symbolic link {:?}: {}",
+                                    link_result.path.as_os_str(),
+                                    err
+                                );
+                                let _ = request_sender.send(Ok(ThreadReady(
+                                    thread_id,
+                                    Accumulator::failed(1),
+                                    None,
+                                )));
+                                continue;
+                            }
+                            let _ = request_sender.send(Err(CopyError::CopyFailed {
+                                src: link_result.path,
+                                dst: new_path,
+                                source: err,
+                                attempts: 1,
+                                partial_removed: false,
+                            }));
+                            return;
+                        }
+                        (
+                            Accumulator::copies(1, 0)
+                                + Accumulator::backed_up(backed_up)
+                                + decided
+                                + remove_source(&link_result.path, 0, &opts),
+                            None,
+                        )
+                    }
+                }
+                SearchResult::Special(_) => (Accumulator::specials_skipped(1), None),
+                SearchResult::Unreadable(..) | SearchResult::Done(_) => {
+                    (Accumulator::default(), None)
+                }
+            };
+
+            // This only fails if the main thread is exiting so we can let the thread die.
+            if request_sender
+                .send(Ok(ThreadReady(thread_id, accumulator, copied)))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// What to do with a file that already exists at the destination, as `(skip, overwrite, update, checksum,
+/// rename_collisions)`: what `rule`, the `--policy` rule the file matches, says if there is one, and the flags if not.
+fn existing_policy(
+    rule: Option<(usize, Policy)>,
+    opts: &CopyOptions,
+) -> (bool, bool, bool, bool, bool) {
+    match rule {
+        Some((_, policy)) => (
+            policy == Policy::Skip,
+            policy == Policy::Overwrite,
+            policy == Policy::Update,
+            policy == Policy::Update && opts.checksum,
+            false,
+        ),
+        None => (
+            opts.skip,
+            opts.overwrite,
+            opts.update,
+            opts.checksum,
+            opts.rename_collisions,
+        ),
+    }
+}
+
+/// `path` relative to `base`, the source directory every path the search finds is under. A path that somehow isn't
+/// can't be copied anywhere, so instead of the path this returns the message to send for it: an error, or with
+/// `--continue-on-error`, a skip once the error's been printed.
+#[allow(clippy::result_large_err)]
+fn relative_path<'a>(
+    path: &'a Path,
+    base: &Path,
+    thread_id: usize,
+    opts: &CopyOptions,
+) -> Result<&'a Path, ThreadMessage> {
+    path.strip_prefix(base).map_err(|_| {
+        let err = CopyError::OutsideSource {
+            path: path.to_path_buf(),
+            base: base.to_path_buf(),
+        };
+        match opts.continue_on_error {
+            true => {
+                reporter::error(err);
+                Ok(ThreadReady(thread_id, Accumulator::failed(1), None))
+            }
+            false => Err(err),
+        }
+    })
+}
+
+/// Apply the source's metadata to a file that's just been copied, per the preservation options. Returns the stats for
+/// anything that couldn't be preserved.
+fn preserve_file(src: &Path, dst: &Path, metadata: &Metadata, opts: &CopyOptions) -> Accumulator {
+    preserve::apply(dst, metadata, opts);
+    let xattrs_failed = opts.xattrs && !preserve::copy_xattrs(src, dst);
+    let acls_failed = opts
+        .acls
+        .is_some_and(|mode| !preserve::copy_acls(src, dst, mode));
+    if !opts.no_preserve_times {
+        if let Err(err) = preserve::set_times(dst, metadata) {
+            message!("Warning: unable to set times on {}: {}", dst.display(), err);
+        }
+    }
+    Accumulator::xattrs_failed(xattrs_failed) + Accumulator::acls_failed(acls_failed)
+}
+
+/// Check a file of `len` bytes that's just been copied, verifying it against its source with `--verify` or
+/// `--verify-sample` and hashing it for `--checksum-file`. `src_digest` is the source's digest if it was hashed as it
+/// was copied, which saves reading it again. Returns the stats for the check and the file's digest, or the error to
+/// exit the thread with.
+fn check_copy(
+    src: &Path,
+    dst: &Path,
+    len: u64,
+    src_digest: Option<Digest>,
+    opts: &CopyOptions,
+    buffer: &mut CopyBuffer,
+) -> Result<(Accumulator, Option<Digest>), CopyError> {
+    let (verified, digest) = match opts.verifies(src, len) {
+        true if opts.verify => verify_copy(src, dst, src_digest, opts, buffer)?,
+        true => {
+            let (verified, digest) = verify_copy(src, dst, src_digest, opts, buffer)?;
+            (verified + Accumulator::sampled(1, len), digest)
+        }
+        // What was read from the source is what was written, so its digest is the destination's too.
+        false => (Accumulator::default(), src_digest),
+    };
+    // Files that failed verification have already been reported, and are left out of the checksum file.
+    if digest.is_some() || verified.file_count_verify_failed > 0 {
+        return Ok((verified, digest));
+    }
+    Ok((verified, manifest_digest(dst, opts, buffer)?))
+}
+
+/// Hash `dst` for `--checksum-file` or `--dedupe-dest`, if either is set. Returns the error to exit the thread with if `dst` can't be read,
+/// or with `--continue-on-error`, reports it and leaves the file out.
+fn manifest_digest(
+    dst: &Path,
+    opts: &CopyOptions,
+    buffer: &mut CopyBuffer,
+) -> Result<Option<Digest>, CopyError> {
+    if opts.checksum_file.is_none() && !opts.dedupe_dest {
+        return Ok(None);
+    }
+    match hash::hash_file(dst, opts.hash, buffer) {
+        Ok((digest, _)) => Ok(Some(digest)),
+        Err(err) => {
+            let err = CopyError::HashFailed {
+                path: dst.to_path_buf(),
+                source: err,
+            };
+            if !opts.continue_on_error {
+                return Err(err);
+            }
+            reporter::error(err);
+            Ok(None)
+        }
+    }
+}
+
+/// Replace `dst`, a copied file of `len` bytes, with a hard link to the first identical file copied, for
+/// `--dedupe-dest`. Failing to link it is only a warning, since the copy is still there.
+fn dedupe(
+    dst: &Path,
+    len: u64,
+    digest: Option<&Digest>,
+    hard_links: &HardLinks,
+    opts: &CopyOptions,
+) -> Accumulator {
+    let Some(digest) = digest.filter(|_| opts.dedupe_dest && len > 0) else {
+        return Accumulator::default();
+    };
+    let Some(first) = hard_links.first_with_contents(len, digest, dst) else {
+        return Accumulator::default();
+    };
+    match links::replace_with_link(&first, dst) {
+        Ok(()) => Accumulator::deduped(len),
+        Err(err) => {
+            message!(
+                "Warning: unable to link {} to identical file {}: {}",
+                dst.display(),
+                first.display(),
+                err
+            );
+            Accumulator::default()
+        }
+    }
+}
+
+/// Remove a source file of `len` bytes for `--move` once it's safely at the destination. Failing to remove it is only
+/// a warning, since the copy itself succeeded.
+fn remove_source(src: &Path, len: u64, opts: &CopyOptions) -> Accumulator {
+    if !opts.move_files {
+        return Accumulator::default();
+    }
+    match std::fs::remove_file(src) {
+        Ok(()) => Accumulator::moves(1, len),
+        Err(err) => {
+            message!(
+                "Warning: unable to remove {} after moving it: {}",
+                src.display(),
+                err
+            );
+            Accumulator::move_failed(1)
+        }
+    }
+}
+
+/// Remove a source file of `len` bytes for `--move` that was already up to date at `dst`, checking `dst` against it
+/// first if `--verify` or `--verify-sample` would have checked a copy of it. Returns the stats for the check and the
+/// move, or the error to exit the thread with. A source whose copy doesn't match is left where it is.
+fn move_up_to_date(
+    src: &Path,
+    dst: &Path,
+    len: u64,
+    opts: &CopyOptions,
+    buffer: &mut CopyBuffer,
+) -> Result<Accumulator, CopyError> {
+    if !opts.move_files || !opts.verifies(src, len) {
+        return Ok(remove_source(src, len, opts));
+    }
+    let (mut verified, _) = verify_copy(src, dst, None, opts, buffer)?;
+    if !opts.verify {
+        verified += Accumulator::sampled(1, len);
+    }
+    Ok(match verified.file_count_verify_failed {
+        0 => verified + remove_source(src, len, opts),
+        _ => verified,
+    })
+}
+
+/// Check `dst` against `src` for `--verify` by hashing both, or just `dst` if `src_digest` is already known. Returns
+/// the stats for the check and the destination's digest if it matched, or the error to exit the thread with. With
+/// `--continue-on-error`, files that don't match are reported and counted instead, except for a sample picked with
+/// `--verify-sample`, where a mismatch means the rest are likely corrupt too.
+fn verify_copy(
+    src: &Path,
+    dst: &Path,
+    src_digest: Option<Digest>,
+    opts: &CopyOptions,
+    buffer: &mut CopyBuffer,
+) -> Result<(Accumulator, Option<Digest>), CopyError> {
+    let src_hashed = match src_digest {
+        Some(src_digest) => Ok((src_digest, 0)),
+        None => hash::hash_file(src, opts.hash, buffer),
+    };
+    let result = src_hashed
+        .map_err(|err| CopyError::HashFailed {
+            path: src.to_path_buf(),
+            source: err,
+        })
+        .and_then(|(src_digest, src_read)| {
+            let (dst_digest, dst_read) =
+                hash::hash_file(dst, opts.hash, buffer).map_err(|err| CopyError::HashFailed {
+                    path: dst.to_path_buf(),
+                    source: err,
+                })?;
+            if src_digest != dst_digest {
+                return Err(CopyError::VerifyFailed {
+                    src: src.to_path_buf(),
+                    dst: dst.to_path_buf(),
+                    src_digest: src_digest.to_string(),
+                    dst_digest: dst_digest.to_string(),
+                });
+            }
+            Ok((
+                Accumulator::verified(1, src_read + dst_read),
+                Some(dst_digest),
+            ))
+        });
+    match result {
+        Err(CopyError::VerifyFailed { .. }) if opts.verify_sample.is_some() => result,
+        Err(err) if opts.continue_on_error => {
+            reporter::error(err);
+            Ok((Accumulator::verify_failed(1), None))
+        }
+        result => result,
+    }
+}
+
+/// Copy one chunk of a file split with `--split-large-files`. Whichever thread copies the file's last chunk finishes
+/// the file and reports it as copied, and the first chunk to fail removes the destination and reports the error.
+///
+/// Returns the stats and copied file to report, or an error if the thread should exit.
+fn copy_chunk(
+    chunk: Chunk,
+    opts: &CopyOptions,
+    throttle: &Throttle,
+    buffer: &mut CopyBuffer,
+    hard_links: &HardLinks,
+) -> Result<(Accumulator, Option<CopiedFile>), CopyError> {
+    let file = &chunk.file;
+    let mut result = Ok(());
+    let mut attempts = 1;
+    let mut landed = 0;
+    if !file.failed() {
+        (result, attempts) = copy::with_retries(opts, || {
+            copy::copy_range(
+                &file.src,
+                &file.target,
+                chunk.offset,
+                chunk.len,
+                buffer,
+                opts.drop_cache,
+                throttle,
+            )
+        });
+        if result.is_ok() {
+            landed = chunk.len;
+        }
+    }
+
+    // Only the thread that finishes the last chunk goes on to finish the file.
+    let last = file.chunk_done(landed);
+    let result = match result {
+        Ok(()) if last && !file.failed() => {
+            copy::finish_split(&file.target, &file.dst, file.metadata.permissions(), opts)
+        }
+        Ok(()) => return Ok((Accumulator::default(), None)),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(sync_time) => {
+            // Only what was found is copied, so a file that grew in the meantime is left out of date.
+            let len = file.metadata.len();
+            let changed = match std::fs::metadata(&file.src) {
+                Ok(now) if now.len() != len => {
+                    message!(
+                        "File changed size during copy: {:?} (found at {} bytes, now {})",
+                        file.src.as_os_str(),
+                        len,
+                        now.len()
+                    );
+                    Accumulator::changed(len, len)
+                }
+                _ => Accumulator::default(),
+            };
+            let preserved = preserve_file(&file.src, &file.dst, &file.metadata, opts);
+            if let Some(id) = file.file_id {
+                hard_links.copied(id, &file.dst);
+            }
+            let (verified, digest) = check_copy(
+                &file.src,
+                &file.dst,
+                file.metadata.len(),
+                None,
+                opts,
+                buffer,
+            )?;
+            let moved = match verified.file_count_verify_failed {
+                0 => {
+                    dedupe(
+                        &file.dst,
+                        file.metadata.len(),
+                        digest.as_ref(),
+                        hard_links,
+                        opts,
+                    ) + remove_source(&file.src, file.metadata.len(), opts)
+                }
+                _ => Accumulator::default(),
+            };
+            Ok((
+                Accumulator::copies(1, file.metadata.len())
+                    + Accumulator::synced(sync_time)
+                    + changed
+                    + preserved
+                    + moved.clone()
+                    + verified,
+                Some(CopiedFile {
+                    relative: file.relative.clone(),
+                    dst: file.dst.clone(),
+                    digest,
+                    len: file.metadata.len(),
+                    modified: preserved_time(&file.metadata, &moved, opts),
+                }),
+            ))
+        }
+        Err(err) => {
+            // Chunks that fail after the first one have nothing new to report.
+            let Some(partial_removed) = file.fail() else {
+                return Ok((Accumulator::default(), None));
+            };
+            if opts.continue_on_error {
+                message!(
+                    "Error copying file: {:?}: {}{}",
+                    file.src.as_os_str(),
+                    err,
+                    if partial_removed {
+                        " (partial file removed)"
+                    } else {
+                        ""
+                    }
+                );
+                return Ok((
+                    Accumulator::failed(1) + Accumulator::partials_removed(partial_removed),
+                    None,
+                ));
+            }
+            Err(CopyError::CopyFailed {
+                src: file.src.clone(),
+                dst: file.dst.clone(),
+                source: err,
+                attempts,
+                partial_removed,
+            })
+        }
+    }
+}
+
+/// Whether `path` is one of the files a previous run recorded in the state file.
+fn is_done(path: &Path, copy_base: &Path, done: &HashSet<PathBuf>) -> bool {
+    path.strip_prefix(copy_base)
+        .is_ok_and(|relative| done.contains(relative))
+}
+
+/// Take the next item to hand to a copy thread from `queue`, skipping files recorded in the state file and spooled
+/// entries that have gone since the search found them, which are added to `vanished`.
+fn next_found(
+    queue: &mut Queue,
+    done: &HashSet<PathBuf>,
+    copy_base: &Path,
+    vanished: &mut Vec<PathBuf>,
+    accumulator: &mut Accumulator,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<Option<SearchResult>, CopyError> {
+    while let Some(popped) = queue.pop_front()? {
+        match popped {
+            Popped::Found(SearchResult::File(file_result))
+                if is_done(&file_result.path, copy_base, done) =>
+            {
+                *accumulator += Accumulator::skips(1, file_result.metadata.len());
+                reporter.on_file_done(&file_result.path, Outcome::Skipped(SkipReason::Resumed));
+            }
+            Popped::Found(item) => return Ok(Some(item)),
+            Popped::Vanished {
+                kind,
+                path,
+                len,
+                source,
+            } => gone(kind, path, len, source, vanished, accumulator, reporter),
+        }
+    }
+    Ok(None)
+}
+
+/// Files taken from the queue that are waiting for a particular copy thread, each with whether it's preferred.
+struct Held {
+    /// Large files waiting for a thread that's allowed to copy one, with `--max-large-concurrent`.
+    large: VecDeque<(SearchResult, bool)>,
+    /// The rest of the directory each thread is working through, with `--locality`.
+    batches: Vec<VecDeque<(SearchResult, bool)>>,
+}
+
+impl Held {
+    fn new(threads: usize) -> Self {
+        Self {
+            large: VecDeque::new(),
+            batches: (0..threads).map(|_| VecDeque::new()).collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.large.len() + self.batches.iter().map(VecDeque::len).sum::<usize>()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many of the held files are preferred.
+    fn preferred(&self) -> usize {
+        self.large
+            .iter()
+            .chain(self.batches.iter().flatten())
+            .filter(|(_, is_preferred)| *is_preferred)
+            .count()
+    }
+
+    /// Put what was waiting for `thread_id` back at the front of `queue`, once that thread has exited.
+    fn release(&mut self, thread_id: usize, queue: &mut Queue) {
+        while let Some((item, _)) = self.batches[thread_id].pop_back() {
+            queue.push_front(item);
+        }
+    }
+}
+
+/// The most files of one directory handed to a copy thread together with `--locality`, so the threads still share a
+/// directory with a great many files in it.
+const LOCALITY_BATCH: usize = 1000;
+
+/// The next file for `thread_id` to copy and whether it's preferred, meaning one of the first `preferred` of the
+/// `queued` taken from `queue`. While `hold_large` is set, files of at least that many bytes are moved to `held`
+/// instead, and once it isn't, they're taken from there before anything else. With `locality`, the files after it in
+/// the same directory are held for the same thread. Files that are gone from the source are added to `vanished`.
+#[allow(clippy::too_many_arguments)]
+fn next_to_copy(
+    queue: &mut Queue,
+    held: &mut Held,
+    thread_id: usize,
+    hold_large: Option<u64>,
+    (queued, preferred): (usize, u64),
+    locality: bool,
+    done: &HashSet<PathBuf>,
+    copy_base: &Path,
+    vanished: &mut Vec<PathBuf>,
+    accumulator: &mut Accumulator,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<Option<(SearchResult, bool)>, CopyError> {
+    if let Some(next) = held.batches[thread_id].pop_front() {
+        return Ok(Some(next));
+    }
+    let mut next = match hold_large {
+        None => held.large.pop_front(),
+        Some(_) => None,
+    };
+    while next.is_none() {
+        let Some(found) = next_found(queue, done, copy_base, vanished, accumulator, reporter)?
+        else {
+            return Ok(None);
+        };
+        let is_preferred = queued.saturating_sub(queue.len()) as u64 <= preferred;
+        match (&found, hold_large) {
+            (SearchResult::File(file_result), Some(size)) if file_result.metadata.len() >= size => {
+                held.large.push_back((found, is_preferred))
+            }
+            _ => next = Some((found, is_preferred)),
+        }
+    }
+
+    if let (true, Some((item, _))) = (locality, &next) {
+        let parent = item.path().and_then(Path::parent);
+        let batch = &mut held.batches[thread_id];
+        while batch.len() + 1 < LOCALITY_BATCH {
+            let Some(found) = next_found(queue, done, copy_base, vanished, accumulator, reporter)?
+            else {
+                break;
+            };
+            if found.path().and_then(Path::parent) != parent {
+                queue.push_front(found);
+                break;
+            }
+            let is_preferred = queued.saturating_sub(queue.len()) as u64 <= preferred;
+            batch.push_back((found, is_preferred));
+        }
+    }
+    Ok(next)
+}
+
+/// Report a spooled entry that was removed after the search found it, and count it as vanished and add it to
+/// `vanished` if it was a file or a symlink.
+fn gone(
+    kind: EntryKind,
+    path: PathBuf,
+    len: u64,
+    source: std::io::Error,
+    vanished: &mut Vec<PathBuf>,
+    accumulator: &mut Accumulator,
+    reporter: &mut dyn ProgressReporter,
+) {
+    message!(
+        "File found during scan no longer exists: {:?} ({})",
+        path.as_os_str(),
+        source
+    );
+    let skipped = match kind {
+        EntryKind::File | EntryKind::Symlink => Accumulator::vanished(len),
+        EntryKind::Directory | EntryKind::Special => return,
+    };
+    reporter.on_file_done(&path, Outcome::of(&skipped));
+    *accumulator += skipped;
+    vanished.push(path);
+}
+
+/// Move the files `--prefer` picks to the front of `queue`, keeping everything else in the order it was in, and return
+/// the queue and how many files were moved. Files that are gone from the source are added to `vanished`.
+fn prefer(
+    mut queue: Queue,
+    copy_base: &Path,
+    opts: &CopyOptions,
+    vanished: &mut Vec<PathBuf>,
+    accumulator: &mut Accumulator,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<(Queue, u64), CopyError> {
+    let mut preferred = queue.new_like(false);
+    // The rest goes straight to the spool, if there is one, so the two halves don't hold twice what the queue did in
+    // memory.
+    let mut rest = queue.new_like(true);
+    let mut count = 0;
+    while let Some(popped) = queue.pop_front()? {
+        match popped {
+            Popped::Found(SearchResult::File(file_result))
+                if file_result
+                    .path
+                    .strip_prefix(copy_base)
+                    .is_ok_and(|relative| opts.prefers(relative)) =>
+            {
+                preferred.push_back(SearchResult::File(file_result))?;
+                count += 1;
+            }
+            Popped::Found(item) => rest.push_back(item)?,
+            Popped::Vanished {
+                kind,
+                path,
+                len,
+                source,
+            } => gone(kind, path, len, source, vanished, accumulator, reporter),
+        }
+    }
+    rest.finish()?;
+    while let Some(popped) = rest.pop_front()? {
+        match popped {
+            Popped::Found(item) => preferred.push_back(item)?,
+            Popped::Vanished {
+                kind,
+                path,
+                len,
+                source,
+            } => gone(kind, path, len, source, vanished, accumulator, reporter),
+        }
+    }
+    preferred.finish()?;
+    Ok((preferred, count))
+}
+
+/// Copy everything in `queue` from `copy_base` to `dest_base` on `threads` threads, returning how long that took,
+/// whether it stopped at `max_files` or `max_bytes` with anything left, and with `audit`, every file copied.
+#[allow(clippy::too_many_arguments)]
+fn copy_queue(
+    mut queue: Queue,
+    copy_base: PathBuf,
+    dest_base: PathBuf,
+    accumulator: &mut Accumulator,
+    threads: usize,
+    opts: Arc<CopyOptions>,
+    cancelled: &CancellationToken,
+    throttle: Arc<Throttle>,
+    case_collisions: HashSet<PathBuf>,
+    search_time: Duration,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<(Duration, bool, Vec<audit::Expected>), CopyError> {
+    let mut state = None;
+    // Files recorded by a previous run, which are skipped as they come up without looking at the destination at all.
+    let mut done = HashSet::new();
+    if let Some(state_path) = opts.state_file.as_deref() {
+        let state_file;
+        (state_file, done) =
+            StateFile::open(state_path).map_err(|err| CopyError::StateFileFailed {
+                path: state_path.to_path_buf(),
+                source: err,
+            })?;
+        state = Some(state_file);
+
+        if !done.is_empty() {
+            let mut resumed = 0;
+            for entry in queue.entries() {
+                let (kind, path) = entry?;
+                if kind == EntryKind::File && is_done(&path, &copy_base, &done) {
+                    resumed += 1;
+                }
+            }
+            message!("Resumed, {} files previously done", resumed);
+        }
+    }
+
+    // Files the search found that were gone from the source by the time they came up, for the error log and
+    // `--checksum-file`.
+    let mut vanished = Vec::new();
+
+    // Files picked with `--prefer` are moved to the front, so they're the first `preferred` taken from the queue.
+    let mut preferred = 0;
+    if !opts.prefer.is_empty() {
+        (queue, preferred) = prefer(
+            queue,
+            &copy_base,
+            &opts,
+            &mut vanished,
+            accumulator,
+            reporter,
+        )?;
+        accumulator.file_count_preferred = preferred;
+        message!("Copying {} priority files first", preferred);
+    }
+    let queued = queue.len();
+    // Preferred files split with `--split-large-files`, which aren't done until all their chunks are.
+    let mut preferred_splits: Vec<Arc<SplitFile>> = Vec::new();
+
+    let hard_links = Arc::new(HardLinks::default());
+    let case_collisions = Arc::new(case_collisions);
+
+    let copy_start = Instant::now();
+    let (request_sender, request_receiver) = unbounded();
+    let (split_sender, split_receiver) = unbounded();
+    // With `--threads auto-tune`, every thread it might use is started, and only as many as it's trying are given work.
+    let workers = opts.copy_threads(threads);
+    let mut tuner = opts
+        .auto_tune
+        .then(|| Tuner::new(threads.min(workers), workers));
+    let mut path_senders = Vec::with_capacity(workers);
+    let mut thread_handles = Vec::with_capacity(workers);
+    // How much of what each thread is copying it has written, for per-file progress.
+    let file_bytes: Vec<Arc<AtomicU64>> = (0..workers).map(|_| Arc::default()).collect();
+
+    for idx in 0..workers {
+        let request_sender = request_sender.clone();
+        let (path_sender, path_receiver) = unbounded();
+        path_senders.push(path_sender);
+        let split_sender = split_sender.clone();
+        let copy_base = copy_base.clone();
+        let dest_base = dest_base.clone();
+        let opts = opts.clone();
+        let cancelled = cancelled.clone();
+        let throttle = throttle.clone();
+        let hard_links = hard_links.clone();
+        let case_collisions = case_collisions.clone();
+        let file_bytes = file_bytes[idx].clone();
+
+        let handle = std::thread::spawn(move || {
+            copy::report_file_bytes(file_bytes);
+            copy_thread(
+                idx,
+                copy_base,
+                dest_base,
+                request_sender,
+                path_receiver,
+                split_sender,
+                opts,
+                cancelled,
+                throttle,
+                hard_links,
+                case_collisions,
+            )
+        });
+        thread_handles.push(Some(handle));
+    }
+
+    // Only the copy threads hold senders from here on, so the receiver closes once they've all exited.
+    drop(request_sender);
+    drop(split_sender);
+
+    // Chunks of files split with `--split-large-files`, which are handed out before anything else in the queue so
+    // split files finish as soon as possible.
+    let mut chunks = VecDeque::new();
+    let mut held = Held::new(workers);
+
+    let mut idle = Vec::with_capacity(workers);
+
+    // Copy threads that haven't exited yet.
+    let mut live = workers;
+
+    let mut last_print = copy_start;
+    let ticker = tick(STATUS_POLL_INTERVAL);
+
+    let mut errors = Vec::new();
+    let mut aborting = false;
+    let mut limit_reached = false;
+
+    // The item each thread is currently working on, for status snapshots.
+    let mut in_flight: Vec<Option<Current>> = (0..workers).map(|_| None).collect();
+
+    let mut paused_since: Option<Instant> = None;
+    let mut paused_time = Duration::ZERO;
+
+    // Copied files that haven't been synced to disk yet, with `--fsync=batch`.
+    let mut unsynced = Vec::new();
+
+    // The digest of every file copied, for `--checksum-file`.
+    let mut checksums = Vec::new();
+
+    // Every file copied, for `--audit` to look for once the copy is done.
+    let mut audited = Vec::new();
+
+    loop {
+        let rq = select! {
+            recv(request_receiver) -> rq => match rq {
+                Ok(rq) => Some(rq),
+                // Every copy thread has exited.
+                Err(_) => break,
+            },
+            recv(ticker) -> _ => None,
+        };
+
+        match rq {
+            Some(Ok(ThreadReady(thread_id, thread_accumulator, copied))) => {
+                chunks.extend(split_receiver.try_iter());
+                if let Some(current) = in_flight[thread_id].take() {
+                    if current.preferred {
+                        let split = chunks
+                            .iter()
+                            .map(|chunk| &chunk.file)
+                            .chain(in_flight.iter().flatten().filter_map(|c| c.split.as_ref()))
+                            .find(|file| file.src == current.path);
+                        preferred_splits.extend(split.cloned());
+                    }
+                    reporter.on_file_done(&current.path, Outcome::of(&thread_accumulator));
+                    if thread_accumulator.file_count_vanished > 0 {
+                        vanished.push(current.path);
+                    }
+                }
+                let changed = thread_accumulator.file_count_changed > 0;
+                // Files that changed size since the search are counted at the size they were copied at.
+                accumulator.byte_count_found = (accumulator.byte_count_found
+                    + thread_accumulator.byte_count_grown)
+                    .saturating_sub(thread_accumulator.byte_count_shrunk);
+                *accumulator += thread_accumulator;
+                if let (Some(FsyncMode::Batch), Some(copied)) = (opts.fsync, copied.as_ref()) {
+                    unsynced.push(copied.dst.clone());
+                }
+                if let (true, Some(copied)) = (opts.print0, copied.as_ref()) {
+                    reporter.on_copied_to(&copied.dst);
+                }
+                if let (Some(state), Some(copied)) = (state.as_mut(), copied.as_ref()) {
+                    if let Err(err) = state.record(&copied.relative) {
+                        message!("Warning: unable to write to state file: {}", err);
+                    }
+                }
+                if let (true, Some(copied)) = (opts.audit, copied.as_ref()) {
+                    audited.push(audit::Expected {
+                        relative: copied
+                            .dst
+                            .strip_prefix(&dest_base)
+                            .unwrap_or(&copied.dst)
+                            .to_path_buf(),
+                        len: copied.len,
+                        modified: copied.modified,
+                    });
+                }
+                if let Some(CopiedFile {
+                    relative: original,
+                    dst,
+                    digest: Some(digest),
+                    ..
+                }) = copied.filter(|_| opts.checksum_file.is_some())
+                {
+                    let relative = dst.strip_prefix(&dest_base).unwrap_or(&dst);
+                    checksums.push(manifest::Entry {
+                        relative: relative.to_path_buf(),
+                        digest,
+                        original: (original != relative).then_some(original),
+                        changed,
+                    });
+                }
+                idle.push(thread_id);
+            }
+            Some(Err(mut err)) => {
+                // The thread that sent this has exited. Whatever it was working on is cleared from `in_flight` when its
+                // handle is joined below, so it stops counting toward `--max-large-concurrent` and `--prefer`.
+                live -= 1;
+                if let CopyError::DestinationFull { remaining, .. } = &mut err {
+                    *remaining = accumulator.byte_count_found.saturating_sub(
+                        accumulator.byte_count_copied + accumulator.byte_count_skipped,
+                    );
+                }
+                errors.push(err);
+                if !opts.continue_on_error {
+                    // Stop handing out work. Dropping the path senders lets the remaining threads finish what
+                    // they're holding and exit, and any errors they hit on the way are still collected.
+                    path_senders.clear();
+                    aborting = true;
+                }
+            }
+            None => {}
+        }
+
+        // A copy thread that panics never reports back, and the others keep the receiver open, so look for threads
+        // that have exited rather than waiting on one forever. Ones that exited normally have already been counted.
+        for (thread_id, handle) in thread_handles.iter_mut().enumerate() {
+            if !handle.as_ref().is_some_and(JoinHandle::is_finished) {
+                continue;
+            }
+            // The rest of its directory goes to whichever thread is ready next.
+            held.release(thread_id, &mut queue);
+            in_flight[thread_id] = None;
+            if let Some(Err(payload)) = handle.take().map(JoinHandle::join) {
+                live -= 1;
+                errors.push(CopyError::worker_panicked(thread_id, payload));
+                if !opts.continue_on_error && !aborting {
+                    path_senders.clear();
+                    aborting = true;
+                }
+            }
+        }
+
+        reporter::deliver(reporter);
+
+        if signals::take_status_request() {
+            reporter.on_copy_progress(accumulator);
+            reporter.on_message(&format!(
+                "{} items waiting in the queue",
+                queue.len() + held.len()
+            ));
+            for (thread_id, current) in in_flight.iter().enumerate() {
+                let Some(current) = current else {
+                    continue;
+                };
+                let progress = match current.len {
+                    0 => String::new(),
+                    len => format!(
+                        " ({:.0}%)",
+                        file_bytes[thread_id].load(Ordering::Relaxed).min(len) as f64 / len as f64
+                            * 100.0
+                    ),
+                };
+                reporter.on_message(&format!(
+                    "  Thread {}: copying {}{}",
+                    thread_id,
+                    current.path.display(),
+                    progress
+                ));
+            }
+        }
+
+        if !aborting && cancelled.is_cancelled() {
+            // Copy threads only ever hold one item, so stopping dispatch here lets in-flight files finish and
+            // nothing new gets started.
+            path_senders.clear();
+            aborting = true;
+        }
+
+        if !aborting
+            && (!queue.is_empty() || !chunks.is_empty() || !held.is_empty())
+            && (opts
+                .max_files
+                .is_some_and(|max| accumulator.file_count_copied >= max)
+                || opts
+                    .max_bytes
+                    .is_some_and(|max| accumulator.byte_count_copied >= max))
+        {
+            // The same as cancelling, with files already being copied left to finish.
+            path_senders.clear();
+            aborting = true;
+            limit_reached = true;
+        }
+
+        if aborting {
+            continue;
+        }
+
+        if signals::pause_requested() {
+            // Hold on to ready threads instead of giving them work until the copy is resumed.
+            if paused_since.is_none() && idle.len() == live {
+                message!(
+                    "Paused at {} files / {}",
+                    accumulator.file_count_copied + accumulator.file_count_skipped,
+                    Byte::from_bytes(
+                        (accumulator.byte_count_copied + accumulator.byte_count_skipped) as u128
+                    )
+                    .get_appropriate_unit(false)
+                );
+                paused_since = Some(Instant::now());
+                signals::paused();
+            }
+            if signals::pause_requested() {
+                continue;
+            }
+        }
+
+        if let Some(since) = paused_since.take() {
+            paused_time += since.elapsed();
+            message!("Resumed");
+            if let Some(tuner) = tuner.as_mut() {
+                tuner.restart(written(accumulator, &in_flight, &file_bytes));
+            }
+        }
+
+        if let Some(tuner) = tuner.as_mut().filter(|_| !queue.is_empty()) {
+            match tuner.update(written(accumulator, &in_flight, &file_bytes)) {
+                Some(Adjustment::Trying { threads, rate }) => message!(
+                    "Auto-tune: {}/s, trying {} threads",
+                    Byte::from_bytes(rate as u128).get_appropriate_unit(true),
+                    threads
+                ),
+                Some(Adjustment::Settled { threads, rate }) => message!(
+                    "Auto-tune: settled on {} threads at {}/s",
+                    threads,
+                    Byte::from_bytes(rate as u128).get_appropriate_unit(true)
+                ),
+                None => {}
+            }
+        }
+
+        while !queue.is_empty() || !chunks.is_empty() || !held.is_empty() {
+            // Threads beyond those auto-tune is trying are left idle.
+            if tuner
+                .as_ref()
+                .is_some_and(|tuner| live - idle.len() >= tuner.active())
+            {
+                break;
+            }
+            let Some(thread_id) = idle.pop() else {
+                break;
+            };
+            // With the threads allowed large files busy with them, large files are held back for the next one
+            // that's free, and smaller files are copied in the meantime.
+            let max_large = opts
+                .max_large_concurrent
+                .unwrap_or(tuner.as_ref().map_or(threads, Tuner::active) / 2)
+                .max(1);
+            let large = in_flight
+                .iter()
+                .flatten()
+                .filter(|current| current.split.is_none() && current.len >= opts.large_file_size)
+                .count();
+            let (item, is_preferred) = match chunks.pop_front() {
+                Some(chunk) => (WorkItem::Chunk(chunk), false),
+                None => match next_to_copy(
+                    &mut queue,
+                    &mut held,
+                    thread_id,
+                    (large >= max_large).then_some(opts.large_file_size),
+                    (queued, preferred),
+                    opts.locality,
+                    &done,
+                    &copy_base,
+                    &mut vanished,
+                    accumulator,
+                    reporter,
+                ) {
+                    Ok(Some((found, is_preferred))) => (WorkItem::Found(found), is_preferred),
+                    // Everything that was left got skipped, or is held back.
+                    Ok(None) => {
+                        idle.push(thread_id);
+                        break;
+                    }
+                    Err(err) => {
+                        idle.push(thread_id);
+                        errors.push(err);
+                        path_senders.clear();
+                        aborting = true;
+                        break;
+                    }
+                },
+            };
+            let mut current = item.current();
+            if let Some(current) = current.as_mut() {
+                current.preferred = is_preferred;
+            }
+            // The thread only starts writing once it has the item.
+            file_bytes[thread_id].store(0, Ordering::Relaxed);
+            match path_senders[thread_id].send(item) {
+                Ok(()) => in_flight[thread_id] = current,
+                Err(SendError(item)) => {
+                    // The thread is gone, so give its item to the next one that's ready.
+                    held.release(thread_id, &mut queue);
+                    match item {
+                        WorkItem::Found(p) => queue.push_front(p),
+                        WorkItem::Chunk(chunk) => chunks.push_front(chunk),
+                    }
+                    live -= 1;
+                }
+            }
+        }
+
+        if accumulator.file_count_preferred_done < preferred {
+            preferred_splits.retain(|file| !file.done());
+            let waiting = preferred.saturating_sub(queued.saturating_sub(queue.len()) as u64)
+                + held.preferred() as u64
+                + in_flight.iter().flatten().filter(|c| c.preferred).count() as u64
+                + preferred_splits.len() as u64;
+            accumulator.file_count_preferred_done = preferred - waiting;
+            if waiting == 0 {
+                message!("All {} priority files done", preferred);
+            }
+        }
+
+        if opts.progress || opts.show_active {
+            let now = Instant::now();
+            if now.duration_since(last_print).as_secs() >= 5 {
+                last_print = now;
+                if opts.progress {
+                    reporter.on_copy_progress(accumulator);
+                    for (path, written, len) in large_files(&in_flight, &file_bytes) {
+                        reporter.on_file_progress(&path, written, len);
+                    }
+                }
+                if opts.show_active {
+                    for (thread_id, current) in in_flight.iter().enumerate() {
+                        if let Some(current) = current {
+                            let relative = current
+                                .path
+                                .strip_prefix(&copy_base)
+                                .unwrap_or(&current.path);
+                            reporter.on_active(thread_id, relative, now - current.started);
+                        }
+                    }
+                }
+            }
+        }
+
+        if idle.len() == live
+            && ((queue.is_empty() && chunks.is_empty() && held.is_empty()) || live == 0)
+        {
+            break;
+        }
+    }
+
+    // Split files with chunks that were never handed out can't be finished, so they're removed.
+    for chunk in chunks.drain(..) {
+        if chunk.file.fail() == Some(true) {
+            *accumulator += Accumulator::partials_removed(true);
+        }
+        chunk.file.chunk_done(0);
+    }
+
+    let was_cancelled = cancelled.is_cancelled();
+    // Stopping after an error leaves the rest behind on purpose, and the error already says why.
+    if !was_cancelled && !limit_reached && !aborting && (!queue.is_empty() || !held.is_empty()) {
+        errors.push(CopyError::Other(format!(
+            "{} items were not copied because every copy thread exited",
+            queue.len() + held.len()
+        )));
+    }
+
+    if !unsynced.is_empty() {
+        let sync_start = Instant::now();
+        let mut directories = BTreeSet::new();
+        for path in unsynced {
+            if let Err(err) = copy::sync_file(&path) {
+                errors.push(CopyError::SyncFailed { path, source: err });
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                directories.insert(parent.to_path_buf());
+            }
+        }
+        for path in directories {
+            if let Err(err) = copy::sync_dir(&path) {
+                errors.push(CopyError::SyncFailed { path, source: err });
+            }
+        }
+        *accumulator += Accumulator::synced(sync_start.elapsed());
+    }
+    let copy_time = Instant::now().duration_since(copy_start) - paused_time;
+    reporter::deliver(reporter);
+    reporter.on_summary(&CopyReport {
+        accumulator: accumulator.clone(),
+        threads,
+        search_time,
+        copy_time,
+        total_time: started.elapsed(),
+        cancelled: was_cancelled,
+        limit_reached,
+        destination: (!opts.also_to.is_empty()).then(|| destination_display(&opts)),
+        ..Default::default()
+    });
+    if accumulator.file_count_verify_failed > 0 {
+        errors.push(CopyError::Other(format!(
+            "{} files failed verification",
+            accumulator.file_count_verify_failed
+        )));
+    }
+    if was_cancelled {
+        message!(
+            "Cancelled by user with {} files not yet copied.",
+            queue.len()
+        );
+    }
+
+    for sender in path_senders {
+        drop(sender);
+    }
+
+    for (thread_id, handle) in thread_handles.into_iter().enumerate() {
+        if let Some(Err(payload)) = handle.map(JoinHandle::join) {
+            errors.push(CopyError::worker_panicked(thread_id, payload));
+        }
+    }
+
+    if let Some(state) = state.as_mut() {
+        if let Err(err) = state.flush() {
+            message!("Warning: unable to write to state file: {}", err);
+        }
+    }
+
+    log_vanished(&vanished, opts.error_log.as_deref());
+
+    if let Some(path) = opts.checksum_file.as_deref() {
+        // Shared with other sources, paths start with the directory this one was copied to.
+        let dir = match opts.checksum_file_append {
+            true => dest_base.file_name().map(PathBuf::from),
+            false => None,
+        };
+        let heading = dir.as_ref().map(|dir| {
+            format!(
+                "{} -> {}: {} files",
+                opts.src_display.display(),
+                dir.display(),
+                checksums.len()
+            )
+        });
+        let within = |relative: PathBuf| match &dir {
+            Some(dir) => dir.join(relative),
+            None => relative,
+        };
+        for entry in &mut checksums {
+            entry.relative = within(std::mem::take(&mut entry.relative));
+        }
+        let vanished = vanished
+            .iter()
+            .filter_map(|path| path.strip_prefix(&copy_base).ok())
+            .map(|relative| within(destination_relative(relative, &opts).into_owned()))
+            .collect();
+        if let Err(err) = manifest::write(path, checksums, vanished, heading) {
+            errors.push(CopyError::ChecksumFileFailed {
+                path: path.to_path_buf(),
+                source: err,
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(collect_errors(errors, opts.error_log.as_deref()));
+    }
+
+    Ok((copy_time, limit_reached, audited))
+}
+
+/// Add `errors` to the end of `error_log`, if there is one. Errors the copy carries on past are added as they
+/// happen, and the log is started afresh by each copy.
+fn log_errors(errors: &[CopyError], error_log: Option<&Path>) {
+    let contents: String = errors.iter().map(|err| format!("{:?}\n", err)).collect();
+    append_to_log(&contents, error_log);
+}
+
+/// Add a line to `error_log` for each file in `vanished`, so they can be told apart from files skipped for existing.
+fn log_vanished(vanished: &[PathBuf], error_log: Option<&Path>) {
+    let contents: String = vanished
+        .iter()
+        .map(|path| {
+            format!(
+                "File found during scan no longer exists: {}\n",
+                path.display()
+            )
+        })
+        .collect();
+    append_to_log(&contents, error_log);
+}
+
+fn append_to_log(contents: &str, error_log: Option<&Path>) {
+    let Some(error_log) = error_log.filter(|_| !contents.is_empty()) else {
+        return;
+    };
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(error_log)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    if let Err(err) = result {
+        message!(
+            "Warning: unable to write error log to {}: {}",
+            error_log.display(),
+            err
+        );
+    }
+}
+
+/// Deduplicate the errors collected during a copy by kind and path, returning the only error if there's just one
+/// or [`CopyError::Multiple`] otherwise.
+///
+/// If `error_log` is set, the full list (before deduplication) is added to it as well.
+fn collect_errors(errors: Vec<CopyError>, error_log: Option<&Path>) -> CopyError {
+    log_errors(&errors, error_log);
+
+    let mut seen = HashSet::new();
+    let mut errors: Vec<CopyError> = errors
+        .into_iter()
+        .filter(|err| {
+            seen.insert((
+                std::mem::discriminant(err),
+                err.path().map(Path::to_path_buf),
+            ))
+        })
+        .collect();
+
+    if errors.len() == 1 {
+        errors.pop().unwrap()
+    } else {
+        CopyError::Multiple(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, io,
+        sync::{atomic::AtomicBool, Mutex},
+    };
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::options::BeforeCopy;
+
+    /// The name of a file that makes the copy thread given it panic, with [`poison`].
+    const POISONED: &str = "poisoned";
+
+    /// Fail thread 0 on the first file it's given.
+    fn fail_first_on_thread_0() -> BeforeCopy {
+        let failed = AtomicBool::new(false);
+        BeforeCopy(Arc::new(move |thread_id, src, dst| {
+            (thread_id == 0 && !failed.swap(true, Ordering::Relaxed)).then(|| {
+                CopyError::CopyFailed {
+                    src: src.to_path_buf(),
+                    dst: dst.to_path_buf(),
+                    source: io::Error::other("injected failure"),
+                    attempts: 1,
+                    partial_removed: false,
+                }
+            })
+        }))
+    }
+
+    /// Panic on a file named [`POISONED`].
+    fn poison() -> BeforeCopy {
+        BeforeCopy(Arc::new(|_, src, _| {
+            if src.file_name() == Some(OsStr::new(POISONED)) {
+                panic!("injected panic copying {}", src.display());
+            }
+            None
+        }))
+    }
+
+    /// Record which thread each file is given to, in order, in `given`.
+    fn record_given(given: &Arc<Mutex<Vec<(usize, PathBuf)>>>) -> BeforeCopy {
+        let given = given.clone();
+        BeforeCopy(Arc::new(move |thread_id, src, _| {
+            given.lock().unwrap().push((thread_id, src.to_path_buf()));
+            None
+        }))
+    }
+
+    /// Counts the files each copy reports as copied.
+    #[derive(Default)]
+    struct Counter {
+        copied: u64,
+    }
+
+    impl ProgressReporter for Counter {
+        fn on_file_done(&mut self, _path: &Path, outcome: Outcome) {
+            if let Outcome::Copied(_) = outcome {
+                self.copied += 1;
+            }
+        }
+    }
+
+    /// A source directory called `name` with `count` files in it, and an empty destination.
+    fn source_named(name: &str, count: usize) -> (TempDir, PathBuf, TempDir) {
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join(name);
+        fs::create_dir(&src).unwrap();
+        for i in 0..count {
+            fs::write(src.join(format!("{i:03}.txt")), i.to_string()).unwrap();
+        }
+        (temp, src, TempDir::new().unwrap())
+    }
+
+    fn files_in(dir: &Path) -> u64 {
+        fs::read_dir(dir).unwrap().count() as u64
+    }
+
+    /// `err`, or what it's made up of if it's several.
+    fn errors(err: &CopyError) -> Vec<&CopyError> {
+        match err {
+            CopyError::Multiple(errors) => errors.iter().collect(),
+            err => vec![err],
+        }
+    }
+
+    /// Copy `src` to `dst` with `before_copy` hooked into each copy thread.
+    fn copy_with(
+        src: &Path,
+        dst: &Path,
+        threads: usize,
+        continue_on_error: bool,
+        before_copy: BeforeCopy,
+    ) -> (Result<CopyReport, CopyError>, Counter) {
+        let mut opts = CopyOptions::builder()
+            .threads(Some(threads))
+            .continue_on_error(continue_on_error)
+            .build()
+            .unwrap();
+        opts.before_copy = Some(before_copy);
+        let mut counter = Counter::default();
+        let result = copy_tree_with_reporter(src, dst, &opts, &mut counter);
+        (result, counter)
+    }
+
+    #[test]
+    fn failed_thread_stops_the_copy() {
+        let (_temp, src, dst) = source_named("source", 200);
+        let (result, counter) = copy_with(&src, dst.path(), 4, false, fail_first_on_thread_0());
+
+        let err = result.unwrap_err();
+        assert!(
+            matches!(err, CopyError::CopyFailed { .. }),
+            "the failure should be the only error: {:?}",
+            err
+        );
+        assert_eq!(files_in(dst.path()), counter.copied);
+        assert!(counter.copied < 200);
+    }
+
+    #[test]
+    fn failed_thread_leaves_its_work_to_the_others() {
+        let (_temp, src, dst) = source_named("source", 200);
+        let (result, counter) = copy_with(&src, dst.path(), 4, true, fail_first_on_thread_0());
+
+        let err = result.unwrap_err();
+        assert!(
+            matches!(err, CopyError::CopyFailed { .. }),
+            "the failure should be the only error: {:?}",
+            err
+        );
+        assert_eq!(counter.copied, 199);
+        assert_eq!(files_in(dst.path()), 199);
+    }
+
+    #[test]
+    fn failed_only_thread_ends_the_copy() {
+        let (_temp, src, dst) = source_named("source", 20);
+        let (result, counter) = copy_with(&src, dst.path(), 1, true, fail_first_on_thread_0());
+
+        let err = result.unwrap_err();
+        let errors = errors(&err);
+        assert_eq!(errors.len(), 2, "{:?}", err);
+        assert!(matches!(errors[0], CopyError::CopyFailed { .. }));
+        assert!(
+            matches!(errors[1], CopyError::Other(message) if message.starts_with("19 items were not copied")),
+            "{:?}",
+            errors[1]
+        );
+        assert_eq!(counter.copied, 0);
+        assert_eq!(files_in(dst.path()), 0);
+    }
+
+    #[test]
+    fn panicked_thread_stops_the_copy() {
+        let (_temp, src, dst) = source_named("source", 200);
+        fs::write(src.join(POISONED), "poison").unwrap();
+        let (result, counter) = copy_with(&src, dst.path(), 4, false, poison());
+
+        let err = result.unwrap_err();
+        assert!(
+            matches!(&err, CopyError::WorkerPanicked { message, .. } if message.contains(POISONED)),
+            "the panic should be the only error: {:?}",
+            err
+        );
+        assert_eq!(files_in(dst.path()), counter.copied);
+        assert!(!dst.path().join(POISONED).exists());
+    }
+
+    #[test]
+    fn panicked_thread_leaves_its_work_to_the_others() {
+        let (_temp, src, dst) = source_named("source", 200);
+        fs::write(src.join(POISONED), "poison").unwrap();
+        let (result, counter) = copy_with(&src, dst.path(), 4, true, poison());
+
+        let err = result.unwrap_err();
+        assert!(
+            matches!(&err, CopyError::WorkerPanicked { message, .. } if message.contains(POISONED)),
+            "the panic should be the only error: {:?}",
+            err
+        );
+        assert_eq!(counter.copied, 200);
+        assert_eq!(files_in(dst.path()), 200);
+    }
+
+    #[test]
+    fn collect_errors_drops_repeats_of_the_same_kind_and_path() {
+        let failed = |path: &str| CopyError::CopyFailed {
+            src: PathBuf::from(path),
+            dst: PathBuf::from("dst").join(path),
+            source: io::Error::other("failed"),
+            attempts: 1,
+            partial_removed: false,
+        };
+        let unreadable = |path: &str| CopyError::Unreadable {
+            path: PathBuf::from(path),
+            source: io::Error::other("failed"),
+        };
+
+        let err = collect_errors(vec![failed("a"), failed("a")], None);
+        assert!(matches!(err, CopyError::CopyFailed { .. }), "{:?}", err);
+
+        let err = collect_errors(
+            vec![failed("a"), unreadable("a"), failed("b"), failed("a")],
+            None,
+        );
+        let CopyError::Multiple(errors) = err else {
+            panic!("{:?}", err);
+        };
+        let kept: Vec<_> = errors
+            .iter()
+            .map(|err| (std::mem::discriminant(err), err.path().unwrap()))
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                (std::mem::discriminant(&failed("a")), Path::new("a")),
+                (std::mem::discriminant(&unreadable("a")), Path::new("a")),
+                (std::mem::discriminant(&failed("b")), Path::new("b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn search_reports_a_directory_it_cant_read() {
+        let temp = TempDir::new().unwrap();
+        let missing = temp.path().join("missing");
+        let (path_sender, path_receiver) = unbounded();
+        let (found_sender, found_receiver) = unbounded();
+        path_sender.send(missing.clone()).unwrap();
+        drop(path_sender);
+
+        search(
+            path_receiver,
+            found_sender,
+            temp.path(),
+            &CopyOptions::default(),
+            &FollowedLinks::default(),
+        );
+
+        let found: Vec<_> = found_receiver.iter().collect();
+        assert!(
+            matches!(
+                &found[..],
+                [SearchResult::Unreadable(path, err), SearchResult::Done(done)]
+                    if *path == missing && err.kind() == ErrorKind::NotFound && *done == missing
+            ),
+            "{:?}",
+            found.iter().map(SearchResult::path).collect::<Vec<_>>()
+        );
+    }
+
+    /// A source directory with `dirs` directories of `files` files each.
+    fn nested_source(dirs: usize, files: usize) -> (TempDir, PathBuf, TempDir) {
+        let (temp, src, dst) = source_named("source", 0);
+        for dir in 0..dirs {
+            let dir = src.join(format!("dir-{dir:02}"));
+            fs::create_dir(&dir).unwrap();
+            for file in 0..files {
+                fs::write(dir.join(format!("{file:03}.txt")), file.to_string()).unwrap();
+            }
+        }
+        (temp, src, dst)
+    }
+
+    #[test]
+    fn locality_gives_each_directory_to_one_thread_in_a_row() {
+        let (_temp, src, dst) = nested_source(20, 30);
+        let recorded = Arc::default();
+        let mut opts = CopyOptions::builder()
+            .threads(Some(4))
+            .locality(true)
+            .build()
+            .unwrap();
+        opts.before_copy = Some(record_given(&recorded));
+        copy_tree(&src, dst.path(), &opts).unwrap();
+
+        let given: Vec<(usize, PathBuf)> = recorded
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(thread_id, path)| (*thread_id, path.parent().unwrap().to_path_buf()))
+            .collect();
+        assert_eq!(given.len(), 20 * 30);
+
+        // Which thread each directory went to, and the directories each thread has finished with.
+        let mut owners: HashMap<&Path, usize> = HashMap::new();
+        let mut current: HashMap<usize, &Path> = HashMap::new();
+        let mut finished: HashSet<&Path> = HashSet::new();
+        for (thread_id, dir) in &given {
+            let owner = *owners.entry(dir).or_insert(*thread_id);
+            assert_eq!(owner, *thread_id, "{} went to two threads", dir.display());
+            if let Some(previous) = current
+                .insert(*thread_id, dir)
+                .filter(|previous| previous != dir)
+            {
+                finished.insert(previous);
+            }
+            assert!(
+                !finished.contains(dir.as_path()),
+                "thread {} came back to {}",
+                thread_id,
+                dir.display()
+            );
+        }
+        assert_eq!(owners.len(), 20);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locality_keeps_directory_times() {
+        let (_temp, src, dst) = nested_source(5, 10);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        for dir in 0..5 {
+            fs::File::open(src.join(format!("dir-{dir:02}")))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        let opts = CopyOptions::builder()
+            .threads(Some(4))
+            .locality(true)
+            .build()
+            .unwrap();
+        copy_tree(&src, dst.path(), &opts).unwrap();
+
+        for dir in 0..5 {
+            let copied = fs::metadata(dst.path().join(format!("dir-{dir:02}"))).unwrap();
+            assert_eq!(copied.modified().unwrap(), modified, "dir-{dir:02}");
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{copy, hash::Digest};
+
+/// Identifies a file independently of its path: the device or volume it's on and its inode or file index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FileId(u64, u64);
+
+/// The identity of the file at `path`, or `None` if it has no other hard links or its identity can't be read.
+#[cfg(unix)]
+pub fn file_id(_path: &Path, metadata: &Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    if metadata.nlink() <= 1 {
+        return None;
+    }
+    Some(FileId(metadata.dev(), metadata.ino()))
+}
+
+/// The identity of the file at `path`, or `None` if it has no other hard links or its identity can't be read.
+#[cfg(windows)]
+pub fn file_id(path: &Path, _metadata: &Metadata) -> Option<FileId> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    // The standard library's link count and file index are still unstable on Windows.
+    let file = std::fs::File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    if info.nNumberOfLinks <= 1 {
+        return None;
+    }
+    Some(FileId(
+        info.dwVolumeSerialNumber as u64,
+        (info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64,
+    ))
+}
+
+/// Hard links can't be detected on this platform.
+#[cfg(not(any(unix, windows)))]
+pub fn file_id(_path: &Path, _metadata: &Metadata) -> Option<FileId> {
+    None
+}
+
+/// The destination of the first copy made of each hard linked source file, and with `--dedupe-dest`, of each distinct
+/// file content, shared between the copy threads.
+#[derive(Default)]
+pub struct HardLinks {
+    by_id: Mutex<HashMap<FileId, PathBuf>>,
+    by_content: Mutex<HashMap<(u64, Digest), PathBuf>>,
+}
+
+impl HardLinks {
+    /// Where the file with this identity was already copied to, if it has been.
+    pub fn first_copy(&self, id: FileId) -> Option<PathBuf> {
+        self.by_id.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Record that the file with this identity has been copied to `dst`. If two links to the same file are copied at
+    /// the same time both are copied in full, and the first one recorded is the one later links point to.
+    pub fn copied(&self, id: FileId, dst: &Path) {
+        self.by_id
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| dst.to_path_buf());
+    }
+
+    /// Record that `dst` holds `len` bytes with this digest, returning the first file recorded with the same contents
+    /// if it isn't `dst`.
+    pub fn first_with_contents(&self, len: u64, digest: &Digest, dst: &Path) -> Option<PathBuf> {
+        let mut by_content = self.by_content.lock().unwrap();
+        match by_content.get(&(len, digest.clone())) {
+            Some(first) => Some(first.clone()),
+            None => {
+                by_content.insert((len, digest.clone()), dst.to_path_buf());
+                None
+            }
+        }
+    }
+}
+
+/// Replace the file at `dst` with a hard link to `existing`. The link is made under a temporary name and renamed over
+/// `dst`, so `dst` is never missing if linking fails.
+pub fn replace_with_link(existing: &Path, dst: &Path) -> std::io::Result<()> {
+    let temporary = copy::partial_path(dst);
+    std::fs::hard_link(existing, &temporary)?;
+    std::fs::rename(&temporary, dst).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temporary);
+    })
+}
+
+/// Create `dst` as a hard link to `existing`, replacing whatever file is at `dst`.
+pub fn hard_link(existing: &Path, dst: &Path) -> std::io::Result<()> {
+    match std::fs::hard_link(existing, dst) {
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            std::fs::remove_file(dst)?;
+            std::fs::hard_link(existing, dst)
+        }
+        result => result,
+    }
+}
+
+/// Returns true if `metadata`, read without following links, is a junction or a symbolic link to a directory. Only
+/// Windows can tell which links point to directories without following them.
+#[cfg(windows)]
+pub fn is_dir_link(metadata: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_REPARSE_POINT,
+    };
+
+    // Other reparse points, like cloud placeholders, are ordinary files and directories as far as copying goes, and
+    // the standard library only counts the ones that stand in for another path as links.
+    let attributes = FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_REPARSE_POINT;
+    metadata.file_attributes() & attributes == attributes && metadata.file_type().is_symlink()
+}
+
+/// Links to directories can't be told apart without following them on this platform.
+#[cfg(not(windows))]
+pub fn is_dir_link(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// The reparse tag of junctions, IO_REPARSE_TAG_MOUNT_POINT.
+#[cfg(windows)]
+const MOUNT_POINT: u32 = 0xA0000003;
+
+/// Returns true if `path` is a junction, rather than a symbolic link or not a link at all.
+#[cfg(windows)]
+pub fn is_junction(path: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::{
+        Foundation::INVALID_HANDLE_VALUE,
+        Storage::FileSystem::{
+            FindClose, FindFirstFileW, FILE_ATTRIBUTE_REPARSE_POINT, WIN32_FIND_DATAW,
+        },
+    };
+
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+    let handle = unsafe { FindFirstFileW(wide.as_ptr(), &mut data) };
+    if handle == INVALID_HANDLE_VALUE {
+        return false;
+    }
+    unsafe { FindClose(handle) };
+    // The reparse tag is only there for reparse points.
+    data.dwFileAttributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 && data.dwReserved0 == MOUNT_POINT
+}
+
+/// Create `dst` as a junction to the directory `target`, which has to be an absolute path on a local volume. Unlike
+/// directory symbolic links, junctions don't need any privileges to create.
+#[cfg(windows)]
+pub fn create_junction(target: &Path, dst: &Path) -> std::io::Result<()> {
+    use std::{
+        ffi::c_void,
+        os::windows::{fs::OpenOptionsExt, io::AsRawHandle},
+    };
+    use windows_sys::Win32::{
+        Storage::FileSystem::{FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT},
+        System::{Ioctl::FSCTL_SET_REPARSE_POINT, IO::DeviceIoControl},
+    };
+
+    // `read_link` gives junction targets in `\\?\` form, and junctions store them in the NT form, `\??\`, along with
+    // the plain path to show.
+    let target = target.as_os_str().to_string_lossy();
+    let plain = target.strip_prefix(r"\\?\").unwrap_or(&target);
+    let nt: Vec<u16> = r"\??\".encode_utf16().chain(plain.encode_utf16()).collect();
+    let print: Vec<u16> = plain.encode_utf16().collect();
+
+    // A REPARSE_DATA_BUFFER holding a MountPointReparseBuffer: the tag, the length of what follows the 8-byte header,
+    // the offsets and lengths of the two names, in bytes, and the names themselves, each followed by a null.
+    let nt_len = (nt.len() * 2) as u16;
+    let print_len = (print.len() * 2) as u16;
+    let data_len = 8 + nt_len + 2 + print_len + 2;
+    let mut buffer = Vec::with_capacity(8 + data_len as usize);
+    buffer.extend_from_slice(&MOUNT_POINT.to_le_bytes());
+    buffer.extend_from_slice(&data_len.to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    for field in [0, nt_len, nt_len + 2, print_len] {
+        buffer.extend_from_slice(&field.to_le_bytes());
+    }
+    for name in [nt, print] {
+        buffer.extend(name.iter().chain(&[0]).flat_map(|unit| unit.to_le_bytes()));
+    }
+
+    std::fs::create_dir(dst)?;
+    let result = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(dst)
+        .and_then(|dir| {
+            let mut returned = 0;
+            if unsafe {
+                DeviceIoControl(
+                    dir.as_raw_handle() as _,
+                    FSCTL_SET_REPARSE_POINT,
+                    buffer.as_ptr() as *const c_void,
+                    buffer.len() as u32,
+                    std::ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    std::ptr::null_mut(),
+                )
+            } == 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    result.inspect_err(|_| {
+        let _ = std::fs::remove_dir(dst);
+    })
+}
+
+/// The directories that links followed by a search lead to, so a link that leads back up the tree, or to somewhere
+/// another link already led, isn't gone through again and again.
+#[derive(Default)]
+pub struct FollowedLinks(Mutex<HashSet<PathBuf>>);
+
+impl FollowedLinks {
+    /// Returns true if the search should go through the link to a directory at `path`: unless the directory it leads
+    /// to contains the link, or another link already led there. Links whose target can't be resolved are followed,
+    /// and fail the way they always have.
+    pub fn follow(&self, path: &Path) -> bool {
+        let (Ok(target), Some(Ok(parent))) = (
+            std::fs::canonicalize(path),
+            path.parent().map(std::fs::canonicalize),
+        ) else {
+            return true;
+        };
+        !parent.starts_with(&target) && self.0.lock().unwrap().insert(target)
+    }
+}
+
+/// Print the warning that `--link` is falling back to copying, the first time it happens.
+pub fn warn_cross_device() {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        message!(
+            "Warning: source and destination are on different devices, so files are being copied instead of linked."
+        );
+    }
+}
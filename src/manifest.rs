@@ -0,0 +1,134 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::hash::Digest;
+
+/// A file listed in a `--checksum-file`.
+pub struct Entry {
+    /// The file's path relative to the destination.
+    pub relative: PathBuf,
+    pub digest: Digest,
+    /// The file's path relative to the source, if it was copied somewhere else.
+    pub original: Option<PathBuf>,
+    /// The file changed size while it was being copied, so the copy may not match the source any more.
+    pub changed: bool,
+}
+
+/// Write a `--checksum-file` listing `entries` in the format `sha256sum` and friends read: the digest, two spaces,
+/// and the path relative to the destination. Entries are sorted by path so the file doesn't depend on the order
+/// threads finished in.
+///
+/// Each file that was copied somewhere other than its path in the source has a comment line before it, like
+/// `# raw/a.jpg -> originals/a.jpg`, which `sha256sum -c` skips. Files that changed size while being copied have one
+/// like `# changed during copy: logs/app.log`. Files in `vanished`, relative to the destination, were gone from the
+/// source before they could be copied and are listed at the end, like `# vanished before copy: tmp/a.part`.
+///
+/// With a `heading`, the file is added to rather than replaced, and the heading comes first as a comment, like
+/// `# /a/logs -> a_logs: 12 files`.
+pub fn write(
+    path: &Path,
+    mut entries: Vec<Entry>,
+    mut vanished: Vec<PathBuf>,
+    heading: Option<String>,
+) -> std::io::Result<()> {
+    entries.sort_unstable_by(|a, b| a.relative.cmp(&b.relative));
+    vanished.sort_unstable();
+    let file = match heading {
+        Some(_) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => File::create(path)?,
+    };
+    let mut writer = BufWriter::new(file);
+    if let Some(heading) = heading {
+        writeln!(writer, "# {}", heading)?;
+    }
+    for entry in entries {
+        let (escaped, name) = escape(relative_name(&entry.relative));
+        if let Some(original) = &entry.original {
+            let (_, original) = escape(relative_name(original));
+            writeln!(writer, "# {} -> {}", original, name)?;
+        }
+        if entry.changed {
+            writeln!(writer, "# changed during copy: {}", name)?;
+        }
+        let prefix = if escaped { "\\" } else { "" };
+        writeln!(writer, "{}{}  {}", prefix, entry.digest, name)?;
+    }
+    for relative in vanished {
+        let (_, name) = escape(relative_name(&relative));
+        writeln!(writer, "# vanished before copy: {}", name)?;
+    }
+    writer.flush()
+}
+
+/// Read back a file written by [`write`], returning each relative path with its digest as hex. Lines in the
+/// `sha256sum -b` format, with a `*` before the path, are read too, and comments are skipped.
+pub fn read(path: &Path) -> std::io::Result<Vec<(PathBuf, String)>> {
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line.as_str()),
+        };
+        let Some((digest, name)) = line.split_once("  ").or_else(|| line.split_once(" *")) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {} isn't a digest followed by a path", number + 1),
+            ));
+        };
+        let name = match escaped {
+            true => unescape(name),
+            false => name.to_string(),
+        };
+        entries.push((PathBuf::from(name), digest.to_ascii_lowercase()));
+    }
+    Ok(entries)
+}
+
+/// The relative path as it's written in the file, which always uses `/` so it can be checked on any platform.
+#[cfg(windows)]
+fn relative_name(relative: &Path) -> String {
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(not(windows))]
+fn relative_name(relative: &Path) -> String {
+    relative.to_string_lossy().into_owned()
+}
+
+/// Escape backslashes and newlines in `name` the way `sha256sum` does, returning whether anything needed it. Lines
+/// with escaped names start with a backslash.
+fn escape(name: String) -> (bool, String) {
+    if !name.contains(['\\', '\n', '\r']) {
+        return (false, name);
+    }
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    (true, escaped)
+}
+
+fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
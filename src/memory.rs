@@ -0,0 +1,86 @@
+use std::mem::size_of;
+
+use crate::{copy::CopyBuffer, hash::Hasher};
+
+/// The smallest `--buffer-size` that `--max-memory` will shrink the copy buffers to.
+pub const MIN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// What each copy thread holds besides its buffer. BLAKE3 is the largest hasher, and the only one kept outside the
+/// [`Hasher`] itself.
+const THREAD_OVERHEAD: usize = size_of::<Hasher>() + size_of::<blake3::Hasher>();
+
+/// How `--max-memory` is shared out between the copy threads and the queue of what the search found.
+///
+/// This is an estimate that only counts the biggest users of memory: each copy thread's buffer and hasher, and the
+/// entries in the queue. Everything else, like the list of everything found that `--delete` keeps, comes on top.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    /// The `--buffer-size` to copy with, which may be smaller than the one asked for.
+    pub buffer_size: usize,
+    /// How many bytes of entries the queue can hold in memory before spooling the rest.
+    pub queue_bytes: u64,
+}
+
+impl Budget {
+    /// Share out `max_memory` between `threads` copy threads that would each like a buffer of `buffer_size`, and the
+    /// queue. The buffers get at most half, shrinking down to [`MIN_BUFFER_SIZE`] if they have to, and the queue gets
+    /// whatever is left, which may be nothing.
+    pub fn new(max_memory: u64, threads: usize, buffer_size: usize) -> Self {
+        let per_thread = max_memory / 2 / threads as u64;
+        let mut buffer_size = buffer_size;
+        while buffer_size > MIN_BUFFER_SIZE && Self::thread_bytes(buffer_size) > per_thread {
+            buffer_size = (buffer_size / 2).max(MIN_BUFFER_SIZE);
+        }
+        Self {
+            buffer_size,
+            queue_bytes: max_memory
+                .saturating_sub(Self::thread_bytes(buffer_size) * threads as u64),
+        }
+    }
+
+    /// What one copy thread holds at most.
+    fn thread_bytes(buffer_size: usize) -> u64 {
+        (CopyBuffer::footprint(buffer_size) + THREAD_OVERHEAD) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_shares_out_more_than_the_limit() {
+        for max_memory in [1024 * 1024, 64 * 1024 * 1024, 512 * 1024 * 1024, 1 << 34] {
+            for threads in [1, 4, 16, 64] {
+                for buffer_size in [MIN_BUFFER_SIZE, 1024 * 1024, 16 * 1024 * 1024] {
+                    let budget = Budget::new(max_memory, threads, buffer_size);
+                    assert!(budget.buffer_size >= MIN_BUFFER_SIZE);
+                    assert!(budget.buffer_size <= buffer_size);
+                    let threads_bytes = Budget::thread_bytes(budget.buffer_size) * threads as u64;
+                    // Buffers at the smallest size may not fit, but then the queue gets nothing.
+                    if budget.buffer_size > MIN_BUFFER_SIZE {
+                        assert!(threads_bytes <= max_memory / 2);
+                    }
+                    if threads_bytes <= max_memory {
+                        assert_eq!(budget.queue_bytes, max_memory - threads_bytes);
+                    } else {
+                        assert_eq!(budget.queue_bytes, 0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_the_buffer_asked_for_when_it_fits() {
+        let budget = Budget::new(1 << 34, 8, 1024 * 1024);
+        assert_eq!(budget.buffer_size, 1024 * 1024);
+    }
+
+    #[test]
+    fn tiny_limit_leaves_nothing_for_the_queue() {
+        let budget = Budget::new(1, 8, 1024 * 1024);
+        assert_eq!(budget.buffer_size, MIN_BUFFER_SIZE);
+        assert_eq!(budget.queue_bytes, 0);
+    }
+}
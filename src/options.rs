@@ -0,0 +1,1304 @@
+use std::{
+    ffi::{OsStr, OsString},
+    ops::BitOr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+use crate::{cancel::CancellationToken, errors::OptionsError, filter::FileFilter, paths};
+
+/// `verify_sample_by` bytes picks files as if each block of this many bytes were sampled on its own.
+const SAMPLE_BLOCK: u64 = 1024 * 1024;
+
+/// How [`copy_tree`](crate::copy_tree) copies, with a field for each of `ninecopy`'s flags. The flags' `--help`
+/// describes each one in full.
+///
+/// The defaults match running `ninecopy` with no flags. [`CopyOptions::builder`] sets them a few at a time and checks
+/// they make sense together, which [`copy_tree`](crate::copy_tree) also does before it starts.
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    /// The source and destination, set by [`copy_tree`](crate::copy_tree) and resolved to canonical paths before
+    /// anything is copied.
+    pub(crate) src: PathBuf,
+    pub(crate) dst: PathBuf,
+    /// The source and destination as they were given, for messages.
+    pub(crate) src_display: PathBuf,
+    pub(crate) dst_display: PathBuf,
+    /// For a single-file source, the name it's copied to inside `dst`, set by [`copy_tree`](crate::copy_tree).
+    pub(crate) single_file: Option<OsString>,
+
+    /// More destinations to copy the source to, alongside the one given to [`copy_tree`](crate::copy_tree). The
+    /// source is only searched once, and each destination is copied to at the same time by copy threads of its own,
+    /// so a failure at one doesn't stop the others.
+    pub also_to: Vec<PathBuf>,
+    /// Overwrite files that already exist at the destination, instead of failing.
+    pub overwrite: bool,
+    /// Copy files that already exist at the destination alongside them, under the first free name.
+    pub rename_collisions: bool,
+    /// What happens to existing files whose paths, relative to the source, match each pattern, instead of what
+    /// `skip`, `overwrite`, `update`, and `checksum` say. The first pattern that matches wins. Any policy but
+    /// [`Policy::Rename`] can be used.
+    pub policies: Vec<(glob::Pattern, Policy)>,
+    /// Replace destination files even if they're read-only.
+    pub force: bool,
+    /// Rename files before overwriting them by adding this suffix.
+    pub backup: Option<String>,
+    /// Move files into this directory before overwriting them.
+    pub backup_dir: Option<PathBuf>,
+    /// Only write the blocks of overwritten files that differ from the source.
+    pub delta: bool,
+    /// Skip files that already exist at the destination.
+    pub skip: bool,
+    /// Skip files that already exist at the destination with the same size and modified time.
+    pub update: bool,
+    /// Compare the contents of files that already exist at the destination, and only overwrite ones that differ.
+    /// With `update`, files whose modified times match are skipped without being read, and only the rest are compared.
+    pub checksum: bool,
+    /// How far apart modified times can be and still count as the same.
+    pub modify_window: Duration,
+    /// Read each file back after copying it and check its checksum against the source's.
+    pub verify: bool,
+    /// Don't copy anything, just check the destination against the source.
+    pub verify_only: bool,
+    /// Verify a sample of this percentage of the files copied, picked by `verify_sample_by`.
+    pub verify_sample: Option<f64>,
+    /// Whether `verify_sample` is a percentage of the files or of the bytes copied.
+    pub verify_sample_by: SampleBy,
+    /// What picks the files `verify_sample` verifies. The same seed picks the same files from the same source.
+    /// [`copy_tree`](crate::copy_tree) picks one if it isn't set.
+    pub verify_sample_seed: Option<u64>,
+    /// Once the copy is done, search the destination again and check that every file copied is there with the size
+    /// and, unless `no_preserve_times` is set, the modified time it was copied with.
+    pub audit: bool,
+    /// The hash used for checksums.
+    pub hash: HashAlgorithm,
+    /// Write the digest of every file copied to this file.
+    pub checksum_file: Option<PathBuf>,
+    /// Add to the end of `checksum_file` instead of replacing it, after a comment naming the source and counting its
+    /// files, with paths relative to the parent of the destination. For listing several sources copied to directories
+    /// of their own under one destination in one file, the way `--per-source-dirs` does.
+    pub checksum_file_append: bool,
+    /// Don't copy anything, just check the destination against this file written with `checksum_file`.
+    pub check_manifest: Option<PathBuf>,
+    /// Don't copy anything, just list how the destination differs from the source.
+    pub diff: bool,
+    /// Hard link files copied to the destination to identical files copied earlier in the same run.
+    pub dedupe_dest: bool,
+    /// Remove each source file once it's been copied.
+    pub move_files: bool,
+    /// Delete everything in the destination that isn't in the source.
+    pub delete: bool,
+    /// Let `delete` run even when the source is empty.
+    pub delete_even_if_empty: bool,
+    /// With `delete`, list what would be deleted instead of deleting it. Conflicts with `detect_renames`.
+    pub delete_dry_run: bool,
+    /// Before copying, rename files `delete` would remove to where identical new source files are copied, instead of
+    /// copying those. Needs `checksum`.
+    pub detect_renames: bool,
+    /// Once the copy is done, keep copying what changes in the source until the copy is cancelled.
+    pub watch: bool,
+    /// With `watch`, how long nothing has to change for before what has is copied.
+    pub watch_debounce: Duration,
+    /// With `watch`, search the whole source again this often, for changes notifications missed.
+    pub watch_rescan: Option<Duration>,
+    /// Search and copy everything again this long after each copy started, until the copy is cancelled. Files
+    /// recorded in `state_file` are skipped by every one of them.
+    pub repeat: Option<Duration>,
+    /// With `repeat`, stop once this many copies in a row have failed, instead of carrying on regardless.
+    pub repeat_max_failures: Option<u32>,
+    /// Periodically log progress.
+    pub progress: bool,
+    /// Periodically list what each copy thread is working on, and for how long.
+    pub show_active: bool,
+    /// Pass where each copied file ended up to [`ProgressReporter::on_copied_to`](crate::ProgressReporter::on_copied_to),
+    /// which [`ConsoleReporter`](crate::ConsoleReporter) prints to stdout separated by NULs.
+    pub print0: bool,
+    /// The number of threads to search and copy with, instead of one per core.
+    pub threads: Option<usize>,
+    /// Start copying with `threads` threads, then every so often try more or fewer, settling on however many copy
+    /// fastest.
+    pub auto_tune: bool,
+    /// With `auto_tune`, never copy with more threads than this. Defaults to four times `threads`.
+    pub max_threads: Option<usize>,
+    /// Run at low CPU and I/O priority.
+    pub background: bool,
+    /// With `skip`, copy files anyway when the source was modified more recently.
+    pub copy_if_newer: bool,
+    /// With `skip`, copy files anyway when the source is larger.
+    pub copy_if_larger: bool,
+    /// Skip files that fail to copy and carry on with the rest.
+    pub continue_on_error: bool,
+    /// Copy files that changed size while they were being copied once more.
+    pub recopy_changed: bool,
+    /// Skip source files that another process has locked, once any retries are used up, and list them at the end.
+    pub skip_locked: bool,
+    /// Fail with [`CopyError::Incomplete`](crate::CopyError::Incomplete) if anything found wasn't copied, even when
+    /// the copy carried on past it.
+    pub strict: bool,
+    /// Write every error to this file.
+    pub error_log: Option<PathBuf>,
+    /// Record copied files in this file, and skip files already recorded there.
+    pub state_file: Option<PathBuf>,
+    /// Write everything the search finds to this file, which `files_from` can read back in a later run.
+    pub save_file_list: Option<PathBuf>,
+    /// Copy what's listed in this file, written by `save_file_list`, instead of searching the source.
+    pub files_from: Option<PathBuf>,
+    /// Stop handing out files to copy once this many have been copied, letting the ones being copied finish. The
+    /// report says the limit was reached if anything was left.
+    pub max_files: Option<u64>,
+    /// Stop handing out files to copy once this many bytes have been copied, like `max_files`.
+    pub max_bytes: Option<u64>,
+    /// Keep the list of what was found in a temporary file in this directory once it's longer than `spool_after`,
+    /// instead of holding it all in memory.
+    pub spool_dir: Option<PathBuf>,
+    /// How many found items to hold in memory before the rest go to `spool_dir`.
+    pub spool_after: usize,
+    /// Keep the copy buffers and the list of what was found within about this many bytes, shrinking the buffers and
+    /// spooling the list to `spool_dir` (or the system temporary directory) as needed.
+    pub max_memory: Option<u64>,
+    /// Copy each file to a temporary name and rename it into place once it's complete.
+    pub atomic: bool,
+    /// Leave partially written files in place when a copy fails.
+    pub keep_partial: bool,
+    /// How many times to retry copies that fail with errors that are likely to be transient.
+    pub retries: u32,
+    /// How long to wait before the first retry. The wait doubles after each attempt.
+    pub retry_wait: Duration,
+    /// Give up on any single file that takes longer than this to copy.
+    pub file_timeout: Option<Duration>,
+    /// Extend `file_timeout` by the time a file would take to copy at this many bytes per second.
+    pub min_rate: Option<u64>,
+    /// Wait for space to be freed when the destination fills up, instead of failing.
+    pub wait_on_full: bool,
+    /// Start copying even if the destination doesn't appear to have enough free space.
+    pub no_space_check: bool,
+    /// Stop before copying if any destination path would be longer than Windows' MAX_PATH.
+    pub check_path_length: bool,
+    /// Rename files whose names Windows can't use, replacing what it doesn't allow with this.
+    pub sanitize_names: Option<String>,
+    /// Normalize the Unicode in destination paths to this form.
+    pub normalize: Normalization,
+    /// Change the case of every name in destination paths.
+    pub transform_case: CaseTransform,
+    /// Rules that move what's copied somewhere else in the destination, applied in order to each path relative to
+    /// the source before anything else changes it.
+    pub remaps: Vec<Remap>,
+    /// Fail if the destination doesn't exist instead of creating it.
+    pub no_create_dest: bool,
+    /// Follow rsync's convention for the source: without a trailing separator, it's copied to a directory of the same
+    /// name inside the destination instead of its contents being copied into the destination.
+    pub rsync_paths: bool,
+    /// Fail with [`CopyError::NotFaster`](crate::CopyError::NotFaster) if the source is a single file, instead of
+    /// copying it.
+    pub strict_dir_only: bool,
+    /// Don't create destination directories that nothing is copied into.
+    pub prune_empty_dirs: bool,
+    /// Only create the source's directories at the destination, leaving out the files and links in them.
+    pub dirs_only: bool,
+    /// Only copy files, leaving out links, and only create the directories they're copied into, as with
+    /// `prune_empty_dirs`.
+    pub files_only: bool,
+    /// Only copy files owned by the user with this ID, which [`user_id`](crate::user_id) looks up by name. Unix only.
+    pub owner: Option<u32>,
+    /// Only copy files whose group has this ID, which [`group_id`](crate::group_id) looks up by name. Unix only.
+    pub group: Option<u32>,
+    /// With `owner` or `group`, skip directories that don't match them too, along with everything in them.
+    pub prune_foreign_dirs: bool,
+    /// Don't give copied files the source's modification and access times.
+    pub no_preserve_times: bool,
+    /// Preserve everything that can be on this platform.
+    pub archive: bool,
+    /// Give copied files and directories the source's permissions.
+    pub preserve_perms: bool,
+    /// Give copied files and directories the source's owner and group, when running as root.
+    pub preserve_owner: bool,
+    /// Copy extended attributes.
+    pub xattrs: bool,
+    /// Copy files with `copyfile(3)` to preserve all of their metadata. macOS only.
+    pub preserve_all: bool,
+    /// Give copied files and directories the source's Windows attributes.
+    pub preserve_attributes: bool,
+    /// Copy Windows ACLs. Windows only.
+    pub acls: Option<AclMode>,
+    /// Fail if the source contains FIFOs, sockets, or device nodes, instead of skipping them.
+    pub fail_on_special: bool,
+    /// Skip cloud placeholder files, whose contents are only online, instead of downloading them to copy them.
+    pub skip_placeholders: bool,
+    /// Copy cloud placeholder files without warning that they'll be downloaded.
+    pub hydrate: bool,
+    /// Sync copied files to disk before counting them as copied.
+    pub fsync: Option<FsyncMode>,
+    /// Copy files without going through the system cache. Windows only.
+    pub unbuffered: bool,
+    /// Size of the buffer files are copied through in chunks.
+    pub buffer_size: usize,
+    /// Limit how many bytes per second are copied, across all threads together.
+    pub bwlimit: Option<u64>,
+    /// Copy files of at least `direct_io_threshold` bytes without going through the system cache.
+    pub direct_io: bool,
+    /// The size from which files are copied with `direct_io`.
+    pub direct_io_threshold: u64,
+    /// Drop files from the system cache as they're copied. Linux and FreeBSD only.
+    pub drop_cache: bool,
+    /// Copy files bigger than this in chunks on several threads at once.
+    pub split_large_files: Option<u64>,
+    /// The size from which files count towards `max_large_concurrent`.
+    pub large_file_size: u64,
+    /// How many copy threads can be copying files of at least `large_file_size` bytes at once, so the rest are left
+    /// for smaller files. Defaults to half the copy threads, and at least one.
+    pub max_large_concurrent: Option<usize>,
+    /// Hand each copy thread the files of one directory at a time, instead of whatever comes next, so destinations on
+    /// hard drives and network shares aren't kept jumping between directories.
+    pub locality: bool,
+    /// Copy files whose paths, relative to the source, match any of these before everything else.
+    pub prefer: Vec<glob::Pattern>,
+    /// When to clone files with copy-on-write instead of copying them.
+    pub reflink: ReflinkMode,
+    /// When to copy files without filling in their holes.
+    pub sparse: SparseMode,
+    /// Hard link destination files to the source files instead of copying them.
+    pub link: Option<LinkMode>,
+    /// Keep hard links within the source as hard links in the copy.
+    pub hard_links: bool,
+    /// Copy symbolic links as links, instead of copying what they point to.
+    pub preserve_symlinks: bool,
+    /// Go through junctions and directory symbolic links, instead of recreating them as links. Windows only.
+    pub follow_junctions: bool,
+    /// Don't preserve permissions, even with `archive`.
+    pub no_preserve_perms: bool,
+    /// Don't preserve ownership, even with `archive`.
+    pub no_preserve_owner: bool,
+    /// Don't copy symbolic links as links, even with `archive`.
+    pub no_preserve_symlinks: bool,
+    /// Don't copy extended attributes, even with `archive`.
+    pub no_preserve_xattrs: bool,
+    /// Don't preserve Windows attributes, even with `archive`.
+    pub no_preserve_attributes: bool,
+    /// Cancel this to stop the copy early.
+    pub cancellation: CancellationToken,
+    /// Only copy what every one of these includes. With `delete`, what they leave out is kept at the destination.
+    pub filters: Vec<Arc<dyn FileFilter>>,
+    /// Called by each copy thread before it copies a file, so tests can make it fail or panic there.
+    #[cfg(test)]
+    pub(crate) before_copy: Option<BeforeCopy>,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            src: PathBuf::new(),
+            dst: PathBuf::new(),
+            src_display: PathBuf::new(),
+            dst_display: PathBuf::new(),
+            single_file: None,
+            also_to: Vec::new(),
+            overwrite: false,
+            rename_collisions: false,
+            policies: Vec::new(),
+            force: false,
+            backup: None,
+            backup_dir: None,
+            delta: false,
+            skip: false,
+            update: false,
+            checksum: false,
+            modify_window: Duration::from_secs(1),
+            verify: false,
+            verify_only: false,
+            verify_sample: None,
+            verify_sample_by: SampleBy::Files,
+            verify_sample_seed: None,
+            audit: false,
+            hash: HashAlgorithm::Xxh3,
+            checksum_file: None,
+            checksum_file_append: false,
+            check_manifest: None,
+            diff: false,
+            dedupe_dest: false,
+            move_files: false,
+            delete: false,
+            detect_renames: false,
+            watch: false,
+            watch_debounce: Duration::from_secs(2),
+            watch_rescan: None,
+            repeat: None,
+            repeat_max_failures: None,
+            delete_even_if_empty: false,
+            delete_dry_run: false,
+            progress: false,
+            show_active: false,
+            print0: false,
+            threads: None,
+            auto_tune: false,
+            max_threads: None,
+            background: false,
+            copy_if_newer: false,
+            copy_if_larger: false,
+            continue_on_error: false,
+            recopy_changed: false,
+            skip_locked: false,
+            strict: false,
+            error_log: None,
+            state_file: None,
+            save_file_list: None,
+            files_from: None,
+            max_files: None,
+            max_bytes: None,
+            spool_dir: None,
+            spool_after: 1_000_000,
+            max_memory: None,
+            atomic: false,
+            keep_partial: false,
+            retries: 0,
+            retry_wait: Duration::from_secs(1),
+            file_timeout: None,
+            min_rate: None,
+            wait_on_full: false,
+            no_space_check: false,
+            check_path_length: false,
+            sanitize_names: None,
+            normalize: Normalization::None,
+            transform_case: CaseTransform::None,
+            remaps: Vec::new(),
+            no_create_dest: false,
+            rsync_paths: false,
+            strict_dir_only: false,
+            prune_empty_dirs: false,
+            dirs_only: false,
+            files_only: false,
+            owner: None,
+            group: None,
+            prune_foreign_dirs: false,
+            no_preserve_times: false,
+            archive: false,
+            preserve_perms: false,
+            preserve_owner: false,
+            xattrs: false,
+            preserve_all: false,
+            preserve_attributes: false,
+            acls: None,
+            fail_on_special: false,
+            skip_placeholders: false,
+            hydrate: false,
+            fsync: None,
+            unbuffered: false,
+            buffer_size: 1024 * 1024,
+            bwlimit: None,
+            direct_io: false,
+            direct_io_threshold: 256 * 1024 * 1024,
+            drop_cache: false,
+            split_large_files: None,
+            large_file_size: 256 * 1024 * 1024,
+            max_large_concurrent: None,
+            locality: false,
+            prefer: Vec::new(),
+            reflink: ReflinkMode::Never,
+            sparse: SparseMode::Never,
+            link: None,
+            hard_links: false,
+            preserve_symlinks: false,
+            follow_junctions: false,
+            no_preserve_perms: false,
+            no_preserve_owner: false,
+            no_preserve_symlinks: false,
+            no_preserve_xattrs: false,
+            no_preserve_attributes: false,
+            cancellation: CancellationToken::new(),
+            filters: Vec::new(),
+            #[cfg(test)]
+            before_copy: None,
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Start building options from the defaults.
+    pub fn builder() -> CopyOptionsBuilder {
+        CopyOptionsBuilder {
+            opts: Self::default(),
+        }
+    }
+
+    /// Check that the options can be used together on this platform, returning the first problem found.
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        conflict(
+            "skip",
+            self.skip,
+            &[
+                ("overwrite", self.overwrite),
+                ("update", self.update),
+                ("checksum", self.checksum),
+            ],
+        )?;
+        conflict(
+            "move",
+            self.move_files,
+            &[
+                ("link", self.link.is_some()),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+            ],
+        )?;
+        conflict(
+            "rename_collisions",
+            self.rename_collisions,
+            &[
+                ("skip", self.skip),
+                ("overwrite", self.overwrite),
+                ("update", self.update),
+                ("checksum", self.checksum),
+                ("delete", self.delete),
+                ("backup", self.backup.is_some()),
+                ("backup_dir", self.backup_dir.is_some()),
+            ],
+        )?;
+        conflict("delta", self.delta, &[("atomic", self.atomic)])?;
+        // The list would be saved again as it's read, and watching needs to search again when it rescans.
+        conflict(
+            "files_from",
+            self.files_from.is_some(),
+            &[
+                ("save_file_list", self.save_file_list.is_some()),
+                ("watch", self.watch),
+            ],
+        )?;
+        // Without any files, these would have nothing to do, or would delete or check every file at the destination.
+        conflict(
+            "dirs_only",
+            self.dirs_only,
+            &[
+                ("files_only", self.files_only),
+                ("prune_empty_dirs", self.prune_empty_dirs),
+                ("move", self.move_files),
+                ("delete", self.delete),
+                ("diff", self.diff),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+            ],
+        )?;
+        conflict(
+            "skip_placeholders",
+            self.skip_placeholders,
+            &[("hydrate", self.hydrate)],
+        )?;
+        conflict(
+            "delete",
+            self.delete,
+            &[
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+            ],
+        )?;
+        conflict(
+            "diff",
+            self.diff,
+            &[
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+                ("move", self.move_files),
+                ("delete", self.delete),
+            ],
+        )?;
+        conflict(
+            "verify_sample",
+            self.verify_sample.is_some(),
+            &[
+                ("verify", self.verify),
+                ("verify_only", self.verify_only),
+                ("diff", self.diff),
+                ("check_manifest", self.check_manifest.is_some()),
+            ],
+        )?;
+        conflict(
+            "audit",
+            self.audit,
+            &[
+                ("diff", self.diff),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+                ("dirs_only", self.dirs_only),
+            ],
+        )?;
+        conflict(
+            "print0",
+            self.print0,
+            &[
+                ("diff", self.diff),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+            ],
+        )?;
+        conflict(
+            "strict",
+            self.strict,
+            &[
+                ("diff", self.diff),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+            ],
+        )?;
+        conflict(
+            "watch",
+            self.watch,
+            &[
+                ("also_to", !self.also_to.is_empty()),
+                ("move", self.move_files),
+                ("diff", self.diff),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+                ("state_file", self.state_file.is_some()),
+                ("rename_collisions", self.rename_collisions),
+                ("max_files", self.max_files.is_some()),
+                ("max_bytes", self.max_bytes.is_some()),
+            ],
+        )?;
+        conflict(
+            "repeat",
+            self.repeat.is_some(),
+            &[
+                ("watch", self.watch),
+                ("diff", self.diff),
+                ("verify_only", self.verify_only),
+                ("check_manifest", self.check_manifest.is_some()),
+                ("rename_collisions", self.rename_collisions),
+            ],
+        )?;
+        // Each destination would start these over, or use them for a copy to somewhere else.
+        conflict(
+            "also_to",
+            !self.also_to.is_empty(),
+            &[
+                ("move", self.move_files),
+                ("diff", self.diff),
+                ("check_manifest", self.check_manifest.is_some()),
+                ("checksum_file", self.checksum_file.is_some()),
+                ("state_file", self.state_file.is_some()),
+                ("backup_dir", self.backup_dir.is_some()),
+            ],
+        )?;
+        conflict(
+            "check_manifest",
+            self.check_manifest.is_some(),
+            &[
+                ("checksum_file", self.checksum_file.is_some()),
+                ("verify_only", self.verify_only),
+            ],
+        )?;
+        for (option, set) in [
+            ("copy_if_newer", self.copy_if_newer),
+            ("copy_if_larger", self.copy_if_larger),
+        ] {
+            if set && !self.skip {
+                return Err(OptionsError::Requires(option, "skip"));
+            }
+        }
+        if self
+            .policies
+            .iter()
+            .any(|(_, policy)| *policy == Policy::Rename)
+        {
+            return Err(OptionsError::Conflict("policies", "Policy::Rename"));
+        }
+        for (other, set) in [("delete", self.delete), ("checksum", self.checksum)] {
+            if self.detect_renames && !set {
+                return Err(OptionsError::Requires("detect_renames", other));
+            }
+        }
+        // Everything that changes is already at the destination, so copying it again would fail.
+        if self.watch && !(self.skip || self.overwrite || self.update || self.checksum) {
+            return Err(OptionsError::Requires(
+                "watch",
+                "one of skip, overwrite, update, or checksum",
+            ));
+        }
+        if self.delete_dry_run && !self.delete {
+            return Err(OptionsError::Requires("delete_dry_run", "delete"));
+        }
+        // Renaming files that would be deleted changes the destination as much as deleting them would.
+        conflict(
+            "delete_dry_run",
+            self.delete_dry_run,
+            &[("detect_renames", self.detect_renames)],
+        )?;
+        if self.watch_rescan.is_some() && !self.watch {
+            return Err(OptionsError::Requires("watch_rescan", "watch"));
+        }
+        // Everything the last copy did is already at the destination.
+        if self.repeat.is_some() && !(self.skip || self.overwrite || self.update || self.checksum) {
+            return Err(OptionsError::Requires(
+                "repeat",
+                "one of skip, overwrite, update, or checksum",
+            ));
+        }
+        if self.prune_foreign_dirs && self.owner.is_none() && self.group.is_none() {
+            return Err(OptionsError::Requires(
+                "prune_foreign_dirs",
+                "owner or group",
+            ));
+        }
+        if self.repeat_max_failures.is_some() && self.repeat.is_none() {
+            return Err(OptionsError::Requires("repeat_max_failures", "repeat"));
+        }
+        if self.max_threads.is_some() && !self.auto_tune {
+            return Err(OptionsError::Requires("max_threads", "auto_tune"));
+        }
+        if self.checksum_file_append && self.checksum_file.is_none() {
+            return Err(OptionsError::Requires(
+                "checksum_file_append",
+                "checksum_file",
+            ));
+        }
+
+        if let Some(replacement) = self.sanitize_names.as_deref() {
+            if replacement.is_empty()
+                || paths::windows_name_problem(OsStr::new(replacement)).is_some()
+            {
+                return Err(OptionsError::InvalidReplacement(replacement.to_string()));
+            }
+        }
+
+        for remap in &self.remaps {
+            if let Remap::Prefix(from, to) = remap {
+                if from.as_os_str().is_empty()
+                    || !paths::is_plain_relative(from)
+                    || !paths::is_plain_relative(to)
+                {
+                    return Err(OptionsError::InvalidRemap(format!(
+                        "{}={}",
+                        from.display(),
+                        to.display()
+                    )));
+                }
+            }
+        }
+
+        for (option, zero) in [
+            ("threads", self.threads == Some(0)),
+            ("max_threads", self.max_threads == Some(0)),
+            ("buffer_size", self.buffer_size == 0),
+            ("bwlimit", self.bwlimit == Some(0)),
+            ("max_memory", self.max_memory == Some(0)),
+            ("split_large_files", self.split_large_files == Some(0)),
+            ("max_large_concurrent", self.max_large_concurrent == Some(0)),
+            ("watch_rescan", self.watch_rescan == Some(Duration::ZERO)),
+            ("repeat", self.repeat == Some(Duration::ZERO)),
+            ("repeat_max_failures", self.repeat_max_failures == Some(0)),
+            ("max_files", self.max_files == Some(0)),
+            ("max_bytes", self.max_bytes == Some(0)),
+        ] {
+            if zero {
+                return Err(OptionsError::Zero(option));
+            }
+        }
+
+        if self
+            .verify_sample
+            .is_some_and(|percent| !(percent > 0.0 && percent <= 100.0))
+        {
+            return Err(OptionsError::Percentage("verify_sample"));
+        }
+        if self.preserve_all && !cfg!(target_os = "macos") {
+            return Err(OptionsError::Unsupported("preserve_all", "macOS"));
+        }
+        if self.unbuffered && !cfg!(windows) {
+            return Err(OptionsError::Unsupported("unbuffered", "Windows"));
+        }
+        if self.drop_cache
+            && !cfg!(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd"
+            ))
+        {
+            return Err(OptionsError::Unsupported("drop_cache", "Linux and FreeBSD"));
+        }
+        if self.follow_junctions && !cfg!(windows) {
+            return Err(OptionsError::Unsupported("follow_junctions", "Windows"));
+        }
+        if self.acls.is_some() && !cfg!(windows) {
+            return Err(OptionsError::Unsupported("acls", "Windows"));
+        }
+        for (option, set) in [
+            ("owner", self.owner.is_some()),
+            ("group", self.group.is_some()),
+        ] {
+            if set && !cfg!(unix) {
+                return Err(OptionsError::Unsupported(option, "Unix"));
+            }
+        }
+        Ok(())
+    }
+
+    /// How many copy threads to start when copying with `threads` threads: all `auto_tune` could ever use, or just
+    /// `threads`.
+    pub(crate) fn copy_threads(&self, threads: usize) -> usize {
+        match self.auto_tune {
+            true => self.max_threads.unwrap_or(threads * 4),
+            false => threads,
+        }
+    }
+
+    /// The first of `policies` whose pattern matches `relative`, a path relative to the source, and its index.
+    pub(crate) fn policy_for(&self, relative: &Path) -> Option<(usize, Policy)> {
+        self.policies
+            .iter()
+            .position(|(pattern, _)| pattern.matches_path(relative))
+            .map(|rule| (rule, self.policies[rule].1))
+    }
+
+    /// Returns true if `relative`, a path relative to the source, matches one of the `prefer` patterns.
+    pub(crate) fn prefers(&self, relative: &Path) -> bool {
+        self.prefer
+            .iter()
+            .any(|pattern| pattern.matches_path(relative))
+    }
+
+    /// Returns true if the file at `src`, `len` bytes long, is checked against its copy: every file with `verify`, and
+    /// a sample of them with `verify_sample`.
+    pub(crate) fn verifies(&self, src: &Path, len: u64) -> bool {
+        self.verify || self.in_sample(src, len)
+    }
+
+    /// Returns true if the file at `src`, `len` bytes long, is one of those `verify_sample` picks. Each file gets a
+    /// number from its path and `verify_sample_seed`, so the choice is random but repeatable, and files are picked
+    /// independently of each other and of which thread copies them.
+    pub(crate) fn in_sample(&self, src: &Path, len: u64) -> bool {
+        let Some(percent) = self.verify_sample else {
+            return false;
+        };
+        let hash = xxh3_64_with_seed(
+            src.as_os_str().as_encoded_bytes(),
+            self.verify_sample_seed.unwrap_or(0),
+        );
+        // The top 53 bits, as an f64 from 0 up to but not including 1.
+        let roll = (hash >> 11) as f64 / (1u64 << 53) as f64;
+        let chance = percent / 100.0;
+        let chance = match self.verify_sample_by {
+            SampleBy::Files => chance,
+            // As if each MiB were sampled on its own, with the file verified if any of them are.
+            SampleBy::Bytes => 1.0 - (1.0 - chance).powf(len as f64 / SAMPLE_BLOCK as f64),
+        };
+        roll < chance
+    }
+
+    /// Turn on everything `archive` stands for on this platform, then turn off whatever was excluded with a
+    /// `no_preserve_*` option.
+    pub fn apply_archive(&mut self) {
+        if self.archive {
+            self.preserve_perms = true;
+            self.preserve_owner |= cfg!(unix);
+            self.preserve_symlinks = true;
+            self.xattrs |= cfg!(unix);
+            self.preserve_attributes |= cfg!(windows);
+        }
+
+        self.preserve_perms &= !self.no_preserve_perms;
+        self.preserve_owner &= !self.no_preserve_owner;
+        self.preserve_symlinks &= !self.no_preserve_symlinks;
+        self.xattrs &= !self.no_preserve_xattrs;
+        self.preserve_attributes &= !self.no_preserve_attributes;
+    }
+}
+
+/// A test hook, called with the copy thread's id and the file's source and destination. The thread fails with the error
+/// it returns instead of copying the file.
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct BeforeCopy(pub Arc<BeforeCopyFn>);
+
+#[cfg(test)]
+type BeforeCopyFn = dyn Fn(usize, &Path, &Path) -> Option<crate::CopyError> + Send + Sync;
+
+#[cfg(test)]
+impl std::fmt::Debug for BeforeCopy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BeforeCopy")
+    }
+}
+
+/// Fail with a conflict between `option` and the first of `others` that's set, if `option` is set too.
+fn conflict(
+    option: &'static str,
+    set: bool,
+    others: &[(&'static str, bool)],
+) -> Result<(), OptionsError> {
+    match others.iter().find(|(_, other_set)| set && *other_set) {
+        Some((other, _)) => Err(OptionsError::Conflict(option, other)),
+        None => Ok(()),
+    }
+}
+
+/// What happens to files that already exist at the destination, set with [`CopyOptionsBuilder::overwrite`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Fail, like `ninecopy` with none of `--skip`, `--overwrite`, `--update`, or `--rename-collisions`.
+    #[default]
+    Error,
+    /// Leave them alone.
+    Skip,
+    /// Replace them.
+    Overwrite,
+    /// Replace them unless they have the same size and modified time.
+    Update,
+    /// Copy alongside them, under the first free name.
+    Rename,
+}
+
+/// A rule that moves what's copied somewhere else in the destination, set with [`CopyOptionsBuilder::remap`].
+#[derive(Clone, Debug)]
+pub enum Remap {
+    /// Paths relative to the source that start with the first path start with the second one instead. An empty second
+    /// path strips the first.
+    Prefix(PathBuf, PathBuf),
+    /// Where `pattern` matches a path relative to the source, written with `/` between names, it's replaced with
+    /// `replacement` the way [`Regex::replace`](regex::Regex::replace) does, or everywhere it matches with `all`.
+    Regex {
+        pattern: regex::Regex,
+        replacement: String,
+        all: bool,
+    },
+}
+
+impl Remap {
+    /// Where this rule moves `relative`, or `None` if it doesn't apply.
+    pub(crate) fn apply(&self, relative: &Path) -> Option<PathBuf> {
+        match self {
+            Self::Prefix(from, to) => relative.strip_prefix(from).ok().map(|rest| to.join(rest)),
+            Self::Regex {
+                pattern,
+                replacement,
+                all,
+            } => {
+                let text = relative.to_str()?;
+                #[cfg(windows)]
+                let text = &text.replace('\\', "/");
+                if !pattern.is_match(text) {
+                    return None;
+                }
+                let replaced = match all {
+                    true => pattern.replace_all(text, replacement.as_str()),
+                    false => pattern.replace(text, replacement.as_str()),
+                };
+                Some(PathBuf::from(replaced.as_ref()))
+            }
+        }
+    }
+}
+
+/// Which metadata is copied along with files, set with [`CopyOptionsBuilder::preserve`]. Combine them with `|`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Preserve(u8);
+
+impl Preserve {
+    pub const NONE: Self = Self(0);
+    /// Modification and access times.
+    pub const TIMES: Self = Self(1);
+    /// Permissions.
+    pub const PERMS: Self = Self(1 << 1);
+    /// Owner and group, when running as root.
+    pub const OWNER: Self = Self(1 << 2);
+    /// Extended attributes.
+    pub const XATTRS: Self = Self(1 << 3);
+    /// Symbolic links, as links.
+    pub const SYMLINKS: Self = Self(1 << 4);
+    /// Windows attributes.
+    pub const ATTRIBUTES: Self = Self(1 << 5);
+
+    /// Whether everything in `other` is in this too.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for Preserve {
+    /// Just the times, like `ninecopy` with no flags.
+    fn default() -> Self {
+        Self::TIMES
+    }
+}
+
+impl BitOr for Preserve {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Sets [`CopyOptions`] a few at a time, from [`CopyOptions::builder`]. Anything not set keeps its default.
+#[derive(Clone, Debug)]
+pub struct CopyOptionsBuilder {
+    opts: CopyOptions,
+}
+
+/// A builder method for each of these fields, setting it to the value given.
+macro_rules! setters {
+    ($($field:ident: $type:ty,)*) => {
+        $(
+            #[doc = concat!("Sets [`CopyOptions::", stringify!($field), "`].")]
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.opts.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+impl CopyOptionsBuilder {
+    /// What to do with files that already exist at the destination.
+    pub fn overwrite(mut self, policy: Policy) -> Self {
+        self.opts.skip = policy == Policy::Skip;
+        self.opts.overwrite = policy == Policy::Overwrite;
+        self.opts.update = policy == Policy::Update;
+        self.opts.rename_collisions = policy == Policy::Rename;
+        self
+    }
+
+    /// Which metadata to copy. Anything left out isn't, except with `archive`, which adds everything it can.
+    pub fn preserve(mut self, preserve: Preserve) -> Self {
+        self.opts.no_preserve_times = !preserve.contains(Preserve::TIMES);
+        self.opts.preserve_perms = preserve.contains(Preserve::PERMS);
+        self.opts.preserve_owner = preserve.contains(Preserve::OWNER);
+        self.opts.xattrs = preserve.contains(Preserve::XATTRS);
+        self.opts.preserve_symlinks = preserve.contains(Preserve::SYMLINKS);
+        self.opts.preserve_attributes = preserve.contains(Preserve::ATTRIBUTES);
+        self
+    }
+
+    /// Metadata not to copy, even with `archive`.
+    pub fn no_preserve(mut self, preserve: Preserve) -> Self {
+        self.opts.no_preserve_times |= preserve.contains(Preserve::TIMES);
+        self.opts.no_preserve_perms = preserve.contains(Preserve::PERMS);
+        self.opts.no_preserve_owner = preserve.contains(Preserve::OWNER);
+        self.opts.no_preserve_xattrs = preserve.contains(Preserve::XATTRS);
+        self.opts.no_preserve_symlinks = preserve.contains(Preserve::SYMLINKS);
+        self.opts.no_preserve_attributes = preserve.contains(Preserve::ATTRIBUTES);
+        self
+    }
+
+    setters! {
+        also_to: Vec<PathBuf>,
+        policies: Vec<(glob::Pattern, Policy)>,
+        force: bool,
+        backup: Option<String>,
+        backup_dir: Option<PathBuf>,
+        delta: bool,
+        checksum: bool,
+        modify_window: Duration,
+        verify: bool,
+        verify_only: bool,
+        verify_sample: Option<f64>,
+        verify_sample_by: SampleBy,
+        verify_sample_seed: Option<u64>,
+        audit: bool,
+        hash: HashAlgorithm,
+        checksum_file: Option<PathBuf>,
+        checksum_file_append: bool,
+        check_manifest: Option<PathBuf>,
+        diff: bool,
+        dedupe_dest: bool,
+        move_files: bool,
+        delete: bool,
+        delete_even_if_empty: bool,
+        delete_dry_run: bool,
+        detect_renames: bool,
+        watch: bool,
+        watch_debounce: Duration,
+        watch_rescan: Option<Duration>,
+        repeat: Option<Duration>,
+        repeat_max_failures: Option<u32>,
+        progress: bool,
+        show_active: bool,
+        print0: bool,
+        threads: Option<usize>,
+        auto_tune: bool,
+        max_threads: Option<usize>,
+        background: bool,
+        copy_if_newer: bool,
+        copy_if_larger: bool,
+        continue_on_error: bool,
+        recopy_changed: bool,
+        skip_locked: bool,
+        strict: bool,
+        error_log: Option<PathBuf>,
+        state_file: Option<PathBuf>,
+        save_file_list: Option<PathBuf>,
+        files_from: Option<PathBuf>,
+        max_files: Option<u64>,
+        max_bytes: Option<u64>,
+        spool_dir: Option<PathBuf>,
+        spool_after: usize,
+        max_memory: Option<u64>,
+        atomic: bool,
+        keep_partial: bool,
+        retries: u32,
+        retry_wait: Duration,
+        file_timeout: Option<Duration>,
+        min_rate: Option<u64>,
+        wait_on_full: bool,
+        no_space_check: bool,
+        check_path_length: bool,
+        sanitize_names: Option<String>,
+        normalize: Normalization,
+        transform_case: CaseTransform,
+        remaps: Vec<Remap>,
+        no_create_dest: bool,
+        rsync_paths: bool,
+        strict_dir_only: bool,
+        prune_empty_dirs: bool,
+        dirs_only: bool,
+        files_only: bool,
+        owner: Option<u32>,
+        group: Option<u32>,
+        prune_foreign_dirs: bool,
+        archive: bool,
+        preserve_all: bool,
+        acls: Option<AclMode>,
+        fail_on_special: bool,
+        skip_placeholders: bool,
+        hydrate: bool,
+        fsync: Option<FsyncMode>,
+        unbuffered: bool,
+        buffer_size: usize,
+        bwlimit: Option<u64>,
+        direct_io: bool,
+        direct_io_threshold: u64,
+        drop_cache: bool,
+        split_large_files: Option<u64>,
+        large_file_size: u64,
+        max_large_concurrent: Option<usize>,
+        locality: bool,
+        prefer: Vec<glob::Pattern>,
+        reflink: ReflinkMode,
+        sparse: SparseMode,
+        link: Option<LinkMode>,
+        hard_links: bool,
+        follow_junctions: bool,
+        cancellation: CancellationToken,
+    }
+
+    /// Existing files whose paths, relative to the source, match `pattern` get `policy` instead of what
+    /// [`overwrite`](Self::overwrite) set, unless a pattern added before matches them first.
+    pub fn policy(mut self, pattern: glob::Pattern, policy: Policy) -> Self {
+        self.opts.policies.push((pattern, policy));
+        self
+    }
+
+    /// Move what's copied somewhere else in the destination with `remap`, after any rules added before.
+    pub fn remap(mut self, remap: Remap) -> Self {
+        self.opts.remaps.push(remap);
+        self
+    }
+
+    /// Only copy what `filter` includes, as well as any filters added before.
+    pub fn filter(mut self, filter: impl FileFilter + 'static) -> Self {
+        self.opts.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Check the options make sense together with [`CopyOptions::validate`], and return them if they do.
+    pub fn build(self) -> Result<CopyOptions, OptionsError> {
+        self.opts.validate()?;
+        Ok(self.opts)
+    }
+}
+
+/// What `--verify-sample` is a percentage of.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleBy {
+    /// Files, each as likely to be picked as any other.
+    #[default]
+    Files,
+    /// Bytes, so bigger files are more likely to be picked, and about that share of the data is verified.
+    Bytes,
+}
+
+/// When `--fsync` syncs copied files to disk.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncMode {
+    /// As each file is copied.
+    Each,
+    /// All at once at the end, along with the directories they're in.
+    Batch,
+}
+
+/// When `--reflink` clones files.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReflinkMode {
+    /// Clone files when the filesystem supports it, and copy them otherwise.
+    Auto,
+    /// Clone every file, failing if that isn't possible.
+    Always,
+    /// Copy every file.
+    Never,
+}
+
+/// When `--sparse` copies files sparsely.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SparseMode {
+    /// Copy files sparsely when they're mostly holes.
+    Auto,
+    /// Look for holes in every file.
+    Always,
+    /// Write out every byte.
+    Never,
+}
+
+/// The Unicode normalization form `--normalize` converts destination paths to.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Composed, as Linux and Windows usually write names.
+    Nfc,
+    /// Decomposed, as HFS+ stores names.
+    Nfd,
+    /// Leave paths as they are.
+    #[default]
+    None,
+}
+
+/// The case `--transform-case` changes destination names to.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseTransform {
+    /// All lowercase.
+    Lower,
+    /// All uppercase.
+    Upper,
+    /// Leave names as they are.
+    #[default]
+    None,
+}
+
+/// What `--link` does when a file can't be linked because it's on a different device than the destination.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Copy the file instead.
+    Fallback,
+    /// Fail.
+    Strict,
+}
+
+/// Which parts of a file's security descriptor `--acls` copies.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclMode {
+    /// Just the permissions.
+    Dacl,
+    /// The permissions, owner, group, and auditing entries.
+    All,
+}
+
+/// The hash `--hash` picks.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// XXH3, 128 bits.
+    Xxh3,
+    /// BLAKE3, 256 bits.
+    Blake3,
+    /// SHA-256.
+    Sha256,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::owner;
+
+    /// The error `validate` gives for the defaults changed by `change`.
+    fn invalid(change: impl FnOnce(&mut CopyOptions)) -> OptionsError {
+        let mut opts = CopyOptions::default();
+        change(&mut opts);
+        opts.validate().unwrap_err()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        CopyOptions::default().validate().unwrap();
+    }
+
+    #[test]
+    fn conflict() {
+        let err = invalid(|opts| {
+            opts.skip = true;
+            opts.overwrite = true;
+        });
+        assert_eq!(err, OptionsError::Conflict("skip", "overwrite"));
+    }
+
+    #[test]
+    fn requires() {
+        let err = invalid(|opts| opts.max_threads = Some(4));
+        assert_eq!(err, OptionsError::Requires("max_threads", "auto_tune"));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn unsupported() {
+        let err = invalid(|opts| opts.unbuffered = true);
+        assert_eq!(err, OptionsError::Unsupported("unbuffered", "Windows"));
+    }
+
+    #[test]
+    fn zero() {
+        let err = invalid(|opts| opts.threads = Some(0));
+        assert_eq!(err, OptionsError::Zero("threads"));
+    }
+
+    #[test]
+    fn percentage() {
+        for percent in [0.0, -5.0, 100.5] {
+            let err = invalid(|opts| opts.verify_sample = Some(percent));
+            assert_eq!(err, OptionsError::Percentage("verify_sample"));
+        }
+        CopyOptions {
+            verify_sample: Some(100.0),
+            ..CopyOptions::default()
+        }
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn invalid_replacement() {
+        for replacement in ["", "?"] {
+            let err = invalid(|opts| opts.sanitize_names = Some(replacement.to_string()));
+            assert_eq!(
+                err,
+                OptionsError::InvalidReplacement(replacement.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_remap() {
+        let err = invalid(|opts| {
+            opts.remaps
+                .push(Remap::Prefix(PathBuf::from("a"), PathBuf::from("../b")))
+        });
+        assert_eq!(err, OptionsError::InvalidRemap("a=../b".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unknown_user() {
+        let name = "ninecopy-no-such-user";
+        assert_eq!(
+            owner::user_id(name),
+            Err(OptionsError::UnknownUser(name.to_string()))
+        );
+        assert_eq!(owner::user_id("0"), Ok(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unknown_group() {
+        let name = "ninecopy-no-such-group";
+        assert_eq!(
+            owner::group_id(name),
+            Err(OptionsError::UnknownGroup(name.to_string()))
+        );
+        assert_eq!(owner::group_id("0"), Ok(0));
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::Metadata;
+
+use crate::{errors::OptionsError, options::CopyOptions};
+
+/// The ID of the user called `name`, or `name` itself if it's a number, for
+/// [`CopyOptions::owner`](crate::CopyOptions::owner). Only supported on Unix.
+pub fn user_id(name: &str) -> Result<u32, OptionsError> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    #[cfg(unix)]
+    {
+        lookup(name, |name, buffer| {
+            let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            let code = unsafe {
+                libc::getpwnam_r(
+                    name,
+                    &mut passwd,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            };
+            (code, (!result.is_null()).then_some(passwd.pw_uid))
+        })
+        .ok_or_else(|| OptionsError::UnknownUser(name.to_string()))
+    }
+    #[cfg(not(unix))]
+    Err(OptionsError::Unsupported("owner", "Unix"))
+}
+
+/// The ID of the group called `name`, or `name` itself if it's a number, for
+/// [`CopyOptions::group`](crate::CopyOptions::group). Only supported on Unix.
+pub fn group_id(name: &str) -> Result<u32, OptionsError> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    #[cfg(unix)]
+    {
+        lookup(name, |name, buffer| {
+            let mut group: libc::group = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            let code = unsafe {
+                libc::getgrnam_r(
+                    name,
+                    &mut group,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            };
+            (code, (!result.is_null()).then_some(group.gr_gid))
+        })
+        .ok_or_else(|| OptionsError::UnknownGroup(name.to_string()))
+    }
+    #[cfg(not(unix))]
+    Err(OptionsError::Unsupported("group", "Unix"))
+}
+
+/// Call one of the reentrant `get*nam_r` functions through `get`, with a buffer that's grown until it's big enough
+/// for the entry.
+#[cfg(unix)]
+fn lookup(
+    name: &str,
+    get: impl Fn(*const libc::c_char, &mut [libc::c_char]) -> (libc::c_int, Option<u32>),
+) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer = vec![0; 1024];
+    loop {
+        match get(name.as_ptr(), &mut buffer) {
+            (libc::ERANGE, _) if buffer.len() < 1024 * 1024 => buffer.resize(buffer.len() * 2, 0),
+            (_, id) => return id,
+        }
+    }
+}
+
+/// Whether what `metadata` belongs to has the owner and group `opts` asks for, if it asks for either.
+pub(crate) fn owned(opts: &CopyOptions, metadata: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        opts.owner.is_none_or(|uid| metadata.uid() == uid)
+            && opts.group.is_none_or(|gid| metadata.gid() == gid)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (opts, metadata);
+        true
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+};
+
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+use crate::options::{CaseTransform, Normalization, Remap};
+
+/// Names Windows reserves for devices, which files and directories can't have, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows doesn't allow in names, besides control characters.
+const INVALID_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// The longest path most Windows APIs accept, including the terminating null, unless it's in extended-length form.
+pub const MAX_PATH: usize = 260;
+
+/// The longest directory path Windows can create without the extended-length form, leaving room for an 8.3 file name
+/// inside it.
+pub const MAX_DIR_PATH: usize = MAX_PATH - 12;
+
+/// Canonicalize `path`, even if it doesn't exist yet, by canonicalizing its nearest existing ancestor and appending
+/// the rest.
+pub fn canonicalize_lenient(path: &Path) -> std::io::Result<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        match std::fs::canonicalize(existing) {
+            Ok(canonical) => {
+                return Ok(missing
+                    .iter()
+                    .rev()
+                    .fold(canonical, |path, component| path.join(component)));
+            }
+            Err(err) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name.to_os_string());
+                    existing = if parent.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        parent
+                    };
+                }
+                _ => return Err(err),
+            },
+        }
+    }
+}
+
+/// Returns true if `path`, as it was typed, ends with a separator, like `src/`. On Windows, `\` and `/` both count.
+pub fn has_trailing_separator(path: &Path) -> bool {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .last()
+        .is_some_and(|&byte| std::path::is_separator(byte as char))
+}
+
+/// Returns true if `path` is `base` or somewhere inside it. Both paths should already be canonical.
+///
+/// On Windows the comparison ignores case, as the filesystem does.
+pub fn is_within(path: &Path, base: &Path) -> bool {
+    if cfg!(windows) {
+        let lower = |path: &Path| PathBuf::from(path.to_string_lossy().to_lowercase());
+        lower(path).starts_with(lower(base))
+    } else {
+        path.starts_with(base)
+    }
+}
+
+/// The length of `path` as Windows counts it, in UTF-16 code units.
+pub fn windows_len(path: &Path) -> usize {
+    path.as_os_str().to_string_lossy().encode_utf16().count()
+}
+
+/// Returns true if `stem`, a name without its extension, is a reserved device name. Windows ignores trailing spaces
+/// and case when matching them, so `Con .txt` is reserved too.
+fn is_reserved(stem: &str) -> bool {
+    let stem = stem.trim_end_matches(' ');
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Returns true for characters Windows doesn't allow in names.
+fn is_invalid_char(c: char) -> bool {
+    (c as u32) < 0x20 || INVALID_CHARS.contains(&c)
+}
+
+/// Why `name` can't be used for a file or directory on Windows, if it can't.
+pub fn windows_name_problem(name: &OsStr) -> Option<String> {
+    let name = name.to_string_lossy();
+    if let Some(invalid) = name.chars().find(|&c| is_invalid_char(c)) {
+        return Some(format!(
+            "contains {:?}, which Windows doesn't allow in names",
+            invalid
+        ));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Some("ends with a dot or space, which Windows strips".to_string());
+    }
+    if is_reserved(name.split('.').next().unwrap_or_default()) {
+        return Some("is a reserved device name".to_string());
+    }
+    None
+}
+
+/// `name` changed so Windows can use it, or `None` if it already can. Characters Windows doesn't allow and trailing
+/// dots and spaces are replaced with `replacement`, and reserved device names get `replacement` before the extension,
+/// so with `_`, `a:b` becomes `a_b`, `con` becomes `con_`, and `aux.txt` becomes `aux_.txt`.
+pub fn sanitize_name(name: &OsStr, replacement: &str) -> Option<OsString> {
+    windows_name_problem(name)?;
+    let mut sanitized = String::new();
+    for c in name.to_string_lossy().chars() {
+        match is_invalid_char(c) {
+            true => sanitized.push_str(replacement),
+            false => sanitized.push(c),
+        }
+    }
+    let kept = sanitized.trim_end_matches(['.', ' ']).len();
+    let stripped = sanitized.len() - kept;
+    sanitized.truncate(kept);
+    sanitized.push_str(&replacement.repeat(stripped));
+    let stem = sanitized.find('.').unwrap_or(sanitized.len());
+    if is_reserved(&sanitized[..stem]) {
+        sanitized.insert_str(stem, replacement);
+    }
+    Some(sanitized.into())
+}
+
+/// `relative` with every name in it that Windows can't use passed through [`sanitize_name`].
+pub fn sanitize<'a>(relative: Cow<'a, Path>, replacement: &str) -> Cow<'a, Path> {
+    let needs_sanitizing = |component: &Component| matches!(component, Component::Normal(name) if windows_name_problem(name).is_some());
+    if !relative
+        .components()
+        .any(|component| needs_sanitizing(&component))
+    {
+        return relative;
+    }
+    Cow::Owned(
+        relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => {
+                    sanitize_name(name, replacement).unwrap_or_else(|| name.to_os_string())
+                }
+                other => other.as_os_str().to_os_string(),
+            })
+            .collect(),
+    )
+}
+
+/// `relative` moved by each of `rules` in turn.
+pub fn remap<'a>(relative: &'a Path, rules: &[Remap]) -> Cow<'a, Path> {
+    let mut remapped = Cow::Borrowed(relative);
+    for rule in rules {
+        if let Some(moved) = rule.apply(&remapped) {
+            remapped = Cow::Owned(moved);
+        }
+    }
+    remapped
+}
+
+/// Returns true if `relative` only goes down from where it starts, so it can't end up outside the destination.
+pub fn is_plain_relative(relative: &Path) -> bool {
+    relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// `relative` with every name in it changed to the case `transform` picks. Names that aren't valid Unicode are left as
+/// they are.
+pub fn transform_case(relative: Cow<Path>, transform: CaseTransform) -> Cow<Path> {
+    let transform_name = |name: &str| match transform {
+        CaseTransform::Lower => name.to_lowercase(),
+        CaseTransform::Upper => name.to_uppercase(),
+        CaseTransform::None => name.to_string(),
+    };
+    let changes = |component: &Component| matches!(component, Component::Normal(name) if name.to_str().is_some_and(|name| transform_name(name) != name));
+    if !relative.components().any(|component| changes(&component)) {
+        return relative;
+    }
+    Cow::Owned(
+        relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => OsString::from(transform_name(name)),
+                    None => name.to_os_string(),
+                },
+                other => other.as_os_str().to_os_string(),
+            })
+            .collect(),
+    )
+}
+
+/// `relative` with its Unicode normalized to `form`. Paths that aren't valid Unicode are left as they are.
+pub fn normalize(relative: Cow<Path>, form: Normalization) -> Cow<Path> {
+    let Some(text) = relative.to_str() else {
+        return relative;
+    };
+    let normalized: String = match form {
+        Normalization::Nfc if !is_nfc(text) => text.nfc().collect(),
+        Normalization::Nfd if !is_nfd(text) => text.nfd().collect(),
+        _ => return relative,
+    };
+    Cow::Owned(PathBuf::from(normalized))
+}
+
+/// Returns true if the filesystem `dir` is on ignores case in names, found by creating a file in it and looking it up
+/// under another case. If that can't be done, assumes the platform's default filesystem does.
+pub fn is_case_insensitive(dir: &Path) -> bool {
+    let probe = dir.join(format!(".ninecopy-case-probe.{}", std::process::id()));
+    if std::fs::File::create(&probe).is_err() {
+        return cfg!(any(windows, target_os = "macos"));
+    }
+    let insensitive =
+        std::fs::symlink_metadata(dir.join(format!(".NINECOPY-CASE-PROBE.{}", std::process::id())))
+            .is_ok();
+    let _ = std::fs::remove_file(&probe);
+    insensitive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_separator() {
+        assert!(has_trailing_separator(Path::new("src/")));
+        assert!(has_trailing_separator(Path::new("src//")));
+        assert!(has_trailing_separator(Path::new("dir/src/")));
+        assert!(has_trailing_separator(Path::new("/")));
+        assert!(!has_trailing_separator(Path::new("src")));
+        assert!(!has_trailing_separator(Path::new("dir/src")));
+        assert!(!has_trailing_separator(Path::new("src/.")));
+        assert!(!has_trailing_separator(Path::new("")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backslash_is_part_of_the_name_on_unix() {
+        assert!(!has_trailing_separator(Path::new("src\\")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn either_slash_is_a_separator_on_windows() {
+        assert!(has_trailing_separator(Path::new("src\\")));
+        assert!(has_trailing_separator(Path::new(r"C:\dir\src\")));
+        assert!(has_trailing_separator(Path::new("C:/dir/src/")));
+        assert!(has_trailing_separator(Path::new(r"C:\")));
+        assert!(!has_trailing_separator(Path::new(r"C:\dir\src")));
+    }
+}
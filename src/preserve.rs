@@ -0,0 +1,372 @@
+use std::{
+    fs::{File, FileTimes, Metadata},
+    path::{Path, PathBuf},
+};
+
+use crate::options::{AclMode, CopyOptions};
+
+/// Open `path`, a file or a directory, in a way that allows its metadata to be changed even if it's read-only.
+fn open_for_metadata(path: &Path) -> std::io::Result<File> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // FILE_WRITE_ATTRIBUTES, and FILE_FLAG_BACKUP_SEMANTICS so directories can be opened too.
+        std::fs::OpenOptions::new()
+            .access_mode(0x100)
+            .custom_flags(0x0200_0000)
+            .open(path)
+    }
+
+    #[cfg(not(windows))]
+    File::open(path)
+}
+
+/// Give `dst` the modification and access times from `src_metadata`.
+pub fn set_times(dst: &Path, src_metadata: &Metadata) -> std::io::Result<()> {
+    let mut times = FileTimes::new().set_modified(src_metadata.modified()?);
+    if let Ok(accessed) = src_metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    open_for_metadata(dst)?.set_times(times)
+}
+
+/// Give `dst` the permissions from `src_metadata`, including setuid, setgid, and sticky bits on Unix.
+pub fn set_permissions(dst: &Path, src_metadata: &Metadata) -> std::io::Result<()> {
+    std::fs::set_permissions(dst, src_metadata.permissions())
+}
+
+/// Give `dst` the owner and group from `src_metadata`.
+#[cfg(unix)]
+pub fn set_owner(dst: &Path, src_metadata: &Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::os::unix::fs::chown(dst, Some(src_metadata.uid()), Some(src_metadata.gid()))
+}
+
+/// Ownership isn't preserved outside of Unix.
+#[cfg(not(unix))]
+pub fn set_owner(_dst: &Path, _src_metadata: &Metadata) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Returns true if this process is able to change file ownership.
+pub fn can_set_owner() -> bool {
+    #[cfg(unix)]
+    return unsafe { libc::geteuid() } == 0;
+
+    #[cfg(not(unix))]
+    return false;
+}
+
+/// Give `dst` the Windows file attributes (hidden, read-only, system, archive, etc.) from `src_metadata`.
+#[cfg(windows)]
+pub fn set_attributes(dst: &Path, src_metadata: &Metadata) -> std::io::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        SetFileAttributesW, FILE_ATTRIBUTE_ARCHIVE, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_NORMAL,
+        FILE_ATTRIBUTE_NOT_CONTENT_INDEXED, FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_READONLY,
+        FILE_ATTRIBUTE_SYSTEM, FILE_ATTRIBUTE_TEMPORARY,
+    };
+
+    // The attributes SetFileAttributesW is able to set. Things like compression and encryption need their own APIs.
+    const SETTABLE: u32 = FILE_ATTRIBUTE_ARCHIVE
+        | FILE_ATTRIBUTE_HIDDEN
+        | FILE_ATTRIBUTE_NOT_CONTENT_INDEXED
+        | FILE_ATTRIBUTE_OFFLINE
+        | FILE_ATTRIBUTE_READONLY
+        | FILE_ATTRIBUTE_SYSTEM
+        | FILE_ATTRIBUTE_TEMPORARY;
+
+    let mut attributes = src_metadata.file_attributes() & SETTABLE;
+    if attributes == 0 {
+        attributes = FILE_ATTRIBUTE_NORMAL;
+    }
+
+    let wide = to_wide(dst);
+    if unsafe { SetFileAttributesW(wide.as_ptr(), attributes) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// File attributes are only preserved on Windows.
+#[cfg(not(windows))]
+pub fn set_attributes(_dst: &Path, _src_metadata: &Metadata) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Copy the extended attributes of `src` to `dst`, printing a warning for each one that couldn't be copied. Returns
+/// false if any attribute failed.
+#[cfg(unix)]
+pub fn copy_xattrs(src: &Path, dst: &Path) -> bool {
+    let names = match xattr::list(src) {
+        Ok(names) => names,
+        Err(err) => {
+            message!(
+                "Warning: unable to list extended attributes of {}: {}",
+                src.display(),
+                err
+            );
+            return false;
+        }
+    };
+
+    let mut success = true;
+    for name in names {
+        let result = xattr::get(src, &name).and_then(|value| match value {
+            Some(value) => xattr::set(dst, &name, &value),
+            None => Ok(()),
+        });
+        if let Err(err) = result {
+            message!(
+                "Warning: unable to copy extended attribute {} to {}: {}",
+                name.to_string_lossy(),
+                dst.display(),
+                err
+            );
+            success = false;
+        }
+    }
+    success
+}
+
+/// Extended attributes are only copied on Unix.
+#[cfg(not(unix))]
+pub fn copy_xattrs(_src: &Path, _dst: &Path) -> bool {
+    true
+}
+
+/// Copy the Windows security descriptor of `src` to `dst`, printing a warning if it couldn't be copied. Returns false
+/// if it failed.
+#[cfg(windows)]
+pub fn copy_acls(src: &Path, dst: &Path, mode: AclMode) -> bool {
+    if let Err(err) = copy_security_descriptor(src, dst, mode) {
+        message!("Warning: unable to copy ACLs to {}: {}", dst.display(), err);
+        return false;
+    }
+    true
+}
+
+/// ACLs are only copied on Windows.
+#[cfg(not(windows))]
+pub fn copy_acls(_src: &Path, _dst: &Path, _mode: AclMode) -> bool {
+    true
+}
+
+#[cfg(windows)]
+fn copy_security_descriptor(src: &Path, dst: &Path, mode: AclMode) -> std::io::Result<()> {
+    use std::ptr::null_mut;
+    use windows_sys::Win32::{
+        Foundation::ERROR_SUCCESS,
+        Security::{
+            Authorization::{GetNamedSecurityInfoW, SetNamedSecurityInfoW, SE_FILE_OBJECT},
+            GetSecurityDescriptorControl, DACL_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION,
+            OWNER_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION,
+            PROTECTED_SACL_SECURITY_INFORMATION, SACL_SECURITY_INFORMATION, SE_DACL_PROTECTED,
+            SE_SACL_PROTECTED, UNPROTECTED_DACL_SECURITY_INFORMATION,
+            UNPROTECTED_SACL_SECURITY_INFORMATION,
+        },
+        System::Memory::LocalFree,
+    };
+
+    let mut info = DACL_SECURITY_INFORMATION;
+    if mode == AclMode::All {
+        info |= OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | SACL_SECURITY_INFORMATION;
+    }
+
+    let src = to_wide(src);
+    let dst = to_wide(dst);
+    let (mut owner, mut group, mut dacl, mut sacl, mut descriptor) =
+        (null_mut(), null_mut(), null_mut(), null_mut(), null_mut());
+    let result = unsafe {
+        GetNamedSecurityInfoW(
+            src.as_ptr(),
+            SE_FILE_OBJECT,
+            info,
+            &mut owner,
+            &mut group,
+            &mut dacl,
+            &mut sacl,
+            &mut descriptor,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(result as i32));
+    }
+
+    // Without these flags the copy would always pick up inheritable entries from its new parent, even if the
+    // source was set not to inherit anything.
+    let (mut control, mut revision) = (0, 0);
+    unsafe { GetSecurityDescriptorControl(descriptor, &mut control, &mut revision) };
+    info |= if control & SE_DACL_PROTECTED != 0 {
+        PROTECTED_DACL_SECURITY_INFORMATION
+    } else {
+        UNPROTECTED_DACL_SECURITY_INFORMATION
+    };
+    if mode == AclMode::All {
+        info |= if control & SE_SACL_PROTECTED != 0 {
+            PROTECTED_SACL_SECURITY_INFORMATION
+        } else {
+            UNPROTECTED_SACL_SECURITY_INFORMATION
+        };
+    }
+
+    let result = unsafe {
+        SetNamedSecurityInfoW(dst.as_ptr(), SE_FILE_OBJECT, info, owner, group, dacl, sacl)
+    };
+    unsafe { LocalFree(descriptor as _) };
+    if result != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(result as i32));
+    }
+    Ok(())
+}
+
+/// Enable the privileges `--acls=all` needs to set owners and auditing entries, failing if this process doesn't hold
+/// them.
+#[cfg(windows)]
+pub fn enable_acl_privileges(mode: AclMode) -> std::io::Result<()> {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, GetLastError, ERROR_NOT_ALL_ASSIGNED, LUID},
+        Security::{
+            AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES,
+            SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+        },
+        System::Threading::{GetCurrentProcess, OpenProcessToken},
+    };
+
+    if mode != AclMode::All {
+        return Ok(());
+    }
+
+    let mut token = 0;
+    if unsafe {
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        )
+    } == 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut result = Ok(());
+    for name in ["SeRestorePrivilege", "SeSecurityPrivilege"] {
+        let mut luid = LUID {
+            LowPart: 0,
+            HighPart: 0,
+        };
+        let wide_name = to_wide(Path::new(name));
+        if unsafe { LookupPrivilegeValueW(std::ptr::null(), wide_name.as_ptr(), &mut luid) } == 0 {
+            result = Err(std::io::Error::last_os_error());
+            break;
+        }
+
+        let privileges = TOKEN_PRIVILEGES {
+            PrivilegeCount: 1,
+            Privileges: [LUID_AND_ATTRIBUTES {
+                Luid: luid,
+                Attributes: SE_PRIVILEGE_ENABLED,
+            }],
+        };
+        // AdjustTokenPrivileges succeeds even when the privilege isn't held, and only says so through GetLastError.
+        let adjusted = unsafe {
+            AdjustTokenPrivileges(
+                token,
+                0,
+                &privileges,
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if adjusted == 0 || unsafe { GetLastError() } == ERROR_NOT_ALL_ASSIGNED {
+            result = Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("this process doesn't hold {}", name),
+            ));
+            break;
+        }
+    }
+
+    unsafe { CloseHandle(token) };
+    result
+}
+
+/// ACLs are only copied on Windows.
+#[cfg(not(windows))]
+pub fn enable_acl_privileges(_mode: AclMode) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+fn to_wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// Apply the preserved metadata for a copied file or directory at `dst`, printing a warning for anything that
+/// couldn't be set.
+///
+/// Ownership is set first, since changing it can clear setuid and setgid bits.
+pub fn apply(dst: &Path, src_metadata: &Metadata, opts: &CopyOptions) {
+    if opts.preserve_owner {
+        if let Err(err) = set_owner(dst, src_metadata) {
+            message!("Warning: unable to set owner on {}: {}", dst.display(), err);
+        }
+    }
+
+    if opts.preserve_perms {
+        if let Err(err) = set_permissions(dst, src_metadata) {
+            message!(
+                "Warning: unable to set permissions on {}: {}",
+                dst.display(),
+                err
+            );
+        }
+    }
+
+    if opts.preserve_attributes {
+        if let Err(err) = set_attributes(dst, src_metadata) {
+            message!(
+                "Warning: unable to set attributes on {}: {}",
+                dst.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Apply preserved metadata to copied directories once everything inside them has been copied. Each entry is the
+/// source directory, its copy, and the source's metadata.
+///
+/// This runs deepest-first, so a directory made read-only doesn't stop its parent from being updated, and nothing
+/// gets written into a directory after its metadata is set. Copying anything into a directory changes its
+/// modification time, which is why its times can't be set as it's created.
+pub fn apply_to_directories(
+    mut directories: Vec<(PathBuf, PathBuf, Metadata)>,
+    opts: &CopyOptions,
+) {
+    directories.sort_by_key(|(_, path, _)| std::cmp::Reverse(path.components().count()));
+    for (src, dst, src_metadata) in directories {
+        // Pruned directories were never created.
+        if (opts.prune_empty_dirs || opts.files_only) && !dst.exists() {
+            continue;
+        }
+        // Before the permissions, which could leave it unopenable.
+        if !opts.no_preserve_times {
+            if let Err(err) = set_times(&dst, &src_metadata) {
+                message!("Warning: unable to set times on {}: {}", dst.display(), err);
+            }
+        }
+        apply(&dst, &src_metadata, opts);
+        if let Some(mode) = opts.acls {
+            copy_acls(&src, &dst, mode);
+        }
+    }
+}
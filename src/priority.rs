@@ -0,0 +1,95 @@
+/// Lower the CPU and I/O priority of this process for `--background`, printing what was lowered and warning about
+/// anything that couldn't be. This has to happen before any threads are started, since they inherit the priority of
+/// the thread that starts them.
+pub fn lower() {
+    let mut lowered = Vec::new();
+    for (what, result) in lower_priorities() {
+        match result {
+            Ok(()) => lowered.push(what),
+            Err(err) => message!("Warning: unable to lower {}: {}", what, err),
+        }
+    }
+    if !lowered.is_empty() {
+        message!(
+            "Running in the background with lowered {}",
+            lowered.join(" and ")
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lower_priorities() -> Vec<(&'static str, std::io::Result<()>)> {
+    // From linux/ioprio.h, which libc doesn't have.
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    let io = match unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    };
+    vec![("I/O priority", io), ("CPU priority", nice())]
+}
+
+#[cfg(target_os = "macos")]
+fn lower_priorities() -> Vec<(&'static str, std::io::Result<()>)> {
+    // setiopolicy_np returns the error rather than setting errno.
+    let io = match unsafe {
+        libc::setiopolicy_np(
+            libc::IOPOL_TYPE_DISK,
+            libc::IOPOL_SCOPE_PROCESS,
+            libc::IOPOL_THROTTLE,
+        )
+    } {
+        0 => Ok(()),
+        err => Err(std::io::Error::from_raw_os_error(err)),
+    };
+    vec![("I/O priority", io), ("CPU priority", nice())]
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+fn lower_priorities() -> Vec<(&'static str, std::io::Result<()>)> {
+    vec![("CPU priority", nice())]
+}
+
+/// Give this process the lowest CPU priority.
+#[cfg(unix)]
+fn nice() -> std::io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn lower_priorities() -> Vec<(&'static str, std::io::Result<()>)> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, SetPriorityClass, PROCESS_MODE_BACKGROUND_BEGIN,
+    };
+
+    // Background mode lowers CPU, I/O, and memory priority all at once.
+    let result =
+        match unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } {
+            0 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        };
+    vec![("CPU and I/O priority", result)]
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lower_priorities() -> Vec<(&'static str, std::io::Result<()>)> {
+    vec![(
+        "priority",
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "not supported on this platform",
+        )),
+    )]
+}
@@ -0,0 +1,504 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ffi::OsStr,
+    fs::{File, Metadata, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{errors::CopyError, ResultInfo, SearchResult};
+
+/// Spool files made by this process so far, so copies running side by side don't share one.
+static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+
+/// What something waiting in the queue is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Special,
+}
+
+impl EntryKind {
+    fn of(item: &SearchResult) -> Option<Self> {
+        match item {
+            SearchResult::File(_) => Some(Self::File),
+            SearchResult::Directory(_) => Some(Self::Directory),
+            SearchResult::Symlink(_) => Some(Self::Symlink),
+            SearchResult::Special(_) => Some(Self::Special),
+            SearchResult::Unreadable(..) | SearchResult::Done(_) => None,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::File),
+            1 => Some(Self::Directory),
+            2 => Some(Self::Symlink),
+            3 => Some(Self::Special),
+            _ => None,
+        }
+    }
+
+    fn result(self, info: ResultInfo) -> SearchResult {
+        match self {
+            Self::File => SearchResult::File(info),
+            Self::Directory => SearchResult::Directory(info),
+            Self::Symlink => SearchResult::Symlink(info),
+            Self::Special => SearchResult::Special(info),
+        }
+    }
+}
+
+/// Something taken from the front of the queue.
+pub(crate) enum Popped {
+    Found(SearchResult),
+    /// A spooled entry that couldn't be looked at again, usually because it was removed after the search found it,
+    /// along with its size when it was found.
+    Vanished {
+        kind: EntryKind,
+        path: PathBuf,
+        len: u64,
+        source: std::io::Error,
+    },
+}
+
+/// Everything the search found, waiting to be copied.
+///
+/// The first `limit` entries are kept in memory. With a spool directory, everything after that is written to a spool
+/// file there instead and read back as the copy gets to it, so a huge tree doesn't need every path and its metadata in
+/// memory at once. Spooled entries only record their path, kind, and size, and their metadata is read again when
+/// they're taken from the queue.
+pub(crate) struct Queue {
+    memory: VecDeque<SearchResult>,
+    /// Roughly how many bytes the entries in `memory` take up, by [`entry_size`].
+    memory_bytes: u64,
+    spool_dir: Option<PathBuf>,
+    limit: usize,
+    max_bytes: Option<u64>,
+    /// Everything spooled comes after everything in memory.
+    spool: Option<Spool>,
+}
+
+impl Queue {
+    /// A queue that spools entries to a file in `spool_dir`, if there is one, once `limit` are held in memory or they
+    /// take up `max_bytes`.
+    pub(crate) fn new(spool_dir: Option<&Path>, limit: usize, max_bytes: Option<u64>) -> Self {
+        Self {
+            memory: VecDeque::new(),
+            memory_bytes: 0,
+            spool_dir: spool_dir.map(Path::to_path_buf),
+            limit,
+            max_bytes,
+            spool: None,
+        }
+    }
+
+    /// An empty queue that spools the same way as this one, or with `spool_all`, spools everything if it can.
+    pub(crate) fn new_like(&self, spool_all: bool) -> Self {
+        match spool_all {
+            true => Self::new(self.spool_dir.as_deref(), 0, Some(0)),
+            false => Self::new(self.spool_dir.as_deref(), self.limit, self.max_bytes),
+        }
+    }
+
+    pub(crate) fn push_back(&mut self, item: SearchResult) -> Result<(), CopyError> {
+        let size = entry_size(&item);
+        let full = self.spool.is_some()
+            || self.memory.len() >= self.limit
+            || self
+                .max_bytes
+                .is_some_and(|max| self.memory_bytes + size > max);
+        let Some(spool_dir) = self.spool_dir.as_deref().filter(|_| full) else {
+            self.memory_bytes += size;
+            self.memory.push_back(item);
+            return Ok(());
+        };
+        let spool = match &mut self.spool {
+            Some(spool) => spool,
+            None => self.spool.insert(Spool::create(spool_dir)?),
+        };
+        spool.write(&item)
+    }
+
+    /// Add an entry by its kind, size, and path alone, the way entries are spooled, so its metadata is only read once
+    /// it's taken from the queue. Needs a spool directory.
+    pub(crate) fn push_record(
+        &mut self,
+        kind: EntryKind,
+        len: u64,
+        path: &Path,
+    ) -> Result<(), CopyError> {
+        let spool = match (&mut self.spool, self.spool_dir.as_deref()) {
+            (Some(spool), _) => spool,
+            (None, Some(spool_dir)) => self.spool.insert(Spool::create(spool_dir)?),
+            (None, None) => {
+                return Err(CopyError::Other(
+                    "Entries can only be added without their metadata to a queue that spools"
+                        .to_string(),
+                ))
+            }
+        };
+        spool.write_record(kind, len, path)
+    }
+
+    /// Put back an item that was just taken from the front.
+    pub(crate) fn push_front(&mut self, item: SearchResult) {
+        self.memory_bytes += entry_size(&item);
+        self.memory.push_front(item);
+    }
+
+    /// Take the next item, reading it back from the spool once everything in memory has been taken.
+    pub(crate) fn pop_front(&mut self) -> Result<Option<Popped>, CopyError> {
+        if let Some(item) = self.memory.pop_front() {
+            self.memory_bytes -= entry_size(&item);
+            return Ok(Some(Popped::Found(item)));
+        }
+        let Some(spool) = &mut self.spool else {
+            return Ok(None);
+        };
+        let Some((kind, len, path)) = spool.read()? else {
+            return Ok(None);
+        };
+        let metadata = match kind {
+            EntryKind::Symlink => std::fs::symlink_metadata(&path),
+            _ => std::fs::metadata(&path),
+        };
+        Ok(Some(match metadata {
+            Ok(metadata) => Popped::Found(kind.result(ResultInfo { path, metadata })),
+            Err(source) => Popped::Vanished {
+                kind,
+                path,
+                len,
+                source,
+            },
+        }))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.memory.len() + self.spool.as_ref().map_or(0, Spool::remaining)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write out anything spooled that's still buffered, so it can be read back. Called once the search is over.
+    pub(crate) fn finish(&mut self) -> Result<(), CopyError> {
+        match &mut self.spool {
+            Some(spool) => spool.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// The kind and path of everything in the queue, for the checks made before the copy starts. Spooled entries are
+    /// read back from the spool file, so this is only meant for before anything has been taken from the queue.
+    pub(crate) fn entries(
+        &self,
+    ) -> impl Iterator<Item = Result<(EntryKind, Cow<'_, Path>), CopyError>> + '_ {
+        let memory = self
+            .memory
+            .iter()
+            .filter_map(|item| Some(Ok((EntryKind::of(item)?, Cow::Borrowed(item.path()?)))));
+        let spooled = self
+            .spool
+            .iter()
+            .flat_map(Spool::records)
+            .map(|record| record.map(|(kind, _, path)| (kind, Cow::Owned(path))));
+        memory.chain(spooled)
+    }
+
+    /// Every directory in the queue, with its metadata. Spooled directories are looked at again, and left out if
+    /// they're gone. Like [`Queue::entries`], this is only meant for before the copy starts.
+    pub(crate) fn directories(
+        &self,
+    ) -> impl Iterator<Item = Result<(Cow<'_, Path>, Cow<'_, Metadata>), CopyError>> + '_ {
+        let memory = self.memory.iter().filter_map(|item| match item {
+            SearchResult::Directory(info) => Some(Ok((
+                Cow::Borrowed(info.path.as_path()),
+                Cow::Borrowed(&info.metadata),
+            ))),
+            _ => None,
+        });
+        let spooled =
+            self.spool
+                .iter()
+                .flat_map(Spool::records)
+                .filter_map(|record| match record {
+                    Ok((EntryKind::Directory, _, path)) => {
+                        let metadata = std::fs::metadata(&path).ok()?;
+                        Some(Ok((Cow::Owned(path), Cow::Owned(metadata))))
+                    }
+                    Ok(_) => None,
+                    Err(err) => Some(Err(err)),
+                });
+        memory.chain(spooled)
+    }
+
+    /// Another queue with everything this one has in it, spooled the same way, for copying it all to more than one
+    /// destination. Like [`Queue::entries`], this is only meant for before the copy starts.
+    pub(crate) fn duplicate(&self) -> Result<Self, CopyError> {
+        let mut copy = self.new_like(false);
+        for item in &self.memory {
+            let (Some(kind), Some(info)) = (EntryKind::of(item), item.info()) else {
+                continue;
+            };
+            copy.memory.push_back(kind.result(info.clone()));
+        }
+        copy.memory_bytes = self.memory_bytes;
+        if let (Some(spool), Some(spool_dir)) = (&self.spool, self.spool_dir.as_deref()) {
+            let mut spooled = Spool::create(spool_dir)?;
+            for record in spool.records() {
+                let (kind, len, path) = record?;
+                spooled.write_record(kind, len, &path)?;
+            }
+            spooled.flush()?;
+            copy.spool = Some(spooled);
+        }
+        Ok(copy)
+    }
+
+    /// Everything in the queue, for callers that need it all in memory anyway. Spooled entries are read back and
+    /// looked at again, and left out if they're gone.
+    pub(crate) fn into_memory(mut self) -> Result<VecDeque<SearchResult>, CopyError> {
+        let mut items = VecDeque::with_capacity(self.len());
+        while let Some(popped) = self.pop_front()? {
+            if let Popped::Found(item) = popped {
+                items.push_back(item);
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Roughly how many bytes `item` takes up in memory: the entry itself and its path. Metadata is stored inline, so
+/// it's part of the entry.
+fn entry_size(item: &SearchResult) -> u64 {
+    let path = item.path().map_or(0, |path| path.as_os_str().len());
+    (size_of::<SearchResult>() + path) as u64
+}
+
+/// A temporary file holding the entries that didn't fit in memory, removed when it's dropped.
+///
+/// Each entry is its kind as one byte, its size as 8 bytes, and the length of its path as 4 bytes, followed by the
+/// path. Numbers are little endian.
+struct Spool {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Where [`Queue::pop_front`] has got to, opened on first use.
+    reader: Option<BufReader<File>>,
+    written: usize,
+    read: usize,
+}
+
+impl Spool {
+    fn create(dir: &Path) -> Result<Self, CopyError> {
+        let path = dir.join(format!(
+            "ninecopy-{}-{}.spool",
+            std::process::id(),
+            SPOOLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|source| CopyError::SpoolFailed {
+                path: path.clone(),
+                source,
+            })?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            reader: None,
+            written: 0,
+            read: 0,
+        })
+    }
+
+    fn failed(&self, source: std::io::Error) -> CopyError {
+        CopyError::SpoolFailed {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    fn write(&mut self, item: &SearchResult) -> Result<(), CopyError> {
+        let (Some(kind), Some(path)) = (EntryKind::of(item), item.path()) else {
+            return Ok(());
+        };
+        let len = match item {
+            SearchResult::File(info) => info.metadata.len(),
+            _ => 0,
+        };
+        self.write_record(kind, len, path)
+    }
+
+    fn write_record(&mut self, kind: EntryKind, len: u64, path: &Path) -> Result<(), CopyError> {
+        let bytes = path.as_os_str().as_encoded_bytes();
+        let record = [
+            &[kind as u8][..],
+            &len.to_le_bytes(),
+            &(bytes.len() as u32).to_le_bytes(),
+            bytes,
+        ];
+        for part in record {
+            self.writer
+                .write_all(part)
+                .map_err(|err| self.failed(err))?;
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), CopyError> {
+        self.writer.flush().map_err(|err| self.failed(err))
+    }
+
+    fn remaining(&self) -> usize {
+        self.written - self.read
+    }
+
+    fn read(&mut self) -> Result<Option<(EntryKind, u64, PathBuf)>, CopyError> {
+        if self.remaining() == 0 {
+            return Ok(None);
+        }
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => {
+                let file = File::open(&self.path).map_err(|err| self.failed(err))?;
+                self.reader.insert(BufReader::new(file))
+            }
+        };
+        let record = read_record(reader);
+        self.read += 1;
+        record.map(Some).map_err(|err| self.failed(err))
+    }
+
+    /// Every entry in the spool, from the start, without moving [`Spool::read`] along.
+    fn records(&self) -> impl Iterator<Item = Result<(EntryKind, u64, PathBuf), CopyError>> + '_ {
+        let mut reader = None;
+        let mut remaining = self.written;
+        std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            let reader = match &mut reader {
+                Some(reader) => reader,
+                None => match File::open(&self.path) {
+                    Ok(file) => reader.insert(BufReader::new(file)),
+                    Err(err) => {
+                        remaining = 0;
+                        return Some(Err(self.failed(err)));
+                    }
+                },
+            };
+            remaining -= 1;
+            Some(read_record(reader).map_err(|err| {
+                remaining = 0;
+                self.failed(err)
+            }))
+        })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn read_record(reader: &mut impl Read) -> std::io::Result<(EntryKind, u64, PathBuf)> {
+    let mut header = [0; 13];
+    reader.read_exact(&mut header)?;
+    let kind = EntryKind::from_byte(header[0]).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt spool entry")
+    })?;
+    let len = u64::from_le_bytes(header[1..9].try_into().unwrap());
+    let path_len = u32::from_le_bytes(header[9..13].try_into().unwrap());
+    let mut bytes = vec![0; path_len as usize];
+    reader.read_exact(&mut bytes)?;
+    // SAFETY: The bytes were written by `Spool::write` from `as_encoded_bytes`, in this same process.
+    let path = unsafe { OsStr::from_encoded_bytes_unchecked(&bytes) };
+    Ok((kind, len, PathBuf::from(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// `count` files in a temporary directory, as the search would find them.
+    fn files(count: usize) -> (TempDir, Vec<SearchResult>) {
+        let dir = TempDir::new().unwrap();
+        let found = (0..count)
+            .map(|i| {
+                let path = dir.path().join(format!("file-{i}"));
+                std::fs::write(&path, "contents").unwrap();
+                let metadata = std::fs::metadata(&path).unwrap();
+                SearchResult::File(ResultInfo { path, metadata })
+            })
+            .collect();
+        (dir, found)
+    }
+
+    #[test]
+    fn memory_stays_within_max_bytes() {
+        let (dir, found) = files(100);
+        let max_bytes = 10 * entry_size(&found[0]) + entry_size(&found[0]) / 2;
+        let mut queue = Queue::new(Some(dir.path()), usize::MAX, Some(max_bytes));
+        for item in found {
+            queue.push_back(item).unwrap();
+            assert!(queue.memory_bytes <= max_bytes);
+        }
+        assert_eq!(queue.memory.len(), 10);
+        assert_eq!(queue.len(), 100);
+        queue.finish().unwrap();
+
+        let mut taken = 0;
+        while let Some(popped) = queue.pop_front().unwrap() {
+            assert!(matches!(popped, Popped::Found(SearchResult::File(_))));
+            assert!(queue.memory_bytes <= max_bytes);
+            taken += 1;
+        }
+        assert_eq!(taken, 100);
+        assert_eq!(queue.memory_bytes, 0);
+    }
+
+    #[test]
+    fn putting_back_is_accounted_for() {
+        let (_dir, found) = files(3);
+        let mut queue = Queue::new(None, usize::MAX, None);
+        let sizes: u64 = found.iter().map(entry_size).sum();
+        for item in found {
+            queue.push_back(item).unwrap();
+        }
+        assert_eq!(queue.memory_bytes, sizes);
+
+        let Some(Popped::Found(item)) = queue.pop_front().unwrap() else {
+            panic!("the queue should have had something in it");
+        };
+        assert_eq!(queue.memory_bytes, sizes - entry_size(&item));
+        queue.push_front(item);
+        assert_eq!(queue.memory_bytes, sizes);
+
+        while queue.pop_front().unwrap().is_some() {}
+        assert_eq!(queue.memory_bytes, 0);
+    }
+
+    #[test]
+    fn without_a_spool_everything_stays_in_memory() {
+        // There's nowhere else for it to go, so the limit is only a guide.
+        let (_dir, found) = files(5);
+        let mut queue = Queue::new(None, 2, Some(0));
+        for item in found {
+            queue.push_back(item).unwrap();
+        }
+        assert_eq!(queue.memory.len(), 5);
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    cancel::CancellationToken,
+    copy::CopyBuffer,
+    errors::CopyError,
+    filter::{self, FileFilter},
+    hash::{self, Digest},
+    options::CopyOptions,
+    queue::{EntryKind, Queue},
+    stats::Accumulator,
+};
+
+/// For `--detect-renames`, find files that are new in the source and identical to files `--delete` would remove from
+/// the destination, and rename those into place instead of copying them. Everything else is left for the copy, which
+/// then finds the renamed files up to date.
+///
+/// Only destination files the same size as some new source file are hashed, and only new source files the same size
+/// as some destination file. `found` is what the search turned up, relative to the source, as passed to
+/// [`delete_extraneous`](crate::delete::delete_extraneous), and `destination` maps those paths to where they're
+/// copied.
+pub fn rename_moved(
+    queue: &Queue,
+    found: &HashSet<PathBuf>,
+    destination: impl Fn(&Path) -> Option<PathBuf>,
+    opts: &CopyOptions,
+    cancelled: &CancellationToken,
+) -> Result<Accumulator, CopyError> {
+    let mut accumulator = Accumulator::default();
+    // New source files, by size.
+    let mut new: HashMap<u64, Vec<(PathBuf, PathBuf)>> = HashMap::new();
+    for entry in queue.entries() {
+        let (EntryKind::File, src) = entry? else {
+            continue;
+        };
+        let Some(dst) = destination(&src) else {
+            continue;
+        };
+        if std::fs::symlink_metadata(&dst).is_ok() {
+            continue;
+        }
+        let Ok(metadata) = std::fs::symlink_metadata(&src) else {
+            continue;
+        };
+        // Empty files are as cheap to create as to rename.
+        if metadata.len() > 0 {
+            new.entry(metadata.len())
+                .or_default()
+                .push((src.into_owned(), dst));
+        }
+    }
+    if new.is_empty() {
+        return Ok(accumulator);
+    }
+
+    let mut buffer = CopyBuffer::new(opts.buffer_size);
+    let mut old: HashMap<(u64, Digest), Vec<PathBuf>> = HashMap::new();
+    for (path, len) in extraneous_files(&opts.dst, found, &opts.filters) {
+        if cancelled.is_cancelled() {
+            return Ok(accumulator);
+        }
+        if !new.contains_key(&len) {
+            continue;
+        }
+        if let Ok((digest, read)) = hash::hash_file(&path, opts.hash, &mut buffer) {
+            accumulator += Accumulator::hashed(read);
+            old.entry((len, digest)).or_default().push(path);
+        }
+    }
+    if old.is_empty() {
+        return Ok(accumulator);
+    }
+    let sizes: HashSet<u64> = old.keys().map(|(len, _)| *len).collect();
+
+    for (len, files) in new.into_iter().filter(|(len, _)| sizes.contains(len)) {
+        for (src, dst) in files {
+            if cancelled.is_cancelled() {
+                return Ok(accumulator);
+            }
+            let Ok((digest, read)) = hash::hash_file(&src, opts.hash, &mut buffer) else {
+                continue;
+            };
+            accumulator += Accumulator::hashed(read);
+            let Some(candidates) = old.get_mut(&(len, digest)) else {
+                continue;
+            };
+            let Some(from) = candidates.pop() else {
+                continue;
+            };
+            let renamed = match dst.parent() {
+                Some(parent) => std::fs::create_dir_all(parent),
+                None => Ok(()),
+            }
+            .and_then(|()| std::fs::rename(&from, &dst));
+            match renamed {
+                Ok(()) => accumulator += Accumulator::renamed(len),
+                // It's copied like any other new file instead.
+                Err(err) => message!(
+                    "Warning: unable to rename {} to {}: {}",
+                    from.display(),
+                    dst.display(),
+                    err
+                ),
+            }
+        }
+    }
+    Ok(accumulator)
+}
+
+/// Every file in `dst` that [`delete_extraneous`](crate::delete::delete_extraneous) would remove, with its size.
+/// Unlike it, this walks into directories that aren't in `found`, since the files in them are what's been moved.
+fn extraneous_files(
+    dst: &Path,
+    found: &HashSet<PathBuf>,
+    filters: &[Arc<dyn FileFilter>],
+) -> Vec<(PathBuf, u64)> {
+    let mut files = Vec::new();
+    // Each directory to look in, and whether it's already extraneous itself.
+    let mut dirs = vec![(PathBuf::new(), false)];
+    while let Some((relative, extraneous)) = dirs.pop() {
+        // Anything that can't be read here can't be deleted either, which `--delete` reports.
+        let Ok(entries) = std::fs::read_dir(dst.join(&relative)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let entry_relative = relative.join(entry.file_name());
+            let Ok(metadata) = std::fs::symlink_metadata(entry.path()) else {
+                continue;
+            };
+            let extraneous = extraneous
+                || (!found.contains(&entry_relative)
+                    && (filters.is_empty()
+                        || filter::included(filters, &entry_relative, &metadata)));
+            if metadata.is_dir() {
+                if extraneous || found.contains(&entry_relative) {
+                    dirs.push((entry_relative, extraneous));
+                }
+            } else if extraneous && metadata.is_file() {
+                files.push((entry.path(), metadata.len()));
+            }
+        }
+    }
+    files
+}
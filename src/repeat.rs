@@ -0,0 +1,125 @@
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use crate::{
+    errors::CopyError,
+    options::CopyOptions,
+    report::{CopyReport, ReportedError},
+    reporter::{self, ProgressReporter},
+    search_and_copy, STATUS_POLL_INTERVAL,
+};
+
+/// Once the first copy is done, with `first`, search and copy everything again each `repeat` after the last one
+/// started, until the copy is cancelled. Each copy prints its own summary, and one for all of them is given once
+/// they stop. A copy that fails is reported and the next one goes ahead, unless `repeat_max_failures` of them in a
+/// row have, which returns the last error.
+///
+/// Returns everything the copies did added together, which is also passed to [`ProgressReporter::on_summary`].
+pub(crate) fn repeat(
+    first: Result<CopyReport, CopyError>,
+    opts: &Arc<CopyOptions>,
+    destinations: &[(Arc<CopyOptions>, PathBuf)],
+    threads: usize,
+    queue_bytes: Option<u64>,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    let Some(interval) = opts.repeat else {
+        return first;
+    };
+    let cancelled = &opts.cancellation;
+    let mut total = CopyReport {
+        threads,
+        ..Default::default()
+    };
+    let mut runs = 0;
+    let mut failures = 0;
+    let mut result = first;
+    let mut run_started = started;
+    loop {
+        runs += 1;
+        match result {
+            Ok(report) => {
+                failures = 0;
+                add(&mut total, &report);
+                if report.cancelled {
+                    total.cancelled = true;
+                    break;
+                }
+            }
+            Err(err) => {
+                failures += 1;
+                reporter::deliver(reporter);
+                reporter.on_error(&ReportedError::from(&err));
+                message!("Run {} failed, {} in a row.", runs, failures);
+                if opts.repeat_max_failures.is_some_and(|max| failures >= max) {
+                    message!("Stopping after {} runs in a row failed.", failures);
+                    summarize(&mut total, runs, started, reporter);
+                    return Err(err);
+                }
+            }
+        }
+
+        let next_run = run_started + interval;
+        message!(
+            "Next run in {:.0} seconds. Press Ctrl-C to stop.",
+            next_run
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64()
+        );
+        reporter::deliver(reporter);
+        while !cancelled.is_cancelled() && Instant::now() < next_run {
+            std::thread::sleep(
+                STATUS_POLL_INTERVAL.min(next_run.saturating_duration_since(Instant::now())),
+            );
+        }
+        if cancelled.is_cancelled() {
+            break;
+        }
+
+        run_started = Instant::now();
+        message!("Starting run {}", runs + 1);
+        result = search_and_copy(
+            opts,
+            destinations,
+            threads,
+            queue_bytes,
+            run_started,
+            reporter,
+        );
+    }
+
+    summarize(&mut total, runs, started, reporter);
+    Ok(total)
+}
+
+/// Add what one copy did to the `total` of all of them, destination by destination. Whether a limit was reached is
+/// only kept for the latest.
+fn add(total: &mut CopyReport, report: &CopyReport) {
+    total.accumulator += report.accumulator.clone();
+    total.limit_reached = report.limit_reached;
+    total.search_time += report.search_time;
+    total.copy_time += report.copy_time;
+    total.destination.clone_from(&report.destination);
+    if total.also_to.len() < report.also_to.len() {
+        total
+            .also_to
+            .resize_with(report.also_to.len(), CopyReport::default);
+    }
+    for (total, report) in total.also_to.iter_mut().zip(&report.also_to) {
+        total.threads = report.threads;
+        add(total, report);
+    }
+}
+
+/// Give the summary of all `runs` copies in `total`.
+fn summarize(
+    total: &mut CopyReport,
+    runs: u32,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) {
+    total.total_time = started.elapsed();
+    message!("All {} runs:", runs);
+    reporter::deliver(reporter);
+    reporter.on_summary(total);
+}
@@ -0,0 +1,67 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{errors::CopyError, reporter::SkipReason, stats::Accumulator};
+
+/// At most this many files are listed in [`CopyReport::skipped`], so skipping a whole tree that's already been copied
+/// doesn't hold a path for every file in it.
+pub const MAX_SKIPPED: usize = 10_000;
+
+/// What [`copy_tree`](crate::copy_tree) did.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CopyReport {
+    /// How many files and bytes were found, copied, skipped, and so on.
+    pub accumulator: Accumulator,
+    /// The number of threads used to search and copy.
+    pub threads: usize,
+    /// How long searching the source took.
+    pub search_time: Duration,
+    /// How long copying took, not counting the search or time spent paused. For `diff` and `check_manifest`, how
+    /// long the whole check took.
+    pub copy_time: Duration,
+    /// How long everything took, from start to finish.
+    pub total_time: Duration,
+    /// The errors the copy carried on past with `continue_on_error`, in the order they happened.
+    pub errors: Vec<ReportedError>,
+    /// The first [`MAX_SKIPPED`] files skipped, and why, along with every file skipped with `skip_locked` and every
+    /// file removed from the source before it could be copied. `accumulator` counts all of them.
+    pub skipped: Vec<Skipped>,
+    /// The copy was stopped early with its [`CancellationToken`](crate::CancellationToken), so the counts are only for
+    /// what was done before that.
+    pub cancelled: bool,
+    /// The copy stopped at [`CopyOptions::max_files`](crate::CopyOptions::max_files) or
+    /// [`max_bytes`](crate::CopyOptions::max_bytes) with files left to copy, which a later run with the same
+    /// `state_file` picks up.
+    pub limit_reached: bool,
+    /// With `also_to`, the destination this report is for, as it was given.
+    pub destination: Option<PathBuf>,
+    /// With `also_to`, the reports for the other destinations, in the order they were given. This report is for the
+    /// first, though its `errors` and `skipped` are for all of them.
+    pub also_to: Vec<CopyReport>,
+}
+
+/// An error the copy carried on past, as it was reported.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReportedError {
+    /// The file or directory it's about, if there is one.
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+impl From<&CopyError> for ReportedError {
+    fn from(err: &CopyError) -> Self {
+        Self {
+            path: err.path().map(PathBuf::from),
+            message: format!("{:?}", err),
+        }
+    }
+}
+
+/// A file that was skipped, with its path in the source.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
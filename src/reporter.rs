@@ -0,0 +1,690 @@
+use std::{fmt, io::Write, path::Path, sync::Mutex, time::Duration};
+
+use byte_unit::Byte;
+use clap::ValueEnum;
+
+use crate::{
+    copy,
+    errors::CopyError,
+    options::{CopyOptions, FsyncMode},
+    report::{CopyReport, ReportedError, Skipped, MAX_SKIPPED},
+    state,
+    stats::Accumulator,
+};
+
+/// How an item handed to a copy thread turned out, for [`ProgressReporter::on_file_done`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The file was copied, cloned, or linked into place, with this many bytes.
+    Copied(u64),
+    /// The directory was created at the destination.
+    DirCreated,
+    /// The file was left alone, for this reason.
+    Skipped(SkipReason),
+    /// Anything else, like a directory with `prune_empty_dirs` or `files_only`, or one chunk of a file split with
+    /// `split_large_files`.
+    Done,
+}
+
+impl Outcome {
+    /// The outcome of an item, given the stats for it.
+    pub(crate) fn of(accumulator: &Accumulator) -> Self {
+        if accumulator.file_count_copied > 0 {
+            Self::Copied(accumulator.byte_count_copied)
+        } else if accumulator.dir_count_created > 0 {
+            Self::DirCreated
+        } else if accumulator.file_count_up_to_date > 0 {
+            Self::Skipped(SkipReason::UpToDate)
+        } else if accumulator.file_count_locked > 0 {
+            Self::Skipped(SkipReason::Locked)
+        } else if accumulator.file_count_vanished > 0 {
+            Self::Skipped(SkipReason::Vanished)
+        } else if accumulator.file_count_unreadable > 0 {
+            Self::Skipped(SkipReason::Unreadable)
+        } else if accumulator.file_count_failed > 0 {
+            Self::Skipped(SkipReason::Failed)
+        } else if accumulator.file_count_special_skipped > 0 {
+            Self::Skipped(SkipReason::Special)
+        } else if accumulator.file_count_skipped > 0 {
+            Self::Skipped(SkipReason::Exists)
+        } else {
+            Self::Done
+        }
+    }
+}
+
+/// Why a file was skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SkipReason {
+    /// It already exists at the destination, with `skip`.
+    Exists,
+    /// It's already at the destination with the same size and modified time, or the same contents, with `update` or
+    /// `checksum`.
+    UpToDate,
+    /// It failed to copy, with `continue_on_error`. The error is in [`CopyReport::errors`].
+    Failed,
+    /// Permission to read it was denied, with `continue_on_error`. The error is in [`CopyReport::errors`].
+    Unreadable,
+    /// Another process had it locked, with `skip_locked`.
+    Locked,
+    /// It was removed from the source after the search found it.
+    Vanished,
+    /// It's a FIFO, socket, or device node.
+    Special,
+    /// It was recorded in the `state_file` by an earlier run.
+    Resumed,
+    /// It's a cloud placeholder whose contents are only online, with `skip_placeholders`.
+    Placeholder,
+}
+
+/// Told what a copy is doing as it goes, in place of the console output `ninecopy` prints.
+///
+/// Every method is called from the thread that started the copy, never from the copy threads, so implementations
+/// only need to be `Send`. They all do nothing by default.
+pub trait ProgressReporter: Send {
+    /// Called every few seconds while searching with `progress`, and whenever a status snapshot is requested.
+    fn on_search_progress(&mut self, _accumulator: &Accumulator) {}
+
+    /// Called every few seconds while copying with `progress`, and whenever a status snapshot is requested.
+    fn on_copy_progress(&mut self, _accumulator: &Accumulator) {}
+
+    /// Called along with [`on_copy_progress`](Self::on_copy_progress) for each large file that's being copied, with
+    /// its path in the source, how many bytes of it have been written, and its length.
+    fn on_file_progress(&mut self, _path: &Path, _written: u64, _len: u64) {}
+
+    /// Called every few seconds while copying with `show_active`, for each copy thread that's working on something,
+    /// with its path relative to the source and how long the thread has been on it.
+    fn on_active(&mut self, _thread_id: usize, _path: &Path, _elapsed: Duration) {}
+
+    /// Called as each file and directory is finished, with its path in the source.
+    fn on_file_done(&mut self, _path: &Path, _outcome: Outcome) {}
+
+    /// Called with where each file that was copied ended up, with `print0`.
+    fn on_copied_to(&mut self, _dst: &Path) {}
+
+    /// Called for errors the copy carries on past with `continue_on_error`. Errors that stop it are returned from
+    /// [`copy_tree_with_reporter`](crate::copy_tree_with_reporter) instead.
+    fn on_error(&mut self, _err: &ReportedError) {}
+
+    /// Called with everything else worth telling the user: warnings, what's about to happen, and the results of
+    /// `check_manifest`.
+    fn on_message(&mut self, _message: &str) {}
+
+    /// Called with each line of what the copy was asked to produce rather than report on, like the differences found
+    /// by `diff`. Passed on to [`on_message`](Self::on_message) unless it's implemented.
+    fn on_output(&mut self, line: &str) {
+        self.on_message(line);
+    }
+
+    /// Called once the files have been copied, before anything is deleted with `delete`.
+    fn on_summary(&mut self, _report: &CopyReport) {}
+}
+
+/// Ignores everything, for copies nobody's watching.
+pub struct NullReporter;
+
+impl ProgressReporter for NullReporter {}
+
+/// Where [`ConsoleReporter`] prints progress, messages, errors, and the summary.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatusOutput {
+    /// Everything on stdout, the way `ninecopy` used to print.
+    Stdout,
+    /// Leaves stdout for output, like the differences found by `--diff`.
+    #[default]
+    Stderr,
+}
+
+/// Like `println!`, but to the reporter's [`StatusOutput`].
+macro_rules! status {
+    ($reporter:expr, $($arg:tt)*) => {
+        $reporter.print_status(format_args!($($arg)*))
+    };
+}
+
+/// Prints the way `ninecopy` does: output to stdout, and everything else to stderr unless told otherwise.
+pub struct ConsoleReporter {
+    fsync: Option<FsyncMode>,
+    status: StatusOutput,
+    skip_placeholders: bool,
+    dirs_only: bool,
+    bwlimit: Option<u64>,
+    /// The pattern of each `--policy` rule, to break down what they did.
+    policies: Vec<String>,
+}
+
+impl ConsoleReporter {
+    pub fn new(opts: &CopyOptions) -> Self {
+        Self {
+            fsync: opts.fsync,
+            status: StatusOutput::default(),
+            skip_placeholders: opts.skip_placeholders,
+            dirs_only: opts.dirs_only,
+            bwlimit: opts.bwlimit,
+            policies: opts
+                .policies
+                .iter()
+                .map(|(pattern, _)| pattern.as_str().to_string())
+                .collect(),
+        }
+    }
+
+    /// Print everything but output to `status`.
+    pub fn status(mut self, status: StatusOutput) -> Self {
+        self.status = status;
+        self
+    }
+
+    fn print_status(&self, args: fmt::Arguments) {
+        match self.status {
+            StatusOutput::Stdout => println!("{}", args),
+            StatusOutput::Stderr => eprintln!("{}", args),
+        }
+    }
+}
+
+impl ProgressReporter for ConsoleReporter {
+    fn on_search_progress(&mut self, accumulator: &Accumulator) {
+        status!(
+            self,
+            "Found {} files so far. Total size: {} bytes",
+            accumulator.file_count_found,
+            Byte::from_bytes(accumulator.byte_count_found as u128).get_appropriate_unit(false)
+        );
+    }
+
+    fn on_copy_progress(&mut self, accumulator: &Accumulator) {
+        let bytes_done = accumulator.byte_count_copied
+            + accumulator.byte_count_skipped
+            + copy::bytes_in_flight();
+        let verified = match accumulator.byte_count_verified {
+            0 => String::new(),
+            bytes => format!(
+                ". Verified: {} files, {} read",
+                accumulator.file_count_verified,
+                Byte::from_bytes(bytes as u128).get_appropriate_unit(false)
+            ),
+        };
+        let preferred = match accumulator.file_count_preferred {
+            0 => String::new(),
+            files => format!(
+                ". Priority files done: {} of {}",
+                accumulator.file_count_preferred_done, files
+            ),
+        };
+        let limit = match self.bwlimit {
+            Some(rate) => format!(
+                ". Limit: {}/s",
+                Byte::from_bytes(rate as u128).get_appropriate_unit(false)
+            ),
+            None => String::new(),
+        };
+        status!(
+            self,
+            "Files: {} / {} ({:.2}%). Bytes: {} / {} ({:.2}%){}{}{}",
+            accumulator.file_count_copied + accumulator.file_count_skipped,
+            accumulator.file_count_found,
+            ((accumulator.file_count_copied + accumulator.file_count_skipped) as f64
+                / accumulator.file_count_found as f64
+                * 100.0)
+                .min(100.0),
+            Byte::from_bytes(bytes_done as u128).get_appropriate_unit(false),
+            Byte::from_bytes(accumulator.byte_count_found as u128).get_appropriate_unit(false),
+            // Files growing as they're copied can be further along than what was found.
+            (bytes_done as f64 / accumulator.byte_count_found as f64 * 100.0).min(100.0),
+            verified,
+            preferred,
+            limit
+        )
+    }
+
+    fn on_file_progress(&mut self, path: &Path, written: u64, len: u64) {
+        status!(
+            self,
+            "  Copying {}: {} / {} ({:.0}%)",
+            path.display(),
+            Byte::from_bytes(written as u128).get_appropriate_unit(false),
+            Byte::from_bytes(len as u128).get_appropriate_unit(false),
+            (written as f64 / len as f64 * 100.0).min(100.0)
+        );
+    }
+
+    fn on_active(&mut self, thread_id: usize, path: &Path, elapsed: Duration) {
+        status!(
+            self,
+            "  Thread {}: {} (for {:.0} seconds)",
+            thread_id,
+            path.display(),
+            elapsed.as_secs_f64()
+        );
+    }
+
+    fn on_error(&mut self, err: &ReportedError) {
+        status!(self, "{}", err.message);
+    }
+
+    fn on_message(&mut self, message: &str) {
+        status!(self, "{}", message);
+    }
+
+    fn on_output(&mut self, line: &str) {
+        println!("{}", line);
+    }
+
+    fn on_copied_to(&mut self, dst: &Path) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout
+            .write_all(&state::path_to_bytes(dst))
+            .and_then(|()| stdout.write_all(b"\0"));
+    }
+
+    fn on_summary(&mut self, report: &CopyReport) {
+        let accumulator = &report.accumulator;
+        let seconds = report.copy_time.as_secs_f64();
+        if let Some(destination) = &report.destination {
+            status!(self, "Copy to {}:", destination.display());
+        }
+        if self.dirs_only {
+            status!(
+                self,
+                "Finished creating {} directories in {:.2} seconds.",
+                accumulator.dir_count_created,
+                seconds
+            );
+        } else {
+            status!(
+                self,
+                "Finished copy of {} files ({}) in {:.2} seconds, (~{}/s), {} files ({}) skipped.",
+                accumulator.file_count_copied,
+                Byte::from_bytes(accumulator.byte_count_copied as u128).get_appropriate_unit(false),
+                seconds,
+                Byte::from_bytes((accumulator.byte_count_copied as f64 / seconds) as u128)
+                    .get_appropriate_unit(false),
+                accumulator.file_count_skipped - accumulator.file_count_up_to_date,
+                Byte::from_bytes(
+                    (accumulator.byte_count_skipped - accumulator.byte_count_up_to_date) as u128
+                )
+                .get_appropriate_unit(false),
+            );
+        }
+        if report.limit_reached {
+            status!(
+                self,
+                "Stopped at the --max-files or --max-bytes limit with files left to copy."
+            );
+        }
+        if accumulator.file_count_up_to_date > 0 {
+            status!(
+                self,
+                "{} files ({}) already up to date.",
+                accumulator.file_count_up_to_date,
+                Byte::from_bytes(accumulator.byte_count_up_to_date as u128)
+                    .get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_failed > 0 {
+            status!(
+                self,
+                "{} files failed to copy and were skipped.",
+                accumulator.file_count_failed
+            );
+        }
+        if accumulator.file_count_locked > 0 {
+            status!(
+                self,
+                "{} files were locked by another process and were skipped:",
+                accumulator.file_count_locked
+            );
+            for skipped in &report.skipped {
+                if skipped.reason == SkipReason::Locked {
+                    status!(self, "  {}", skipped.path.display());
+                }
+            }
+        }
+        if accumulator.file_count_vanished > 0 {
+            status!(
+                self,
+                "{} files were removed from the source before they could be copied:",
+                accumulator.file_count_vanished
+            );
+            for skipped in &report.skipped {
+                if skipped.reason == SkipReason::Vanished {
+                    status!(self, "  {}", skipped.path.display());
+                }
+            }
+        }
+        if accumulator.file_count_unreadable > 0 || accumulator.dir_count_unreadable > 0 {
+            status!(
+                self,
+                "{} files and {} directories couldn't be read and were skipped.",
+                accumulator.file_count_unreadable,
+                accumulator.dir_count_unreadable
+            );
+        }
+        if accumulator.file_count_sampled > 0 {
+            status!(
+                self,
+                "{} files ({}) sampled for verification.",
+                accumulator.file_count_sampled,
+                Byte::from_bytes(accumulator.byte_count_sampled as u128)
+                    .get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_verified > 0 {
+            status!(
+                self,
+                "{} files verified, reading {}.",
+                accumulator.file_count_verified,
+                Byte::from_bytes(accumulator.byte_count_verified as u128)
+                    .get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_verify_failed > 0 {
+            status!(
+                self,
+                "{} files failed verification.",
+                accumulator.file_count_verify_failed
+            );
+        }
+        if accumulator.byte_count_hashed > 0 {
+            status!(
+                self,
+                "{} read to compare checksums.",
+                Byte::from_bytes(accumulator.byte_count_hashed as u128).get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_moved > 0 {
+            status!(
+                self,
+                "{} files moved, freeing {} at the source.",
+                accumulator.file_count_moved,
+                Byte::from_bytes(accumulator.byte_count_moved as u128).get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_move_failed > 0 {
+            status!(
+                self,
+                "{} files were copied but couldn't be removed from the source.",
+                accumulator.file_count_move_failed
+            );
+        }
+        if accumulator.file_count_backed_up > 0 {
+            status!(
+                self,
+                "{} existing files backed up before being overwritten.",
+                accumulator.file_count_backed_up
+            );
+        }
+        if accumulator.byte_count_delta_compared > 0 {
+            status!(
+                self,
+                "{} actually written of {} compared.",
+                Byte::from_bytes(accumulator.byte_count_delta_written as u128)
+                    .get_appropriate_unit(false),
+                Byte::from_bytes(accumulator.byte_count_delta_compared as u128)
+                    .get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_deduped > 0 {
+            status!(
+                self,
+                "{} files hard linked to identical copies, saving {}.",
+                accumulator.file_count_deduped,
+                Byte::from_bytes(accumulator.byte_count_deduped as u128)
+                    .get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_renamed > 0 {
+            status!(
+                self,
+                "{} files renamed, {} saved.",
+                accumulator.file_count_renamed,
+                Byte::from_bytes(accumulator.byte_count_renamed as u128)
+                    .get_appropriate_unit(false)
+            );
+        }
+        // Rules that matched nothing are listed too, since that's usually a sign an earlier one matched first.
+        for (rule, pattern) in self.policies.iter().enumerate() {
+            let count = accumulator
+                .policy_counts
+                .get(rule)
+                .copied()
+                .unwrap_or_default();
+            status!(
+                self,
+                "--policy {}: {} existing files skipped, {} overwritten.",
+                pattern,
+                count.skipped,
+                count.overwritten
+            );
+        }
+        if accumulator.file_count_cloned > 0 {
+            status!(
+                self,
+                "{} files ({}) cloned instead of copied.",
+                accumulator.file_count_cloned,
+                Byte::from_bytes(accumulator.byte_count_cloned as u128).get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_sparse > 0 {
+            status!(
+                self,
+                "{} sparse files copied: {} logical, {} physical.",
+                accumulator.file_count_sparse,
+                Byte::from_bytes(accumulator.byte_count_sparse as u128).get_appropriate_unit(false),
+                Byte::from_bytes(
+                    (accumulator.byte_count_sparse - accumulator.byte_count_holes) as u128
+                )
+                .get_appropriate_unit(false)
+            );
+        }
+        if accumulator.file_count_linked > 0 {
+            status!(
+                self,
+                "{} files ({}) linked instead of copied.",
+                accumulator.file_count_linked,
+                Byte::from_bytes(accumulator.byte_count_linked as u128).get_appropriate_unit(false)
+            );
+        }
+        if accumulator.nanos_syncing > 0 {
+            status!(
+                self,
+                "{:.2} seconds spent syncing files to disk{}.",
+                Duration::from_nanos(accumulator.nanos_syncing).as_secs_f64(),
+                match self.fsync {
+                    Some(FsyncMode::Each) => ", added up across threads",
+                    _ => "",
+                }
+            );
+        }
+        if accumulator.file_count_placeholders > 0 {
+            status!(
+                self,
+                "{} cloud placeholder files ({}) {}.",
+                accumulator.file_count_placeholders,
+                Byte::from_bytes(accumulator.byte_count_placeholders as u128)
+                    .get_appropriate_unit(false),
+                match self.skip_placeholders {
+                    true => "skipped",
+                    false => "downloaded to copy them",
+                }
+            );
+        }
+        if accumulator.file_count_foreign > 0 || accumulator.dir_count_foreign > 0 {
+            status!(
+                self,
+                "{} files and {} directories owned by someone else left out.",
+                accumulator.file_count_foreign,
+                accumulator.dir_count_foreign
+            );
+        }
+        if accumulator.file_count_special_skipped > 0 {
+            status!(
+                self,
+                "{} special files skipped.",
+                accumulator.file_count_special_skipped
+            );
+        }
+        if accumulator.file_count_retried > 0 {
+            status!(
+                self,
+                "{} files needed more than one attempt.",
+                accumulator.file_count_retried
+            );
+        }
+        if accumulator.file_count_changed > 0 {
+            status!(
+                self,
+                "{} files changed size while being copied{}.",
+                accumulator.file_count_changed,
+                match accumulator.file_count_recopied {
+                    0 => String::new(),
+                    recopied => format!(", {} of them copied again", recopied),
+                }
+            );
+        }
+        if accumulator.file_count_xattrs_failed > 0 {
+            status!(
+                self,
+                "{} files had extended attributes that couldn't be copied.",
+                accumulator.file_count_xattrs_failed
+            );
+        }
+        if accumulator.file_count_acls_failed > 0 {
+            status!(
+                self,
+                "{} files had ACLs that couldn't be copied.",
+                accumulator.file_count_acls_failed
+            );
+        }
+        if accumulator.file_count_partial_removed > 0 {
+            status!(
+                self,
+                "{} partially written files were removed.",
+                accumulator.file_count_partial_removed
+            );
+        }
+    }
+}
+
+/// Passes everything on to another reporter, keeping the errors and skipped files for the [`CopyReport`].
+pub(crate) struct Recorder<'a> {
+    inner: &'a mut dyn ProgressReporter,
+    errors: Vec<ReportedError>,
+    skipped: Vec<Skipped>,
+}
+
+impl<'a> Recorder<'a> {
+    pub(crate) fn new(inner: &'a mut dyn ProgressReporter) -> Self {
+        Self {
+            inner,
+            errors: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// `report` with the errors and skipped files recorded so far.
+    pub(crate) fn fill(&self, report: CopyReport) -> CopyReport {
+        CopyReport {
+            errors: self.errors.clone(),
+            skipped: self.skipped.clone(),
+            ..report
+        }
+    }
+}
+
+impl ProgressReporter for Recorder<'_> {
+    fn on_search_progress(&mut self, accumulator: &Accumulator) {
+        self.inner.on_search_progress(accumulator);
+    }
+
+    fn on_copy_progress(&mut self, accumulator: &Accumulator) {
+        self.inner.on_copy_progress(accumulator);
+    }
+
+    fn on_file_progress(&mut self, path: &Path, written: u64, len: u64) {
+        self.inner.on_file_progress(path, written, len);
+    }
+
+    fn on_active(&mut self, thread_id: usize, path: &Path, elapsed: Duration) {
+        self.inner.on_active(thread_id, path, elapsed);
+    }
+
+    fn on_file_done(&mut self, path: &Path, outcome: Outcome) {
+        if let Outcome::Skipped(reason) = outcome {
+            // Locked and vanished files are always listed, so they can be copied again or accounted for later.
+            if self.skipped.len() < MAX_SKIPPED
+                || matches!(reason, SkipReason::Locked | SkipReason::Vanished)
+            {
+                self.skipped.push(Skipped {
+                    path: path.to_path_buf(),
+                    reason,
+                });
+            }
+        }
+        self.inner.on_file_done(path, outcome);
+    }
+
+    fn on_error(&mut self, err: &ReportedError) {
+        self.errors.push(err.clone());
+        self.inner.on_error(err);
+    }
+
+    fn on_message(&mut self, message: &str) {
+        self.inner.on_message(message);
+    }
+
+    fn on_output(&mut self, line: &str) {
+        self.inner.on_output(line);
+    }
+
+    fn on_copied_to(&mut self, dst: &Path) {
+        self.inner.on_copied_to(dst);
+    }
+
+    fn on_summary(&mut self, report: &CopyReport) {
+        let report = self.fill(report.clone());
+        self.inner.on_summary(&report);
+    }
+}
+
+/// Something said by the copy threads, or by anything else without a reporter at hand.
+enum Notice {
+    Message(String),
+    Output(String),
+    Error(ReportedError),
+}
+
+/// Notices waiting for [`deliver`] to pass them on to the reporter from the thread that started the copy.
+static NOTICES: Mutex<Vec<Notice>> = Mutex::new(Vec::new());
+
+/// Queue a message for the reporter. Used through the `message!` macro.
+pub(crate) fn message(message: String) {
+    NOTICES.lock().unwrap().push(Notice::Message(message));
+}
+
+/// Queue a line of output for the reporter.
+pub(crate) fn output(line: String) {
+    NOTICES.lock().unwrap().push(Notice::Output(line));
+}
+
+/// Queue an error the copy carried on past for the reporter.
+pub(crate) fn error(err: CopyError) {
+    NOTICES
+        .lock()
+        .unwrap()
+        .push(Notice::Error(ReportedError::from(&err)));
+}
+
+/// Pass everything queued so far on to `reporter`, in the order it was queued.
+pub(crate) fn deliver(reporter: &mut dyn ProgressReporter) {
+    let notices = std::mem::take(&mut *NOTICES.lock().unwrap());
+    for notice in notices {
+        match notice {
+            Notice::Message(message) => reporter.on_message(&message),
+            Notice::Output(line) => reporter.on_output(&line),
+            Notice::Error(err) => reporter.on_error(&err),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::cancel::CancellationToken;
+
+/// Exit code used when the copy is cancelled with Ctrl-C, matching the usual `128 + SIGINT` convention.
+pub const CANCELLED_EXIT_CODE: u8 = 130;
+
+/// Install a Ctrl-C handler that cancels `cancellation`.
+///
+/// The first Ctrl-C only cancels the copy, so files in flight can be finished or abandoned cleanly and the summary
+/// can print. A second one exits immediately.
+pub fn install_cancel_handler(cancellation: CancellationToken) {
+    let result = ctrlc::set_handler(move || {
+        if cancellation.cancel() {
+            crate::copy::remove_in_flight();
+            std::process::exit(CANCELLED_EXIT_CODE as i32);
+        }
+        eprintln!("Cancelling. Press Ctrl-C again to exit immediately.");
+    });
+
+    if let Err(err) = result {
+        eprintln!("Warning: unable to install Ctrl-C handler: {}", err);
+    }
+}
+
+static STATUS_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Install handlers that request a status snapshot: SIGUSR1 (and SIGINFO where it exists) on Unix, and Ctrl-Break on
+/// Windows.
+///
+/// The handlers only set a flag, which the main loop picks up with [`take_status_request`].
+pub fn install_status_handler() {
+    #[cfg(unix)]
+    unsafe {
+        extern "C" fn handler(_: libc::c_int) {
+            STATUS_REQUESTED.store(true, Ordering::SeqCst);
+        }
+
+        let handler = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGUSR1, handler);
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        libc::signal(libc::SIGINFO, handler);
+    }
+
+    // Console control handlers run most-recently-registered first, so this has to be installed after the Ctrl-C
+    // handler to keep Ctrl-Break from cancelling the copy.
+    #[cfg(windows)]
+    unsafe {
+        use windows_sys::Win32::{
+            Foundation::BOOL,
+            System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT},
+        };
+
+        unsafe extern "system" fn handler(ctrl_type: u32) -> BOOL {
+            if ctrl_type == CTRL_BREAK_EVENT {
+                STATUS_REQUESTED.store(true, Ordering::SeqCst);
+                1
+            } else {
+                0
+            }
+        }
+
+        if SetConsoleCtrlHandler(Some(handler), 1) == 0 {
+            eprintln!("Warning: unable to install Ctrl-Break handler.");
+        }
+    }
+}
+
+/// Returns true if a status snapshot has been requested since the last call.
+pub fn take_status_request() -> bool {
+    STATUS_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+static PAUSE_REQUESTED: AtomicBool = AtomicBool::new(false);
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Install the ways of pausing a copy: typing `p` and Enter on the terminal (Enter again resumes), and on Unix,
+/// Ctrl-Z. Ctrl-Z waits for in-flight files to finish before actually stopping the process, and the copy resumes on
+/// `SIGCONT` (e.g. `fg`).
+pub fn install_pause_handler() {
+    if std::io::stdin().is_terminal() {
+        std::thread::spawn(|| {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if PAUSE_REQUESTED.load(Ordering::SeqCst) {
+                    PAUSE_REQUESTED.store(false, Ordering::SeqCst);
+                } else if line.trim().eq_ignore_ascii_case("p") {
+                    PAUSE_REQUESTED.store(true, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    unsafe {
+        extern "C" fn handler(_: libc::c_int) {
+            STOP_REQUESTED.store(true, Ordering::SeqCst);
+            PAUSE_REQUESTED.store(true, Ordering::SeqCst);
+        }
+
+        libc::signal(
+            libc::SIGTSTP,
+            handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Returns true while the copy should be paused.
+pub fn pause_requested() -> bool {
+    PAUSE_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Called once the copy has reached a paused state with nothing in flight. If the pause came from Ctrl-Z, this stops
+/// the process and returns once it's continued, clearing the pause.
+pub fn paused() {
+    if STOP_REQUESTED.swap(false, Ordering::SeqCst) {
+        #[cfg(unix)]
+        unsafe {
+            libc::raise(libc::SIGSTOP);
+        }
+        PAUSE_REQUESTED.store(false, Ordering::SeqCst);
+    }
+}
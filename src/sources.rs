@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::errors::CopyError;
+
+/// The characters that make a source a glob pattern.
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+
+/// Everything `src` matches, in order, if it's a glob pattern, or `None` if it's an ordinary path.
+///
+/// A path that exists is never treated as a pattern, even with `*`, `?`, or `[` in it, so arguments a Unix shell has
+/// already expanded aren't expanded again. Shells on Windows leave patterns alone, so they're expanded here instead.
+pub fn expand_glob(src: &Path) -> Result<Option<Vec<PathBuf>>, CopyError> {
+    let pattern = match src.to_str() {
+        Some(pattern) if pattern.contains(GLOB_CHARS) && src.symlink_metadata().is_err() => pattern,
+        _ => return Ok(None),
+    };
+    let paths = glob::glob(pattern)
+        .map_err(|err| CopyError::Other(format!("Invalid pattern {}: {}", pattern, err)))?;
+    let mut matches = Vec::new();
+    for path in paths {
+        let path = path.map_err(|err| CopyError::MetadataFailed {
+            path: err.path().to_path_buf(),
+            source: err.into(),
+        })?;
+        matches.push(path);
+    }
+    match matches.is_empty() {
+        true => Err(CopyError::NoMatches(src.to_path_buf())),
+        false => Ok(Some(matches)),
+    }
+}
+
+/// Check that no two of `roots` have the same name, since each is copied to `DESTINATION/<name>` and the copies would
+/// be merged into one.
+pub fn check_distinct_names(roots: &[PathBuf]) -> Result<(), CopyError> {
+    let mut seen: HashMap<&OsStr, &PathBuf> = HashMap::new();
+    for root in roots {
+        let name = root.file_name().unwrap_or(root.as_os_str());
+        if let Some(first) = seen.insert(name, root) {
+            return Err(CopyError::SourceNameCollision {
+                name: name.to_os_string(),
+                first: first.clone(),
+                second: root.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The name of the directory each of `roots` is copied to under the destination with `--per-source-dirs`: the one
+/// `names` gives it, if any, or the shortest end of its path that no other root's ends the same way, with `_` between
+/// the parts, like `a_logs` for `/a/logs` next to `/b/logs`.
+pub fn per_source_names(
+    roots: &[PathBuf],
+    names: &[(OsString, PathBuf)],
+) -> Result<Vec<OsString>, CopyError> {
+    let mut given = vec![None; roots.len()];
+    for (name, path) in names {
+        let Some(idx) = roots.iter().position(|root| same_path(root, path)) else {
+            return Err(CopyError::Other(format!(
+                "--source-name {}:{} isn't one of the sources",
+                name.to_string_lossy(),
+                path.display()
+            )));
+        };
+        given[idx] = Some(name.clone());
+    }
+    for (root, _) in roots
+        .iter()
+        .zip(&given)
+        .filter(|(_, given)| given.is_none())
+    {
+        if root
+            .components()
+            .all(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(CopyError::Other(format!(
+                "{} has no name to copy it to, so give it one with --source-name",
+                root.display()
+            )));
+        }
+    }
+    let parts: Vec<Vec<&OsStr>> = roots
+        .iter()
+        .map(|root| {
+            root.components()
+                .filter_map(|component| match component {
+                    Component::Normal(part) => Some(part),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+    // How many parts from the end of each root its name is made of, which only grows while it collides.
+    let mut used = vec![1; roots.len()];
+    loop {
+        let names: Vec<OsString> = (0..roots.len())
+            .map(|idx| match &given[idx] {
+                Some(name) => name.clone(),
+                None => render(&parts[idx], used[idx]),
+            })
+            .collect();
+        let mut by_name: HashMap<&OsString, Vec<usize>> = HashMap::new();
+        for (idx, name) in names.iter().enumerate() {
+            by_name.entry(name).or_default().push(idx);
+        }
+        let mut grew = false;
+        for (name, sharing) in by_name.iter().filter(|(_, sharing)| sharing.len() > 1) {
+            let mut longer = false;
+            for &idx in sharing {
+                if given[idx].is_none() && used[idx] < parts[idx].len() {
+                    used[idx] += 1;
+                    longer = true;
+                }
+            }
+            if !longer {
+                return Err(CopyError::SourceNameCollision {
+                    name: (*name).clone(),
+                    first: roots[sharing[0]].clone(),
+                    second: roots[sharing[1]].clone(),
+                });
+            }
+            grew = true;
+        }
+        if !grew {
+            return Ok(names);
+        }
+    }
+}
+
+/// The last `used` of `parts` joined with `_`.
+fn render(parts: &[&OsStr], used: usize) -> OsString {
+    let mut name = OsString::new();
+    for (idx, part) in parts[parts.len().saturating_sub(used)..].iter().enumerate() {
+        if idx > 0 {
+            name.push("_");
+        }
+        name.push(part);
+    }
+    name
+}
+
+/// Whether `a` and `b` are the same path as given, or lead to the same place.
+fn same_path(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (a.canonicalize(), b.canonicalize()),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
@@ -0,0 +1,176 @@
+use std::fs::{File, Metadata};
+
+/// In `--sparse=auto` mode, a file is only copied sparsely if at least this fraction of it (1/N) is unallocated, so
+/// ordinary files don't pay for looking for holes.
+#[cfg(unix)]
+const AUTO_MIN_HOLE_FRACTION: u64 = 10;
+
+/// Returns true if the file described by `metadata` has enough holes to be worth copying sparsely.
+#[cfg(unix)]
+pub fn is_sparse(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let allocated = metadata.blocks() * 512;
+    allocated < metadata.len() - metadata.len() / AUTO_MIN_HOLE_FRACTION
+}
+
+/// Returns true if the file described by `metadata` is marked sparse.
+#[cfg(windows)]
+pub fn is_sparse(metadata: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    // FILE_ATTRIBUTE_SPARSE_FILE. Windows doesn't cheaply report how much of a file is allocated, so any file marked
+    // sparse counts.
+    metadata.file_attributes() & 0x200 != 0
+}
+
+/// Sparse files can't be detected on this platform.
+#[cfg(not(any(unix, windows)))]
+pub fn is_sparse(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// The `(offset, length)` of each range of `file` that holds data, in order. Everything else, up to `len`, is a
+/// hole.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+pub fn data_extents(file: &File, len: u64) -> std::io::Result<Vec<(u64, u64)>> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let mut extents = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = std::io::Error::last_os_error();
+            return match err.raw_os_error() {
+                // There's no more data after offset.
+                Some(libc::ENXIO) => Ok(extents),
+                // The filesystem can't report holes, so treat the whole file as data.
+                Some(libc::EINVAL) if offset == 0 => Ok(vec![(0, len)]),
+                _ => Err(err),
+            };
+        }
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let (data, hole) = (data as u64, (hole as u64).min(len));
+        if hole > data {
+            extents.push((data, hole - data));
+        }
+        offset = hole;
+    }
+    Ok(extents)
+}
+
+/// The `(offset, length)` of each range of `file` that holds data, in order. Everything else, up to `len`, is a
+/// hole.
+#[cfg(windows)]
+pub fn data_extents(file: &File, len: u64) -> std::io::Result<Vec<(u64, u64)>> {
+    use std::{ffi::c_void, os::windows::io::AsRawHandle};
+    use windows_sys::Win32::{
+        Foundation::ERROR_MORE_DATA,
+        System::{
+            Ioctl::{FILE_ALLOCATED_RANGE_BUFFER, FSCTL_QUERY_ALLOCATED_RANGES},
+            IO::DeviceIoControl,
+        },
+    };
+
+    let mut extents = Vec::new();
+    let mut query = FILE_ALLOCATED_RANGE_BUFFER {
+        FileOffset: 0,
+        Length: len as i64,
+    };
+    let mut ranges = vec![
+        FILE_ALLOCATED_RANGE_BUFFER {
+            FileOffset: 0,
+            Length: 0,
+        };
+        64
+    ];
+    loop {
+        let mut returned = 0;
+        let success = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as _,
+                FSCTL_QUERY_ALLOCATED_RANGES,
+                &query as *const _ as *const c_void,
+                std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32,
+                ranges.as_mut_ptr() as *mut c_void,
+                (ranges.len() * std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>()) as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        let err = std::io::Error::last_os_error();
+        if success == 0 && err.raw_os_error() != Some(ERROR_MORE_DATA as i32) {
+            return Err(err);
+        }
+
+        let count = returned as usize / std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>();
+        extents.extend(
+            ranges[..count]
+                .iter()
+                .map(|range| (range.FileOffset as u64, range.Length as u64)),
+        );
+        match extents.last() {
+            // ERROR_MORE_DATA means the buffer filled up, so ask again from where it left off.
+            Some(&(offset, length)) if success == 0 => {
+                query.FileOffset = (offset + length) as i64;
+                query.Length = len as i64 - query.FileOffset;
+            }
+            _ => return Ok(extents),
+        }
+    }
+}
+
+/// Holes can't be detected on this platform, so the whole file is data.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd",
+    windows
+)))]
+pub fn data_extents(_file: &File, len: u64) -> std::io::Result<Vec<(u64, u64)>> {
+    Ok(vec![(0, len)])
+}
+
+/// Prepare `file` to have holes left in it. Only Windows needs this, and elsewhere any range that isn't written is
+/// a hole.
+#[cfg(windows)]
+pub fn mark_sparse(file: &File) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::System::{Ioctl::FSCTL_SET_SPARSE, IO::DeviceIoControl};
+
+    let mut returned = 0;
+    if unsafe {
+        DeviceIoControl(
+            file.as_raw_handle() as _,
+            FSCTL_SET_SPARSE,
+            std::ptr::null(),
+            0,
+            std::ptr::null_mut(),
+            0,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    } == 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Prepare `file` to have holes left in it. Only Windows needs this, and elsewhere any range that isn't written is
+/// a hole.
+#[cfg(not(windows))]
+pub fn mark_sparse(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs::Metadata,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{copy, links::FileId, options::CopyOptions};
+
+/// The most any one chunk of a split file holds, so files well over `--split-large-files` are spread across as many
+/// threads as are free.
+const MAX_CHUNK_SIZE: u64 = 256 * 1024 * 1024;
+
+/// A file that's too big to copy on one thread, which is being copied in chunks by several at once with
+/// `--split-large-files`.
+pub struct SplitFile {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    /// Where the chunks are written: `dst` itself, or its partial file with `--atomic`.
+    pub target: PathBuf,
+    /// `dst` relative to the destination directory.
+    pub relative: PathBuf,
+    pub metadata: Metadata,
+    /// Set with `--hard-links` if the source has other links to it.
+    pub file_id: Option<FileId>,
+    /// Chunks that haven't finished yet, successfully or not.
+    remaining: AtomicUsize,
+    /// Bytes written by chunks that have finished successfully.
+    landed: AtomicU64,
+    failed: AtomicBool,
+}
+
+/// One piece of a [`SplitFile`] for a copy thread to copy.
+pub struct Chunk {
+    pub file: Arc<SplitFile>,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl SplitFile {
+    /// Create the destination at its full size, ready to be written by several threads at once, and return the
+    /// chunks to copy into it.
+    pub fn split(
+        src: PathBuf,
+        dst: PathBuf,
+        relative: PathBuf,
+        metadata: Metadata,
+        file_id: Option<FileId>,
+        opts: &CopyOptions,
+    ) -> std::io::Result<Vec<Chunk>> {
+        let len = metadata.len();
+        let chunk_size = opts
+            .split_large_files
+            .unwrap_or(MAX_CHUNK_SIZE)
+            .min(MAX_CHUNK_SIZE);
+        let target = match opts.atomic {
+            true => copy::partial_path(&dst),
+            false => dst.clone(),
+        };
+        std::fs::File::create(&target)?.set_len(len)?;
+
+        let count = len.div_ceil(chunk_size);
+        let file = Arc::new(SplitFile {
+            src,
+            dst,
+            target,
+            relative,
+            metadata,
+            file_id,
+            remaining: AtomicUsize::new(count as usize),
+            landed: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+        });
+        Ok((0..count)
+            .map(|idx| {
+                let offset = idx * chunk_size;
+                Chunk {
+                    file: file.clone(),
+                    offset,
+                    len: chunk_size.min(len - offset),
+                }
+            })
+            .collect())
+    }
+
+    /// Bytes written by chunks that have finished successfully so far.
+    pub fn landed(&self) -> u64 {
+        self.landed.load(Ordering::SeqCst)
+    }
+
+    /// Returns true once every chunk has finished, successfully or not.
+    pub fn done(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+
+    /// Returns true if a chunk of this file has failed, so the rest don't need copying.
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Mark the file as failed and remove whatever has been written of it. Returns `None` if it had already failed,
+    /// and otherwise whether the destination was removed.
+    pub fn fail(&self) -> Option<bool> {
+        if self.failed.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(std::fs::remove_file(&self.target).is_ok())
+    }
+
+    /// Record that a chunk has finished, having written `landed` bytes. Returns true if it was the last one, in which
+    /// case the file is either complete or [failed](Self::failed).
+    pub fn chunk_done(&self, landed: u64) -> bool {
+        self.landed.fetch_add(landed, Ordering::SeqCst);
+        let last = self.remaining.fetch_sub(1, Ordering::SeqCst) == 1;
+        if last {
+            // Progress counts the whole file once it's done, rather than its chunks.
+            copy::release_in_flight(self.landed.load(Ordering::SeqCst));
+        }
+        last
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// How often recorded entries are flushed to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An append-only record of the relative paths that have been copied, so an interrupted copy can pick up where it
+/// left off.
+///
+/// Each entry is one line. Only complete lines are read back, so a crash in the middle of a write loses at most the
+/// entries that hadn't been flushed yet.
+pub struct StateFile {
+    writer: BufWriter<File>,
+    last_flush: Instant,
+}
+
+impl StateFile {
+    /// Open the state file at `path`, creating it if it doesn't exist, and return it along with the entries that were
+    /// already recorded.
+    pub fn open(path: &Path) -> std::io::Result<(Self, HashSet<PathBuf>)> {
+        let mut done = HashSet::new();
+        let mut complete_len = 0;
+        if path.exists() {
+            let mut contents = Vec::new();
+            File::open(path)?.read_to_end(&mut contents)?;
+            complete_len = contents
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |i| i + 1);
+            let mut lines: Vec<&[u8]> = contents.split(|b| *b == b'\n').collect();
+            // The last piece is either empty or a partially written entry.
+            lines.pop();
+            done.extend(
+                lines
+                    .into_iter()
+                    .filter(|line| !line.is_empty())
+                    .map(path_from_bytes),
+            );
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Drop any partially written entry so it can't run into the first new one.
+        file.set_len(complete_len as u64)?;
+
+        let state = Self {
+            writer: BufWriter::new(file),
+            last_flush: Instant::now(),
+        };
+        Ok((state, done))
+    }
+
+    /// Record `relative` as copied. Entries are flushed periodically, and on [`StateFile::flush`].
+    pub fn record(&mut self, relative: &Path) -> std::io::Result<()> {
+        self.writer.write_all(&path_to_bytes(relative))?;
+        self.writer.write_all(b"\n")?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+}
+
+/// `path` as bytes: exactly as they are on Unix, and as UTF-8 elsewhere, replacing anything that isn't valid.
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
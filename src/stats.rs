@@ -1,6 +1,7 @@
 use std::ops::{Add, AddAssign};
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Accumulator {
     pub file_count_found: u64,
     pub byte_count_found: u64,
@@ -8,9 +9,94 @@ pub struct Accumulator {
     pub byte_count_copied: u64,
     pub file_count_skipped: u64,
     pub byte_count_skipped: u64,
+    /// Files skipped by `--update` because they were already up to date. These are also counted as skipped.
+    pub file_count_up_to_date: u64,
+    pub byte_count_up_to_date: u64,
+    /// Files that failed to copy and were skipped with `continue_on_error`. These are also counted as skipped.
+    pub file_count_failed: u64,
+    /// Files and directories that couldn't be read because permission was denied, and were skipped with
+    /// `continue_on_error`. Unreadable files are also counted as skipped.
+    pub file_count_unreadable: u64,
+    pub dir_count_unreadable: u64,
+    /// Files skipped with `--skip-locked` because another process had them locked. These are also counted as skipped.
+    pub file_count_locked: u64,
+    /// Files the search found that were gone from the source by the time they were to be copied. These are also counted
+    /// as skipped.
+    pub file_count_vanished: u64,
+    /// Bytes read from sources and destinations to compare their checksums.
+    pub byte_count_hashed: u64,
+    /// Files checked with `--verify`, and the bytes read from sources and destinations to check them.
+    pub file_count_verified: u64,
+    pub byte_count_verified: u64,
+    pub file_count_verify_failed: u64,
+    /// Files picked with `--verify-sample`, and their sizes.
+    pub file_count_sampled: u64,
+    pub byte_count_sampled: u64,
+    pub file_count_cloned: u64,
+    pub byte_count_cloned: u64,
+    pub file_count_sparse: u64,
+    pub byte_count_sparse: u64,
+    pub byte_count_holes: u64,
+    pub file_count_linked: u64,
+    pub byte_count_linked: u64,
+    pub file_count_partial_removed: u64,
+    pub file_count_retried: u64,
+    /// Files whose size when copied wasn't what the search found, or that changed size while being copied, and how
+    /// many bytes bigger or smaller than found they were copied at. The bytes found are adjusted by the difference.
+    pub file_count_changed: u64,
+    pub byte_count_grown: u64,
+    pub byte_count_shrunk: u64,
+    /// Files copied a second time with `--recopy-changed` because they changed while being copied.
+    pub file_count_recopied: u64,
+    pub file_count_xattrs_failed: u64,
+    pub file_count_acls_failed: u64,
+    pub file_count_special_skipped: u64,
+    /// Cloud placeholder files the search found, and their sizes. With `--skip-placeholders`, these are left out of
+    /// what was found rather than counted as skipped.
+    pub file_count_placeholders: u64,
+    pub byte_count_placeholders: u64,
+    /// Files left out because `--owner`, `--uid`, or `--group` didn't match them, and directories skipped for the same
+    /// reason with `--prune-foreign-dirs`.
+    pub file_count_foreign: u64,
+    pub dir_count_foreign: u64,
+    /// Directories created at the destination.
+    pub dir_count_created: u64,
+    /// Files and directories removed from the destination by `--delete`, and files that couldn't be.
+    pub file_count_deleted: u64,
+    pub byte_count_deleted: u64,
+    pub dir_count_deleted: u64,
+    pub file_count_delete_failed: u64,
+    /// Source files removed by `--move` once they were copied or renamed into place, and ones that couldn't be.
+    pub file_count_moved: u64,
+    pub byte_count_moved: u64,
+    pub file_count_move_failed: u64,
+    /// Destination files moved out of the way by `--backup` or `--backup-dir` before being overwritten.
+    pub file_count_backed_up: u64,
+    /// Bytes of files compared block by block with `--delta`, and how many of them had to be written.
+    pub byte_count_delta_compared: u64,
+    pub byte_count_delta_written: u64,
+    /// Copied files replaced with hard links to identical files with `--dedupe-dest`, and the space that saved.
+    pub file_count_deduped: u64,
+    pub byte_count_deduped: u64,
+    /// Files renamed into place at the destination by `--detect-renames` instead of copied, and their sizes.
+    pub file_count_renamed: u64,
+    pub byte_count_renamed: u64,
+    /// Files picked with `--prefer`, and how many of them are done, copied or not.
+    pub file_count_preferred: u64,
+    pub file_count_preferred_done: u64,
+    /// Existing files each `--policy` rule decided about, in the order the rules were given.
+    pub policy_counts: Vec<PolicyCount>,
+    pub nanos_syncing: u64,
 }
 
 impl Accumulator {
+    /// Files that were found but neither copied nor already up to date, because they failed, couldn't be read, or
+    /// were skipped.
+    pub fn files_not_copied(&self) -> u64 {
+        self.file_count_found
+            .saturating_sub(self.file_count_copied + self.file_count_up_to_date)
+    }
+
     #[inline(always)]
     pub fn found(files: u64, bytes: u64) -> Self {
         Self {
@@ -31,8 +117,337 @@ impl Accumulator {
 
     #[inline(always)]
     pub fn skips(files: u64, bytes: u64) -> Self {
-        Self { file_count_skipped: files, byte_count_skipped: bytes, ..Default::default() }
+        Self {
+            file_count_skipped: files,
+            byte_count_skipped: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn up_to_date(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_skipped: files,
+            byte_count_skipped: bytes,
+            file_count_up_to_date: files,
+            byte_count_up_to_date: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn failed(files: u64) -> Self {
+        Self {
+            file_count_skipped: files,
+            file_count_failed: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn hashed(bytes: u64) -> Self {
+        Self {
+            byte_count_hashed: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn verified(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_verified: files,
+            byte_count_verified: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn verify_failed(files: u64) -> Self {
+        Self {
+            file_count_verify_failed: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn clones(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_cloned: files,
+            byte_count_cloned: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn sparse(files: u64, bytes: u64, holes: u64) -> Self {
+        Self {
+            file_count_sparse: files,
+            byte_count_sparse: bytes,
+            byte_count_holes: holes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn links(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_linked: files,
+            byte_count_linked: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn specials_skipped(files: u64) -> Self {
+        Self {
+            file_count_special_skipped: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn dirs_created(dirs: u64) -> Self {
+        Self {
+            dir_count_created: dirs,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn synced(time: std::time::Duration) -> Self {
+        Self {
+            nanos_syncing: time.as_nanos() as u64,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn retried(retried: bool) -> Self {
+        Self {
+            file_count_retried: retried as u64,
+            ..Default::default()
+        }
+    }
+
+    /// A file the search found to be `found` bytes that was `copied` at a different size, or changed while it was
+    /// being copied.
+    #[inline(always)]
+    pub fn changed(found: u64, copied: u64) -> Self {
+        Self {
+            file_count_changed: 1,
+            byte_count_grown: copied.saturating_sub(found),
+            byte_count_shrunk: found.saturating_sub(copied),
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn recopied(recopied: bool) -> Self {
+        Self {
+            file_count_recopied: recopied as u64,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn xattrs_failed(failed: bool) -> Self {
+        Self {
+            file_count_xattrs_failed: failed as u64,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn acls_failed(failed: bool) -> Self {
+        Self {
+            file_count_acls_failed: failed as u64,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn partials_removed(removed: bool) -> Self {
+        Self {
+            file_count_partial_removed: removed as u64,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn deleted(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_deleted: files,
+            byte_count_deleted: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn dirs_deleted(dirs: u64) -> Self {
+        Self {
+            dir_count_deleted: dirs,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn delete_failed(files: u64) -> Self {
+        Self {
+            file_count_delete_failed: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn moves(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_moved: files,
+            byte_count_moved: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn move_failed(files: u64) -> Self {
+        Self {
+            file_count_move_failed: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn backed_up(backed_up: bool) -> Self {
+        Self {
+            file_count_backed_up: backed_up as u64,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn delta(compared: u64, written: u64) -> Self {
+        Self {
+            byte_count_delta_compared: compared,
+            byte_count_delta_written: written,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn deduped(len: u64) -> Self {
+        Self {
+            file_count_deduped: 1,
+            byte_count_deduped: len,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn unreadable_files(files: u64) -> Self {
+        Self {
+            file_count_skipped: files,
+            file_count_unreadable: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn locked(files: u64) -> Self {
+        Self {
+            file_count_skipped: files,
+            file_count_locked: files,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn vanished(len: u64) -> Self {
+        Self {
+            file_count_skipped: 1,
+            byte_count_skipped: len,
+            file_count_vanished: 1,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn placeholders(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_placeholders: files,
+            byte_count_placeholders: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn foreign(files: u64, dirs: u64) -> Self {
+        Self {
+            file_count_foreign: files,
+            dir_count_foreign: dirs,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn unreadable_dirs(dirs: u64) -> Self {
+        Self {
+            dir_count_unreadable: dirs,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn sampled(files: u64, bytes: u64) -> Self {
+        Self {
+            file_count_sampled: files,
+            byte_count_sampled: bytes,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn renamed(len: u64) -> Self {
+        Self {
+            file_count_renamed: 1,
+            byte_count_renamed: len,
+            ..Default::default()
+        }
+    }
+
+    /// An existing file that `--policy` rule `rule` decided to skip, or to overwrite if `overwritten`.
+    pub fn policy_applied(rule: usize, overwritten: bool) -> Self {
+        let mut policy_counts = vec![PolicyCount::default(); rule + 1];
+        match overwritten {
+            true => policy_counts[rule].overwritten = 1,
+            false => policy_counts[rule].skipped = 1,
+        }
+        Self {
+            policy_counts,
+            ..Default::default()
+        }
+    }
+}
+
+/// What one `--policy` rule decided about existing files it matched.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PolicyCount {
+    /// Files left alone, including ones `update` found up to date.
+    pub skipped: u64,
+    /// Files replaced.
+    pub overwritten: u64,
+}
+
+/// `lhs` and `rhs` added rule by rule. Either can be shorter than the other, since counts only go as far as the last
+/// rule that matched anything.
+fn add_policy_counts(mut lhs: Vec<PolicyCount>, rhs: Vec<PolicyCount>) -> Vec<PolicyCount> {
+    if lhs.len() < rhs.len() {
+        lhs.resize(rhs.len(), PolicyCount::default());
+    }
+    for (lhs, rhs) in lhs.iter_mut().zip(rhs) {
+        lhs.skipped += rhs.skipped;
+        lhs.overwritten += rhs.overwritten;
     }
+    lhs
 }
 
 impl Add for Accumulator {
@@ -46,6 +461,62 @@ impl Add for Accumulator {
             byte_count_copied: self.byte_count_copied + rhs.byte_count_copied,
             file_count_skipped: self.file_count_skipped + rhs.file_count_skipped,
             byte_count_skipped: self.byte_count_skipped + rhs.byte_count_skipped,
+            file_count_up_to_date: self.file_count_up_to_date + rhs.file_count_up_to_date,
+            byte_count_up_to_date: self.byte_count_up_to_date + rhs.byte_count_up_to_date,
+            file_count_failed: self.file_count_failed + rhs.file_count_failed,
+            file_count_unreadable: self.file_count_unreadable + rhs.file_count_unreadable,
+            dir_count_unreadable: self.dir_count_unreadable + rhs.dir_count_unreadable,
+            file_count_locked: self.file_count_locked + rhs.file_count_locked,
+            file_count_vanished: self.file_count_vanished + rhs.file_count_vanished,
+            byte_count_hashed: self.byte_count_hashed + rhs.byte_count_hashed,
+            file_count_verified: self.file_count_verified + rhs.file_count_verified,
+            byte_count_verified: self.byte_count_verified + rhs.byte_count_verified,
+            file_count_verify_failed: self.file_count_verify_failed + rhs.file_count_verify_failed,
+            file_count_sampled: self.file_count_sampled + rhs.file_count_sampled,
+            byte_count_sampled: self.byte_count_sampled + rhs.byte_count_sampled,
+            file_count_cloned: self.file_count_cloned + rhs.file_count_cloned,
+            byte_count_cloned: self.byte_count_cloned + rhs.byte_count_cloned,
+            file_count_sparse: self.file_count_sparse + rhs.file_count_sparse,
+            byte_count_sparse: self.byte_count_sparse + rhs.byte_count_sparse,
+            byte_count_holes: self.byte_count_holes + rhs.byte_count_holes,
+            file_count_linked: self.file_count_linked + rhs.file_count_linked,
+            byte_count_linked: self.byte_count_linked + rhs.byte_count_linked,
+            file_count_partial_removed: self.file_count_partial_removed
+                + rhs.file_count_partial_removed,
+            file_count_retried: self.file_count_retried + rhs.file_count_retried,
+            file_count_changed: self.file_count_changed + rhs.file_count_changed,
+            byte_count_grown: self.byte_count_grown + rhs.byte_count_grown,
+            byte_count_shrunk: self.byte_count_shrunk + rhs.byte_count_shrunk,
+            file_count_recopied: self.file_count_recopied + rhs.file_count_recopied,
+            file_count_xattrs_failed: self.file_count_xattrs_failed + rhs.file_count_xattrs_failed,
+            file_count_acls_failed: self.file_count_acls_failed + rhs.file_count_acls_failed,
+            file_count_special_skipped: self.file_count_special_skipped
+                + rhs.file_count_special_skipped,
+            file_count_placeholders: self.file_count_placeholders + rhs.file_count_placeholders,
+            byte_count_placeholders: self.byte_count_placeholders + rhs.byte_count_placeholders,
+            file_count_foreign: self.file_count_foreign + rhs.file_count_foreign,
+            dir_count_foreign: self.dir_count_foreign + rhs.dir_count_foreign,
+            dir_count_created: self.dir_count_created + rhs.dir_count_created,
+            file_count_deleted: self.file_count_deleted + rhs.file_count_deleted,
+            byte_count_deleted: self.byte_count_deleted + rhs.byte_count_deleted,
+            dir_count_deleted: self.dir_count_deleted + rhs.dir_count_deleted,
+            file_count_delete_failed: self.file_count_delete_failed + rhs.file_count_delete_failed,
+            file_count_moved: self.file_count_moved + rhs.file_count_moved,
+            byte_count_moved: self.byte_count_moved + rhs.byte_count_moved,
+            file_count_move_failed: self.file_count_move_failed + rhs.file_count_move_failed,
+            file_count_backed_up: self.file_count_backed_up + rhs.file_count_backed_up,
+            byte_count_delta_compared: self.byte_count_delta_compared
+                + rhs.byte_count_delta_compared,
+            byte_count_delta_written: self.byte_count_delta_written + rhs.byte_count_delta_written,
+            file_count_deduped: self.file_count_deduped + rhs.file_count_deduped,
+            byte_count_deduped: self.byte_count_deduped + rhs.byte_count_deduped,
+            file_count_renamed: self.file_count_renamed + rhs.file_count_renamed,
+            byte_count_renamed: self.byte_count_renamed + rhs.byte_count_renamed,
+            file_count_preferred: self.file_count_preferred + rhs.file_count_preferred,
+            file_count_preferred_done: self.file_count_preferred_done
+                + rhs.file_count_preferred_done,
+            policy_counts: add_policy_counts(self.policy_counts, rhs.policy_counts),
+            nanos_syncing: self.nanos_syncing + rhs.nanos_syncing,
         }
     }
 }
@@ -59,5 +530,58 @@ impl AddAssign for Accumulator {
         self.byte_count_copied += rhs.byte_count_copied;
         self.file_count_skipped += rhs.file_count_skipped;
         self.byte_count_skipped += rhs.byte_count_skipped;
+        self.file_count_up_to_date += rhs.file_count_up_to_date;
+        self.byte_count_up_to_date += rhs.byte_count_up_to_date;
+        self.file_count_failed += rhs.file_count_failed;
+        self.file_count_unreadable += rhs.file_count_unreadable;
+        self.dir_count_unreadable += rhs.dir_count_unreadable;
+        self.file_count_locked += rhs.file_count_locked;
+        self.file_count_vanished += rhs.file_count_vanished;
+        self.byte_count_hashed += rhs.byte_count_hashed;
+        self.file_count_verified += rhs.file_count_verified;
+        self.byte_count_verified += rhs.byte_count_verified;
+        self.file_count_verify_failed += rhs.file_count_verify_failed;
+        self.file_count_sampled += rhs.file_count_sampled;
+        self.byte_count_sampled += rhs.byte_count_sampled;
+        self.file_count_cloned += rhs.file_count_cloned;
+        self.byte_count_cloned += rhs.byte_count_cloned;
+        self.file_count_sparse += rhs.file_count_sparse;
+        self.byte_count_sparse += rhs.byte_count_sparse;
+        self.byte_count_holes += rhs.byte_count_holes;
+        self.file_count_linked += rhs.file_count_linked;
+        self.byte_count_linked += rhs.byte_count_linked;
+        self.file_count_partial_removed += rhs.file_count_partial_removed;
+        self.file_count_retried += rhs.file_count_retried;
+        self.file_count_changed += rhs.file_count_changed;
+        self.byte_count_grown += rhs.byte_count_grown;
+        self.byte_count_shrunk += rhs.byte_count_shrunk;
+        self.file_count_recopied += rhs.file_count_recopied;
+        self.file_count_xattrs_failed += rhs.file_count_xattrs_failed;
+        self.file_count_acls_failed += rhs.file_count_acls_failed;
+        self.file_count_special_skipped += rhs.file_count_special_skipped;
+        self.file_count_placeholders += rhs.file_count_placeholders;
+        self.byte_count_placeholders += rhs.byte_count_placeholders;
+        self.file_count_foreign += rhs.file_count_foreign;
+        self.dir_count_foreign += rhs.dir_count_foreign;
+        self.dir_count_created += rhs.dir_count_created;
+        self.file_count_deleted += rhs.file_count_deleted;
+        self.byte_count_deleted += rhs.byte_count_deleted;
+        self.dir_count_deleted += rhs.dir_count_deleted;
+        self.file_count_delete_failed += rhs.file_count_delete_failed;
+        self.file_count_moved += rhs.file_count_moved;
+        self.byte_count_moved += rhs.byte_count_moved;
+        self.file_count_move_failed += rhs.file_count_move_failed;
+        self.file_count_backed_up += rhs.file_count_backed_up;
+        self.byte_count_delta_compared += rhs.byte_count_delta_compared;
+        self.byte_count_delta_written += rhs.byte_count_delta_written;
+        self.file_count_deduped += rhs.file_count_deduped;
+        self.byte_count_deduped += rhs.byte_count_deduped;
+        self.file_count_renamed += rhs.file_count_renamed;
+        self.byte_count_renamed += rhs.byte_count_renamed;
+        self.file_count_preferred += rhs.file_count_preferred;
+        self.file_count_preferred_done += rhs.file_count_preferred_done;
+        self.policy_counts =
+            add_policy_counts(std::mem::take(&mut self.policy_counts), rhs.policy_counts);
+        self.nanos_syncing += rhs.nanos_syncing;
     }
 }
@@ -0,0 +1,155 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How much unused allowance can build up while nothing is being copied, as a fraction of a second (1/N).
+const BURST_FRACTION: f64 = 10.0;
+
+/// The `--bwlimit` token bucket shared by every thread of one copy, or nothing if there's no limit.
+#[derive(Default)]
+pub struct Throttle(Option<Mutex<Bucket>>);
+
+struct Bucket {
+    /// Bytes per second.
+    rate: f64,
+    /// Bytes that can be copied right now. This goes negative when threads take more than there is, and they wait
+    /// for it to be paid back.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    /// Limit everything copied through this to `rate` bytes per second, across all threads, if there's a rate.
+    pub fn new(rate: Option<u64>) -> Self {
+        Self(rate.map(|rate| {
+            Mutex::new(Bucket {
+                rate: rate as f64,
+                tokens: 0.0,
+                refilled: Instant::now(),
+            })
+        }))
+    }
+
+    /// The limit, in bytes per second.
+    pub fn rate(&self) -> Option<u64> {
+        self.0
+            .as_ref()
+            .map(|bucket| bucket.lock().unwrap().rate as u64)
+    }
+
+    /// Wait until `bytes` can be copied without going over the limit. Returns straight away if there's no limit.
+    pub fn acquire(&self, bytes: u64) {
+        let Some(bucket) = &self.0 else {
+            return;
+        };
+        let wait = bucket.lock().unwrap().take(bytes, Instant::now());
+        std::thread::sleep(wait);
+    }
+}
+
+impl Bucket {
+    /// Take `bytes` from the bucket at `now`, returning how long to wait before copying them.
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let refill = now.duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate / BURST_FRACTION);
+        self.refilled = now;
+        self.tokens -= bytes as f64;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `total` bytes in `chunk`s through a bucket limited to `rate`, to a sink that takes `write_time` per chunk,
+    /// on a clock that only moves as the writes and waits say. Returns the rate the bytes went through at.
+    fn throughput(rate: u64, total: u64, chunk: u64, write_time: Duration) -> f64 {
+        let start = Instant::now();
+        let mut now = start;
+        let mut bucket = Bucket {
+            rate: rate as f64,
+            tokens: 0.0,
+            refilled: now,
+        };
+        let mut written = 0;
+        while written < total {
+            now += bucket.take(chunk, now);
+            now += write_time;
+            written += chunk;
+        }
+        written as f64 / now.duration_since(start).as_secs_f64()
+    }
+
+    #[track_caller]
+    fn assert_within_10_percent(actual: f64, expected: u64) {
+        let error = (actual - expected as f64).abs() / expected as f64;
+        assert!(
+            error <= 0.1,
+            "{:.0} bytes/s is {:.1}% off {}",
+            actual,
+            error * 100.0,
+            expected
+        );
+    }
+
+    #[test]
+    fn holds_a_fast_sink_to_the_limit() {
+        let rate = 1024 * 1024;
+        assert_within_10_percent(throughput(rate, 10 * rate, 64 * 1024, Duration::ZERO), rate);
+        assert_within_10_percent(
+            throughput(rate, 10 * rate, 1024 * 1024, Duration::from_micros(100)),
+            rate,
+        );
+    }
+
+    #[test]
+    fn holds_small_writes_to_the_limit() {
+        let rate = 100 * 1024;
+        assert_within_10_percent(
+            throughput(rate, 10 * rate, 512, Duration::from_micros(10)),
+            rate,
+        );
+    }
+
+    #[test]
+    fn leaves_a_slow_sink_alone() {
+        // The sink can only take half the limit, so the bucket shouldn't slow it down further.
+        let rate = 1024 * 1024;
+        let chunk = 64 * 1024;
+        let write_time = Duration::from_secs_f64(2.0 * chunk as f64 / rate as f64);
+        assert_within_10_percent(throughput(rate, 10 * rate, chunk, write_time), rate / 2);
+    }
+
+    #[test]
+    fn saves_up_no_more_than_a_burst() {
+        let rate = 1024 * 1024;
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            rate: rate as f64,
+            tokens: 0.0,
+            refilled: start,
+        };
+        // After idling for a minute, only a tenth of a second's worth goes through without waiting.
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.take(rate / 10, later), Duration::ZERO);
+        assert!(bucket.take(rate / 10, later) >= Duration::from_millis(99));
+    }
+
+    #[test]
+    fn each_throttle_has_its_own_limit() {
+        let limited = Throttle::new(Some(1024));
+        let unlimited = Throttle::new(None);
+        assert_eq!(limited.rate(), Some(1024));
+        assert_eq!(Throttle::new(Some(4096)).rate(), Some(4096));
+        assert_eq!(unlimited.rate(), None);
+        // Far more than the other one's limit, without waiting for it.
+        let start = Instant::now();
+        unlimited.acquire(1024 * 1024 * 1024);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+/// How long each thread count is given to show what it can do.
+const WINDOW: Duration = Duration::from_secs(15);
+
+/// How much faster a thread count has to be than the best so far to count as better, so noise isn't chased.
+const MARGIN: f64 = 0.05;
+
+/// Finds the number of copy threads that copies fastest, for `--threads auto-tune`, by trying more or fewer every
+/// [`WINDOW`] and comparing how much each count copied.
+///
+/// It climbs in one direction while that keeps helping, and once it stops, goes back to the best count and tries the
+/// other way in steps half the size. When the step can't get any smaller, it settles on the best count it found.
+pub(crate) struct Tuner {
+    /// How many threads to keep busy right now.
+    active: usize,
+    max: usize,
+    /// The fastest count so far, and how fast it copied in bytes per second.
+    best: Option<(usize, f64)>,
+    step: usize,
+    up: bool,
+    settled: bool,
+    window_started: Instant,
+    /// What had been copied when the window started.
+    window_bytes: u64,
+}
+
+/// A change [`Tuner::update`] made, to log.
+pub(crate) enum Adjustment {
+    /// Trying this many threads next, after the last count copied this many bytes per second.
+    Trying { threads: usize, rate: f64 },
+    /// Staying at this many threads from now on, the fastest found at this many bytes per second.
+    Settled { threads: usize, rate: f64 },
+}
+
+impl Tuner {
+    /// A tuner starting at `active` threads, which never goes above `max`.
+    pub(crate) fn new(active: usize, max: usize) -> Self {
+        Self {
+            active,
+            max,
+            best: None,
+            step: (active / 2).max(1),
+            up: true,
+            settled: false,
+            window_started: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Start the window over from `copied` bytes, so time spent paused isn't measured.
+    pub(crate) fn restart(&mut self, copied: u64) {
+        self.window_started = Instant::now();
+        self.window_bytes = copied;
+    }
+
+    /// Given that `copied` bytes have been copied so far, move on to another thread count if the window is up.
+    pub(crate) fn update(&mut self, copied: u64) -> Option<Adjustment> {
+        let elapsed = self.window_started.elapsed();
+        if self.settled || elapsed < WINDOW {
+            return None;
+        }
+        let rate = copied.saturating_sub(self.window_bytes) as f64 / elapsed.as_secs_f64();
+        self.restart(copied);
+
+        match self.best {
+            Some((_, best_rate)) if rate <= best_rate * (1.0 + MARGIN) => {
+                // Back the other way from the best count, more carefully.
+                self.up = !self.up;
+                self.step /= 2;
+            }
+            _ => self.best = Some((self.active, rate)),
+        }
+        let (best, best_rate) = self.best.unwrap_or((self.active, rate));
+
+        let next = self
+            .next(best)
+            .or_else(|| {
+                // Nowhere to go this way, so try the other.
+                self.up = !self.up;
+                self.next(best)
+            })
+            .filter(|_| self.step > 0);
+        match next {
+            Some(next) => {
+                self.active = next;
+                Some(Adjustment::Trying {
+                    threads: next,
+                    rate,
+                })
+            }
+            None => {
+                self.settled = true;
+                self.active = best;
+                Some(Adjustment::Settled {
+                    threads: best,
+                    rate: best_rate,
+                })
+            }
+        }
+    }
+
+    /// The count one step from `from` in the current direction, if that's not past 1 or `max`.
+    fn next(&self, from: usize) -> Option<usize> {
+        let next = match self.up {
+            true => (from + self.step).min(self.max),
+            false => from.saturating_sub(self.step).max(1),
+        };
+        (next != from).then_some(next)
+    }
+}
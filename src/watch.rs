@@ -0,0 +1,379 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use byte_unit::Byte;
+use notify::{
+    event::{MetadataKind, ModifyKind},
+    EventKind, RecursiveMode, Watcher as _,
+};
+
+use crate::{
+    cloud, copy_queue, copy_to,
+    errors::CopyError,
+    filter, is_special, links,
+    options::CopyOptions,
+    owner, preserve,
+    queue::{Popped, Queue},
+    queued_directories,
+    report::{CopyReport, ReportedError},
+    reporter::{self, Outcome, ProgressReporter, SkipReason},
+    search_dir,
+    stats::Accumulator,
+    throttle::Throttle,
+    ResultInfo, SearchResult, STATUS_POLL_INTERVAL,
+};
+
+/// Notifications of what changes in the source, for `--watch`. Started before the first copy, so nothing that
+/// changes while it's running is missed.
+pub(crate) struct Watcher {
+    // Notifications stop once this is dropped.
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl Watcher {
+    pub(crate) fn new(src: &Path) -> Result<Self, CopyError> {
+        let failed = |source| CopyError::WatchFailed {
+            path: src.to_path_buf(),
+            source,
+        };
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(failed)?;
+        watcher
+            .watch(src, RecursiveMode::Recursive)
+            .map_err(failed)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+}
+
+/// Once the first copy is done, with `report`, keep copying what `watcher` says has changed in the source until the
+/// copy is cancelled. Changes are copied together once nothing more has changed for `watch_debounce`, and the whole
+/// source is searched again every `watch_rescan`. Errors are reported and watching carries on.
+///
+/// Returns `report` with everything copied while watching added to it, which is also passed to
+/// [`ProgressReporter::on_summary`].
+pub(crate) fn watch(
+    watcher: Watcher,
+    mut report: CopyReport,
+    opts: Arc<CopyOptions>,
+    absolute_dst: &Path,
+    threads: usize,
+    started: Instant,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<CopyReport, CopyError> {
+    let cancelled = opts.cancellation.clone();
+    // One `--bwlimit` for everything copied while watching, however it's batched.
+    let throttle = Arc::new(Throttle::new(opts.bwlimit));
+    let watching = Instant::now();
+    message!(
+        "Watching {} for changes. Press Ctrl-C to stop.",
+        opts.src_display.display()
+    );
+
+    let mut changed = BTreeSet::new();
+    let mut last_change = watching;
+    let mut next_rescan = opts.watch_rescan.map(|interval| watching + interval);
+    // Everything copied since watching started.
+    let mut watched = Accumulator::default();
+    let mut copy_time = Duration::ZERO;
+
+    while !cancelled.is_cancelled() {
+        match watcher.events.recv_timeout(STATUS_POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                // Notifications were dropped, so there's no telling what else changed.
+                if event.need_rescan() {
+                    next_rescan = Some(Instant::now());
+                }
+                if !ignored(&event.kind) {
+                    changed.extend(event.paths);
+                    last_change = Instant::now();
+                }
+            }
+            Ok(Err(err)) => message!("Warning: {}", err),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(CopyError::Other(
+                    "Notifications of changes to the source stopped".to_string(),
+                ));
+            }
+        }
+        reporter::deliver(reporter);
+
+        let rescanning = next_rescan.is_some_and(|at| Instant::now() >= at);
+        if !rescanning && (changed.is_empty() || last_change.elapsed() < opts.watch_debounce) {
+            continue;
+        }
+        let mut accumulator = Accumulator::default();
+        let mut batch = BatchReporter(reporter);
+        let result = match rescanning {
+            true => {
+                // Everything that changed is found again.
+                changed.clear();
+                next_rescan = opts.watch_rescan.map(|interval| Instant::now() + interval);
+                rescan(
+                    &opts,
+                    absolute_dst,
+                    threads,
+                    started,
+                    &mut accumulator,
+                    &mut batch,
+                )
+            }
+            false => copy_changed(
+                std::mem::take(&mut changed),
+                &opts,
+                &throttle,
+                threads,
+                started,
+                &mut accumulator,
+                &mut batch,
+            ),
+        };
+        match result {
+            Ok(time) => copy_time += time,
+            Err(err) => reporter.on_error(&ReportedError::from(&err)),
+        }
+        watched += accumulator.clone();
+        reporter::deliver(reporter);
+        if accumulator.file_count_copied > 0 || accumulator.file_count_failed > 0 {
+            reporter.on_message(&format!(
+                "{}: {} files ({}) copied, {} skipped, {} failed. {} files ({}) copied while watching.",
+                match rescanning {
+                    true => "Rescan",
+                    false => "Changes",
+                },
+                accumulator.file_count_copied,
+                Byte::from_bytes(accumulator.byte_count_copied as u128).get_appropriate_unit(false),
+                accumulator.file_count_skipped,
+                accumulator.file_count_failed,
+                watched.file_count_copied,
+                Byte::from_bytes(watched.byte_count_copied as u128).get_appropriate_unit(false)
+            ));
+        }
+    }
+
+    message!(
+        "Stopped watching after {:.0} seconds.",
+        watching.elapsed().as_secs_f64()
+    );
+    reporter::deliver(reporter);
+    report.accumulator += watched;
+    report.copy_time += copy_time;
+    report.total_time = started.elapsed();
+    reporter.on_summary(&report);
+    Ok(report)
+}
+
+/// Changes that don't need anything copied: reads, removals, and access times.
+fn ignored(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Access(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime))
+    )
+}
+
+/// Copy what's at `paths` in the source, along with everything in any directories among them. Paths that are gone
+/// by now, or that the filters leave out, are skipped.
+fn copy_changed(
+    paths: BTreeSet<PathBuf>,
+    opts: &Arc<CopyOptions>,
+    throttle: &Arc<Throttle>,
+    threads: usize,
+    started: Instant,
+    accumulator: &mut Accumulator,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<Duration, CopyError> {
+    let cancelled = opts.cancellation.clone();
+    let mut queue = Queue::new(opts.spool_dir.as_deref(), opts.spool_after, None);
+    // Directories searched already. Paths come in order, so anything inside one comes after it.
+    let mut searched: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        if searched.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(&opts.src) else {
+            continue;
+        };
+        if relative.as_os_str().is_empty() || !included(opts, relative) {
+            continue;
+        }
+        let Ok(mut metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        // Links are copied the way the search would have found them.
+        if metadata.is_symlink() {
+            if opts.preserve_symlinks || (links::is_dir_link(&metadata) && !opts.follow_junctions) {
+                *accumulator += Accumulator::found(1, 0);
+                queue.push_back(SearchResult::Symlink(ResultInfo { path, metadata }))?;
+                continue;
+            }
+            match std::fs::metadata(&path) {
+                Ok(target_metadata) => metadata = target_metadata,
+                Err(_) => continue,
+            }
+        }
+
+        if metadata.is_dir() {
+            if opts.prune_foreign_dirs && !owner::owned(opts, &metadata) {
+                *accumulator += Accumulator::foreign(0, 1);
+                continue;
+            }
+            let mut found = search_dir(
+                &opts.src,
+                &path,
+                accumulator,
+                threads,
+                opts.clone(),
+                None,
+                &cancelled,
+                reporter,
+            )?;
+            queue.push_back(SearchResult::Directory(ResultInfo {
+                path: path.clone(),
+                metadata,
+            }))?;
+            while let Some(popped) = found.pop_front()? {
+                if let Popped::Found(item) = popped {
+                    queue.push_back(item)?;
+                }
+            }
+            searched.push(path);
+        } else if is_special(&metadata.file_type()) {
+            queue.push_back(SearchResult::Special(ResultInfo { path, metadata }))?;
+        } else {
+            if !owner::owned(opts, &metadata) {
+                *accumulator += Accumulator::foreign(1, 0);
+                continue;
+            }
+            if cloud::is_placeholder(&metadata) {
+                *accumulator += Accumulator::placeholders(1, metadata.len());
+                if opts.skip_placeholders {
+                    reporter.on_file_done(&path, Outcome::Skipped(SkipReason::Placeholder));
+                    continue;
+                }
+            }
+            *accumulator += Accumulator::found(1, metadata.len());
+            queue.push_back(SearchResult::File(ResultInfo { path, metadata }))?;
+        }
+    }
+    queue.finish()?;
+    if queue.is_empty() {
+        return Ok(Duration::ZERO);
+    }
+
+    let directories = queued_directories(&queue, &opts.src, &opts.dst, opts)?;
+    let copied = copy_queue(
+        queue,
+        opts.src.clone(),
+        opts.dst.clone(),
+        accumulator,
+        threads,
+        opts.clone(),
+        &cancelled,
+        throttle.clone(),
+        HashSet::new(),
+        Duration::ZERO,
+        started,
+        reporter,
+    );
+    preserve::apply_to_directories(directories, opts);
+    copied.map(|(copy_time, ..)| copy_time)
+}
+
+/// Whether the filters include `relative` and every directory it's in, so the search would have found it.
+fn included(opts: &CopyOptions, relative: &Path) -> bool {
+    let mut ancestor = PathBuf::new();
+    relative.components().all(|component| {
+        ancestor.push(component);
+        std::fs::symlink_metadata(opts.src.join(&ancestor))
+            .is_ok_and(|metadata| filter::included(&opts.filters, &ancestor, &metadata))
+    })
+}
+
+/// Search the whole source again and copy it the way the first copy did, for `--watch-rescan`.
+fn rescan(
+    opts: &Arc<CopyOptions>,
+    absolute_dst: &Path,
+    threads: usize,
+    started: Instant,
+    accumulator: &mut Accumulator,
+    reporter: &mut dyn ProgressReporter,
+) -> Result<Duration, CopyError> {
+    let search_start = Instant::now();
+    let mut found = Accumulator::default();
+    let queue = search_dir(
+        &opts.src,
+        &opts.src,
+        &mut found,
+        threads,
+        opts.clone(),
+        None,
+        &opts.cancellation,
+        reporter,
+    )?;
+    let report = copy_to(
+        queue,
+        found,
+        absolute_dst,
+        threads,
+        opts.clone(),
+        search_start.elapsed(),
+        started,
+        reporter,
+    )?;
+    *accumulator += report.accumulator;
+    Ok(report.copy_time)
+}
+
+/// Passes everything on to another reporter apart from the summary, which is only given once watching stops.
+struct BatchReporter<'a>(&'a mut dyn ProgressReporter);
+
+impl ProgressReporter for BatchReporter<'_> {
+    fn on_search_progress(&mut self, accumulator: &Accumulator) {
+        self.0.on_search_progress(accumulator);
+    }
+
+    fn on_copy_progress(&mut self, accumulator: &Accumulator) {
+        self.0.on_copy_progress(accumulator);
+    }
+
+    fn on_file_progress(&mut self, path: &Path, written: u64, len: u64) {
+        self.0.on_file_progress(path, written, len);
+    }
+
+    fn on_active(&mut self, thread_id: usize, path: &Path, elapsed: Duration) {
+        self.0.on_active(thread_id, path, elapsed);
+    }
+
+    fn on_file_done(&mut self, path: &Path, outcome: Outcome) {
+        self.0.on_file_done(path, outcome);
+    }
+
+    fn on_copied_to(&mut self, dst: &Path) {
+        self.0.on_copied_to(dst);
+    }
+
+    fn on_error(&mut self, err: &ReportedError) {
+        self.0.on_error(err);
+    }
+
+    fn on_message(&mut self, message: &str) {
+        self.0.on_message(message);
+    }
+
+    fn on_output(&mut self, line: &str) {
+        self.0.on_output(line);
+    }
+}
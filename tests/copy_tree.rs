@@ -0,0 +1,339 @@
+//! Runs [`ninecopy::copy_tree`] against trees made in temporary directories.
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, Metadata},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use ninecopy::{
+    options::{Policy, Preserve},
+    CopyError, CopyOptions,
+};
+use tempfile::TempDir;
+
+/// A source tree with a few files in nested directories, and an empty directory next to it to copy it to.
+fn tree() -> (TempDir, TempDir) {
+    let src = TempDir::new().unwrap();
+    write(src.path(), "a.txt", "alpha");
+    write(src.path(), "sub/b.txt", "bravo");
+    write(src.path(), "sub/deeper/c.txt", "charlie");
+    (src, TempDir::new().unwrap())
+}
+
+fn write(base: &Path, relative: &str, contents: &str) {
+    let path = base.join(relative);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn read(base: &Path, relative: &str) -> String {
+    fs::read_to_string(base.join(relative)).unwrap()
+}
+
+/// `err`, or what it's made up of if it's several.
+fn errors(err: &CopyError) -> Vec<&CopyError> {
+    match err {
+        CopyError::Multiple(errors) => errors.iter().collect(),
+        err => vec![err],
+    }
+}
+
+/// Everything under `base`, relative to it.
+fn contents(base: &Path) -> BTreeSet<PathBuf> {
+    let mut found = BTreeSet::new();
+    let mut dirs = vec![base.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path.clone());
+            }
+            found.insert(path.strip_prefix(base).unwrap().to_path_buf());
+        }
+    }
+    found
+}
+
+fn set_modified(base: &Path, relative: &str, modified: SystemTime) {
+    fs::File::options()
+        .write(true)
+        .open(base.join(relative))
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+}
+
+#[test]
+fn copies_everything() {
+    let (src, dst) = tree();
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &CopyOptions::default()).unwrap();
+
+    assert_eq!(read(dst.path(), "a.txt"), "alpha");
+    assert_eq!(read(dst.path(), "sub/b.txt"), "bravo");
+    assert_eq!(read(dst.path(), "sub/deeper/c.txt"), "charlie");
+    assert_eq!(report.accumulator.file_count_found, 3);
+    assert_eq!(report.accumulator.file_count_copied, 3);
+    assert_eq!(report.accumulator.byte_count_copied, 17);
+    assert_eq!(report.accumulator.file_count_skipped, 0);
+    assert!(!report.cancelled);
+}
+
+#[test]
+fn update_only_copies_changed_files() {
+    let (src, dst) = tree();
+    ninecopy::copy_tree(src.path(), dst.path(), &CopyOptions::default()).unwrap();
+    write(src.path(), "sub/b.txt", "bravo!");
+    set_modified(
+        src.path(),
+        "sub/b.txt",
+        SystemTime::now() + Duration::from_secs(60),
+    );
+
+    let opts = CopyOptions::builder()
+        .overwrite(Policy::Update)
+        .build()
+        .unwrap();
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+
+    assert_eq!(read(dst.path(), "sub/b.txt"), "bravo!");
+    assert_eq!(report.accumulator.file_count_copied, 1);
+    assert_eq!(report.accumulator.file_count_up_to_date, 2);
+    assert_eq!(report.accumulator.file_count_skipped, 2);
+}
+
+#[test]
+fn continue_on_error_counts_failures() {
+    let (src, dst) = tree();
+    write(dst.path(), "sub/b.txt", "already here");
+
+    let opts = CopyOptions::builder()
+        .continue_on_error(true)
+        .build()
+        .unwrap();
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+
+    // Without a policy for existing files, the one already there fails and the rest are copied.
+    assert_eq!(read(dst.path(), "sub/b.txt"), "already here");
+    assert_eq!(read(dst.path(), "sub/deeper/c.txt"), "charlie");
+    assert_eq!(report.accumulator.file_count_copied, 2);
+    assert_eq!(report.accumulator.file_count_failed, 1);
+    assert_eq!(report.accumulator.files_not_copied(), 1);
+}
+
+#[test]
+fn existing_file_stops_the_copy_without_continue_on_error() {
+    let (src, dst) = tree();
+    write(dst.path(), "sub/b.txt", "already here");
+
+    let err = ninecopy::copy_tree(src.path(), dst.path(), &CopyOptions::default()).unwrap_err();
+
+    assert!(
+        errors(&err).iter().any(
+            |err| matches!(err, CopyError::CannotOverwrite { dst: path, .. } if path.ends_with("sub/b.txt"))
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(read(dst.path(), "sub/b.txt"), "already here");
+}
+
+#[test]
+fn cancelled_copy_stops_early() {
+    let (src, dst) = tree();
+    let opts = CopyOptions::default();
+    opts.cancellation.cancel();
+
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+
+    assert!(report.cancelled);
+    assert_eq!(report.accumulator.file_count_copied, 0);
+    assert!(!dst.path().join("a.txt").exists());
+}
+
+#[test]
+fn prune_empty_dirs_only_creates_parents_of_copied_files() {
+    let (src, dst) = tree();
+    write(src.path(), "docs/readme.md", "read me");
+    write(src.path(), "docs/drafts/old.tmp", "old");
+    write(src.path(), "deep/a/b/c.md", "deep");
+    write(src.path(), "deep/other/z.tmp", "other");
+    fs::create_dir(src.path().join("empty")).unwrap();
+
+    let opts = CopyOptions::builder()
+        .prune_empty_dirs(true)
+        .filter(|path: &Path, metadata: &Metadata| {
+            metadata.is_dir() || path.extension().is_some_and(|extension| extension == "md")
+        })
+        .build()
+        .unwrap();
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+
+    let expected: BTreeSet<PathBuf> = [
+        "docs",
+        "docs/readme.md",
+        "deep",
+        "deep/a",
+        "deep/a/b",
+        "deep/a/b/c.md",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(contents(dst.path()), expected);
+    assert_eq!(report.accumulator.file_count_copied, 2);
+}
+
+#[test]
+fn checksum_with_update_only_reads_files_whose_times_differ() {
+    let (src, dst) = tree();
+    let opts = CopyOptions::builder()
+        .overwrite(Policy::Update)
+        .checksum(true)
+        .build()
+        .unwrap();
+    ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+    let later = SystemTime::now() + Duration::from_secs(60);
+    // The same contents with a different time: compared, and left alone.
+    set_modified(src.path(), "a.txt", later);
+    // Different contents of the same size with a different time: compared, and copied.
+    write(src.path(), "sub/b.txt", "BRAVO");
+    set_modified(src.path(), "sub/b.txt", later);
+    // Different contents of the same size with the same time: trusted to match without being read.
+    let modified = fs::metadata(src.path().join("sub/deeper/c.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    write(src.path(), "sub/deeper/c.txt", "CHARLIE");
+    set_modified(src.path(), "sub/deeper/c.txt", modified);
+
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+
+    assert_eq!(read(dst.path(), "a.txt"), "alpha");
+    assert_eq!(read(dst.path(), "sub/b.txt"), "BRAVO");
+    assert_eq!(read(dst.path(), "sub/deeper/c.txt"), "charlie");
+    assert_eq!(report.accumulator.file_count_copied, 1);
+    assert_eq!(report.accumulator.file_count_up_to_date, 2);
+    // Both copies of a.txt and sub/b.txt are read, and neither of sub/deeper/c.txt.
+    assert_eq!(report.accumulator.byte_count_hashed, 2 * 5 + 2 * 5);
+}
+
+#[test]
+fn move_with_update_verifies_files_already_there_before_removing_them() {
+    let (src, dst) = tree();
+    ninecopy::copy_tree(src.path(), dst.path(), &CopyOptions::default()).unwrap();
+    // Different contents of the same size with the same time, so it looks up to date.
+    let modified = fs::metadata(src.path().join("sub/b.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    write(dst.path(), "sub/b.txt", "BRAVO");
+    set_modified(dst.path(), "sub/b.txt", modified);
+
+    let opts = CopyOptions::builder()
+        .overwrite(Policy::Update)
+        .move_files(true)
+        .verify(true)
+        .continue_on_error(true)
+        .build()
+        .unwrap();
+    // A file that fails verification fails the copy, even if it carries on past it.
+    assert!(ninecopy::copy_tree(src.path(), dst.path(), &opts).is_err());
+
+    assert_eq!(read(src.path(), "sub/b.txt"), "bravo");
+    assert!(!src.path().join("a.txt").exists());
+    assert!(!src.path().join("sub/deeper/c.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn policy_rules_apply_to_symlinks() {
+    let (src, dst) = tree();
+    std::os::unix::fs::symlink("a.txt", src.path().join("kept.lnk")).unwrap();
+    std::os::unix::fs::symlink("a.txt", src.path().join("replaced.lnk")).unwrap();
+    std::os::unix::fs::symlink("elsewhere", dst.path().join("kept.lnk")).unwrap();
+    std::os::unix::fs::symlink("elsewhere", dst.path().join("replaced.lnk")).unwrap();
+
+    let opts = CopyOptions::builder()
+        .preserve(Preserve::SYMLINKS)
+        .policy(glob::Pattern::new("kept.lnk").unwrap(), Policy::Skip)
+        .policy(glob::Pattern::new("*.lnk").unwrap(), Policy::Overwrite)
+        .build()
+        .unwrap();
+    let report = ninecopy::copy_tree(src.path(), dst.path(), &opts).unwrap();
+
+    let target = |name: &str| fs::read_link(dst.path().join(name)).unwrap();
+    assert_eq!(target("kept.lnk"), Path::new("elsewhere"));
+    assert_eq!(target("replaced.lnk"), Path::new("a.txt"));
+    let counts = &report.accumulator.policy_counts;
+    assert_eq!((counts[0].skipped, counts[0].overwritten), (1, 0));
+    assert_eq!((counts[1].skipped, counts[1].overwritten), (0, 1));
+}
+
+/// A source with a link in `Local` back to `Local` itself, like Windows' `Application Data` junction, made by `link`
+/// from the link's path and its target.
+fn looping_tree(link: impl FnOnce(&Path, &Path)) -> (TempDir, TempDir) {
+    let (src, dst) = tree();
+    write(src.path(), "Local/settings.ini", "settings");
+    link(
+        &src.path().join("Local/Application Data"),
+        &src.path().join("Local"),
+    );
+    (src, dst)
+}
+
+/// Check that copying a [`looping_tree`] with `opts` finishes, copying the files once and the loop as a link.
+#[track_caller]
+fn copies_loop_as_link(src: &Path, dst: &Path, opts: &CopyOptions) {
+    let report = ninecopy::copy_tree(src, dst, opts).unwrap();
+
+    assert_eq!(read(dst, "Local/settings.ini"), "settings");
+    let link = fs::symlink_metadata(dst.join("Local/Application Data")).unwrap();
+    assert!(link.file_type().is_symlink());
+    // The link counts as copied too.
+    assert_eq!(report.accumulator.file_count_copied, 5);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_loop_is_copied_as_a_link() {
+    let (src, dst) = looping_tree(|link, target| std::os::unix::fs::symlink(target, link).unwrap());
+    copies_loop_as_link(src.path(), dst.path(), &CopyOptions::default());
+}
+
+#[cfg(unix)]
+#[test]
+fn relative_symlink_loop_is_copied_as_a_link() {
+    let (src, dst) = looping_tree(|link, _| std::os::unix::fs::symlink(".", link).unwrap());
+    copies_loop_as_link(src.path(), dst.path(), &CopyOptions::default());
+}
+
+#[cfg(windows)]
+fn junction(link: &Path, target: &Path) {
+    let status = std::process::Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(target)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[cfg(windows)]
+#[test]
+fn junction_loop_is_copied_as_a_link() {
+    let (src, dst) = looping_tree(junction);
+    copies_loop_as_link(src.path(), dst.path(), &CopyOptions::default());
+}
+
+#[cfg(windows)]
+#[test]
+fn followed_junction_loop_is_copied_as_a_link() {
+    let (src, dst) = looping_tree(junction);
+    let opts = CopyOptions::builder()
+        .follow_junctions(true)
+        .build()
+        .unwrap();
+    copies_loop_as_link(src.path(), dst.path(), &opts);
+}
@@ -0,0 +1,119 @@
+//! Runs `ninecopy` with the source given in different forms that all lead to the same directory.
+
+use std::{fs, path::Path, process::Command};
+
+use tempfile::TempDir;
+
+/// A directory with a source tree called `src` in it.
+fn source() -> TempDir {
+    let temp = TempDir::new().unwrap();
+    fs::create_dir_all(temp.path().join("src/sub")).unwrap();
+    fs::write(temp.path().join("src/a.txt"), "alpha").unwrap();
+    fs::write(temp.path().join("src/sub/b.txt"), "bravo").unwrap();
+    temp
+}
+
+/// Copy `src`, as it's given, to `dst` inside `dir`, from `dir`, and check everything in the source ended up directly
+/// inside `dst`.
+#[track_caller]
+fn copies_contents(dir: &Path, src: &str) {
+    let output = Command::new(env!("CARGO_BIN_EXE_ninecopy"))
+        .arg(src)
+        .arg("dst")
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "copying {:?} failed: {}",
+        src,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let dst = dir.join("dst");
+    assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "alpha");
+    assert_eq!(fs::read_to_string(dst.join("sub/b.txt")).unwrap(), "bravo");
+    assert_eq!(fs::read_dir(&dst).unwrap().count(), 2, "copying {:?}", src);
+}
+
+#[test]
+fn trailing_separator() {
+    let temp = source();
+    copies_contents(temp.path(), "src/");
+}
+
+#[test]
+fn current_directory() {
+    let temp = source();
+    copies_contents(temp.path(), "./src");
+}
+
+#[test]
+fn parent_directory() {
+    let temp = source();
+    copies_contents(temp.path(), "src/../src");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_directory() {
+    let temp = source();
+    std::os::unix::fs::symlink("src", temp.path().join("link")).unwrap();
+    copies_contents(temp.path(), "link");
+}
+
+#[test]
+fn absolute_forms_copy_the_same() {
+    for form in ["src/", "./src", "src/../src"] {
+        let temp = source();
+        let src = temp.path().join(form);
+        let dst = temp.path().join("dst");
+        fs::create_dir(&dst).unwrap();
+        let report = ninecopy::copy_tree(&src, &dst, &ninecopy::CopyOptions::default()).unwrap();
+        assert_eq!(report.accumulator.file_count_copied, 2, "copying {:?}", src);
+        assert_eq!(fs::read_dir(&dst).unwrap().count(), 2, "copying {:?}", src);
+    }
+}
+
+/// Copy `src` inside `dir` to an empty `dst` there with `--rsync-paths`, returning what's directly inside `dst`.
+fn rsync_copy(dir: &Path, src: &std::ffi::OsStr) -> Vec<String> {
+    let mut path = dir.as_os_str().to_os_string();
+    path.push(std::path::MAIN_SEPARATOR_STR);
+    path.push(src);
+    let dst = dir.join("dst");
+    fs::create_dir(&dst).unwrap();
+    let opts = ninecopy::CopyOptions::builder()
+        .rsync_paths(true)
+        .build()
+        .unwrap();
+    ninecopy::copy_tree(Path::new(&path), &dst, &opts).unwrap();
+    let mut names: Vec<_> = fs::read_dir(&dst)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn rsync_paths_without_separator_copies_the_directory() {
+    let temp = source();
+    assert_eq!(rsync_copy(temp.path(), "src".as_ref()), ["src"]);
+    assert_eq!(
+        fs::read_to_string(temp.path().join("dst/src/sub/b.txt")).unwrap(),
+        "bravo"
+    );
+}
+
+#[test]
+fn rsync_paths_with_slash_copies_the_contents() {
+    let temp = source();
+    assert_eq!(rsync_copy(temp.path(), "src/".as_ref()), ["a.txt", "sub"]);
+}
+
+#[cfg(windows)]
+#[test]
+fn rsync_paths_with_backslash_copies_the_contents() {
+    let temp = source();
+    assert_eq!(rsync_copy(temp.path(), r"src\".as_ref()), ["a.txt", "sub"]);
+}